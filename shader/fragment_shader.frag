@@ -1,113 +1,331 @@
 #version 450
 #pragma shader_stage(fragment)
 
-const int GLOBAL_LIGHT_COUNT = 4;
+const int GLOBAL_LIGHT_COUNT = 8;
 const int POINT_LIGHT_COUNT = 8;
 const int SPOT_LIGHT_COUNT = 8;
 
-const float AMBIENT_STRENGTH = 0.02;
+const uint FOG_LINEAR = 1u;
+const uint FOG_EXPONENTIAL = 2u;
 
 layout (location = 0) in vec2 tex_coord;
 layout (location = 1) in vec3 normal_world;
 layout (location = 2) in vec3 position_world;
+layout (location = 3) in vec4 vertex_color;
+// from the MaterialParams component, zero when an entity has none.
+// params_0.x dissolve amount, params_0.y dissolve edge width, params_1.xyz dissolve edge color
+layout (location = 4) flat in vec4 params_0;
+layout (location = 5) flat in vec4 params_1;
+// array layer of the texture, the frame of an animated texture
+layout (location = 6) flat in float layer;
+// from the Material component, emissive.xyz and roughness then metallic in material_1.x
+layout (location = 7) flat in vec4 material_0;
+layout (location = 8) flat in vec4 material_1;
+layout (location = 9) in vec4 tangent_world; // w is the bitangent's handedness, zero without uvs
 
 layout (location = 0) out vec4 outFragColor;
 
 layout (set = 0, binding = 0) uniform Camera {
     mat4 projection_view;
     vec3 position;
+    uint fog_mode; // FogFalloff::shader_id, 0 while fog is off
+    vec3 forward;
+    float fog_density;
+    vec3 fog_color;
+    float fog_start;
+    float fog_end;
 } cam;
 
-layout (set = 1, binding = 0) uniform texture2D tex;
+layout (set = 1, binding = 0) uniform texture2DArray tex;
 layout (set = 1, binding = 1) uniform sampler sam;
 
-layout (set = 2, binding = 0) uniform GlobalLights {
+// FlatNormalTexture for objects without a NormalMap
+layout (set = 3, binding = 0) uniform texture2DArray normal_tex;
+layout (set = 3, binding = 1) uniform sampler normal_sam;
+
+struct GlobalLight {
     vec3 color;
     float power;
     vec3 direction;
-} global_lights[GLOBAL_LIGHT_COUNT];
+};
 
-layout (set = 2, binding = 1) uniform PointLights {
+struct PointLight {
     vec3 position;
     float radius;
     vec3 color;
     float power;
-} point_lights[POINT_LIGHT_COUNT];
+};
 
-layout (set = 2, binding = 2) uniform SpotLights {
+struct SpotLight {
     vec3 position;
     float radius;
     vec3 color;
     float power;
     vec3 direction;
     float cut_off;
-} spot_lights[SPOT_LIGHT_COUNT];
+    // tile in the spot shadow atlas, no shadow while strength is 0
+    mat4 shadow_view_projection;
+    vec4 shadow_rect;
+    float shadow_strength;
+    // layer in spot_cookies, 0 is white
+    uint cookie_layer;
+};
 
+// each light type is a single buffer binding holding a fixed size array
+layout (set = 2, binding = 0) uniform GlobalLights {
+    GlobalLight global_lights[GLOBAL_LIGHT_COUNT];
+};
+
+layout (set = 2, binding = 1) uniform PointLights {
+    PointLight point_lights[POINT_LIGHT_COUNT];
+};
+
+layout (set = 2, binding = 2) uniform SpotLights {
+    SpotLight spot_lights[SPOT_LIGHT_COUNT];
+};
 
+layout (set = 2, binding = 3) uniform AmbientLight {
+    vec3 sky_color;
+    float intensity;
+    vec3 ground_color;
+} ambient_light;
+
+// number of used slots in each light array
+layout (set = 2, binding = 4) uniform LightCounts {
+    uint global_count;
+    uint point_count;
+    uint spot_count;
+} light_counts;
+
+// shadow map of the first global light
+layout (set = 2, binding = 5) uniform Shadow {
+    mat4 view_projection;
+    uint enabled;
+} shadow;
+
+layout (set = 2, binding = 6) uniform texture2D shadow_map;
+layout (set = 2, binding = 7) uniform samplerShadow shadow_sampler;
+layout (set = 2, binding = 8) uniform texture2D spot_shadow_atlas;
+layout (set = 2, binding = 10) uniform texture2DArray spot_cookies;
+layout (set = 2, binding = 11) uniform sampler cookie_sampler;
+
+
+// Stable per texel noise in [0, 1) for the dissolve threshold.
+float dissolve_noise(vec2 uv)
+{
+    return fract(sin(dot(floor(uv * 256.0), vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// Fraction of the first global light reaching position, 3x3 pcf over the shadow map. Explicit lod
+// so it can run after the dissolve discard.
+float shadow_factor(vec3 position)
+{
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+
+    vec4 clip = shadow.view_projection * vec4(position, 1.0);
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+
+    // nothing was drawn there so it counts as lit
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(shadow_map, shadow_sampler), 0));
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 coord = vec3(uv + vec2(x, y) * texel, ndc.z);
+            lit += textureLod(sampler2DShadow(shadow_map, shadow_sampler), coord, 0.0);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Fraction of spot light i reaching position, 3x3 pcf inside the light's atlas tile. Taps are
+// clamped to the tile since its neighbours belong to other lights. Fades to fully lit with the
+// light's shadow strength.
+float spot_shadow_factor(int i, vec3 position)
+{
+    if (spot_lights[i].shadow_strength <= 0.0) {
+        return 1.0;
+    }
+
+    vec4 clip = spot_lights[i].shadow_view_projection * vec4(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    vec3 ndc = clip.xyz / clip.w;
+    vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    vec4 rect = spot_lights[i].shadow_rect;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(spot_shadow_atlas, shadow_sampler), 0));
+    vec2 tile_min = rect.xy + texel * 0.5;
+    vec2 tile_max = rect.xy + rect.zw - texel * 0.5;
+    vec2 center = rect.xy + uv * rect.zw;
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 coord = vec3(clamp(center + vec2(x, y) * texel, tile_min, tile_max), ndc.z);
+            lit += textureLod(sampler2DShadow(spot_shadow_atlas, shadow_sampler), coord, 0.0);
+        }
+    }
+    return mix(1.0, lit / 9.0, spot_lights[i].shadow_strength);
+}
+
+// Tint of spot light i at position, its cookie projected the same way as its shadow.
+vec3 spot_cookie(int i, vec3 position)
+{
+    uint layer = spot_lights[i].cookie_layer;
+    if (layer == 0u) {
+        return vec3(1.0);
+    }
+
+    vec4 clip = spot_lights[i].shadow_view_projection * vec4(position, 1.0);
+    if (clip.w <= 0.0) {
+        return vec3(0.0);
+    }
+    vec2 uv = clip.xy / clip.w * vec2(0.5, -0.5) + 0.5;
+    return textureLod(sampler2DArray(spot_cookies, cookie_sampler), vec3(uv, layer), 0.0).rgb;
+}
+
+// Bends normal by a tangent space normal map sample. Without a tangent, from meshes without uvs,
+// the normal is kept as is.
+vec3 perturbed_normal(vec3 normal, vec4 tangent, vec3 map_sample)
+{
+    vec3 n = normalize(normal);
+    // interpolation shortens the tangent and can leave it off the normal's plane
+    vec3 t = tangent.xyz - n * dot(n, tangent.xyz);
+    if (abs(tangent.w) < 0.5 || dot(t, t) < 1e-8) {
+        return n;
+    }
+
+    t = normalize(t);
+    vec3 b = cross(n, t) * sign(tangent.w);
+    return normalize(mat3(t, b, n) * (map_sample * 2.0 - 1.0));
+}
+
+// Blinn-Phong exponent for a roughness, the usual alpha = roughness^2 mapping. Roughness 0.5 gives
+// about 32, the exponent used before materials. Clamped so a roughness of 0 keeps a visible highlight.
+float specular_exponent(float roughness)
+{
+    float alpha = max(roughness * roughness, 0.05);
+    return 2.0 / (alpha * alpha) - 2.0;
+}
+
+// Diffuse and specular strength of one light, shared by every light type. The specular term is
+// normalized so tighter highlights get brighter instead of just smaller, scaled to 1 at exponent 32.
+vec2 blinn_phong(vec3 normal, vec3 view_dir, vec3 light_dir, float exponent)
+{
+    vec3 half_dir = normalize(view_dir + light_dir);
+
+    float diffuse_strength = max(dot(normal, light_dir), 0.0);
+    float specular_strength = pow(max(dot(normal, half_dir), 0.0), exponent) * (exponent + 8.0) / 40.0;
+
+    return vec2(diffuse_strength, specular_strength);
+}
+
+// How much of a fragment at this view space depth the fog covers.
+float fog_factor(float depth)
+{
+    if (cam.fog_mode == FOG_LINEAR) {
+        return clamp((depth - cam.fog_start) / max(cam.fog_end - cam.fog_start, 0.0001), 0.0, 1.0);
+    }
+    return 1.0 - exp(-cam.fog_density * max(depth, 0.0));
+}
 
-// The current shader does not handle non uniform scaling as normal vectors will not be properly aligned or scaled.
-// TODO: update shader to handle non uniform scaling
 // TODO: update both shaders fragment and vertex to use view space instead of world
 void main()
 {
+    vec3 normal_sample = texture(sampler2DArray(normal_tex, normal_sam), vec3(tex_coord, 0.0)).xyz;
+    vec3 normal = perturbed_normal(normal_world, tangent_world, normal_sample);
+
     //vec3 lightPosition = vec3(0.0, 0.0, 0.0);
     //vec3 lightColor = vec3(1.0, 0.5, 0.5);
 
-    vec3 ambient_color = vec3(1.0, 1.0, 1.0) * AMBIENT_STRENGTH;
+    // hemisphere ambient, blends from ground to sky color as the normal turns upwards
+    float sky_factor = normal.y * 0.5 + 0.5;
+    vec3 ambient_color = mix(ambient_light.ground_color, ambient_light.sky_color, sky_factor) * ambient_light.intensity;
+
+    vec3 texture_color = texture(sampler2DArray(tex, sam), vec3(tex_coord, layer)).xyz;
 
-    vec3 texture_color = texture(sampler2D(tex, sam), tex_coord).xyz;
+    // after sampling so the implicit derivatives stay in uniform control flow
+    float dissolve_amount = params_0.x;
+    float dissolve_edge = 0.0;
+    if (dissolve_amount > 0.0) {
+        float noise = dissolve_noise(tex_coord);
+        if (noise < dissolve_amount) {
+            discard;
+        }
+        float edge_width = params_0.y;
+        if (edge_width > 0.0) {
+            dissolve_edge = 1.0 - smoothstep(dissolve_amount, dissolve_amount + edge_width, noise);
+        }
+    }
 
     vec3 view_dir = normalize(cam.position.xyz - position_world);
 
+    float exponent = specular_exponent(material_0.w);
+    float metallic = material_1.x;
+
     // possible to cut off lights outside their radius but currently it is probably more performant to not have branching in the shader.
     // If more lights are needed or the system is made more complex it would be a good idea to check this over again.
     // Currently you would also have to calculate the world position of each fragment.
-    vec3 light_sum = vec3(0.0);
+    vec3 diffuse_sum = vec3(0.0);
+    vec3 specular_sum = vec3(0.0);
 
-    for (int i=0; i<GLOBAL_LIGHT_COUNT; i++) {
+    for (int i=0; i<min(int(light_counts.global_count), GLOBAL_LIGHT_COUNT); i++) {
         vec3 light_dir = normalize(-global_lights[i].direction);
-        vec3 half_dir = normalize(view_dir + light_dir);
 
-        float specular_strength = pow(max(dot(normal_world, half_dir), 0.0), 32.0);
-        vec3 specular_color = specular_strength * global_lights[i].color;
-
-        float diffuse_strength = max(dot(normal_world, light_dir), 0.0);
-        vec3 diffuse_color = global_lights[i].color * diffuse_strength;
-
-        light_sum += specular_color + diffuse_color;
+        float visibility = i == 0 ? shadow_factor(position_world) : 1.0;
+        vec2 strength = blinn_phong(normal, view_dir, light_dir, exponent) * visibility;
+        diffuse_sum += global_lights[i].color * strength.x;
+        specular_sum += global_lights[i].color * strength.y;
     }
 
-    for (int i=0; i<POINT_LIGHT_COUNT; i++) {
+    for (int i=0; i<min(int(light_counts.point_count), POINT_LIGHT_COUNT); i++) {
         vec3 light_dir = normalize(point_lights[i].position - position_world);
-        vec3 half_dir = normalize(view_dir + light_dir);
-
-        float specular_strength = pow(max(dot(normal_world, half_dir), 0.0), 32.0);
-        vec3 specular_color = specular_strength * point_lights[i].color;
-
-        float diffuse_strength = max(dot(normal_world, light_dir), 0.0);
-        vec3 diffuse_color = point_lights[i].color * diffuse_strength;
 
-        light_sum += specular_color + diffuse_color;
+        vec2 strength = blinn_phong(normal, view_dir, light_dir, exponent);
+        diffuse_sum += point_lights[i].color * strength.x;
+        specular_sum += point_lights[i].color * strength.y;
     }
 
-    for (int i=0; i<SPOT_LIGHT_COUNT; i++) {
+    for (int i=0; i<min(int(light_counts.spot_count), SPOT_LIGHT_COUNT); i++) {
         vec3 light_dir = normalize(spot_lights[i].position - position_world);
 
         // TODO: create gradual falloff for lighting
         float theta = dot(light_dir, normalize(-spot_lights[i].direction));
         if( theta > spot_lights[i].cut_off ){
-            vec3 half_dir = normalize(view_dir + light_dir);
+            vec2 strength = blinn_phong(normal, view_dir, light_dir, exponent) * spot_shadow_factor(i, position_world);
+            vec3 color = spot_lights[i].color * spot_cookie(i, position_world);
+            diffuse_sum += color * strength.x;
+            specular_sum += color * strength.y;
+        }
+    }
 
-            float specular_strength = pow(max(dot(normal_world, half_dir), 0.0), 32.0);
-            vec3 specular_color = specular_strength * spot_lights[i].color;
+    // metals have no diffuse and highlights in their own color, everything else reflects white
+    vec3 base_color = texture_color * vertex_color.rgb;
+    vec3 specular_color = mix(vec3(1.0), base_color, metallic);
+    vec3 color = (ambient_color + diffuse_sum * (1.0 - metallic)) * base_color + specular_sum * specular_color;
 
-            float diffuse_strength = max(dot(normal_world, light_dir), 0.0);
-            vec3 diffuse_color = spot_lights[i].color * diffuse_strength;
+    // emissive is unlit
+    color += material_0.xyz;
 
-            light_sum += specular_color + diffuse_color;
-        }
+    // the dissolve edge glows regardless of lighting
+    color += params_1.xyz * dissolve_edge;
+
+    // skipped entirely while off so frames without fog are unchanged
+    if (cam.fog_mode != 0u) {
+        float depth = dot(position_world - cam.position, cam.forward);
+        color = mix(color, cam.fog_color, fog_factor(depth));
     }
 
-    
-    outFragColor = vec4((ambient_color + light_sum) * texture_color, 1.0);
+    // linear and unbounded, the tonemap pass brings it into the output's range
+    outFragColor = vec4(color, 1.0);
 }
\ No newline at end of file
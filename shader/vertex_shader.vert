@@ -4,27 +4,54 @@
 layout (location = 0) in vec4 position;
 layout (location = 1) in vec4 normal;
 layout (location = 2) in vec2 tex_coord;
+layout (location = 3) in vec4 color;
+layout (location = 4) in vec4 tangent; // w is the bitangent's handedness, zero without uvs
+
+// per instance, see data_types::Instance
+layout (location = 5) in vec4 model_0;
+layout (location = 6) in vec4 model_1;
+layout (location = 7) in vec4 model_2;
+layout (location = 8) in vec4 model_3;
+layout (location = 9) in vec4 params_0;
+layout (location = 10) in vec4 params_1;
+layout (location = 11) in vec4 scale; // w is the texture array layer
+layout (location = 12) in vec4 tint;
+layout (location = 13) in vec4 material_0; // emissive, w is roughness
+layout (location = 14) in vec4 material_1; // x is metallic
 
 layout (set = 0, binding = 0) uniform Camera {
     mat4 projection_view;
 	vec3 position;
 } cam;
 
-layout (push_constant) uniform PushConstants {
-	mat4 model;
-} pc;
-
 layout (location = 0) out vec2 tex_coord_out;
 layout (location = 1) out vec3 normal_world;
 layout (location = 2) out vec3 position_world;
+layout (location = 3) out vec4 color_out;
+layout (location = 4) flat out vec4 params_0_out;
+layout (location = 5) flat out vec4 params_1_out;
+layout (location = 6) flat out float layer_out;
+layout (location = 7) flat out vec4 material_0_out;
+layout (location = 8) flat out vec4 material_1_out;
+layout (location = 9) out vec4 tangent_world;
 
 void main()
 {
-	mat4 mvp = cam.projection_view * pc.model;
+	mat4 model = mat4(model_0, model_1, model_2, model_3);
+	mat4 mvp = cam.projection_view * model;
 	gl_Position = mvp * position;
 
 	tex_coord_out = tex_coord;
-	normal_world = (pc.model * normal).xyz;
-	position_world = (pc.model * position).xyz;
+	// the inverse transpose of rotation * scale is rotation * inverse scale, which is model * scale^-2
+	normal_world = normalize(mat3(model) * (normal.xyz / (scale.xyz * scale.xyz)));
+	position_world = (model * position).xyz;
+	color_out = color * tint;
+	params_0_out = params_0;
+	params_1_out = params_1;
+	layer_out = scale.w;
+	material_0_out = material_0;
+	material_1_out = material_1;
+	// tangents lie in the surface so they transform like positions, not like normals
+	tangent_world = vec4(mat3(model) * tangent.xyz, tangent.w);
 
 }
\ No newline at end of file
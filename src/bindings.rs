@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bevy_ecs::world::World;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
+
+// Things the event loop does in response to a key, as opposed to keys read by update systems
+// through Input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Quit,
+    ToggleConsole,
+    ToggleCursorGrab,
+    ToggleFullscreen,
+    TogglePause,
+    CycleRenderMode,
+    Screenshot,
+    RebuildRenderState, // simulates device loss, only bound in debug builds
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::Quit,
+        Action::ToggleConsole,
+        Action::ToggleCursorGrab,
+        Action::ToggleFullscreen,
+        Action::TogglePause,
+        Action::CycleRenderMode,
+        Action::Screenshot,
+        Action::RebuildRenderState,
+    ];
+}
+
+// Key to action map. A copy of Game's is inserted as a resource so systems can show or look up
+// bindings, insert it again after rebinding.
+#[derive(Clone, Debug, Default)]
+pub struct KeyBindings {
+    keys: HashMap<VirtualKeyCode, Action>,
+}
+
+impl KeyBindings {
+    pub fn defaults() -> Self {
+        let mut bindings = Self::default();
+        bindings.bind(VirtualKeyCode::Escape, Action::Quit);
+        bindings.bind(VirtualKeyCode::Grave, Action::ToggleConsole);
+        bindings.bind(VirtualKeyCode::Tab, Action::ToggleCursorGrab);
+        bindings.bind(VirtualKeyCode::F11, Action::ToggleFullscreen);
+        bindings.bind(VirtualKeyCode::P, Action::TogglePause);
+        bindings.bind(VirtualKeyCode::F3, Action::CycleRenderMode);
+        bindings.bind(VirtualKeyCode::F12, Action::Screenshot);
+        #[cfg(debug_assertions)]
+        bindings.bind(VirtualKeyCode::F10, Action::RebuildRenderState);
+
+        bindings
+    }
+
+    // A key triggers one action, binding it again replaces the old one. An action can have several
+    // keys.
+    pub fn bind(&mut self, key: VirtualKeyCode, action: Action) -> Option<Action> {
+        self.keys.insert(key, action)
+    }
+
+    pub fn unbind(&mut self, key: VirtualKeyCode) -> Option<Action> {
+        self.keys.remove(&key)
+    }
+
+    // Overrides from the player profile, None removes the key's default binding.
+    pub fn apply(&mut self, overrides: &BTreeMap<VirtualKeyCode, Option<Action>>) {
+        for (key, action) in overrides {
+            match action {
+                Some(action) => self.bind(*key, *action),
+                None => self.unbind(*key),
+            };
+        }
+    }
+
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.keys.get(&key).copied()
+    }
+
+    // Sorted so the order is stable for display.
+    pub fn keys_for(&self, action: Action) -> Vec<VirtualKeyCode> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort();
+
+        keys
+    }
+}
+
+// Owned by Game. Turns keyboard events into actions, a key fires once when pressed and not again
+// until it is released, so key repeat is ignored.
+#[derive(Debug, Default)]
+pub struct Bindings {
+    keys: KeyBindings,
+    held: HashSet<VirtualKeyCode>,
+}
+
+impl Bindings {
+    pub fn new(keys: KeyBindings) -> Self {
+        Self {
+            keys,
+            held: HashSet::new(),
+        }
+    }
+
+    pub fn keys(&self) -> &KeyBindings {
+        &self.keys
+    }
+
+    pub fn handle_key(&mut self, input: &KeyboardInput) -> Option<Action> {
+        let key = input.virtual_keycode?;
+
+        match input.state {
+            ElementState::Pressed => {
+                if self.held.insert(key) {
+                    self.keys.action(key)
+                } else {
+                    None
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&key);
+                None
+            }
+        }
+    }
+
+    // Release events are not delivered to an unfocused window, without this a key held while
+    // focus was lost would never fire again.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+}
+
+// Lists the keys bound to each action, read from the KeyBindings resource.
+pub fn bindings_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("expected no arguments".to_owned());
+    }
+
+    let keys = world.resource::<KeyBindings>();
+    for action in Action::ALL {
+        log::info!("{:?}: {:?}", action, keys.keys_for(action));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::ModifiersState;
+
+    #[allow(deprecated)]
+    fn input(key: VirtualKeyCode, state: ElementState) -> KeyboardInput {
+        KeyboardInput {
+            scancode: 0,
+            state,
+            virtual_keycode: Some(key),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    #[test]
+    fn lookup_test() {
+        let mut keys = KeyBindings::defaults();
+        assert_eq!(keys.action(VirtualKeyCode::Escape), Some(Action::Quit));
+        assert_eq!(keys.action(VirtualKeyCode::Q), None);
+
+        // rebinding a key replaces its action, an action can have several keys
+        assert_eq!(
+            keys.bind(VirtualKeyCode::F11, Action::Quit),
+            Some(Action::ToggleFullscreen)
+        );
+        assert_eq!(
+            keys.keys_for(Action::Quit),
+            [VirtualKeyCode::Escape, VirtualKeyCode::F11]
+        );
+        assert!(keys.keys_for(Action::ToggleFullscreen).is_empty());
+
+        assert_eq!(keys.unbind(VirtualKeyCode::Escape), Some(Action::Quit));
+        assert_eq!(keys.action(VirtualKeyCode::Escape), None);
+    }
+
+    #[test]
+    fn profile_overrides_test() {
+        let mut keys = KeyBindings::defaults();
+        keys.apply(&BTreeMap::from([
+            (VirtualKeyCode::Q, Some(Action::Quit)),
+            (VirtualKeyCode::P, None),
+        ]));
+
+        assert_eq!(
+            keys.keys_for(Action::Quit),
+            [VirtualKeyCode::Q, VirtualKeyCode::Escape]
+        );
+        assert!(keys.keys_for(Action::TogglePause).is_empty());
+        assert_eq!(
+            keys.action(VirtualKeyCode::F3),
+            Some(Action::CycleRenderMode)
+        );
+    }
+
+    #[test]
+    fn key_repeat_fires_once_test() {
+        let mut bindings = Bindings::new(KeyBindings::defaults());
+        let press = input(VirtualKeyCode::Escape, ElementState::Pressed);
+        let release = input(VirtualKeyCode::Escape, ElementState::Released);
+
+        assert_eq!(bindings.handle_key(&press), Some(Action::Quit));
+        // repeats while held
+        assert_eq!(bindings.handle_key(&press), None);
+        assert_eq!(bindings.handle_key(&press), None);
+
+        assert_eq!(bindings.handle_key(&release), None);
+        assert_eq!(bindings.handle_key(&press), Some(Action::Quit));
+    }
+
+    #[test]
+    fn release_all_rearms_keys_test() {
+        let mut bindings = Bindings::new(KeyBindings::defaults());
+        let press = input(VirtualKeyCode::P, ElementState::Pressed);
+
+        assert_eq!(bindings.handle_key(&press), Some(Action::TogglePause));
+        // focus lost, the release never arrives
+        bindings.release_all();
+        assert_eq!(bindings.handle_key(&press), Some(Action::TogglePause));
+    }
+
+    #[test]
+    fn unbound_and_unknown_keys_test() {
+        let mut bindings = Bindings::new(KeyBindings::defaults());
+        assert_eq!(
+            bindings.handle_key(&input(VirtualKeyCode::Q, ElementState::Pressed)),
+            None
+        );
+
+        let mut unknown = input(VirtualKeyCode::Q, ElementState::Pressed);
+        unknown.virtual_keycode = None;
+        assert_eq!(bindings.handle_key(&unknown), None);
+    }
+}
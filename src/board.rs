@@ -0,0 +1,771 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3};
+
+use bevy_ecs::{
+    entity::Entity,
+    event::EventWriter,
+    prelude::Component,
+    query::{Added, With},
+    system::{CommandQueue, Commands, Query, Res, ResMut},
+    world::World,
+};
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use crate::{
+    camera_cut::{CameraCut, CutTarget, Transition},
+    camera_shake::CameraShake,
+    common_component::{Camera, MainCamera, RenderGeometry, Texture, Transform},
+    console::Console,
+    culling::Aabb,
+    debug_draw::DebugDraw,
+    geometry_library::GeometryId,
+    input::Input,
+    material::Dissolve,
+    math::Easing,
+    picking::{CursorMarker, CursorWorldPosition},
+    profile::ProfileStore,
+    render_system::RenderState,
+    state_hash::{HashState, StateHasher},
+};
+
+// Camera shake from a card landing on the board.
+const CARD_DROP_TRAUMA: f32 = 0.4;
+
+// Cards sent back to the hand dissolve away over this many seconds.
+const CARD_DISSOLVE_SECONDS: f32 = 0.6;
+
+// Held to look down at the board.
+const PEEK_KEY: VirtualKeyCode = VirtualKeyCode::B;
+const PEEK_FOVY: f32 = FRAC_PI_3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SlotCoord {
+    pub row: u32,
+    pub column: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlacementError {
+    OutOfBounds(SlotCoord),
+    Occupied(Entity),
+}
+
+// Grid of card slots on a zone entity. The grid lies in the local xz plane of origin and is
+// centered on it, rows run along z and columns along x.
+#[derive(Clone, Debug, Component)]
+pub struct BoardGrid {
+    pub rows: u32,
+    pub columns: u32,
+    pub cell_size: f32,
+    pub origin: Isometry3<f32>,
+
+    occupants: Vec<Option<Entity>>, // row major
+    slots: Vec<Entity>,             // filled in by spawn_board_slots
+}
+
+impl HashState for BoardGrid {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.rows);
+        hasher.write_u32(self.columns);
+        hasher.write_f32(self.cell_size);
+        self.origin.hash_state(hasher);
+
+        for occupant in &self.occupants {
+            hasher.write_u64(occupant.map_or(u64::MAX, Entity::to_bits));
+        }
+    }
+}
+
+// Child of a BoardGrid entity, one per cell.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct BoardSlot {
+    pub grid: Entity,
+    pub coord: SlotCoord,
+}
+
+impl BoardGrid {
+    pub fn new(rows: u32, columns: u32, cell_size: f32, origin: Isometry3<f32>) -> Self {
+        Self {
+            rows,
+            columns,
+            cell_size,
+            origin,
+            occupants: vec![None; (rows * columns) as usize],
+            slots: Vec::new(),
+        }
+    }
+
+    fn index(&self, coord: SlotCoord) -> Option<usize> {
+        if coord.row < self.rows && coord.column < self.columns {
+            Some((coord.row * self.columns + coord.column) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn coords(&self) -> impl Iterator<Item = SlotCoord> + '_ {
+        (0..self.rows)
+            .flat_map(move |row| (0..self.columns).map(move |column| SlotCoord { row, column }))
+    }
+
+    // World space center of a cell.
+    pub fn slot_position(&self, coord: SlotCoord) -> Point3<f32> {
+        let x = (coord.column as f32 + 0.5 - self.columns as f32 / 2.0) * self.cell_size;
+        let z = (coord.row as f32 + 0.5 - self.rows as f32 / 2.0) * self.cell_size;
+
+        self.origin * Point3::new(x, 0.0, z)
+    }
+
+    // Cell containing the point once projected onto the grid plane, None outside the grid.
+    pub fn slot_at_world_pos(&self, point: &Point3<f32>) -> Option<SlotCoord> {
+        let local = self.origin.inverse_transform_point(point);
+
+        let column = (local.x / self.cell_size + self.columns as f32 / 2.0).floor();
+        let row = (local.z / self.cell_size + self.rows as f32 / 2.0).floor();
+
+        if column < 0.0 || row < 0.0 || column >= self.columns as f32 || row >= self.rows as f32 {
+            return None;
+        }
+
+        Some(SlotCoord {
+            row: row as u32,
+            column: column as u32,
+        })
+    }
+
+    // Free cell whose center is closest to the point, used to snap dropped cards.
+    pub fn nearest_free_slot(&self, point: &Point3<f32>) -> Option<SlotCoord> {
+        self.coords()
+            .filter(|coord| self.occupant(*coord).is_none())
+            .map(|coord| (coord, (self.slot_position(coord) - point).norm_squared()))
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(coord, _)| coord)
+    }
+
+    pub fn occupant(&self, coord: SlotCoord) -> Option<Entity> {
+        self.index(coord).and_then(|i| self.occupants[i])
+    }
+
+    pub fn slot_of(&self, card: Entity) -> Option<SlotCoord> {
+        self.coords()
+            .find(|coord| self.occupant(*coord) == Some(card))
+    }
+
+    pub fn slot_entity(&self, coord: SlotCoord) -> Option<Entity> {
+        self.index(coord).and_then(|i| self.slots.get(i).copied())
+    }
+
+    // Moving a card that is already on this grid frees its old slot. Placing a card on the slot
+    // it already occupies is a no op.
+    pub fn place_card(&mut self, card: Entity, coord: SlotCoord) -> Result<(), PlacementError> {
+        let index = self
+            .index(coord)
+            .ok_or(PlacementError::OutOfBounds(coord))?;
+
+        match self.occupants[index] {
+            Some(occupant) if occupant == card => return Ok(()),
+            Some(occupant) => return Err(PlacementError::Occupied(occupant)),
+            None => (),
+        }
+
+        self.remove_card(card);
+        self.occupants[index] = Some(card);
+
+        Ok(())
+    }
+
+    // Returns the slot the card was removed from.
+    pub fn remove_card(&mut self, card: Entity) -> Option<SlotCoord> {
+        let coord = self.slot_of(card)?;
+        let index = self.index(coord)?;
+        self.occupants[index] = None;
+
+        Some(coord)
+    }
+
+    // Cards still on the grid, in row major order.
+    pub fn cards(&self) -> impl Iterator<Item = (SlotCoord, Entity)> + '_ {
+        self.coords()
+            .filter_map(|coord| self.occupant(coord).map(|card| (coord, card)))
+    }
+}
+
+// Spawns the slot entities for newly added grids.
+pub fn spawn_board_slots(
+    mut commands: Commands,
+    mut grids: Query<(Entity, &mut BoardGrid), Added<BoardGrid>>,
+) {
+    for (grid_entity, mut grid) in grids.iter_mut() {
+        let coords: Vec<SlotCoord> = grid.coords().collect();
+
+        grid.slots = coords
+            .into_iter()
+            .map(|coord| {
+                let position = grid.slot_position(coord);
+                commands
+                    .spawn()
+                    .insert(Transform {
+                        isometry: Isometry3::from_parts(
+                            Translation3::from(position.coords),
+                            grid.origin.rotation,
+                        ),
+                        scale: Vector3::repeat(1.0),
+                        parent: Some(grid_entity),
+                        children: vec![],
+                    })
+                    .insert(BoardSlot {
+                        grid: grid_entity,
+                        coord,
+                    })
+                    .id()
+            })
+            .collect();
+    }
+}
+
+// Outlines every cell while the gizmos console toggle is on, free cells in green and taken ones
+// in red. Rotated grids get the box around each cell.
+pub fn draw_board_slots(mut debug: ResMut<DebugDraw>, grids: Query<&BoardGrid>) {
+    if !debug.enabled {
+        return;
+    }
+
+    for grid in grids.iter() {
+        let half = grid.cell_size / 2.0;
+        for coord in grid.coords() {
+            let center = grid.slot_position(coord);
+            let corners = [(-half, -half), (-half, half), (half, -half), (half, half)]
+                .map(|(x, z)| center + grid.origin.rotation * Vector3::new(x, 0.0, z));
+            let color = match grid.occupant(coord) {
+                Some(_) => DebugDraw::RED,
+                None => DebugDraw::GREEN,
+            };
+
+            if let Some(cell) = Aabb::from_points(&corners) {
+                debug.aabb(cell.min, cell.max, color);
+            }
+        }
+    }
+}
+
+// Card picked up by the player, follows the cursor until it is dropped.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct DraggedCard;
+
+// Card meshes face +z, lying face up on the grid they face along its local y.
+fn card_on_grid(grid: &BoardGrid, position: Point3<f32>) -> Isometry3<f32> {
+    Isometry3::from_parts(
+        Translation3::from(position.coords),
+        grid.origin.rotation * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2),
+    )
+}
+
+// Drag and drop onto the one board on the table. Pressing the left button over a card picks it up,
+// anywhere else deals a new card from the hand. Releasing drops it on the free slot nearest the
+// cursor. The right button sends the card under the cursor back to the hand, so does dropping on a
+// full board. The hand isn't shown yet, cards going back to it dissolve where they are. A card
+// landing on the board shakes the camera.
+#[allow(clippy::too_many_arguments)]
+pub fn drag_cards(
+    mut commands: Commands,
+    input: Res<Input>,
+    cursor: Res<CursorWorldPosition>,
+    profile: Res<ProfileStore>,
+    state: Option<Res<RenderState>>,
+    mut grids: Query<&mut BoardGrid>,
+    mut dragged: Query<(Entity, &mut Transform), With<DraggedCard>>,
+    mut shakes: Query<&mut CameraShake>,
+) {
+    let mut grid = match grids.get_single_mut() {
+        Ok(grid) => grid,
+        Err(_) => return,
+    };
+    let buttons = &input.mouse_buttons;
+
+    if let Ok((card, mut transform)) = dragged.get_single_mut() {
+        if !buttons.just_released(MouseButton::Left) {
+            return;
+        }
+        // a cursor outside the window drops the card where it was last seen
+        let point = cursor
+            .world
+            .unwrap_or_else(|| transform.isometry.translation.vector.into());
+
+        let placed = grid
+            .nearest_free_slot(&point)
+            .ok_or_else(|| "the board is full".to_owned())
+            .and_then(|coord| {
+                grid.place_card(card, coord)
+                    .map(|()| coord)
+                    .map_err(|e| format!("{:?}", e))
+            });
+        match placed {
+            Ok(coord) => {
+                transform.isometry = card_on_grid(&grid, grid.slot_position(coord));
+                transform.parent = grid.slot_entity(coord);
+                commands
+                    .entity(card)
+                    .remove::<DraggedCard>()
+                    .remove::<CursorMarker>();
+                for mut shake in shakes.iter_mut() {
+                    shake.add_trauma(CARD_DROP_TRAUMA);
+                }
+            }
+            Err(e) => {
+                log::info!("card went back to the hand, {}", e);
+                commands
+                    .entity(card)
+                    .remove::<DraggedCard>()
+                    .remove::<CursorMarker>()
+                    .insert(Dissolve::new(CARD_DISSOLVE_SECONDS));
+            }
+        }
+        return;
+    }
+
+    let point = match cursor.world {
+        Some(point) => point,
+        None => return,
+    };
+    let under_cursor = grid
+        .slot_at_world_pos(&point)
+        .and_then(|coord| grid.occupant(coord));
+
+    if buttons.just_pressed(MouseButton::Left) {
+        let card = match under_cursor {
+            Some(card) => {
+                grid.remove_card(card);
+                card
+            }
+            None => commands
+                .spawn()
+                .insert(RenderGeometry::new(GeometryId::CardGeometry))
+                .insert(Texture::new(profile.card_sleeve(state.as_deref())))
+                .id(),
+        };
+        commands
+            .entity(card)
+            .insert(Transform {
+                isometry: card_on_grid(&grid, point),
+                scale: Vector3::repeat(1.0),
+                parent: None,
+                children: vec![],
+            })
+            .insert(DraggedCard)
+            .insert(CursorMarker);
+    } else if buttons.just_pressed(MouseButton::Right) {
+        if let Some(card) = under_cursor {
+            grid.remove_card(card);
+            commands
+                .entity(card)
+                .insert(Dissolve::new(CARD_DISSOLVE_SECONDS));
+        }
+    }
+}
+
+// Cuts the view to above the board while the peek key is held and hands it back to the main camera
+// once it is let go. Pressing the key is ignored while typing, letting go never is so the view
+// can't get stuck on the board.
+pub fn peek_at_board(
+    input: Res<Input>,
+    console: Option<Res<Console>>,
+    grids: Query<Entity, With<BoardGrid>>,
+    cameras: Query<&Camera, With<MainCamera>>,
+    mut cuts: EventWriter<CameraCut>,
+) {
+    let typing = console.is_some_and(|c| c.open);
+
+    if input.just_pressed(PEEK_KEY) && !typing {
+        let board = match grids.get_single() {
+            Ok(board) => board,
+            Err(_) => return,
+        };
+        // above the near edge looking down at the center
+        let offset = Isometry3::look_at_rh(
+            &Point3::new(0.0, 3.0, 2.0),
+            &Point3::origin(),
+            &Vector3::y(),
+        )
+        .inverse();
+
+        cuts.send(CameraCut {
+            target: CutTarget::Entity {
+                entity: board,
+                offset,
+            },
+            transition: Transition::Blend {
+                duration: 0.6,
+                easing: Easing::EaseInOutCubic,
+            },
+            fovy: Some(PEEK_FOVY),
+            interrupt: true,
+        });
+    } else if input.just_released(PEEK_KEY) {
+        cuts.send(CameraCut {
+            target: CutTarget::Release,
+            transition: Transition::Blend {
+                duration: 0.4,
+                easing: Easing::SmoothStep,
+            },
+            // blends back to the camera's own field of view
+            fovy: cameras
+                .get_single()
+                .ok()
+                .and_then(|camera| camera.projection.fovy()),
+            interrupt: true,
+        });
+    }
+}
+
+// Replaces the board with an empty one of a new size, the cards on the old one go back to the hand.
+pub fn board_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let (rows, columns) = match args {
+        [rows, columns] => (
+            rows.parse::<u32>()
+                .map_err(|e| format!("bad row count {}: {}", rows, e))?,
+            columns
+                .parse::<u32>()
+                .map_err(|e| format!("bad column count {}: {}", columns, e))?,
+        ),
+        _ => return Err("expected a row and a column count".to_owned()),
+    };
+    if rows == 0 || columns == 0 {
+        return Err("a board needs at least one slot".to_owned());
+    }
+
+    let mut grids = world.query::<(Entity, &BoardGrid)>();
+    let (grid_entity, grid) = grids
+        .iter(world)
+        .next()
+        .ok_or_else(|| "there is no board".to_owned())?;
+    let replacement = BoardGrid::new(rows, columns, grid.cell_size, grid.origin);
+    let transform = world.get::<Transform>(grid_entity).cloned();
+
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, world);
+    for card in despawn_board(&mut commands, grid_entity, grid) {
+        commands.entity(card).despawn();
+    }
+    let mut board = commands.spawn();
+    board.insert(replacement);
+    if let Some(transform) = transform {
+        board.insert(transform);
+    }
+    queue.apply(world);
+
+    Ok(())
+}
+
+// Despawns the grid and its slots. The cards that were on it are returned so the caller can decide
+// where they go.
+pub fn despawn_board(
+    commands: &mut Commands,
+    grid_entity: Entity,
+    grid: &BoardGrid,
+) -> Vec<Entity> {
+    for slot in &grid.slots {
+        commands.entity(*slot).despawn();
+    }
+    commands.entity(grid_entity).despawn();
+
+    grid.cards().map(|(_, card)| card).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::picking::PlaneTarget;
+    use bevy_ecs::{
+        event::Events,
+        schedule::{Stage, SystemStage},
+    };
+    use nalgebra::Vector2;
+
+    fn coord(row: u32, column: u32) -> SlotCoord {
+        SlotCoord { row, column }
+    }
+
+    fn assert_near(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn slot_layout_test() {
+        let grid = BoardGrid::new(2, 3, 1.0, Isometry3::identity());
+
+        // centered on the origin, columns along x and rows along z
+        assert_near(
+            grid.slot_position(coord(0, 0)),
+            Point3::new(-1.0, 0.0, -0.5),
+        );
+        assert_near(grid.slot_position(coord(1, 2)), Point3::new(1.0, 0.0, 0.5));
+        assert_near(grid.slot_position(coord(0, 1)), Point3::new(0.0, 0.0, -0.5));
+
+        for row in 0..2 {
+            for column in 0..3 {
+                let center = grid.slot_position(coord(row, column));
+                assert_eq!(grid.slot_at_world_pos(&center), Some(coord(row, column)));
+            }
+        }
+
+        // the height above the plane is ignored, points past the edges are not on the grid
+        assert_eq!(
+            grid.slot_at_world_pos(&Point3::new(-1.4, 5.0, 0.9)),
+            Some(coord(1, 0))
+        );
+        assert_eq!(grid.slot_at_world_pos(&Point3::new(1.6, 0.0, 0.0)), None);
+        assert_eq!(grid.slot_at_world_pos(&Point3::new(0.0, 0.0, -1.1)), None);
+    }
+
+    #[test]
+    fn transformed_origin_test() {
+        let origin = Isometry3::from_parts(
+            Translation3::new(10.0, 1.0, 0.0),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2),
+        );
+        let grid = BoardGrid::new(1, 2, 2.0, origin);
+
+        // columns now run along -z
+        assert_near(grid.slot_position(coord(0, 0)), Point3::new(10.0, 1.0, 1.0));
+        assert_near(
+            grid.slot_position(coord(0, 1)),
+            Point3::new(10.0, 1.0, -1.0),
+        );
+        assert_eq!(
+            grid.slot_at_world_pos(&Point3::new(10.5, 0.0, -1.5)),
+            Some(coord(0, 1))
+        );
+    }
+
+    #[test]
+    fn occupancy_test() {
+        let mut grid = BoardGrid::new(2, 2, 1.0, Isometry3::identity());
+        let a = Entity::from_raw(1);
+        let b = Entity::from_raw(2);
+
+        assert_eq!(grid.place_card(a, coord(0, 0)), Ok(()));
+        assert_eq!(grid.place_card(a, coord(0, 0)), Ok(()));
+        assert_eq!(grid.occupant(coord(0, 0)), Some(a));
+
+        assert_eq!(
+            grid.place_card(b, coord(0, 0)),
+            Err(PlacementError::Occupied(a))
+        );
+        assert_eq!(
+            grid.place_card(b, coord(2, 0)),
+            Err(PlacementError::OutOfBounds(coord(2, 0)))
+        );
+        assert_eq!(grid.slot_of(b), None);
+
+        // moving frees the old slot
+        assert_eq!(grid.place_card(a, coord(1, 1)), Ok(()));
+        assert_eq!(grid.occupant(coord(0, 0)), None);
+        assert_eq!(grid.slot_of(a), Some(coord(1, 1)));
+
+        assert_eq!(grid.place_card(b, coord(0, 0)), Ok(()));
+        assert_eq!(
+            grid.cards().collect::<Vec<_>>(),
+            [(coord(0, 0), b), (coord(1, 1), a)]
+        );
+
+        assert_eq!(grid.remove_card(a), Some(coord(1, 1)));
+        assert_eq!(grid.remove_card(a), None);
+        assert_eq!(grid.occupant(coord(1, 1)), None);
+    }
+
+    #[test]
+    fn nearest_free_slot_test() {
+        let mut grid = BoardGrid::new(1, 3, 1.0, Isometry3::identity());
+        let point = Point3::new(-0.9, 0.0, 0.0);
+        assert_eq!(grid.nearest_free_slot(&point), Some(coord(0, 0)));
+
+        // the closest slot is taken, snap to the next one
+        grid.place_card(Entity::from_raw(1), coord(0, 0)).unwrap();
+        assert_eq!(grid.nearest_free_slot(&point), Some(coord(0, 1)));
+
+        grid.place_card(Entity::from_raw(2), coord(0, 1)).unwrap();
+        grid.place_card(Entity::from_raw(3), coord(0, 2)).unwrap();
+        assert_eq!(grid.nearest_free_slot(&point), None);
+    }
+
+    #[test]
+    fn spawn_and_despawn_test() {
+        let mut world = World::new();
+        let grid_entity = world
+            .spawn()
+            .insert(BoardGrid::new(2, 2, 1.0, Isometry3::identity()))
+            .id();
+
+        let mut stage = bevy_ecs::schedule::SystemStage::single(spawn_board_slots);
+        stage.run(&mut world);
+        stage.run(&mut world);
+
+        let grid = world.get::<BoardGrid>(grid_entity).unwrap().clone();
+        let mut slots = world.query::<(&BoardSlot, &Transform)>();
+        assert_eq!(slots.iter(&world).count(), 4);
+        for (slot, transform) in slots.iter(&world) {
+            assert_eq!(slot.grid, grid_entity);
+            assert_eq!(transform.parent, Some(grid_entity));
+            assert_near(
+                Point3::from(transform.isometry.translation.vector),
+                grid.slot_position(slot.coord),
+            );
+        }
+        let slot = grid.slot_entity(coord(1, 0)).unwrap();
+        assert_eq!(world.get::<BoardSlot>(slot).unwrap().coord, coord(1, 0));
+
+        let card = world.spawn().id();
+        let mut grid = grid;
+        grid.place_card(card, coord(1, 0)).unwrap();
+
+        let mut queue = bevy_ecs::system::CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let evicted = despawn_board(&mut commands, grid_entity, &grid);
+        queue.apply(&mut world);
+
+        assert_eq!(evicted, [card]);
+        assert!(world.get_entity(grid_entity).is_none());
+        assert_eq!(slots.iter(&world).count(), 0);
+        assert!(world.get_entity(card).is_some());
+    }
+
+    // One update tick with the cursor at x along the grid's row.
+    fn tick(world: &mut World, stage: &mut SystemStage, x: f32, input: impl FnOnce(&mut Input)) {
+        world.resource_mut::<CursorWorldPosition>().world = Some(Point3::new(x, 0.0, 0.0));
+        input(&mut world.resource_mut::<Input>());
+        stage.run(world);
+        world.resource_mut::<Input>().end_tick();
+    }
+
+    #[test]
+    fn drag_and_drop_test() {
+        let directory =
+            std::env::temp_dir().join(format!("card_game_board_{}", std::process::id()));
+        let mut world = World::new();
+        world.insert_resource(Input::default());
+        world.insert_resource(CursorWorldPosition::new(
+            PlaneTarget::horizontal(0.0),
+            Vector2::new(800.0, 600.0),
+        ));
+        world.insert_resource(ProfileStore::load(&directory));
+        let grid_entity = world
+            .spawn()
+            .insert(BoardGrid::new(1, 2, 1.0, Isometry3::identity()))
+            .id();
+        let camera = world.spawn().insert(CameraShake::new(0.1, 0.05, 0)).id();
+        let grid = |world: &World| world.get::<BoardGrid>(grid_entity).unwrap().clone();
+
+        let mut stage = SystemStage::single_threaded()
+            .with_system(spawn_board_slots)
+            .with_system(drag_cards);
+        let mut dragged = world.query_filtered::<Entity, With<DraggedCard>>();
+        let press = |input: &mut Input| input.mouse_buttons.press(MouseButton::Left);
+        let release = |input: &mut Input| input.mouse_buttons.release(MouseButton::Left);
+
+        // pressing over an empty slot deals a card, releasing snaps it to the nearest slot
+        tick(&mut world, &mut stage, -0.5, press);
+        let card = dragged.iter(&world).next().unwrap();
+        assert_eq!(grid(&world).cards().count(), 0);
+        tick(&mut world, &mut stage, 0.8, release);
+        assert_eq!(dragged.iter(&world).count(), 0);
+        assert_eq!(
+            world.get::<CameraShake>(camera).unwrap().trauma,
+            CARD_DROP_TRAUMA
+        );
+        assert_eq!(grid(&world).occupant(coord(0, 1)), Some(card));
+        let transform = world.get::<Transform>(card).unwrap();
+        assert_near(
+            transform.isometry * Point3::origin(),
+            Point3::new(0.5, 0.0, 0.0),
+        );
+        assert_eq!(transform.parent, grid(&world).slot_entity(coord(0, 1)));
+
+        // picking it up frees its slot
+        tick(&mut world, &mut stage, 0.5, press);
+        assert_eq!(dragged.iter(&world).next().unwrap(), card);
+        assert_eq!(grid(&world).occupant(coord(0, 1)), None);
+        tick(&mut world, &mut stage, 0.5, release);
+        assert_eq!(grid(&world).occupant(coord(0, 1)), Some(card));
+
+        // dropped next to the taken slot, the card goes to the free one
+        tick(&mut world, &mut stage, -0.5, press);
+        let second = dragged.iter(&world).next().unwrap();
+        tick(&mut world, &mut stage, 0.6, release);
+        assert_eq!(grid(&world).occupant(coord(0, 0)), Some(second));
+
+        // a full board sends the card back
+        tick(&mut world, &mut stage, -2.0, press);
+        let third = dragged.iter(&world).next().unwrap();
+        tick(&mut world, &mut stage, -2.0, release);
+        assert_eq!(dragged.iter(&world).count(), 0);
+        assert!(world.get::<Dissolve>(third).is_some());
+
+        tick(&mut world, &mut stage, 0.5, |input| {
+            input.mouse_buttons.press(MouseButton::Right)
+        });
+        assert!(world.get::<Dissolve>(card).is_some());
+        assert_eq!(
+            grid(&world).cards().collect::<Vec<_>>(),
+            [(coord(0, 0), second)]
+        );
+
+        // a new board evicts the cards of the old one
+        assert!(board_command(&mut world, &["2", "0"]).is_err());
+        assert!(board_command(&mut world, &["2"]).is_err());
+        board_command(&mut world, &["2", "3"]).unwrap();
+        assert!(world.get_entity(grid_entity).is_none());
+        assert!(world.get_entity(second).is_none());
+        let mut grids = world.query::<&BoardGrid>();
+        let grid = grids.iter(&world).next().unwrap();
+        assert_eq!((grid.rows, grid.columns), (2, 3));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn peek_at_board_test() {
+        let mut world = World::new();
+        world.insert_resource(Input::default());
+        world.insert_resource(Events::<CameraCut>::default());
+        let board = world
+            .spawn()
+            .insert(BoardGrid::new(1, 1, 1.0, Isometry3::identity()))
+            .id();
+
+        let mut stage = SystemStage::single_threaded().with_system(peek_at_board);
+        let mut run = |world: &mut World, input: fn(&mut Input)| {
+            input(&mut world.resource_mut::<Input>());
+            stage.run(world);
+            world.resource_mut::<Input>().end_tick();
+
+            let mut reader = world.resource::<Events<CameraCut>>().get_reader();
+            let cuts: Vec<CameraCut> = reader
+                .iter(world.resource::<Events<CameraCut>>())
+                .copied()
+                .collect();
+            world.resource_mut::<Events<CameraCut>>().clear();
+            cuts
+        };
+
+        let cuts = run(&mut world, |input| input.keys.press(PEEK_KEY));
+        assert!(matches!(
+            cuts[..],
+            [CameraCut {
+                target: CutTarget::Entity { entity, .. },
+                fovy: Some(PEEK_FOVY),
+                ..
+            }] if entity == board
+        ));
+        // held, nothing new
+        assert!(run(&mut world, |_| ()).is_empty());
+
+        let cuts = run(&mut world, |input| input.keys.release(PEEK_KEY));
+        assert!(matches!(
+            cuts[..],
+            [CameraCut {
+                target: CutTarget::Release,
+                ..
+            }]
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy_ecs::{
+    prelude::Component,
+    query::With,
+    system::{Query, Res},
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    common_component::{MainCamera, Transform},
+    console::Console,
+    input::Input,
+    math::{exp_decay, exp_decay_angle, wrap_angle, SmoothDamped},
+    time::TimeResource,
+};
+
+// keeps the view from flipping over when looking straight up or down
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+// how quickly the view catches up with the mouse, in 1/seconds
+const LOOK_DECAY: f32 = 30.0;
+// roughly the time it takes to get up to speed or stop
+const MOVE_SMOOTH_TIME: f32 = 0.1;
+
+// speed factor per line scrolled
+const SPEED_STEP: f32 = 1.25;
+const SPEED_RANGE: (f32, f32) = (0.25, 50.0);
+
+// Free flying camera. WASD moves along the view, space and left shift move up and down and the
+// mouse looks around while the cursor is grabbed. Scrolling changes the speed. Turning and moving
+// are smoothed. The controller owns the camera's rotation.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct CameraController {
+    pub speed: f32,       // world units per second
+    pub sensitivity: f32, // radians per pixel of mouse motion
+
+    pub yaw: f32, // where the mouse turned to, the view follows smoothly
+    pub pitch: f32,
+
+    view_yaw: f32,
+    view_pitch: f32,
+    velocity: SmoothDamped<Vector3<f32>>,
+}
+
+impl CameraController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sensitivity,
+            yaw: 0.0,
+            pitch: 0.0,
+            view_yaw: 0.0,
+            view_pitch: 0.0,
+            velocity: SmoothDamped::new(Vector3::zeros(), MOVE_SMOOTH_TIME),
+        }
+    }
+
+    // The rotation currently shown, trailing yaw and pitch.
+    pub fn rotation(&self) -> UnitQuaternion<f32> {
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), self.view_yaw)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.view_pitch)
+    }
+}
+
+pub fn camera_controller(
+    time: Res<TimeResource>,
+    input: Res<Input>,
+    console: Option<Res<Console>>,
+    mut cameras: Query<(&mut CameraController, &mut Transform), With<MainCamera>>,
+) {
+    let dt = time.update_dt.as_secs_f32();
+
+    let mouse_delta = input.mouse_delta();
+    let typing = console.is_some_and(|c| c.open);
+
+    for (mut controller, mut transform) in cameras.iter_mut() {
+        controller.speed = (controller.speed * SPEED_STEP.powf(input.scroll().y))
+            .clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+
+        if input.cursor_grabbed {
+            controller.yaw = wrap_angle(controller.yaw - mouse_delta.x * controller.sensitivity);
+            controller.pitch = (controller.pitch - mouse_delta.y * controller.sensitivity)
+                .clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        controller.view_yaw = exp_decay_angle(controller.view_yaw, controller.yaw, LOOK_DECAY, dt);
+        controller.view_pitch = exp_decay(controller.view_pitch, controller.pitch, LOOK_DECAY, dt);
+
+        let rotation = controller.rotation();
+        transform.isometry.rotation = rotation;
+
+        // the camera looks down its local -z, typing brings it to a stop
+        let movement = rotation * Vector3::x() * input.axis(VirtualKeyCode::A, VirtualKeyCode::D)
+            + rotation * -Vector3::z() * input.axis(VirtualKeyCode::S, VirtualKeyCode::W)
+            + Vector3::y() * input.axis(VirtualKeyCode::LShift, VirtualKeyCode::Space);
+        let target_velocity = match movement.try_normalize(f32::EPSILON) {
+            Some(direction) if !typing => direction * controller.speed,
+            _ => Vector3::zeros(),
+        };
+
+        let velocity = controller.velocity.update(target_velocity, dt);
+        transform.isometry.translation.vector += velocity * dt;
+    }
+}
@@ -0,0 +1,494 @@
+use std::{collections::VecDeque, time::Instant};
+
+use bevy_ecs::{
+    entity::Entity,
+    event::{EventReader, Events},
+    query::With,
+    system::{Query, ResMut},
+    world::World,
+};
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+use crate::{
+    common_component::{Camera, MainCamera, Transform},
+    math::{Easing, Interpolate},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum CutTarget {
+    Pose(Isometry3<f32>),
+    // offset is in the entity's local space, followed every frame while the cut is active
+    Entity {
+        entity: Entity,
+        offset: Isometry3<f32>,
+    },
+    // blends back to the MainCamera's own transform and hands control back to it
+    Release,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Transition {
+    Instant,
+    Blend { duration: f32, easing: Easing },
+}
+
+// Event requesting the presented view to move somewhere. Cuts play in the order they are sent, an
+// interrupting cut drops the queue and starts from wherever the view currently is.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraCut {
+    pub target: CutTarget,
+    pub transition: Transition,
+    pub fovy: Option<f32>, // radians, None keeps the current field of view
+    pub interrupt: bool,
+}
+
+struct ActiveCut {
+    cut: CameraCut,
+    from_pose: Isometry3<f32>,
+    from_fovy: f32,
+    elapsed: f32,
+}
+
+// Virtual camera sitting between gameplay and rendering. While it holds a pose the render system
+// draws from it instead of the MainCamera transform, so camera controllers writing to that
+// transform are never fought.
+#[derive(Default)]
+pub struct CameraDirector {
+    queue: VecDeque<CameraCut>,
+    active: Option<ActiveCut>,
+
+    pose: Option<Isometry3<f32>>,
+    fovy: Option<f32>,
+
+    last_update: Option<Instant>,
+}
+
+impl CameraDirector {
+    // View pose overriding the MainCamera transform, None when gameplay owns the camera.
+    pub fn pose(&self) -> Option<Isometry3<f32>> {
+        self.pose
+    }
+
+    pub fn fovy(&self) -> Option<f32> {
+        self.fovy
+    }
+
+    // The camera and pose the view is presented from, gameplay's own when no cut is active.
+    pub fn presented(&self, camera: &Camera, pose: &Isometry3<f32>) -> (Camera, Isometry3<f32>) {
+        let mut camera = camera.clone();
+        if let Some(fovy) = self.fovy {
+            camera.projection.set_fovy(fovy);
+        }
+
+        (camera, self.pose.unwrap_or(*pose))
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.active.is_none() && self.queue.is_empty()
+    }
+
+    fn push(&mut self, cut: CameraCut) {
+        if cut.interrupt {
+            self.queue.clear();
+            self.active = None;
+        }
+        self.queue.push_back(cut);
+    }
+
+    // Advances the active cut by dt seconds. camera_pose and camera_fovy describe the MainCamera
+    // itself, resolve maps a cut target to its current pose.
+    fn advance(
+        &mut self,
+        dt: f32,
+        camera_pose: Isometry3<f32>,
+        camera_fovy: f32,
+        resolve: impl Fn(&CutTarget) -> Option<Isometry3<f32>>,
+    ) {
+        let mut dt = dt;
+
+        loop {
+            if self.active.is_none() {
+                let cut = match self.queue.pop_front() {
+                    Some(cut) => cut,
+                    None => return,
+                };
+                // starts from the blended pose so retargeting never jumps
+                self.active = Some(ActiveCut {
+                    cut,
+                    from_pose: self.pose.unwrap_or(camera_pose),
+                    from_fovy: self.fovy.unwrap_or(camera_fovy),
+                    elapsed: 0.0,
+                });
+            }
+            let active = self.active.as_mut().unwrap();
+
+            let target_pose = match resolve(&active.cut.target) {
+                Some(pose) => pose,
+                None => {
+                    log::warn!("camera cut target no longer exists, skipping it");
+                    self.active = None;
+                    continue;
+                }
+            };
+            let target_fovy = active.cut.fovy.unwrap_or(active.from_fovy);
+
+            let duration = match active.cut.transition {
+                Transition::Instant => 0.0,
+                Transition::Blend { duration, .. } => duration.max(0.0),
+            };
+
+            active.elapsed += dt;
+            let (pose, fovy) = match active.cut.transition {
+                Transition::Blend { easing, .. } if active.elapsed < duration => {
+                    let t = easing.apply(active.elapsed / duration);
+                    (
+                        blend_pose(&active.from_pose, &target_pose, t),
+                        active.from_fovy.interpolate(&target_fovy, t),
+                    )
+                }
+                _ => (target_pose, target_fovy),
+            };
+
+            self.pose = Some(pose);
+            self.fovy = Some(fovy);
+
+            if active.elapsed < duration {
+                return;
+            }
+
+            // carry the leftover time into the next cut, entity targets keep following until
+            // something else is queued
+            dt = active.elapsed - duration;
+            match active.cut.target {
+                CutTarget::Release => {
+                    self.pose = None;
+                    self.fovy = None;
+                    self.active = None;
+                }
+                CutTarget::Entity { .. } if self.queue.is_empty() => {
+                    active.elapsed = duration;
+                    return;
+                }
+                _ => self.active = None,
+            }
+        }
+    }
+}
+
+pub fn blend_pose(from: &Isometry3<f32>, to: &Isometry3<f32>, t: f32) -> Isometry3<f32> {
+    let translation = from
+        .translation
+        .vector
+        .interpolate(&to.translation.vector, t);
+    let rotation = from.rotation.interpolate(&to.rotation, t);
+
+    Isometry3::from_parts(Translation3::from(translation), rotation)
+}
+
+// Frame system, runs before render so the blended pose is used the same frame.
+pub fn direct_camera(
+    mut cuts: EventReader<CameraCut>,
+    mut director: ResMut<CameraDirector>,
+    camera: Query<(&Transform, &Camera), With<MainCamera>>,
+    transforms: Query<&Transform>,
+) {
+    for cut in cuts.iter() {
+        director.push(*cut);
+    }
+
+    let now = Instant::now();
+    let dt = director
+        .last_update
+        .map_or(0.0, |last| (now - last).as_secs_f32());
+    director.last_update = Some(now);
+
+    let (camera_transform, camera) = match camera.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let camera_pose = camera_transform.isometry;
+
+    director.advance(
+        dt,
+        camera_pose,
+        // orthographic cameras ignore the field of view, any value does for them
+        camera.projection.fovy().unwrap_or_default(),
+        |target| match target {
+            CutTarget::Pose(pose) => Some(*pose),
+            CutTarget::Entity { entity, offset } => {
+                transforms.get(*entity).ok().map(|t| t.isometry * offset)
+            }
+            CutTarget::Release => Some(camera_pose),
+        },
+    );
+}
+
+// Parses the optional blend duration in seconds of the camera command, instant without one.
+fn parse_transition(seconds: Option<&str>) -> Result<Transition, String> {
+    match seconds {
+        None => Ok(Transition::Instant),
+        Some(seconds) => Ok(Transition::Blend {
+            duration: seconds
+                .parse::<f32>()
+                .map_err(|e| format!("bad duration {}: {}", seconds, e))?,
+            easing: Easing::Linear,
+        }),
+    }
+}
+
+pub fn camera_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let (target, seconds) = match args {
+        [] => {
+            let director = world.resource::<CameraDirector>();
+            match director.pose() {
+                Some(pose) => log::info!(
+                    "cut view at {} facing {}, fovy {:?}, {}",
+                    pose.translation.vector,
+                    pose.rotation * -Vector3::z(),
+                    director.fovy(),
+                    if director.is_idle() { "idle" } else { "moving" }
+                ),
+                None => log::info!("the main camera owns the view"),
+            }
+            return Ok(());
+        }
+        ["pose", x, y, z, yaw, seconds @ ..] if seconds.len() <= 1 => {
+            let parse = |value: &str| {
+                value
+                    .parse::<f32>()
+                    .map_err(|e| format!("bad number {}: {}", value, e))
+            };
+            let pose = Isometry3::from_parts(
+                Translation3::new(parse(x)?, parse(y)?, parse(z)?),
+                UnitQuaternion::from_axis_angle(&Vector3::y_axis(), parse(yaw)?.to_radians()),
+            );
+            (CutTarget::Pose(pose), seconds.first().copied())
+        }
+        ["release", seconds @ ..] if seconds.len() <= 1 => {
+            (CutTarget::Release, seconds.first().copied())
+        }
+        _ => return Err("expected pose or release".to_string()),
+    };
+
+    let cut = CameraCut {
+        target,
+        transition: parse_transition(seconds)?,
+        fovy: None,
+        interrupt: true,
+    };
+    world.resource_mut::<Events<CameraCut>>().send(cut);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Easing;
+
+    fn pose(x: f32, yaw: f32) -> Isometry3<f32> {
+        Isometry3::from_parts(
+            Translation3::new(x, 0.0, 0.0),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw),
+        )
+    }
+
+    fn cut(target: Isometry3<f32>, transition: Transition) -> CameraCut {
+        CameraCut {
+            target: CutTarget::Pose(target),
+            transition,
+            fovy: None,
+            interrupt: false,
+        }
+    }
+
+    fn blend(duration: f32) -> Transition {
+        Transition::Blend {
+            duration,
+            easing: Easing::Linear,
+        }
+    }
+
+    fn assert_pose_near(a: &Isometry3<f32>, b: &Isometry3<f32>, epsilon: f32) {
+        let translation = (a.translation.vector - b.translation.vector).norm();
+        let angle = a.rotation.angle_to(&b.rotation);
+        assert!(
+            translation < epsilon && angle < epsilon,
+            "{} != {} (off by {} and {} rad)",
+            a,
+            b,
+            translation,
+            angle
+        );
+    }
+
+    // Director driven by a camera at the origin, pose and release targets only.
+    fn advance(director: &mut CameraDirector, dt: f32) {
+        let camera = Isometry3::identity();
+        director.advance(dt, camera, 1.0, |target| match target {
+            CutTarget::Pose(pose) => Some(*pose),
+            CutTarget::Release => Some(camera),
+            CutTarget::Entity { .. } => None,
+        });
+    }
+
+    #[test]
+    fn instant_cut_lands_exactly_test() {
+        let mut director = CameraDirector::default();
+        assert_eq!(director.pose(), None);
+
+        let target = pose(3.0, 1.2);
+        director.push(cut(target, Transition::Instant));
+        advance(&mut director, 0.0);
+
+        assert_eq!(director.pose(), Some(target));
+        assert_eq!(director.fovy(), Some(1.0));
+        assert!(director.is_idle());
+    }
+
+    #[test]
+    fn blend_timing_test() {
+        let mut director = CameraDirector::default();
+        let target = pose(4.0, 0.0);
+        director.push(CameraCut {
+            fovy: Some(0.5),
+            ..cut(target, blend(2.0))
+        });
+
+        advance(&mut director, 0.5);
+        assert_pose_near(&director.pose().unwrap(), &pose(1.0, 0.0), 1e-5);
+        assert!((director.fovy().unwrap() - 0.875).abs() < 1e-5);
+
+        advance(&mut director, 1.0);
+        assert_pose_near(&director.pose().unwrap(), &pose(3.0, 0.0), 1e-5);
+        assert!(!director.is_idle());
+
+        // overshooting the duration lands on the target
+        advance(&mut director, 1.0);
+        assert_eq!(director.pose(), Some(target));
+        assert_eq!(director.fovy(), Some(0.5));
+        assert!(director.is_idle());
+    }
+
+    #[test]
+    fn queued_cuts_play_in_sequence_test() {
+        let mut director = CameraDirector::default();
+        director.push(cut(pose(2.0, 0.0), blend(1.0)));
+        director.push(cut(pose(2.0, 1.0), blend(1.0)));
+
+        advance(&mut director, 0.5);
+        assert_pose_near(&director.pose().unwrap(), &pose(1.0, 0.0), 1e-5);
+
+        // the leftover time carries into the second cut
+        advance(&mut director, 1.0);
+        assert_pose_near(&director.pose().unwrap(), &pose(2.0, 0.5), 1e-5);
+
+        advance(&mut director, 0.5);
+        assert_pose_near(&director.pose().unwrap(), &pose(2.0, 1.0), 1e-5);
+        assert!(director.is_idle());
+    }
+
+    #[test]
+    fn retarget_is_continuous_test() {
+        let mut director = CameraDirector::default();
+        director.push(cut(pose(4.0, 0.0), blend(1.0)));
+        advance(&mut director, 0.5);
+        let before = director.pose().unwrap();
+
+        director.push(CameraCut {
+            interrupt: true,
+            ..cut(pose(-4.0, 1.0), blend(1.0))
+        });
+
+        // no time passed, the view stays where it was
+        advance(&mut director, 0.0);
+        assert_pose_near(&director.pose().unwrap(), &before, 1e-5);
+
+        // and moves on from there a little at a time
+        advance(&mut director, 0.01);
+        assert_pose_near(&director.pose().unwrap(), &before, 0.1);
+        advance(&mut director, 0.99);
+        assert_pose_near(&director.pose().unwrap(), &pose(-4.0, 1.0), 1e-5);
+    }
+
+    #[test]
+    fn release_hands_back_the_camera_test() {
+        let mut director = CameraDirector::default();
+        director.push(cut(pose(2.0, 0.0), Transition::Instant));
+        director.push(CameraCut {
+            target: CutTarget::Release,
+            transition: blend(1.0),
+            fovy: None,
+            interrupt: false,
+        });
+
+        advance(&mut director, 0.5);
+        assert_pose_near(&director.pose().unwrap(), &pose(1.0, 0.0), 1e-5);
+
+        advance(&mut director, 0.5);
+        assert_eq!(director.pose(), None);
+        assert_eq!(director.fovy(), None);
+        assert!(director.is_idle());
+    }
+
+    #[test]
+    fn missing_entity_is_skipped_test() {
+        let mut director = CameraDirector::default();
+        director.push(CameraCut {
+            target: CutTarget::Entity {
+                entity: Entity::from_raw(7),
+                offset: Isometry3::identity(),
+            },
+            transition: Transition::Instant,
+            fovy: None,
+            interrupt: false,
+        });
+        let target = pose(1.0, 0.0);
+        director.push(cut(target, Transition::Instant));
+
+        advance(&mut director, 0.0);
+        assert_eq!(director.pose(), Some(target));
+        assert!(director.is_idle());
+    }
+
+    #[test]
+    fn camera_command_test() {
+        let mut world = World::new();
+        world.insert_resource(CameraDirector::default());
+        world.insert_resource(Events::<CameraCut>::default());
+
+        camera_command(&mut world, &[]).unwrap();
+        camera_command(&mut world, &["pose", "1", "2", "3", "90"]).unwrap();
+        camera_command(&mut world, &["release", "0.5"]).unwrap();
+        assert!(camera_command(&mut world, &["pose", "1", "2", "3"]).is_err());
+        assert!(camera_command(&mut world, &["release", "soon"]).is_err());
+
+        let events = world.resource::<Events<CameraCut>>();
+        let cuts: Vec<CameraCut> = events.get_reader().iter(events).copied().collect();
+        match cuts[..] {
+            [CameraCut {
+                target: CutTarget::Pose(pose),
+                transition: Transition::Instant,
+                ..
+            }, CameraCut {
+                target: CutTarget::Release,
+                transition: Transition::Blend { duration, .. },
+                ..
+            }] => {
+                assert_pose_near(
+                    &pose,
+                    &Isometry3::from_parts(
+                        Translation3::new(1.0, 2.0, 3.0),
+                        UnitQuaternion::from_axis_angle(
+                            &Vector3::y_axis(),
+                            std::f32::consts::FRAC_PI_2,
+                        ),
+                    ),
+                    1e-5,
+                );
+                assert_eq!(duration, 0.5);
+            }
+            _ => panic!("unexpected cuts {:?}", cuts),
+        }
+    }
+}
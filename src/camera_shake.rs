@@ -0,0 +1,168 @@
+use bevy_ecs::{
+    prelude::Component,
+    system::{Query, Res},
+};
+use nalgebra::{Isometry3, Vector3};
+
+use crate::{math::hash_noise, time::TimeResource};
+
+// Trauma based camera shake. Shake strength is trauma squared so small amounts of trauma are
+// subtle while large amounts ramp up quickly. Trauma and the noise time only advance on fixed
+// updates so the shake looks the same at any frame rate, frames in between blend from the previous
+// update's state like interpolated transforms do.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct CameraShake {
+    pub trauma: f32,
+    pub decay: f32, // trauma lost per second
+
+    pub max_offset: f32, // world units
+    pub max_roll: f32,   // radians
+    pub frequency: f32,  // noise lattice points per second
+    pub seed: u32,
+
+    time: f32,
+    previous: (f32, f32), // trauma and time before the last update
+}
+
+impl CameraShake {
+    pub fn new(max_offset: f32, max_roll: f32, seed: u32) -> Self {
+        Self {
+            trauma: 0.0,
+            decay: 1.0,
+            max_offset,
+            max_roll,
+            frequency: 15.0,
+            seed,
+            time: 0.0,
+            previous: (0.0, 0.0),
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    // One fixed update of dt seconds.
+    pub fn advance(&mut self, dt: f32) {
+        self.previous = (self.trauma, self.time);
+        self.time += dt;
+        self.trauma = (self.trauma - self.decay * dt).max(0.0);
+    }
+
+    // Offsets the camera pose in its own local space. The persistent Transform is never touched,
+    // this is only applied when building the view matrix. blend is TimeResource::blend, 0 shakes as
+    // of the previous update and 1 as of the latest.
+    pub fn apply(&self, isometry: &Isometry3<f32>, blend: f32) -> Isometry3<f32> {
+        let (previous_trauma, previous_time) = self.previous;
+        let trauma = previous_trauma + (self.trauma - previous_trauma) * blend;
+        if trauma <= 0.0 {
+            return *isometry;
+        }
+
+        let shake = trauma * trauma;
+        let t = (previous_time + (self.time - previous_time) * blend) * self.frequency;
+
+        let offset = Vector3::new(
+            smooth_noise(self.seed, t),
+            smooth_noise(self.seed.wrapping_add(1), t),
+            0.0,
+        ) * self.max_offset
+            * shake;
+        let roll = smooth_noise(self.seed.wrapping_add(2), t) * self.max_roll * shake;
+
+        isometry * Isometry3::new(offset, Vector3::z() * roll)
+    }
+}
+
+pub fn decay_camera_shake(time: Res<TimeResource>, mut shakes: Query<&mut CameraShake>) {
+    let dt = time.update_dt.as_secs_f32();
+    for mut shake in shakes.iter_mut() {
+        shake.advance(dt);
+    }
+}
+
+// Value noise in [-1, 1], smoothstep interpolated between integer lattice points.
+fn smooth_noise(seed: u32, x: f32) -> f32 {
+    let i = x.floor();
+    let f = x - i;
+    let u = f * f * (3.0 - 2.0 * f);
+
+    let a = hash_noise(seed, i as i32);
+    let b = hash_noise(seed, i as i32 + 1);
+
+    a + (b - a) * u
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::UnitQuaternion;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn pose() -> Isometry3<f32> {
+        Isometry3::from_parts(
+            Vector3::new(1.0, 2.0, 3.0).into(),
+            UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3),
+        )
+    }
+
+    #[test]
+    fn zero_trauma_is_identical_test() {
+        let mut shake = CameraShake::new(0.1, 0.05, 7);
+        for _ in 0..10 {
+            shake.advance(DT);
+        }
+
+        for blend in [0.0, 0.5, 1.0] {
+            let shaken = shake.apply(&pose(), blend);
+            assert_eq!(shaken.to_matrix(), pose().to_matrix());
+        }
+    }
+
+    #[test]
+    fn decay_timing_test() {
+        let mut shake = CameraShake::new(0.1, 0.05, 7);
+        shake.add_trauma(0.7);
+        shake.add_trauma(0.7);
+        assert_eq!(shake.trauma, 1.0);
+
+        // one unit per second
+        for _ in 0..30 {
+            shake.advance(DT);
+        }
+        assert!((shake.trauma - 0.5).abs() < 1e-4, "{}", shake.trauma);
+        for _ in 0..31 {
+            shake.advance(DT);
+        }
+        assert_eq!(shake.trauma, 0.0);
+    }
+
+    #[test]
+    fn blends_between_updates_test() {
+        let mut shake = CameraShake::new(0.1, 0.05, 7);
+        shake.add_trauma(1.0);
+        shake.advance(DT);
+
+        for _ in 0..20 {
+            let before = shake.apply(&pose(), 1.0);
+            shake.advance(DT);
+
+            // continuous across the update, the frame right after it shows the same pose
+            let after = shake.apply(&pose(), 0.0);
+            assert!((before.to_matrix() - after.to_matrix()).norm() < 1e-6);
+
+            // frames in between move part of the way, more frames mean smaller steps
+            let steps = |frames: usize| {
+                (0..frames)
+                    .map(|i| {
+                        let a = shake.apply(&pose(), i as f32 / frames as f32);
+                        let b = shake.apply(&pose(), (i + 1) as f32 / frames as f32);
+                        (a.translation.vector - b.translation.vector).norm()
+                    })
+                    .fold(0.0, f32::max)
+            };
+            assert!(steps(4) <= steps(1));
+        }
+    }
+}
@@ -1,22 +1,230 @@
 use bevy_ecs::{entity::Entity, prelude::Component};
-use nalgebra::{Isometry3, Perspective3, Vector3};
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3, UnitQuaternion, Vector3, Vector4};
 
-use crate::{geometry_library::GeometryId, texture_library::TextureId};
+use crate::{geometry_library::GeometryId, texture_library::TextureHandle};
 
 #[derive(Clone, Debug, Component)]
 pub struct Transform {
     pub isometry: Isometry3<f32>,
+    pub scale: Vector3<f32>, // applied before the isometry, in the entity's local axes
 
     pub parent: Option<Entity>,
     pub children: Vec<Entity>,
 }
+// Both look down the camera's local -z and follow nalgebra's clip space conventions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective(Perspective3<f32>),
+    Orthographic(Orthographic3<f32>),
+}
+
+impl Projection {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        match self {
+            Self::Perspective(p) => *p.as_matrix(),
+            Self::Orthographic(o) => *o.as_matrix(),
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        match self {
+            Self::Perspective(p) => p.aspect(),
+            Self::Orthographic(o) => (o.right() - o.left()) / (o.top() - o.bottom()),
+        }
+    }
+
+    // Orthographic views keep their height and center and change their width.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        match self {
+            Self::Perspective(p) => p.set_aspect(aspect),
+            Self::Orthographic(o) => {
+                let center = (o.left() + o.right()) / 2.0;
+                let half_width = (o.top() - o.bottom()) * aspect / 2.0;
+                o.set_left_and_right(center - half_width, center + half_width);
+            }
+        }
+    }
+
+    pub fn znear(&self) -> f32 {
+        match self {
+            Self::Perspective(p) => p.znear(),
+            Self::Orthographic(o) => o.znear(),
+        }
+    }
+
+    pub fn zfar(&self) -> f32 {
+        match self {
+            Self::Perspective(p) => p.zfar(),
+            Self::Orthographic(o) => o.zfar(),
+        }
+    }
+
+    // None for orthographic views, which have no field of view.
+    pub fn fovy(&self) -> Option<f32> {
+        match self {
+            Self::Perspective(p) => Some(p.fovy()),
+            Self::Orthographic(_) => None,
+        }
+    }
+
+    // Ignored by orthographic views.
+    pub fn set_fovy(&mut self, fovy: f32) {
+        if let Self::Perspective(p) = self {
+            p.set_fovy(fovy);
+        }
+    }
+}
+
 #[derive(Clone, Debug, Component)]
 pub struct Camera {
-    pub projection: Perspective3<f32>,
+    pub projection: Projection,
+    pub layers: RenderLayers, // only objects and lights sharing one of these are drawn
 }
+
+impl Camera {
+    // fovy in radians.
+    pub fn perspective(aspect: f32, fovy: f32, near: f32, far: f32) -> Self {
+        Self {
+            projection: Projection::Perspective(Perspective3::new(aspect, fovy, near, far)),
+            layers: RenderLayers::default(),
+        }
+    }
+
+    // height is the world space extent of the view, centered on the camera.
+    pub fn orthographic(height: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+
+        Self {
+            projection: Projection::Orthographic(Orthographic3::new(
+                -half_width,
+                half_width,
+                -half_height,
+                half_height,
+                near,
+                far,
+            )),
+            layers: RenderLayers::default(),
+        }
+    }
+
+    pub fn view_projection(&self, isometry: &Isometry3<f32>) -> Matrix4<f32> {
+        self.projection.matrix() * isometry.inverse().to_matrix()
+    }
+
+    // Matches the aspect ratio to a new surface size. Zero sizes come from minimized windows and
+    // are ignored, the old aspect is still right once the window is restored.
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.projection.set_aspect(width as f32 / height as f32);
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Component)]
 pub struct MainCamera;
 
+// Bitmask of the layers an object or light is on, cameras draw what shares a layer with their own
+// mask. Entities without this component are on layer 0 only.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Component)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const COUNT: u32 = 32;
+    pub const NONE: Self = Self(0);
+
+    pub fn layer(n: u32) -> Self {
+        Self::NONE.with(n)
+    }
+
+    pub fn with(self, n: u32) -> Self {
+        assert!(n < Self::COUNT, "render layer {} out of range", n);
+        Self(self.0 | 1 << n)
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    // For entities that may not have the component.
+    pub fn of(layers: Option<&Self>) -> Self {
+        layers.copied().unwrap_or_default()
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::layer(0)
+    }
+}
+
+// Hides renderable entities and lights without despawning them. Entities without this component
+// are visible.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Visibility {
+    pub visible: bool,
+}
+
+impl Visibility {
+    pub fn is_visible(visibility: Option<&Self>) -> bool {
+        visibility.is_none_or(|v| v.visible)
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self { visible: true }
+    }
+}
+
+// Pushes coplanar surfaces apart in depth, positive values move towards the camera. Only a few
+// levels get their own pipeline, see render_system::depth_bias_level.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct DepthBias(pub i32);
+
+// Draw order among objects with the same depth bias, higher keys are drawn later and win ties in
+// the depth test. Used for cards stacked in a pile.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct SortKey(pub i32);
+
+// Generic per entity shader parameters, what each slot means is up to the shader. Entities without
+// this component draw with all zeros. See material for typed views over the slots.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Component)]
+pub struct MaterialParams(pub [f32; 8]);
+
+// RGBA multiplied into the object's color, entities without this component draw white. Gives
+// untextured objects a flat color.
+#[derive(Copy, Clone, Debug, PartialEq, Component)]
+pub struct Tint(pub Vector4<f32>);
+
+impl Tint {
+    pub const WHITE: Self = Self(Vector4::new(1.0, 1.0, 1.0, 1.0));
+}
+
+impl Default for Tint {
+    fn default() -> Self {
+        Self::WHITE
+    }
+}
+
+// How the surface responds to light, entities without this component use Material::default.
+#[derive(Copy, Clone, Debug, PartialEq, Component)]
+pub struct Material {
+    pub roughness: f32, // 0 gives a sharp highlight, 1 spreads it over the whole lit side
+    pub metallic: f32,  // 1 drops the diffuse term and tints the highlight by the surface color
+    pub emissive: Vector3<f32>, // added after lighting, shows even in the dark
+}
+
+impl Default for Material {
+    // roughness 0.5 is close to the fixed specular exponent used before materials existed
+    fn default() -> Self {
+        Self {
+            roughness: 0.5,
+            metallic: 0.0,
+            emissive: Vector3::zeros(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Component)]
 pub struct RenderGeometry {
     pub geom_type: GeometryId,
@@ -30,23 +238,59 @@ impl RenderGeometry {
 
 #[derive(Clone, Copy, Debug, Component)]
 pub struct Texture {
-    pub texture_id: TextureId,
+    pub handle: TextureHandle,
 }
 
 impl Texture {
-    pub fn new(texture_id: TextureId) -> Self {
-        Self { texture_id }
+    // Takes a TextureId for builtin textures or a handle from TextureLibrary::register.
+    pub fn new(handle: impl Into<TextureHandle>) -> Self {
+        Self {
+            handle: handle.into(),
+        }
+    }
+}
+
+// Tangent space normal map, stored linear like FlatNormalTexture. Only the first layer is used,
+// AnimatedTexture doesn't advance it.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct NormalMap {
+    pub handle: TextureHandle,
+}
+
+impl NormalMap {
+    pub fn new(handle: impl Into<TextureHandle>) -> Self {
+        Self {
+            handle: handle.into(),
+        }
+    }
+}
+
+// Cycles through the layers of an array texture, frame wraps around the layer count when drawn.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct AnimatedTexture {
+    pub fps: f32,
+    pub frame: u32,
+    pub progress: f32, // towards the next frame, 0 to 1
+}
+
+impl AnimatedTexture {
+    pub fn new(fps: f32) -> Self {
+        Self {
+            fps,
+            frame: 0,
+            progress: 0.0,
+        }
     }
 }
 
 trait GetTextureId {
-    fn get_texture_id(&self) -> Option<TextureId>;
+    fn get_texture_id(&self) -> Option<TextureHandle>;
 }
 
 impl GetTextureId for Option<Texture> {
-    fn get_texture_id(&self) -> Option<TextureId> {
+    fn get_texture_id(&self) -> Option<TextureHandle> {
         match self {
-            Some(s) => Some(s.texture_id),
+            Some(s) => Some(s.handle),
             None => None,
         }
     }
@@ -66,6 +310,8 @@ pub struct SpotLight {
     pub radius: f32,
     pub direction: Vector3<f32>,
     pub cut_off: f32,
+    pub cookie: Option<TextureHandle>, // projected through the cone and tinting the light
+    pub cast_shadows: bool,            // lights without shadows never take a shadow atlas tile
 }
 
 #[derive(Clone, Copy, Debug, Component)]
@@ -75,7 +321,181 @@ pub struct GlobalLight {
     pub direction: Vector3<f32>,
 }
 
+// Resource instead of a component, there is only one ambient term per scene.
+// sky_color is used for normals facing +y, ground_color for normals facing -y and blended in between.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientLight {
+    pub sky_color: Vector3<f32>,
+    pub ground_color: Vector3<f32>,
+    pub intensity: f32,
+}
+
+impl AmbientLight {
+    pub fn uniform(color: Vector3<f32>, intensity: f32) -> Self {
+        Self {
+            sky_color: color,
+            ground_color: color,
+            intensity,
+        }
+    }
+}
+
+impl Default for AmbientLight {
+    // matches the constant ambient term the fragment shader used before it was configurable
+    fn default() -> Self {
+        Self::uniform([1.0, 1.0, 1.0].into(), 0.02)
+    }
+}
+
+// Spins around axis, its length is the speed in radians per second.
 #[derive(Clone, Copy, Debug, Component)]
 pub struct Rotate {
     pub axis: Vector3<f32>,
+    // With an anchor the rotation is recomputed from the ticks since the anchor tick instead of
+    // stepped every update, so it doesn't accumulate rounding and only depends on the tick count.
+    pub anchor: Option<(u64, UnitQuaternion<f32>)>,
+}
+
+impl Rotate {
+    pub fn new(axis: Vector3<f32>) -> Self {
+        Self { axis, anchor: None }
+    }
+
+    pub fn anchored(axis: Vector3<f32>, tick: u64, rotation: UnitQuaternion<f32>) -> Self {
+        Self {
+            axis,
+            anchor: Some((tick, rotation)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    // both views are 10 units deep starting 1 unit in front of a camera at z = 5, points are
+    // transformed as row * column
+    #[test]
+    fn perspective_view_projection_test() {
+        let camera = Camera::perspective(2.0, std::f32::consts::FRAC_PI_2, 1.0, 11.0);
+        let view_projection = camera.view_projection(&Isometry3::translation(0.0, 0.0, 5.0));
+
+        #[rustfmt::skip]
+        let expected = Matrix4::new(
+            0.5, 0.0, 0.0,  0.0,
+            0.0, 1.0, 0.0,  0.0,
+            0.0, 0.0, -1.2, 3.8,
+            0.0, 0.0, -1.0, 5.0,
+        );
+        assert!((view_projection - expected).norm() < EPSILON);
+    }
+
+    #[test]
+    fn orthographic_view_projection_test() {
+        let mut camera = Camera::orthographic(4.0, 2.0, 1.0, 11.0);
+        let view_projection = camera.view_projection(&Isometry3::translation(0.0, 0.0, 5.0));
+
+        #[rustfmt::skip]
+        let expected = Matrix4::new(
+            0.25, 0.0, 0.0,  0.0,
+            0.0,  0.5, 0.0,  0.0,
+            0.0,  0.0, -0.2, -0.2,
+            0.0,  0.0, 0.0,  1.0,
+        );
+        assert!((view_projection - expected).norm() < EPSILON);
+
+        // a square viewport keeps the height and narrows the width to match
+        camera.set_viewport_size(100, 100);
+        assert!((camera.projection.aspect() - 1.0).abs() < EPSILON);
+        assert!((camera.projection.matrix()[(0, 0)] - 0.5).abs() < EPSILON);
+        assert!((camera.projection.matrix()[(1, 1)] - 0.5).abs() < EPSILON);
+        assert_eq!(camera.projection.fovy(), None);
+    }
+
+    #[test]
+    fn set_viewport_size_test() {
+        let fovy = std::f32::consts::FRAC_PI_2;
+        let mut camera = Camera::perspective(800.0 / 600.0, fovy, 0.1, 100.0);
+
+        camera.set_viewport_size(1920, 1080);
+        assert!((camera.projection.aspect() - 16.0 / 9.0).abs() < EPSILON);
+        // the vertical field of view stays, the horizontal one widens
+        assert!((camera.projection.fovy().unwrap() - fovy).abs() < EPSILON);
+        assert!((camera.projection.matrix()[(1, 1)] - 1.0).abs() < EPSILON);
+        assert!((camera.projection.matrix()[(0, 0)] - 9.0 / 16.0).abs() < EPSILON);
+
+        // a minimized window keeps the last aspect
+        camera.set_viewport_size(0, 0);
+        camera.set_viewport_size(1920, 0);
+        camera.set_viewport_size(0, 1080);
+        assert!((camera.projection.aspect() - 16.0 / 9.0).abs() < EPSILON);
+
+        camera.set_viewport_size(600, 800);
+        assert!((camera.projection.aspect() - 0.75).abs() < EPSILON);
+    }
+
+    #[test]
+    fn render_layers_test() {
+        assert_eq!(RenderLayers::default(), RenderLayers(1));
+        assert_eq!(RenderLayers::layer(3), RenderLayers(0b1000));
+        assert_eq!(RenderLayers::layer(31), RenderLayers(1 << 31));
+        assert_eq!(RenderLayers::layer(0).with(2).with(2), RenderLayers(0b101));
+
+        let world = RenderLayers::default();
+        let menu = RenderLayers::layer(1);
+        assert!(world.intersects(&world));
+        assert!(!world.intersects(&menu));
+        assert!(world.with(1).intersects(&menu));
+        assert!(RenderLayers(u32::MAX).intersects(&menu));
+        assert!(!RenderLayers::NONE.intersects(&RenderLayers(u32::MAX)));
+
+        assert_eq!(RenderLayers::of(None), world);
+        assert_eq!(RenderLayers::of(Some(&menu)), menu);
+    }
+
+    #[test]
+    #[should_panic]
+    fn render_layer_out_of_range_test() {
+        RenderLayers::layer(RenderLayers::COUNT);
+    }
+
+    #[test]
+    fn visibility_filter_test() {
+        let mut world = World::new();
+        let unset = world.spawn().insert(Rotate::new(Vector3::y())).id();
+        let shown = world
+            .spawn()
+            .insert(Rotate::new(Vector3::y()))
+            .insert(Visibility::default())
+            .id();
+        let hidden = world
+            .spawn()
+            .insert(Rotate::new(Vector3::y()))
+            .insert(Visibility { visible: false })
+            .id();
+
+        // the same filter the render system applies to objects and lights
+        let mut query = world.query::<(Entity, &Rotate, Option<&Visibility>)>();
+        let mut visible: Vec<Entity> = query
+            .iter(&world)
+            .filter(|(.., visibility)| Visibility::is_visible(*visibility))
+            .map(|(entity, ..)| entity)
+            .collect();
+        visible.sort();
+
+        let mut expected = vec![unset, shown];
+        expected.sort();
+        assert_eq!(visible, expected);
+
+        // showing it again needs no other component changes
+        world.get_mut::<Visibility>(hidden).unwrap().visible = true;
+        assert_eq!(query.iter(&world).count(), 3);
+        assert!(query
+            .iter(&world)
+            .all(|(.., visibility)| Visibility::is_visible(visibility)));
+    }
 }
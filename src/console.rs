@@ -0,0 +1,799 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use bevy_ecs::{
+    entity::Entity,
+    query::With,
+    world::{Mut, World},
+};
+use log::{Log, Metadata, Record};
+use nalgebra::{Isometry3, Vector3};
+use simple_logger::SimpleLogger;
+
+use crate::{
+    bindings, board, camera_cut,
+    common_component::{Camera, MainCamera, RenderGeometry, Texture, Transform},
+    day_night, debug_draw, fog,
+    frame_stats::FrameStats,
+    geometry_library::GEOMETRY_DESC_PAIRS,
+    library_stats::{self, GpuMemoryStats},
+    post_process, profile,
+    render_system::{self, RenderSettings, RenderState, RenderStats},
+    state_hash, strings,
+    texture_library::TextureId,
+    time::TimeResource,
+    tonemap,
+};
+
+const SCROLLBACK_LINES: usize = 256;
+
+// Filled by ConsoleLogger, shared between every console as there is only one logger.
+static SCROLLBACK: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// Forwards everything to simple_logger and keeps a copy of recent lines for the console.
+struct ConsoleLogger {
+    inner: SimpleLogger,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+
+        let mut scrollback = SCROLLBACK.lock().unwrap();
+        if scrollback.len() == SCROLLBACK_LINES {
+            scrollback.pop_front();
+        }
+        scrollback.push_back(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub fn init_logger(level: log::Level) -> Result<(), log::SetLoggerError> {
+    let inner = SimpleLogger::new().with_level(level.to_level_filter());
+
+    log::set_boxed_logger(Box::new(ConsoleLogger { inner }))?;
+    log::set_max_level(level.to_level_filter());
+
+    Ok(())
+}
+
+// Most recent lines last.
+pub fn scrollback(lines: usize) -> Vec<String> {
+    let scrollback = SCROLLBACK.lock().unwrap();
+    let skip = scrollback.len().saturating_sub(lines);
+
+    scrollback.iter().skip(skip).cloned().collect()
+}
+
+#[derive(Default)]
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+
+    // submitted lines waiting for the next fixed update
+    pending: Vec<String>,
+}
+
+impl Console {
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    // Fed from WindowEvent::ReceivedCharacter while the console is open.
+    pub fn receive_character(&mut self, c: char, registry: &CommandRegistry) {
+        match c {
+            '\r' | '\n' => {
+                let line = std::mem::take(&mut self.input);
+                if !line.trim().is_empty() {
+                    self.pending.push(line);
+                }
+            }
+            '\u{8}' => {
+                self.input.pop();
+            }
+            '\t' => {
+                if let Some(completed) = registry.complete(self.input.trim_start()) {
+                    self.input = completed;
+                }
+            }
+            // the toggle key also produces a character
+            '`' => (),
+            c if !c.is_control() => self.input.push(c),
+            _ => (),
+        }
+    }
+}
+
+pub type CommandHandler = fn(&mut World, &[&str]) -> Result<(), String>;
+
+struct Command {
+    usage: &'static str,
+    handler: CommandHandler,
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+}
+
+impl CommandRegistry {
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry.register("spawn", "spawn <geometry> <x> <y> <z>", spawn_command);
+        registry.register("time", "time set <phase 0..1>", day_night::time_command);
+        registry.register("language", "language <code>", strings::language_command);
+        registry.register("timescale", "timescale <factor>", timescale_command);
+        registry.register("vsync", "vsync <on|off>", vsync_command);
+        registry.register("prepass", "prepass <on|off>", prepass_command);
+        registry.register("msaa", "msaa <1|4>", msaa_command);
+        registry.register("gpu", "gpu", gpu_command);
+        registry.register("stats", "stats", stats_command);
+        registry.register("bindings", "bindings", bindings::bindings_command);
+        registry.register(
+            "hash",
+            "hash | save <path> | diff <path>",
+            state_hash::hash_command,
+        );
+        registry.register("board", "board <rows> <columns>", board::board_command);
+        registry.register(
+            "camera",
+            "camera | pose <x> <y> <z> <yaw degrees> [seconds] | release [seconds]",
+            camera_cut::camera_command,
+        );
+        registry.register(
+            "profile",
+            "profile | name <name> | sleeve <texture|none>",
+            profile::profile_command,
+        );
+        registry.register(
+            "gizmos",
+            "gizmos <on|off> | lights <on|off>",
+            debug_draw::gizmos_command,
+        );
+        registry.register(
+            "projection",
+            "projection perspective <fovy degrees> | orthographic <height>",
+            projection_command,
+        );
+        registry.register(
+            "tonemap",
+            "tonemap <operator> | compare <operator|off> | exposure <value|default>",
+            tonemap::tonemap_command,
+        );
+        registry.register(
+            "bloom",
+            "bloom <on|off> | threshold <value> | intensity <value>",
+            post_process::bloom_command,
+        );
+        registry.register(
+            "fog",
+            "fog <on|off> | linear <start> <end> | exp <density> | color <r> <g> <b> | clear <on|off>",
+            fog::fog_command,
+        );
+        registry.register(
+            "texture",
+            "texture load <name> <path> | unload <name> | set <name> <entity id>",
+            texture_command,
+        );
+        registry.register(
+            "grading",
+            "grading load <name> <path> | use <name|identity> | strength <0..1>",
+            grading_command,
+        );
+        registry.register(
+            "debug",
+            "debug texture <checker|uvgrid|white> <entity id>",
+            debug_command,
+        );
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, usage: &'static str, handler: CommandHandler) {
+        self.commands
+            .insert(name.to_string(), Command { usage, handler });
+    }
+
+    // Longest common prefix of all command names starting with prefix. Only completes the command
+    // name, None once arguments have been typed or nothing matches.
+    pub fn complete(&self, prefix: &str) -> Option<String> {
+        if prefix.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let mut matches = self
+            .commands
+            .keys()
+            .map(String::as_str)
+            .chain(std::iter::once("help"))
+            .filter(|name| name.starts_with(prefix));
+
+        let first = matches.next()?.to_string();
+        let common = matches.fold(first, |common, name| {
+            let len = common
+                .chars()
+                .zip(name.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            common.chars().take(len).collect()
+        });
+
+        Some(common)
+    }
+
+    // help is handled here directly as it needs the registry which handlers don't get access to.
+    pub fn execute(&self, world: &mut World, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let args: Vec<&str> = words.collect();
+
+        if name == "help" {
+            log::info!("commands: help");
+            for command in self.commands.values() {
+                log::info!("{}", command.usage);
+            }
+            return Ok(());
+        }
+
+        let command = self
+            .commands
+            .get(name)
+            .ok_or_else(|| format!("unknown command {}, type help for a list of commands", name))?;
+
+        (command.handler)(world, &args).map_err(|e| format!("{}\nusage: {}", e, command.usage))
+    }
+}
+
+// Exclusive system, runs commands submitted since the last fixed update.
+pub fn run_console_commands(world: &mut World) {
+    let pending = std::mem::take(&mut world.resource_mut::<Console>().pending);
+    if pending.is_empty() {
+        return;
+    }
+
+    world.resource_scope(|world, registry: Mut<CommandRegistry>| {
+        for line in pending {
+            log::info!("> {}", line);
+            if let Err(e) = registry.execute(world, &line) {
+                log::warn!("{}", e);
+            }
+        }
+    });
+}
+
+fn spawn_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let (name, position) = match args {
+        [name, x, y, z] => (name, [x, y, z]),
+        _ => return Err("expected 4 arguments".to_string()),
+    };
+
+    // geometry names are matched case insensitively with or without the Geometry suffix
+    let geom_type = GEOMETRY_DESC_PAIRS
+        .iter()
+        .map(|(id, _)| *id)
+        .find(|id| {
+            let id_name = format!("{:?}", id).to_lowercase();
+            let name = name.to_lowercase();
+            id_name == name || id_name.strip_suffix("geometry") == Some(name.as_str())
+        })
+        .ok_or_else(|| format!("unknown geometry {}", name))?;
+
+    let mut translation = [0.0; 3];
+    for (value, arg) in translation.iter_mut().zip(position) {
+        *value = arg
+            .parse::<f32>()
+            .map_err(|e| format!("bad coordinate {}: {}", arg, e))?;
+    }
+
+    world
+        .spawn()
+        .insert(Transform {
+            isometry: Isometry3::translation(translation[0], translation[1], translation[2]),
+            scale: Vector3::repeat(1.0),
+            parent: None,
+            children: vec![],
+        })
+        .insert(RenderGeometry::new(geom_type));
+
+    Ok(())
+}
+
+// Swaps an entity's texture for one of the procedural debug textures, entity ids are the numbers
+// shown in entity debug output.
+fn debug_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let (texture, id) = match args {
+        ["texture", texture, id] => (texture, id),
+        _ => return Err("expected texture <name> <entity id>".to_string()),
+    };
+
+    let texture_id = match *texture {
+        "checker" => TextureId::CheckerTexture,
+        "uvgrid" => TextureId::UvGridTexture,
+        "white" => TextureId::WhiteTexture,
+        _ => return Err(format!("unknown debug texture {}", texture)),
+    };
+
+    let id = id
+        .parse::<u32>()
+        .map_err(|e| format!("bad entity id {}: {}", id, e))?;
+
+    let mut entity = world
+        .get_entity_mut(Entity::from_raw(id))
+        .ok_or_else(|| format!("no entity with id {}", id))?;
+    entity.insert(Texture::new(texture_id));
+
+    Ok(())
+}
+
+// Names are the ones textures were registered under, builtin textures go by their TextureId name.
+fn texture_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let mut state = world.resource_mut::<RenderState>();
+
+    match args {
+        ["load", name, path] => state.register_texture(name, Path::new(path)).map(|_| ()),
+        ["unload", name] => {
+            let handle = state
+                .texture_handle(name)
+                .ok_or_else(|| format!("no texture named {}", name))?;
+            state.unregister_texture(handle)
+        }
+        ["set", name, id] => {
+            let handle = state
+                .texture_handle(name)
+                .ok_or_else(|| format!("no texture named {}", name))?;
+            let id = id
+                .parse::<u32>()
+                .map_err(|e| format!("bad entity id {}: {}", id, e))?;
+
+            let mut entity = world
+                .get_entity_mut(Entity::from_raw(id))
+                .ok_or_else(|| format!("no entity with id {}", id))?;
+            entity.insert(Texture::new(handle));
+
+            Ok(())
+        }
+        _ => Err("expected load, unload or set".to_string()),
+    }
+}
+
+fn grading_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    match args {
+        ["load", name, path] => {
+            let mut state = world.resource_mut::<RenderState>();
+            state.register_lut(name, Path::new(path)).map(|_| ())
+        }
+        ["use", "identity"] => {
+            world.resource_mut::<RenderSettings>().color_grading.lut = None;
+            Ok(())
+        }
+        ["use", name] => {
+            let state = world.resource::<RenderState>();
+            let lut = state
+                .texture_handle(name)
+                .filter(|handle| state.texture_library().lut(*handle).is_some())
+                .ok_or_else(|| format!("no color grading LUT named {}", name))?;
+
+            world.resource_mut::<RenderSettings>().color_grading.lut = Some(lut);
+            Ok(())
+        }
+        ["strength", value] => {
+            let strength = value
+                .parse::<f32>()
+                .ok()
+                .filter(|strength| (0.0..=1.0).contains(strength))
+                .ok_or_else(|| format!("bad strength {}, expected 0 to 1", value))?;
+
+            world
+                .resource_mut::<RenderSettings>()
+                .color_grading
+                .strength = strength;
+            Ok(())
+        }
+        _ => Err("expected load, use or strength".to_string()),
+    }
+}
+
+// Adapter and surface setup, and the memory held by the texture and geometry libraries.
+fn gpu_command(world: &mut World, _args: &[&str]) -> Result<(), String> {
+    let state = world.resource::<RenderState>();
+    let info = state.adapter_info();
+
+    log::info!(
+        "adapter {} ({:?}) on {:?}, vendor {:#x} device {:#x}, present mode {:?}, {} msaa \
+         samples",
+        info.name,
+        info.device_type,
+        info.backend,
+        info.vendor,
+        info.device,
+        state.present_mode(),
+        state.sample_count()
+    );
+
+    let memory = world.resource::<GpuMemoryStats>();
+    log::info!(
+        "{} in {} textures, {} in {} meshes, {} total",
+        library_stats::format_bytes(memory.textures.total_bytes),
+        memory.textures.count(),
+        library_stats::format_bytes(memory.geometry.total_bytes),
+        memory.geometry.count(),
+        library_stats::format_bytes(memory.total_bytes())
+    );
+
+    Ok(())
+}
+
+fn timescale_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let scale = match args {
+        [scale] => scale
+            .parse::<f32>()
+            .map_err(|_| format!("{} is not a number", scale))?,
+        _ => return Err("expected a factor".to_string()),
+    };
+    if !(scale >= 0.0 && scale.is_finite()) {
+        return Err("factor must be 0 or more".to_string());
+    }
+
+    world.resource_mut::<TimeResource>().time_scale = scale;
+
+    Ok(())
+}
+
+// Toggles the depth pre pass to compare frame times with and without it.
+fn prepass_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let enabled = match args {
+        ["on"] => true,
+        ["off"] => false,
+        _ => return Err("expected on or off".to_string()),
+    };
+
+    world
+        .resource_mut::<RenderState>()
+        .set_depth_pre_pass(enabled);
+
+    Ok(())
+}
+
+// Unsupported counts fall back to 1 with a warning, see render_system::choose_sample_count.
+fn msaa_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let sample_count = match args {
+        [count] => count
+            .parse::<u32>()
+            .map_err(|e| format!("bad sample count {}: {}", count, e))?,
+        _ => return Err("expected a sample count".to_string()),
+    };
+
+    world.resource_mut::<RenderSettings>().sample_count = sample_count;
+
+    Ok(())
+}
+
+// Replaces the main camera's projection, keeping its aspect, clip planes and layers.
+fn projection_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let (kind, value) = match args {
+        [kind, value] => (
+            *kind,
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("{} is not a number", value))?,
+        ),
+        _ => return Err("expected a projection and a size".to_string()),
+    };
+    if !(value > 0.0 && value.is_finite()) {
+        return Err("size must be more than 0".to_string());
+    }
+
+    let mut cameras = world.query_filtered::<&mut Camera, With<MainCamera>>();
+    let mut camera = cameras
+        .iter_mut(world)
+        .next()
+        .ok_or_else(|| "there is no main camera".to_string())?;
+
+    let projection = &camera.projection;
+    let (aspect, near, far) = (projection.aspect(), projection.znear(), projection.zfar());
+    let replacement = match kind {
+        "perspective" => Camera::perspective(aspect, value.to_radians(), near, far),
+        "orthographic" => Camera::orthographic(value, aspect, near, far),
+        _ => return Err("expected perspective or orthographic".to_string()),
+    };
+    camera.projection = replacement.projection;
+
+    Ok(())
+}
+
+// Counters from the last rendered frame and the recent frame times.
+fn stats_command(world: &mut World, _args: &[&str]) -> Result<(), String> {
+    let stats = world.resource::<RenderStats>();
+
+    log::info!(
+        "{} objects drawn, {} culled, {} hidden. point lights {} of {}, spot lights {} of {}, {} \
+         lights off screen, {} lights hidden, {:.2} lights per tile",
+        stats.drawn_objects,
+        stats.culled_objects,
+        stats.hidden_objects,
+        stats.point_lights.submitted,
+        stats.point_lights.total,
+        stats.spot_lights.submitted,
+        stats.spot_lights.total,
+        stats.point_lights.outside_frustum + stats.spot_lights.outside_frustum,
+        stats.hidden_lights,
+        stats.lights_per_tile
+    );
+    log::info!(
+        "{} draw calls, {} instances, {} triangles, {} bind group switches, {} bytes written",
+        stats.gpu.draw_calls,
+        stats.gpu.instances,
+        stats.gpu.triangles,
+        stats.gpu.bind_group_switches,
+        stats.gpu.bytes_written
+    );
+
+    let frames = world.resource::<FrameStats>();
+    let ms = |d: Option<Duration>| d.map_or(0.0, |d| d.as_secs_f32() * 1000.0);
+    log::info!(
+        "last frame {:.2} ms, {:.2} ms average, {:.2} ms min, {:.2} ms max over {} frames",
+        ms(frames.frame_times.latest()),
+        ms(frames.frame_times.average()),
+        ms(frames.frame_times.min()),
+        ms(frames.frame_times.max()),
+        frames.frame_times.len()
+    );
+
+    Ok(())
+}
+
+// Off picks the lowest latency mode the surface supports, which may still be vsynced.
+fn vsync_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let mut state = world.resource_mut::<RenderState>();
+
+    let mode = match args {
+        ["on"] => wgpu::PresentMode::Fifo,
+        ["off"] => render_system::choose_present_mode(state.supported_present_modes()),
+        _ => return Err("expected on or off".to_string()),
+    };
+
+    if state.present_mode() == mode {
+        return Ok(());
+    }
+    state.set_present_mode(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geometry_library::GeometryId, texture_library::TextureHandle};
+    use std::time::Duration;
+
+    // Arguments the echo command was last called with.
+    struct Echoed(Vec<String>);
+
+    fn echo_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+        if args == ["fail"] {
+            return Err("asked to fail".to_string());
+        }
+        world.insert_resource(Echoed(args.iter().map(|a| a.to_string()).collect()));
+        Ok(())
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::with_builtins();
+        registry.register("echo", "echo <words>", echo_command);
+        registry
+    }
+
+    #[test]
+    fn execute_parses_arguments_test() {
+        let registry = registry();
+        let mut world = World::new();
+
+        registry.execute(&mut world, "  echo a   b\tc ").unwrap();
+        assert_eq!(world.resource::<Echoed>().0, ["a", "b", "c"]);
+
+        registry.execute(&mut world, "echo").unwrap();
+        assert!(world.resource::<Echoed>().0.is_empty());
+
+        // blank lines do nothing
+        registry.execute(&mut world, "   ").unwrap();
+        registry.execute(&mut world, "help").unwrap();
+    }
+
+    #[test]
+    fn execute_errors_test() {
+        let registry = registry();
+        let mut world = World::new();
+
+        let unknown = registry.execute(&mut world, "ehco a").unwrap_err();
+        assert!(unknown.contains("unknown command ehco"), "{}", unknown);
+
+        // handler errors come with the usage
+        let failed = registry.execute(&mut world, "echo fail").unwrap_err();
+        assert_eq!(failed, "asked to fail\nusage: echo <words>");
+    }
+
+    #[test]
+    fn complete_test() {
+        let registry = registry();
+        // time and timescale share their whole name up to the shorter one
+        assert_eq!(registry.complete("ti").as_deref(), Some("time"));
+        assert_eq!(registry.complete("times").as_deref(), Some("timescale"));
+        assert_eq!(registry.complete("he").as_deref(), Some("help"));
+        assert_eq!(registry.complete("ec").as_deref(), Some("echo"));
+        assert_eq!(registry.complete("zzz"), None);
+        // only the command name is completed
+        assert_eq!(registry.complete("echo a"), None);
+    }
+
+    #[test]
+    fn typing_and_submitting_test() {
+        let registry = registry();
+        let mut console = Console::default();
+
+        for c in "`ec\tx\u{8} hello\n\n".chars() {
+            console.receive_character(c, &registry);
+        }
+        assert_eq!(console.pending, ["echo hello"]);
+        assert!(console.input.is_empty());
+
+        let mut world = World::new();
+        world.insert_resource(console);
+        world.insert_resource(registry);
+        run_console_commands(&mut world);
+
+        assert_eq!(world.resource::<Echoed>().0, ["hello"]);
+        assert!(world.resource::<Console>().pending.is_empty());
+    }
+
+    #[test]
+    fn spawn_command_test() {
+        let registry = registry();
+        let mut world = World::new();
+
+        // with or without the Geometry suffix, any case
+        registry.execute(&mut world, "spawn torus 1 2 -3").unwrap();
+        registry
+            .execute(&mut world, "spawn TorusGeometry 0 0 0")
+            .unwrap();
+
+        let mut spawned = world.query::<(&Transform, &RenderGeometry)>();
+        let positions: Vec<_> = spawned
+            .iter(&world)
+            .map(|(transform, geometry)| {
+                assert_eq!(geometry.geom_type, GeometryId::TorusGeometry);
+                transform.isometry.translation.vector
+            })
+            .collect();
+        assert!(positions.contains(&Vector3::new(1.0, 2.0, -3.0)));
+        assert_eq!(positions.len(), 2);
+
+        for line in ["spawn cube 0 0 0", "spawn torus 0 x 0", "spawn torus 0 0"] {
+            assert!(registry.execute(&mut world, line).is_err(), "{}", line);
+        }
+        assert_eq!(spawned.iter(&world).count(), 2);
+    }
+
+    #[test]
+    fn timescale_command_test() {
+        let registry = registry();
+        let mut world = World::new();
+        world.insert_resource(TimeResource::new(
+            Duration::from_secs_f64(1.0 / 60.0),
+            Duration::from_secs_f64(1.0 / 60.0),
+        ));
+
+        registry.execute(&mut world, "timescale 0.5").unwrap();
+        assert_eq!(world.resource::<TimeResource>().time_scale, 0.5);
+
+        for line in [
+            "timescale -1",
+            "timescale inf",
+            "timescale fast",
+            "timescale",
+        ] {
+            assert!(registry.execute(&mut world, line).is_err(), "{}", line);
+        }
+        assert_eq!(world.resource::<TimeResource>().time_scale, 0.5);
+    }
+
+    #[test]
+    fn projection_command_test() {
+        let registry = registry();
+        let mut world = World::new();
+        assert!(registry
+            .execute(&mut world, "projection orthographic 10")
+            .unwrap_err()
+            .contains("no main camera"));
+
+        let camera = world
+            .spawn()
+            .insert(Camera::perspective(2.0, 1.0, 0.1, 100.0))
+            .insert(MainCamera)
+            .id();
+        registry
+            .execute(&mut world, "projection orthographic 10")
+            .unwrap();
+
+        let projection = &world.get::<Camera>(camera).unwrap().projection;
+        assert_eq!(projection.fovy(), None);
+        assert!((projection.aspect() - 2.0).abs() < 1e-5);
+        assert!((projection.znear() - 0.1).abs() < 1e-5);
+
+        registry
+            .execute(&mut world, "projection perspective 90")
+            .unwrap();
+        let projection = &world.get::<Camera>(camera).unwrap().projection;
+        assert!((projection.fovy().unwrap() - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+
+        assert!(registry
+            .execute(&mut world, "projection fisheye 1")
+            .is_err());
+        assert!(registry
+            .execute(&mut world, "projection perspective 0")
+            .is_err());
+    }
+
+    #[test]
+    fn grading_command_test() {
+        let registry = registry();
+        let mut world = World::new();
+        world.insert_resource(RenderSettings::default());
+
+        registry
+            .execute(&mut world, "grading strength 0.25")
+            .unwrap();
+        registry
+            .execute(&mut world, "grading use identity")
+            .unwrap();
+        let grading = world.resource::<RenderSettings>().color_grading;
+        assert_eq!((grading.lut, grading.strength), (None, 0.25));
+
+        for line in [
+            "grading strength 2",
+            "grading strength -0.5",
+            "grading warm",
+        ] {
+            assert!(registry.execute(&mut world, line).is_err(), "{}", line);
+        }
+        assert_eq!(world.resource::<RenderSettings>().color_grading, grading);
+    }
+
+    #[test]
+    fn debug_texture_command_test() {
+        let registry = registry();
+        let mut world = World::new();
+        let entity = world.spawn().id();
+
+        registry
+            .execute(&mut world, &format!("debug texture uvgrid {}", entity.id()))
+            .unwrap();
+        let texture = world.get::<Texture>(entity).unwrap();
+        assert_eq!(
+            texture.handle,
+            TextureHandle::from(TextureId::UvGridTexture)
+        );
+
+        assert!(registry
+            .execute(&mut world, "debug texture shiny 0")
+            .is_err());
+        assert!(registry
+            .execute(&mut world, "debug texture checker 999")
+            .unwrap_err()
+            .contains("no entity"));
+    }
+}
@@ -0,0 +1,242 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    // None for an empty set of points.
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Point3<f32>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = *points.next()?;
+
+        Some(points.fold(Self::new(first, first), |aabb, p| Self {
+            min: aabb.min.inf(p),
+            max: aabb.max.sup(p),
+        }))
+    }
+
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // Smallest box around this one after an affine transform, see Arvo's "Transforming Axis-Aligned
+    // Bounding Boxes".
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let center = transform.transform_point(&self.center());
+        let linear = transform.fixed_slice::<3, 3>(0, 0).abs();
+        let extents = linear * self.half_extents();
+
+        Self::new(center - extents, center + extents)
+    }
+}
+
+// Points p with normal.dot(p) + d >= 0 are on the inside.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_row(row: Vector4<f32>) -> Self {
+        let length = row.xyz().norm();
+
+        Self {
+            normal: row.xyz() / length,
+            d: row.w / length,
+        }
+    }
+
+    pub fn distance(&self, point: &Point3<f32>) -> f32 {
+        self.normal.dot(&point.coords) + self.d
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6], // left, right, bottom, top, near, far
+}
+
+impl Frustum {
+    // Gribb and Hartmann's plane extraction for wgpu's clip space, where depth runs from 0 to 1 so
+    // the near plane is the z row on its own.
+    pub fn from_view_projection(view_projection: &Matrix4<f32>) -> Self {
+        let row = |i| view_projection.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [
+                Plane::from_row(w + x),
+                Plane::from_row(w - x),
+                Plane::from_row(w + y),
+                Plane::from_row(w - y),
+                Plane::from_row(z),
+                Plane::from_row(w - z),
+            ],
+        }
+    }
+
+    // Conservative, boxes near the frustum corners can pass while being just outside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let extents = aabb.half_extents();
+
+        self.planes.iter().all(|plane| {
+            // distance from the center to the box corner furthest along the plane normal
+            let radius = extents.dot(&plane.normal.abs());
+            plane.distance(&center) >= -radius
+        })
+    }
+
+    // Conservative like intersects_aabb, spheres just outside a frustum corner can pass.
+    pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.distance(center) >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Isometry3, Perspective3};
+
+    // identity clip space, the frustum is the box from (-1, -1, 0) to (1, 1, 1)
+    fn unit_frustum() -> Frustum {
+        Frustum::from_view_projection(&Matrix4::identity())
+    }
+
+    #[test]
+    fn sphere_inside_test() {
+        let frustum = unit_frustum();
+        assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, 0.5), 0.1));
+        // larger than the frustum itself
+        assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, 0.5), 10.0));
+    }
+
+    #[test]
+    fn sphere_overlapping_plane_test() {
+        let frustum = unit_frustum();
+        assert!(frustum.intersects_sphere(&Point3::new(-1.4, 0.0, 0.5), 0.5));
+        assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, 1.2), 0.5));
+    }
+
+    #[test]
+    fn sphere_outside_test() {
+        let frustum = unit_frustum();
+        assert!(!frustum.intersects_sphere(&Point3::new(-2.0, 0.0, 0.5), 0.5));
+        assert!(!frustum.intersects_sphere(&Point3::new(0.0, 3.0, 0.5), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, -1.0), 0.5));
+    }
+
+    #[test]
+    fn sphere_behind_camera_test() {
+        let view = Isometry3::look_at_rh(
+            &Point3::origin(),
+            &Point3::new(0.0, 0.0, -1.0),
+            &Vector3::y(),
+        );
+        let projection = Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let frustum =
+            Frustum::from_view_projection(&(projection.as_matrix() * view.to_homogeneous()));
+
+        assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, -10.0), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, 10.0), 1.0));
+        // beside the camera, reaching into the view only through its radius
+        assert!(!frustum.intersects_sphere(&Point3::new(5.0, 0.0, -1.0), 1.0));
+        assert!(frustum.intersects_sphere(&Point3::new(5.0, 0.0, -1.0), 5.0));
+    }
+
+    #[test]
+    fn aabb_test() {
+        let frustum = unit_frustum();
+        let inside = Aabb::new(Point3::new(-0.1, -0.1, 0.4), Point3::new(0.1, 0.1, 0.6));
+        let outside = Aabb::new(Point3::new(2.0, 2.0, 0.4), Point3::new(3.0, 3.0, 0.6));
+        assert!(frustum.intersects_aabb(&inside));
+        assert!(!frustum.intersects_aabb(&outside));
+    }
+
+    #[test]
+    fn aabb_straddling_test() {
+        let frustum = unit_frustum();
+        // crosses the left plane, then the far plane, then covers the whole frustum
+        let left = Aabb::new(Point3::new(-1.5, -0.1, 0.4), Point3::new(-0.5, 0.1, 0.6));
+        let far = Aabb::new(Point3::new(-0.1, -0.1, 0.9), Point3::new(0.1, 0.1, 2.0));
+        let around = Aabb::new(Point3::new(-5.0, -5.0, -5.0), Point3::new(5.0, 5.0, 5.0));
+        assert!(frustum.intersects_aabb(&left));
+        assert!(frustum.intersects_aabb(&far));
+        assert!(frustum.intersects_aabb(&around));
+    }
+
+    #[test]
+    fn aabb_behind_camera_test() {
+        let view = Isometry3::look_at_rh(
+            &Point3::origin(),
+            &Point3::new(0.0, 0.0, -1.0),
+            &Vector3::y(),
+        );
+        let projection = Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let frustum =
+            Frustum::from_view_projection(&(projection.as_matrix() * view.to_homogeneous()));
+
+        let cube = |z: f32| {
+            Aabb::new(
+                Point3::new(-1.0, -1.0, z - 1.0),
+                Point3::new(1.0, 1.0, z + 1.0),
+            )
+        };
+        assert!(frustum.intersects_aabb(&cube(-10.0)));
+        assert!(!frustum.intersects_aabb(&cube(10.0)));
+        assert!(!frustum.intersects_aabb(&cube(-200.0)));
+        // the camera sits inside this one
+        assert!(frustum.intersects_aabb(&cube(0.0)));
+    }
+
+    #[test]
+    fn aabb_from_points_test() {
+        assert_eq!(Aabb::from_points(&[]), None);
+
+        let points = [
+            Point3::new(1.0, -2.0, 0.0),
+            Point3::new(-1.0, 3.0, 0.5),
+            Point3::new(0.0, 0.0, -4.0),
+        ];
+        assert_eq!(
+            Aabb::from_points(&points),
+            Some(Aabb::new(
+                Point3::new(-1.0, -2.0, -4.0),
+                Point3::new(1.0, 3.0, 0.5)
+            ))
+        );
+    }
+
+    #[test]
+    fn aabb_transformed_test() {
+        let aabb = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+
+        let moved = aabb.transformed(&Matrix4::new_translation(&Vector3::new(2.0, 0.0, 0.0)));
+        assert_eq!(moved.min, Point3::new(1.0, -1.0, -1.0));
+        assert_eq!(moved.max, Point3::new(3.0, 1.0, 1.0));
+
+        // a quarter turn around z keeps the cube, an eighth grows it to sqrt(2) in x and y
+        let turned = aabb.transformed(&Matrix4::from_axis_angle(
+            &Vector3::z_axis(),
+            std::f32::consts::FRAC_PI_4,
+        ));
+        let diagonal = std::f32::consts::SQRT_2;
+        assert!((turned.max.x - diagonal).abs() < 1e-5);
+        assert!((turned.max.y - diagonal).abs() < 1e-5);
+        assert!((turned.max.z - 1.0).abs() < 1e-5);
+    }
+}
@@ -1,13 +1,15 @@
 #![allow(dead_code)]
 
 use bytemuck::{Pod, Zeroable};
-use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3, Vector4};
 use std::{mem::size_of, num::NonZeroU64};
 
 use crate::common_component::{
-    GlobalLight as GlobalLightComponent, PointLight as PointLightComponent,
+    AmbientLight as AmbientLightResource, GlobalLight as GlobalLightComponent,
+    Material as MaterialComponent, PointLight as PointLightComponent,
     SpotLight as SpotLightComponent, Transform,
 };
+use crate::texture_library::TextureHandle;
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -15,11 +17,16 @@ pub struct Vertex {
     pub position: Vector4<f32>,
     pub normal: Vector4<f32>,
     pub texture: Vector2<f32>,
+    pub color: Vector4<f32>, // multiplied into the lit color, white when the source has none
+    pub tangent: Vector4<f32>, // w is the bitangent's handedness, all zero when there are no uvs
 }
 
 impl Vertex {
-    const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
-        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x2];
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x4, 1 => Float32x4, 2 => Float32x2, 3 => Float32x4, 4 => Float32x4
+    ];
+
+    pub const WHITE: Vector4<f32> = Vector4::new(1.0, 1.0, 1.0, 1.0);
 
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
@@ -34,6 +41,8 @@ impl Vertex {
             position: [pos.x, pos.y, pos.z, 1.0].into(),
             normal: Vector4::zeros(),
             texture: Vector2::zeros(),
+            color: Self::WHITE,
+            tangent: Vector4::zeros(),
         }
     }
 
@@ -42,6 +51,36 @@ impl Vertex {
             position: [pos.x, pos.y, pos.z, 1.0].into(),
             normal: Vector4::zeros(),
             texture: *tex,
+            color: Self::WHITE,
+            tangent: Vector4::zeros(),
+        }
+    }
+}
+
+// Vertex of the debug line list, see DebugDraw.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct LineVertex {
+    pub position: Vector3<f32>,
+    pub color: Vector4<f32>,
+}
+
+impl LineVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    pub fn new(position: Point3<f32>, color: Vector4<f32>) -> Self {
+        Self {
+            position: position.coords,
+            color,
+        }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
         }
     }
 }
@@ -50,7 +89,14 @@ impl Vertex {
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Camera {
     pub view_projection: Matrix4<f32>,
-    pub position: Vector4<f32>,
+    pub position: Vector3<f32>,
+    pub fog_mode: u32,         // FogFalloff::shader_id, 0 while fog is off
+    pub forward: Vector3<f32>, // view direction, fog uses the depth along it
+    pub fog_density: f32,
+    pub fog_color: Vector3<f32>,
+    pub fog_start: f32,
+    pub fog_end: f32,
+    pub _padding: [u32; 3],
 }
 
 impl Camera {
@@ -58,6 +104,45 @@ impl Camera {
         NonZeroU64::new(std::mem::size_of::<Self>() as u64);
 }
 
+// Read by the tonemap pass.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Tonemap {
+    pub op: u32, // TonemapOperator::shader_id
+    pub compare_op: u32,
+    pub exposure: f32,
+    pub compare_exposure: f32,
+    pub split_x: f32, // pixels right of this use the compare operator, 0 disables the split
+    pub manual_gamma: u32, // non zero when the surface format does not do srgb conversion itself
+    pub bloom_intensity: f32, // 0 while bloom is off
+    pub grading_strength: f32, // ColorGrading::strength, 0 in debug views
+}
+
+impl Tonemap {
+    pub const BINDING_SIZE: Option<NonZeroU64> =
+        NonZeroU64::new(std::mem::size_of::<Self>() as u64);
+}
+
+// Read by the bloom passes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub _padding: [u32; 3],
+}
+
+impl Bloom {
+    pub const BINDING_SIZE: Option<NonZeroU64> =
+        NonZeroU64::new(std::mem::size_of::<Self>() as u64);
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct ObjectConstants {
+    pub model: Matrix4<f32>,
+    pub params: [f32; 8], // MaterialParams, read by the fragment shader as vec4 params[2]
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct GlobalLight {
@@ -65,6 +150,13 @@ pub struct GlobalLight {
     pub direction: Vector4<f32>, // w is always 0 as this is a direction not point
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct AmbientLight {
+    pub sky_color: Vector4<f32>,    // w is intensity
+    pub ground_color: Vector4<f32>, // w is unused
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct PointLight {
@@ -78,6 +170,64 @@ pub struct SpotLight {
     pub position: Vector4<f32>,  // w is radius
     pub color: Vector4<f32>,     // w is power
     pub direction: Vector4<f32>, // w is cut off
+    pub shadow_view_projection: Matrix4<f32>,
+    pub shadow_rect: Vector4<f32>, // of its tile in the spot shadow atlas
+    pub shadow_strength: f32,      // 0 without a tile
+    pub cookie_layer: u32,         // in the spot cookie array, 0 is white
+    pub _padding: [u32; 2],
+}
+
+// A spot light as the render system extracts it, the gpu data along with what is only needed
+// while filling in its shadow and cookie.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ExtractedSpotLight {
+    pub data: SpotLight,
+    pub cookie: Option<TextureHandle>,
+    pub cast_shadows: bool,
+}
+
+// Fills a fixed size gpu array from items, returning it with the number of slots used. Unused slots
+// are default and items past N are dropped.
+pub fn pack_fixed<T: Pod + Default, const N: usize>(
+    items: impl Iterator<Item = T>,
+) -> ([T; N], usize) {
+    let mut packed = [T::default(); N];
+    let mut count = 0;
+
+    for (slot, item) in packed.iter_mut().zip(items) {
+        *slot = item;
+        count += 1;
+    }
+
+    (packed, count)
+}
+
+// How many slots of each light array are in use, the shader skips the rest.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
+pub struct LightCounts {
+    pub global: u32,
+    pub point: u32,
+    pub spot: u32,
+    pub _padding: u32,
+}
+
+// Start of the light tile buffer, the per tile light lists of LightTiles follow it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct LightTileHeader {
+    pub screen_size: Vector2<f32>, // in pixels, for finding a fragment's tile
+    pub columns: u32,
+    pub rows: u32,
+}
+
+// Maps world positions into the shadow map of the first global light.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Shadow {
+    pub view_projection: Matrix4<f32>,
+    pub enabled: u32, // zero without a global light, everything is lit then
+    pub _padding: [u32; 3],
 }
 
 impl Default for GlobalLight {
@@ -104,6 +254,11 @@ impl Default for SpotLight {
             position: [0.0, 0.0, 0.0, 0.0].into(),
             color: [0.0, 0.0, 0.0, 0.0].into(),
             direction: [1.0, 0.0, 0.0, 1.0].into(),
+            shadow_view_projection: Matrix4::identity(),
+            shadow_rect: Vector4::zeros(),
+            shadow_strength: 0.0,
+            cookie_layer: 0,
+            _padding: [0; 2],
         }
     }
 }
@@ -117,6 +272,17 @@ impl From<&GlobalLightComponent> for GlobalLight {
     }
 }
 
+impl From<&AmbientLightResource> for AmbientLight {
+    fn from(al: &AmbientLightResource) -> Self {
+        let (s, g) = (&al.sky_color, &al.ground_color);
+
+        Self {
+            sky_color: [s.x, s.y, s.z, al.intensity].into(),
+            ground_color: [g.x, g.y, g.z, 0.0].into(),
+        }
+    }
+}
+
 impl From<(&PointLightComponent, &Transform)> for PointLight {
     fn from((pl, t): (&PointLightComponent, &Transform)) -> Self {
         let t = &t.isometry.translation;
@@ -136,37 +302,112 @@ impl From<(&SpotLightComponent, &Transform)> for SpotLight {
             position: [t.x, t.y, t.z, sl.radius].into(),
             color: [sl.color.x, sl.color.y, sl.color.z, sl.power].into(),
             direction: [sl.direction.x, sl.direction.y, sl.direction.z, sl.cut_off].into(),
+            // filled in once the shadow atlas and the cookie array have assigned their slots
+            shadow_view_projection: Matrix4::identity(),
+            shadow_rect: Vector4::zeros(),
+            shadow_strength: 0.0,
+            cookie_layer: 0,
+            _padding: [0; 2],
+        }
+    }
+}
+
+impl From<(&SpotLightComponent, &Transform)> for ExtractedSpotLight {
+    fn from((sl, t): (&SpotLightComponent, &Transform)) -> Self {
+        Self {
+            data: (sl, t).into(),
+            cookie: sl.cookie,
+            cast_shadows: sl.cast_shadows,
         }
     }
 }
 
+// Material component as the vertex shader reads it, two instance attributes.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct Material {
+    pub emissive: Vector3<f32>,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub _padding: [f32; 3],
+}
+
+impl From<&MaterialComponent> for Material {
+    fn from(m: &MaterialComponent) -> Self {
+        Self {
+            emissive: m.emissive,
+            roughness: m.roughness,
+            metallic: m.metallic,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct Instance {
     pub model: Matrix4<f32>,
+    pub params: [f32; 8],    // MaterialParams, forwarded to the fragment shader
+    pub scale: Vector4<f32>, // lets the vertex shader correct normals under non uniform scale, w is the texture layer
+    pub tint: Vector4<f32>,  // Tint, multiplied into the vertex color
+    pub material: Material,
 }
 
+// Locations follow on from the Vertex attributes.
 impl Instance {
-    const ATTRIBUTES: [wgpu::VertexAttribute; 4] = [
+    const ATTRIBUTES: [wgpu::VertexAttribute; 10] = [
         wgpu::VertexAttribute {
             offset: 0,
-            shader_location: 3,
+            shader_location: 5,
             format: wgpu::VertexFormat::Float32x4,
         },
         wgpu::VertexAttribute {
             offset: size_of::<Vector4<f32>>() as u64,
-            shader_location: 4,
+            shader_location: 6,
             format: wgpu::VertexFormat::Float32x4,
         },
         wgpu::VertexAttribute {
             offset: size_of::<Vector4<f32>>() as u64 * 2,
-            shader_location: 5,
+            shader_location: 7,
             format: wgpu::VertexFormat::Float32x4,
         },
         wgpu::VertexAttribute {
             offset: size_of::<Vector4<f32>>() as u64 * 3,
-            shader_location: 6,
+            shader_location: 8,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 4,
+            shader_location: 9,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 5,
+            shader_location: 10,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 6,
+            shader_location: 11,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 7,
+            shader_location: 12,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 8,
+            shader_location: 13,
+            format: wgpu::VertexFormat::Float32x4,
+        },
+        wgpu::VertexAttribute {
+            offset: size_of::<Vector4<f32>>() as u64 * 9,
+            shader_location: 14,
             format: wgpu::VertexFormat::Float32x4,
         },
     ];
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: size_of::<Self>() as u64,
@@ -175,3 +416,29 @@ impl Instance {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_fixed_empty_test() {
+        let (packed, count) = pack_fixed::<u32, 4>(std::iter::empty());
+        assert_eq!(packed, [0; 4]);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn pack_fixed_exactly_full_test() {
+        let (packed, count) = pack_fixed::<u32, 4>(1..=4);
+        assert_eq!(packed, [1, 2, 3, 4]);
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn pack_fixed_drops_overflow_test() {
+        let (packed, count) = pack_fixed::<u32, 4>(1..=6);
+        assert_eq!(packed, [1, 2, 3, 4]);
+        assert_eq!(count, 4);
+    }
+}
@@ -0,0 +1,309 @@
+use std::f32::consts::TAU;
+
+use bevy_ecs::{
+    system::{Query, Res, ResMut},
+    world::World,
+};
+use nalgebra::Vector3;
+
+use crate::{
+    common_component::{AmbientLight, GlobalLight},
+    time::TimeResource,
+};
+
+// Lighting at one point of the day. time is the phase in [0, 1), 0 is midnight and 0.5 is noon.
+#[derive(Clone, Copy, Debug)]
+pub struct SkyKeyframe {
+    pub time: f32,
+    pub sun_color: Vector3<f32>,
+    pub sun_power: f32,
+    pub sky_color: Vector3<f32>,
+    pub ground_color: Vector3<f32>,
+    pub ambient_intensity: f32,
+}
+
+impl SkyKeyframe {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let f = |a: f32, b: f32| a + (b - a) * t;
+
+        Self {
+            time: f(self.time, other.time),
+            sun_color: self.sun_color.lerp(&other.sun_color, t),
+            sun_power: f(self.sun_power, other.sun_power),
+            sky_color: self.sky_color.lerp(&other.sky_color, t),
+            ground_color: self.ground_color.lerp(&other.ground_color, t),
+            ambient_intensity: f(self.ambient_intensity, other.ambient_intensity),
+        }
+    }
+}
+
+// Resource driving the GlobalLight and AmbientLight from the time of day. Only advances on fixed
+// updates so it stops whenever the simulation does.
+pub struct DayNightCycle {
+    pub time_of_day: f32,
+    pub day_length: f32,             // ingame seconds per full day
+    pub keyframes: Vec<SkyKeyframe>, // sorted by time
+
+    warned_missing_light: bool,
+}
+
+impl Default for DayNightCycle {
+    fn default() -> Self {
+        Self::new(0.3, 600.0, default_keyframes())
+    }
+}
+
+impl DayNightCycle {
+    pub fn new(time_of_day: f32, day_length: f32, mut keyframes: Vec<SkyKeyframe>) -> Self {
+        assert!(
+            !keyframes.is_empty(),
+            "day night cycle needs at least one keyframe"
+        );
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            time_of_day: time_of_day.rem_euclid(1.0),
+            day_length,
+            keyframes,
+            warned_missing_light: false,
+        }
+    }
+
+    pub fn set_time_of_day(&mut self, time_of_day: f32) {
+        self.time_of_day = time_of_day.rem_euclid(1.0);
+    }
+
+    pub fn current(&self) -> SkyKeyframe {
+        evaluate_keyframes(&self.keyframes, self.time_of_day)
+    }
+}
+
+// Smoothly interpolates between the keyframes around time, wrapping from the last keyframe back to
+// the first across midnight. keyframes must be sorted by time and not empty.
+pub fn evaluate_keyframes(keyframes: &[SkyKeyframe], time: f32) -> SkyKeyframe {
+    let time = time.rem_euclid(1.0);
+
+    let next = keyframes.iter().position(|k| k.time > time).unwrap_or(0);
+    let previous = (next + keyframes.len() - 1) % keyframes.len();
+    let (a, b) = (&keyframes[previous], &keyframes[next]);
+
+    let span = (b.time - a.time).rem_euclid(1.0);
+    if span <= f32::EPSILON {
+        return *a;
+    }
+
+    let t = ((time - a.time).rem_euclid(1.0) / span).clamp(0.0, 1.0);
+    let mut k = a.lerp(b, t * t * (3.0 - 2.0 * t));
+    k.time = time;
+
+    k
+}
+
+// Direction the sunlight travels. The sun rises along +x at 0.25, peaks at noon and sets along -x.
+// The slight z tilt keeps it from passing straight overhead.
+pub fn sun_direction(time_of_day: f32) -> Vector3<f32> {
+    let angle = (time_of_day - 0.25) * TAU;
+    let sun = Vector3::new(angle.cos(), angle.sin(), 0.3).normalize();
+
+    -sun
+}
+
+pub fn default_keyframes() -> Vec<SkyKeyframe> {
+    let keyframe =
+        |time, sun: [f32; 3], sun_power, sky: [f32; 3], ground: [f32; 3], ambient| SkyKeyframe {
+            time,
+            sun_color: sun.into(),
+            sun_power,
+            sky_color: sky.into(),
+            ground_color: ground.into(),
+            ambient_intensity: ambient,
+        };
+
+    vec![
+        keyframe(
+            0.0,
+            [0.2, 0.25, 0.5],
+            0.0,
+            [0.1, 0.1, 0.3],
+            [0.02, 0.02, 0.05],
+            0.01,
+        ),
+        keyframe(
+            0.25,
+            [1.0, 0.6, 0.3],
+            20.0,
+            [0.9, 0.6, 0.5],
+            [0.3, 0.2, 0.2],
+            0.02,
+        ),
+        keyframe(
+            0.5,
+            [1.0, 1.0, 0.95],
+            100.0,
+            [0.6, 0.8, 1.0],
+            [0.4, 0.35, 0.3],
+            0.04,
+        ),
+        keyframe(
+            0.75,
+            [1.0, 0.5, 0.25],
+            20.0,
+            [0.9, 0.5, 0.4],
+            [0.3, 0.2, 0.2],
+            0.02,
+        ),
+    ]
+}
+
+// Does nothing unless a DayNightCycle resource exists.
+pub fn advance_day_night(
+    time: Res<TimeResource>,
+    cycle: Option<ResMut<DayNightCycle>>,
+    ambient: Option<ResMut<AmbientLight>>,
+    mut global_lights: Query<&mut GlobalLight>,
+) {
+    let mut cycle = match cycle {
+        Some(cycle) => cycle,
+        None => return,
+    };
+
+    let day_length = cycle.day_length.max(f32::EPSILON);
+    let time_of_day = cycle.time_of_day + time.update_dt.as_secs_f32() / day_length;
+    cycle.set_time_of_day(time_of_day);
+
+    let sky = cycle.current();
+
+    if let Some(mut ambient) = ambient {
+        ambient.sky_color = sky.sky_color;
+        ambient.ground_color = sky.ground_color;
+        ambient.intensity = sky.ambient_intensity;
+    }
+
+    // only the first global light is treated as the sun
+    match global_lights.iter_mut().next() {
+        Some(mut sun) => {
+            sun.direction = sun_direction(cycle.time_of_day);
+            sun.color = sky.sun_color;
+            sun.power = sky.sun_power;
+        }
+        None if !cycle.warned_missing_light => {
+            log::warn!("day night cycle is running without a GlobalLight to drive");
+            cycle.warned_missing_light = true;
+        }
+        None => (),
+    }
+}
+
+pub fn time_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let phase = match args {
+        ["set", phase] => phase
+            .parse::<f32>()
+            .map_err(|e| format!("bad phase {}: {}", phase, e))?,
+        _ => return Err("expected set <phase>".to_string()),
+    };
+
+    let mut cycle = world
+        .get_resource_mut::<DayNightCycle>()
+        .ok_or_else(|| "no day night cycle is running".to_string())?;
+    cycle.set_time_of_day(phase);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_ecs::system::{IntoSystem, System};
+    use std::time::Duration;
+
+    fn keyframe(time: f32, sun_power: f32) -> SkyKeyframe {
+        SkyKeyframe {
+            time,
+            sun_color: Vector3::repeat(sun_power / 100.0),
+            sun_power,
+            sky_color: Vector3::zeros(),
+            ground_color: Vector3::zeros(),
+            ambient_intensity: 0.0,
+        }
+    }
+
+    fn assert_power(keyframes: &[SkyKeyframe], time: f32, power: f32) {
+        let sky = evaluate_keyframes(keyframes, time);
+        assert!(
+            (sky.sun_power - power).abs() < 1e-3,
+            "power {} at {}, expected {}",
+            sky.sun_power,
+            time,
+            power
+        );
+    }
+
+    #[test]
+    fn keyframe_boundaries_test() {
+        let keyframes = default_keyframes();
+        for k in &keyframes {
+            let sky = evaluate_keyframes(&keyframes, k.time);
+            assert_eq!(sky.sun_power, k.sun_power);
+            assert_eq!(sky.sun_color, k.sun_color);
+            assert_eq!(sky.time, k.time);
+        }
+
+        // continuous on both sides of a keyframe, and the smoothstep is halfway at the midpoint
+        assert_power(&keyframes, 0.5 - 1e-4, 100.0);
+        assert_power(&keyframes, 0.5 + 1e-4, 100.0);
+        assert_power(&keyframes, 0.375, 60.0);
+    }
+
+    #[test]
+    fn midnight_wrap_around_test() {
+        let keyframes = default_keyframes();
+        // between dusk at 0.75 and midnight at 0 (or 1)
+        assert_power(&keyframes, 0.875, 10.0);
+        assert_power(&keyframes, 1.0, 0.0);
+        assert_power(&keyframes, 1.0 - 1e-4, 0.0);
+        assert_power(&keyframes, -0.125, 10.0);
+        assert_power(&keyframes, 2.5, 100.0);
+
+        // no keyframe at midnight, the last one blends into the first across it
+        let keyframes = [keyframe(0.2, 0.0), keyframe(0.8, 100.0)];
+        assert_power(&keyframes, 0.0, 50.0);
+        assert_power(&keyframes, 0.9, 100.0 - 100.0 * (0.25 * 0.25 * 2.5));
+        assert_power(&keyframes, 0.1, 100.0 * (0.25 * 0.25 * 2.5));
+    }
+
+    #[test]
+    fn single_keyframe_test() {
+        let keyframes = [keyframe(0.4, 30.0)];
+        assert_power(&keyframes, 0.0, 30.0);
+        assert_power(&keyframes, 0.9, 30.0);
+    }
+
+    #[test]
+    fn advance_without_light_test() {
+        let mut world = World::new();
+        world.insert_resource(TimeResource::new(
+            Duration::from_secs(6),
+            Duration::from_secs(6),
+        ));
+        world.insert_resource(DayNightCycle::new(0.0, 60.0, default_keyframes()));
+
+        let mut system = IntoSystem::into_system(advance_day_night);
+        system.initialize(&mut world);
+        system.run((), &mut world);
+        system.run((), &mut world);
+
+        // no GlobalLight or AmbientLight to drive, the cycle still moves on
+        let cycle = world.resource::<DayNightCycle>();
+        assert!((cycle.time_of_day - 0.2).abs() < 1e-5);
+        assert!(cycle.warned_missing_light);
+
+        world.spawn().insert(GlobalLight {
+            color: Vector3::zeros(),
+            power: 0.0,
+            direction: Vector3::y(),
+        });
+        system.run((), &mut world);
+        let light = world.query::<&GlobalLight>().iter(&world).next().unwrap();
+        assert_eq!(light.direction, sun_direction(0.3));
+    }
+}
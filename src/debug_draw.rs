@@ -0,0 +1,368 @@
+use bevy_ecs::prelude::*;
+use nalgebra::{Isometry3, Point3, Vector3, Vector4};
+
+use crate::common_component::{GlobalLight, MainCamera, PointLight, SpotLight, Transform};
+use crate::data_types::LineVertex;
+use crate::frame_scratch::FrameScratch;
+use crate::render_system::RenderSettings;
+
+// lines per circle, enough to read as round at gizmo sizes
+const CIRCLE_SEGMENTS: usize = 24;
+
+// Immediate mode lines, drawn on top of the scene for one frame. Lines from the fixed update are
+// kept until the next tick replaces them, since a frame can run zero or several ticks. Lines from
+// frame systems are drawn by the next render and then dropped.
+#[derive(Default)]
+pub struct DebugDraw {
+    pub enabled: bool, // drawing is a no-op while off so systems can always call it
+
+    tick_vertices: Vec<LineVertex>,
+    frame_vertices: Vec<LineVertex>,
+    in_tick: bool,
+}
+
+impl DebugDraw {
+    pub const RED: Vector4<f32> = Vector4::new(1.0, 0.0, 0.0, 1.0);
+    pub const GREEN: Vector4<f32> = Vector4::new(0.0, 1.0, 0.0, 1.0);
+    pub const BLUE: Vector4<f32> = Vector4::new(0.0, 0.0, 1.0, 1.0);
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: Vector4<f32>) {
+        if !self.enabled {
+            return;
+        }
+
+        let vertices = match self.in_tick {
+            true => &mut self.tick_vertices,
+            false => &mut self.frame_vertices,
+        };
+        vertices.push(LineVertex::new(a, color));
+        vertices.push(LineVertex::new(b, color));
+    }
+
+    // The 12 edges of an axis aligned box.
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: Vector4<f32>) {
+        let corner = |i: usize| {
+            Point3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        // each edge joins two corners differing in one bit
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    // x, y and z of the isometry in red, green and blue.
+    pub fn axes(&mut self, isometry: &Isometry3<f32>, size: f32) {
+        let origin = Point3::from(isometry.translation.vector);
+        for (axis, color) in [
+            (Vector3::x(), Self::RED),
+            (Vector3::y(), Self::GREEN),
+            (Vector3::z(), Self::BLUE),
+        ] {
+            self.line(origin, origin + isometry.rotation * axis * size, color);
+        }
+    }
+
+    // A circle around center in the plane facing normal.
+    pub fn circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: &Vector3<f32>,
+        radius: f32,
+        color: Vector4<f32>,
+    ) {
+        let (u, v) = perpendicular_axes(normal);
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + (u * angle.cos() + v * angle.sin()) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    // A circle around each axis, reads as a sphere from any side.
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: Vector4<f32>) {
+        for axis in [Vector3::x(), Vector3::y(), Vector3::z()] {
+            self.circle(center, &axis, radius, color);
+        }
+    }
+
+    // A cone opening from apex along direction. cos_half_angle is the cosine of the angle between
+    // the axis and the edge, like SpotLight::cut_off.
+    pub fn cone(
+        &mut self,
+        apex: Point3<f32>,
+        direction: &Vector3<f32>,
+        length: f32,
+        cos_half_angle: f32,
+        color: Vector4<f32>,
+    ) {
+        // kept below 90 degrees, anything wider has no base to draw
+        let half_angle = cos_half_angle.clamp(-1.0, 1.0).acos().min(1.5);
+        let axis = direction.normalize();
+        let base = apex + axis * length;
+        let base_radius = length * half_angle.tan();
+
+        self.circle(base, &axis, base_radius, color);
+        let (u, v) = perpendicular_axes(&axis);
+        for side in [u, -u, v, -v] {
+            self.line(apex, base + side * base_radius, color);
+        }
+    }
+
+    // A line with a head of four short lines at to.
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, color: Vector4<f32>) {
+        self.line(from, to, color);
+
+        let length = (to - from).norm();
+        if length == 0.0 {
+            return;
+        }
+        let axis = (to - from) / length;
+        let head = length * 0.2;
+        let (u, v) = perpendicular_axes(&axis);
+        for side in [u, -u, v, -v] {
+            self.line(to, to - axis * head + side * head * 0.5, color);
+        }
+    }
+
+    pub fn vertices(&self) -> impl Iterator<Item = &LineVertex> {
+        self.tick_vertices.iter().chain(&self.frame_vertices)
+    }
+
+    pub fn clear(&mut self) {
+        self.tick_vertices.clear();
+        self.frame_vertices.clear();
+    }
+
+    // Lines drawn until end_tick belong to the tick and replace the previous tick's.
+    fn begin_tick(&mut self) {
+        self.tick_vertices.clear();
+        self.in_tick = true;
+    }
+
+    fn end_tick(&mut self) {
+        self.in_tick = false;
+    }
+}
+
+// Two unit vectors perpendicular to normal and each other.
+fn perpendicular_axes(normal: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let normal = normal.normalize();
+    // any axis not parallel to the normal works
+    let helper = match normal.x.abs() < 0.9 {
+        true => Vector3::x(),
+        false => Vector3::y(),
+    };
+    let u = normal.cross(&helper).normalize();
+    (u, normal.cross(&u))
+}
+
+// Light colors scaled so the brightest channel is 1, dim lights stay visible that way.
+fn gizmo_color(color: &Vector3<f32>) -> Vector4<f32> {
+    let brightest = color.max();
+    match brightest > 0.0 {
+        true => (color / brightest).push(1.0),
+        false => Vector4::new(1.0, 1.0, 1.0, 1.0),
+    }
+}
+
+pub fn begin_debug_tick(mut debug: ResMut<DebugDraw>) {
+    debug.begin_tick();
+}
+
+pub fn end_debug_tick(mut debug: ResMut<DebugDraw>) {
+    debug.end_tick();
+}
+
+// Runs before render, the frame lines are dropped here whether or not the frame gets drawn.
+pub fn upload_debug_lines(mut debug: ResMut<DebugDraw>, mut scratch: ResMut<FrameScratch>) {
+    scratch.debug_lines.extend(debug.vertices());
+    debug.frame_vertices.clear();
+}
+
+// Shows where lights are while placing them, each in its light's color. Point lights get a sphere
+// of their radius, spot lights their cone and global lights an arrow along their direction in front
+// of the camera. Drawn per frame while RenderSettings::light_gizmos is on.
+pub fn draw_light_gizmos(
+    settings: Res<RenderSettings>,
+    mut debug: ResMut<DebugDraw>,
+    camera: Query<&Transform, With<MainCamera>>,
+    point_lights: Query<(&PointLight, &Transform)>,
+    spot_lights: Query<(&SpotLight, &Transform)>,
+    global_lights: Query<&GlobalLight>,
+) {
+    if !settings.light_gizmos {
+        return;
+    }
+    // independent of the gizmos command, which is for the lines systems draw
+    let enabled = std::mem::replace(&mut debug.enabled, true);
+
+    for (light, transform) in point_lights.iter() {
+        let position = Point3::from(transform.isometry.translation.vector);
+        debug.sphere(position, light.radius, gizmo_color(&light.color));
+    }
+
+    for (light, transform) in spot_lights.iter() {
+        let position = Point3::from(transform.isometry.translation.vector);
+        debug.cone(
+            position,
+            &light.direction,
+            light.radius,
+            light.cut_off,
+            gizmo_color(&light.color),
+        );
+    }
+
+    if let Ok(camera) = camera.get_single() {
+        let anchor = camera.isometry * Point3::new(0.0, 0.0, -3.0);
+        for light in global_lights.iter() {
+            let direction = light.direction.normalize() * 0.5;
+            debug.arrow(
+                anchor - direction,
+                anchor + direction,
+                gizmo_color(&light.color),
+            );
+        }
+    }
+
+    debug.enabled = enabled;
+}
+
+pub fn gizmos_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    if let ["lights", toggle] = args {
+        world.resource_mut::<RenderSettings>().light_gizmos = match *toggle {
+            "on" => true,
+            "off" => false,
+            _ => return Err("expected lights on or lights off".to_string()),
+        };
+        return Ok(());
+    }
+
+    let mut debug = world.resource_mut::<DebugDraw>();
+    debug.enabled = match args {
+        ["on"] => true,
+        ["off"] => false,
+        _ => return Err("expected on, off or lights <on|off>".to_string()),
+    };
+    // lines from the last tick would otherwise stay until the next one
+    debug.clear();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> DebugDraw {
+        DebugDraw {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn aabb_edges_test() {
+        let mut debug = enabled();
+        debug.aabb(Point3::origin(), Point3::new(1.0, 2.0, 3.0), DebugDraw::RED);
+
+        let vertices: Vec<_> = debug.vertices().collect();
+        assert_eq!(vertices.len(), 24);
+        for pair in vertices.chunks(2) {
+            let delta = pair[1].position - pair[0].position;
+            // every edge runs along exactly one axis
+            assert_eq!(delta.iter().filter(|d| **d != 0.0).count(), 1);
+        }
+    }
+
+    #[test]
+    fn circle_radius_test() {
+        let mut debug = enabled();
+        let center = Point3::new(1.0, 2.0, 3.0);
+        let normal = Vector3::new(1.0, 1.0, 0.0);
+        debug.circle(center, &normal, 2.0, DebugDraw::RED);
+
+        assert_eq!(debug.vertices().count(), CIRCLE_SEGMENTS * 2);
+        for vertex in debug.vertices() {
+            let offset = Point3::from(vertex.position) - center;
+            assert!((offset.norm() - 2.0).abs() < 1e-4);
+            assert!(offset.dot(&normal).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn cone_base_test() {
+        let mut debug = enabled();
+        let half_angle = std::f32::consts::FRAC_PI_4;
+        debug.cone(
+            Point3::origin(),
+            &-Vector3::y(),
+            2.0,
+            half_angle.cos(),
+            DebugDraw::RED,
+        );
+
+        // at 45 degrees the base is as wide as the cone is long
+        let base_vertices: Vec<_> = debug.vertices().take(CIRCLE_SEGMENTS * 2).collect();
+        for vertex in base_vertices {
+            assert!((vertex.position.y + 2.0).abs() < 1e-4);
+            assert!((vertex.position.xz().norm() - 2.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn arrow_head_test() {
+        let mut debug = enabled();
+        let to = Point3::new(0.0, 0.0, 1.0);
+        debug.arrow(Point3::origin(), to, DebugDraw::RED);
+
+        let vertices: Vec<_> = debug.vertices().collect();
+        assert_eq!(vertices.len(), 10);
+        // the head points back from the tip
+        for pair in vertices[2..].chunks(2) {
+            assert_eq!(Point3::from(pair[0].position), to);
+            assert!(pair[1].position.z < to.z);
+        }
+    }
+
+    #[test]
+    fn disabled_draws_nothing_test() {
+        let mut debug = DebugDraw::default();
+        debug.axes(&Isometry3::identity(), 1.0);
+        assert_eq!(debug.vertices().count(), 0);
+    }
+
+    #[test]
+    fn tick_lines_kept_until_next_tick_test() {
+        let mut debug = enabled();
+
+        debug.begin_tick();
+        debug.line(Point3::origin(), Point3::new(1.0, 0.0, 0.0), DebugDraw::RED);
+        debug.end_tick();
+
+        debug.line(
+            Point3::origin(),
+            Point3::new(0.0, 1.0, 0.0),
+            DebugDraw::GREEN,
+        );
+        assert_eq!(debug.vertices().count(), 4);
+
+        // a frame without a tick keeps the tick lines
+        debug.frame_vertices.clear();
+        assert_eq!(debug.vertices().count(), 2);
+
+        debug.begin_tick();
+        assert_eq!(debug.vertices().count(), 0);
+    }
+}
@@ -0,0 +1,120 @@
+use bevy_ecs::world::World;
+use nalgebra::Vector3;
+
+use crate::data_types;
+
+// How fog thickens with view space depth, the distance in front of the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogFalloff {
+    Linear { start: f32, end: f32 }, // clear before start, fully fogged past end
+    Exponential { density: f32 },    // covers 1 - e^(-density * depth)
+}
+
+impl FogFalloff {
+    // Value the fragment shader switches on, 0 is disabled fog.
+    pub fn shader_id(&self) -> u32 {
+        match self {
+            Self::Linear { .. } => 1,
+            Self::Exponential { .. } => 2,
+        }
+    }
+}
+
+// Resource, apply_render_settings passes changes on to the RenderState. While disabled the shader
+// skips fog entirely so frames are the same as without it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    pub enabled: bool,
+    pub color: Vector3<f32>, // linear
+    pub falloff: FogFalloff,
+    // clear the background to the fog color so fogged geometry blends into the horizon
+    pub clear_to_fog_color: bool,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: Vector3::new(0.5, 0.55, 0.6),
+            falloff: FogFalloff::Linear {
+                start: 10.0,
+                end: 60.0,
+            },
+            clear_to_fog_color: true,
+        }
+    }
+}
+
+impl Fog {
+    // A camera uniform with only the fog fields filled in.
+    pub fn uniform_fields(&self) -> data_types::Camera {
+        let (fog_start, fog_end, fog_density) = match self.falloff {
+            FogFalloff::Linear { start, end } => (start, end, 0.0),
+            FogFalloff::Exponential { density } => (0.0, 0.0, density),
+        };
+
+        data_types::Camera {
+            fog_mode: match self.enabled {
+                true => self.falloff.shader_id(),
+                false => 0,
+            },
+            fog_color: self.color,
+            fog_start,
+            fog_end,
+            fog_density,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+
+    // What the main pass clears to.
+    pub fn clear_color(&self) -> wgpu::Color {
+        match self.enabled && self.clear_to_fog_color {
+            true => wgpu::Color {
+                r: self.color.x as f64,
+                g: self.color.y as f64,
+                b: self.color.z as f64,
+                a: 1.0,
+            },
+            false => wgpu::Color::BLACK,
+        }
+    }
+}
+
+pub fn fog_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let parse = |value: &str| {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|v| *v >= 0.0)
+            .ok_or_else(|| format!("expected a number of at least 0, got {}", value))
+    };
+
+    let mut fog = world.resource_mut::<Fog>();
+    match args {
+        ["on"] => fog.enabled = true,
+        ["off"] => fog.enabled = false,
+        ["linear", start, end] => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end <= start {
+                return Err("the end has to be further away than the start".to_string());
+            }
+            fog.falloff = FogFalloff::Linear { start, end };
+        }
+        ["exp", density] => {
+            fog.falloff = FogFalloff::Exponential {
+                density: parse(density)?,
+            }
+        }
+        ["color", r, g, b] => fog.color = Vector3::new(parse(r)?, parse(g)?, parse(b)?),
+        ["clear", "on"] => fog.clear_to_fog_color = true,
+        ["clear", "off"] => fog.clear_to_fog_color = false,
+        _ => {
+            return Err(
+                "expected on, off, linear <start> <end>, exp <density>, color <r> <g> <b> or clear <on|off>"
+                    .to_string(),
+            )
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::mem::size_of;
+
+use bevy_ecs::system::ResMut;
+
+use crate::data_types::{
+    ExtractedSpotLight, GlobalLight as GlobalLightData, Instance as InstanceData, LineVertex,
+    PointLight as PointLightData,
+};
+use crate::light_lod::LightCandidate;
+use crate::render_system::{DrawBatch, DrawItem};
+use crate::shadow_atlas::ShadowTile;
+
+// Reusable buffers for per frame extraction. Buffers are cleared instead of reallocated so their
+// capacity settles at the largest frame seen. Only borrowed for the duration of a system run so
+// nothing can hold on to scratch data across frames, short of moving a buffer out. Debug builds
+// check for that in reset, a buffer taken and not returned comes back without its capacity.
+#[derive(Default)]
+pub struct FrameScratch {
+    pub draws: Vec<DrawItem>,
+    pub instances: Vec<InstanceData>,
+    pub batches: Vec<DrawBatch>,
+    pub shadow_batches: Vec<DrawBatch>, // every draw, before culling
+    pub global_lights: Vec<GlobalLightData>,
+    pub point_lights: Vec<PointLightData>,
+    pub spot_lights: Vec<ExtractedSpotLight>,
+
+    pub point_light_candidates: Vec<LightCandidate<PointLightData>>,
+    pub spot_light_candidates: Vec<LightCandidate<ExtractedSpotLight>>,
+    pub spot_shadows: Vec<Option<ShadowTile>>, // atlas tile of each submitted spot light
+
+    pub debug_lines: Vec<LineVertex>, // DebugDraw lines, pairs of line ends
+
+    peak_bytes: usize,
+    capacities: [usize; 11], // of every buffer after the last reset
+}
+
+impl FrameScratch {
+    // Called once at the start of each frame before any extraction.
+    pub fn reset(&mut self) {
+        debug_assert!(
+            self.capacities()
+                .iter()
+                .zip(self.capacities)
+                .all(|(now, last)| *now >= last),
+            "a frame scratch buffer was moved out and not returned"
+        );
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes());
+
+        self.draws.clear();
+        self.instances.clear();
+        self.batches.clear();
+        self.shadow_batches.clear();
+        self.global_lights.clear();
+        self.point_lights.clear();
+        self.spot_lights.clear();
+        self.point_light_candidates.clear();
+        self.spot_light_candidates.clear();
+        self.spot_shadows.clear();
+        self.debug_lines.clear();
+
+        self.capacities = self.capacities();
+    }
+
+    fn capacities(&self) -> [usize; 11] {
+        [
+            self.draws.capacity(),
+            self.instances.capacity(),
+            self.batches.capacity(),
+            self.shadow_batches.capacity(),
+            self.global_lights.capacity(),
+            self.point_lights.capacity(),
+            self.spot_lights.capacity(),
+            self.point_light_candidates.capacity(),
+            self.spot_light_candidates.capacity(),
+            self.spot_shadows.capacity(),
+            self.debug_lines.capacity(),
+        ]
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.draws.len() * size_of::<DrawItem>()
+            + self.instances.len() * size_of::<InstanceData>()
+            + (self.batches.len() + self.shadow_batches.len()) * size_of::<DrawBatch>()
+            + self.global_lights.len() * size_of::<GlobalLightData>()
+            + self.point_lights.len() * size_of::<PointLightData>()
+            + self.spot_lights.len() * size_of::<ExtractedSpotLight>()
+            + self.point_light_candidates.len() * size_of::<LightCandidate<PointLightData>>()
+            + self.spot_light_candidates.len() * size_of::<LightCandidate<ExtractedSpotLight>>()
+            + self.spot_shadows.len() * size_of::<Option<ShadowTile>>()
+            + self.debug_lines.len() * size_of::<LineVertex>()
+    }
+
+    // Highest number of bytes used by a single frame so far.
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes.max(self.used_bytes())
+    }
+}
+
+// First system of the frame, everything filling scratch runs after it.
+pub fn reset_frame_scratch(mut scratch: ResMut<FrameScratch>) {
+    scratch.reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector4};
+
+    fn line(scratch: &mut FrameScratch, count: usize) {
+        let vertex = LineVertex::new(Point3::origin(), Vector4::repeat(1.0));
+        scratch
+            .debug_lines
+            .extend(std::iter::repeat_n(vertex, count));
+    }
+
+    #[test]
+    fn peak_bytes_test() {
+        let mut scratch = FrameScratch::default();
+        line(&mut scratch, 10);
+        assert_eq!(scratch.peak_bytes(), 10 * size_of::<LineVertex>());
+
+        // the peak sticks through smaller frames, and so does the capacity
+        scratch.reset();
+        line(&mut scratch, 4);
+        assert_eq!(scratch.used_bytes(), 4 * size_of::<LineVertex>());
+        assert_eq!(scratch.peak_bytes(), 10 * size_of::<LineVertex>());
+        assert!(scratch.debug_lines.capacity() >= 10);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "moved out and not returned")]
+    fn buffer_kept_across_frames_test() {
+        let mut scratch = FrameScratch::default();
+        line(&mut scratch, 10);
+        scratch.reset();
+
+        let _kept = std::mem::take(&mut scratch.debug_lines);
+        scratch.reset();
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::system::ResMut;
+
+const FRAME_HISTORY: usize = 120;
+
+// Fixed size window over the most recent durations, the oldest is dropped once full.
+#[derive(Clone, Debug)]
+pub struct RollingDurations {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+    sum: Duration,
+}
+
+impl RollingDurations {
+    // capacity must not be 0.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            sum: Duration::ZERO,
+        }
+    }
+
+    pub fn push(&mut self, sample: Duration) {
+        if self.samples.len() == self.capacity {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.sum -= oldest;
+            }
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn latest(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.sum / self.samples.len() as u32)
+        }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+}
+
+// Frame pacing and update rate. Frame times are the time between drawn frames, not how long
+// drawing took.
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    pub frame_times: RollingDurations,
+    pub frames_per_second: f32, // counted over the last full second
+    pub updates_per_second: f32,
+
+    pub total_frames: u64,
+    pub total_updates: u64,
+    started: Instant,
+
+    last_frame: Option<Instant>,
+    second_start: Instant,
+    frames_this_second: u32,
+    updates_this_second: u32,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        Self {
+            frame_times: RollingDurations::new(FRAME_HISTORY),
+            frames_per_second: 0.0,
+            updates_per_second: 0.0,
+
+            total_frames: 0,
+            total_updates: 0,
+            started: now,
+
+            last_frame: None,
+            second_start: now,
+            frames_this_second: 0,
+            updates_this_second: 0,
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn record_frame(&mut self, now: Instant) {
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times.push(now - last_frame);
+        }
+        self.last_frame = Some(now);
+        self.total_frames += 1;
+        self.frames_this_second += 1;
+
+        let elapsed = (now - self.second_start).as_secs_f32();
+        if elapsed >= 1.0 {
+            self.frames_per_second = self.frames_this_second as f32 / elapsed;
+            self.updates_per_second = self.updates_this_second as f32 / elapsed;
+            self.frames_this_second = 0;
+            self.updates_this_second = 0;
+            self.second_start = now;
+        }
+    }
+
+    pub fn record_update(&mut self) {
+        self.total_updates += 1;
+        self.updates_this_second += 1;
+    }
+
+    pub fn average_frame_ms(&self) -> f32 {
+        self.frame_times
+            .average()
+            .map_or(0.0, |average| average.as_secs_f32() * 1000.0)
+    }
+
+    // Averages over the whole run, logged on exit.
+    pub fn summary(&self) -> String {
+        let seconds = self.started.elapsed().as_secs_f32().max(f32::EPSILON);
+        let ms = |d: Option<Duration>| d.map_or(0.0, |d| d.as_secs_f32() * 1000.0);
+
+        format!(
+            "{} frames at {:.1} fps, {} updates at {:.1} per second, last {} frames {:.2} ms average, {:.2} ms min, {:.2} ms max",
+            self.total_frames,
+            self.total_frames as f32 / seconds,
+            self.total_updates,
+            self.total_updates as f32 / seconds,
+            self.frame_times.len(),
+            ms(self.frame_times.average()),
+            ms(self.frame_times.min()),
+            ms(self.frame_times.max()),
+        )
+    }
+}
+
+pub fn record_frame_stats(mut stats: ResMut<FrameStats>) {
+    stats.record_frame(Instant::now());
+}
+
+pub fn record_update_stats(mut stats: ResMut<FrameStats>) {
+    stats.record_update();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn empty_test() {
+        let durations = RollingDurations::new(4);
+        assert_eq!(durations.len(), 0);
+        assert_eq!(durations.latest(), None);
+        assert_eq!(durations.average(), None);
+        assert_eq!(durations.min(), None);
+        assert_eq!(durations.max(), None);
+    }
+
+    #[test]
+    fn statistics_test() {
+        let mut durations = RollingDurations::new(4);
+        for sample in [16, 17, 33] {
+            durations.push(ms(sample));
+        }
+
+        assert_eq!(durations.len(), 3);
+        assert_eq!(durations.latest(), Some(ms(33)));
+        assert_eq!(durations.average(), Some(ms(22)));
+        assert_eq!(durations.min(), Some(ms(16)));
+        assert_eq!(durations.max(), Some(ms(33)));
+    }
+
+    #[test]
+    fn oldest_dropped_when_full_test() {
+        let mut durations = RollingDurations::new(3);
+        // the 100ms hitch falls out of the window
+        for sample in [100, 10, 20, 30] {
+            durations.push(ms(sample));
+        }
+
+        assert_eq!(durations.len(), 3);
+        assert_eq!(durations.average(), Some(ms(20)));
+        assert_eq!(durations.max(), Some(ms(30)));
+    }
+
+    #[test]
+    fn frame_rate_over_a_second_test() {
+        let mut stats = FrameStats::default();
+        let start = stats.second_start;
+
+        // frames 100ms apart with two updates each, the frame at one second closes it and still
+        // counts towards it
+        for frame in 0..=10 {
+            stats.record_update();
+            stats.record_update();
+            stats.record_frame(start + ms(100) * frame);
+        }
+
+        assert_eq!(stats.total_frames, 11);
+        assert_eq!(stats.total_updates, 22);
+        assert_eq!(stats.frame_times.len(), 10);
+        assert_eq!(stats.frame_times.average(), Some(ms(100)));
+        assert!((stats.frames_per_second - 11.0).abs() < 1e-3);
+        assert!((stats.updates_per_second - 22.0).abs() < 1e-3);
+    }
+}
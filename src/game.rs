@@ -1,26 +1,65 @@
-use std::time::Duration;
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
 
 use bevy_ecs::{
-    schedule::{Schedule, Stage, SystemStage},
-    system::{Query, Res},
-    world::World,
+    event::{Events, ManualEventReader},
+    query::With,
+    schedule::{
+        ExclusiveSystemDescriptorCoercion, ParallelSystemDescriptorCoercion, Schedule, Stage,
+        SystemStage,
+    },
+    system::{Commands, IntoExclusiveSystem, Query, Res, ResMut},
+    world::{Mut, World},
 };
-use nalgebra::{Isometry3, Perspective3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry3, UnitQuaternion, Vector2, Vector3, Vector4};
 use rand::Rng;
 use winit::{
+    dpi::PhysicalSize,
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    monitor::VideoMode,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 use crate::{
+    bindings::{Action, Bindings, KeyBindings},
+    board::{self, BoardGrid},
+    camera_controller::{self, CameraController},
+    camera_cut::{self, CameraCut, CameraDirector},
+    camera_shake::{self, CameraShake},
     common_component::{
-        Camera, GlobalLight, MainCamera, PointLight, RenderGeometry, Rotate, Texture, Transform,
+        AmbientLight, AnimatedTexture, Camera, DepthBias, GlobalLight, MainCamera, Material,
+        NormalMap, PointLight, RenderGeometry, Rotate, Texture, Tint, Transform,
     },
-    geometry_library::GeometryId,
-    render_system::{self, RenderState},
+    console::{self, CommandRegistry, Console},
+    day_night::{self, DayNightCycle},
+    debug_draw::{self, DebugDraw},
+    fog::Fog,
+    frame_scratch::{self, FrameScratch},
+    frame_stats::{self, FrameStats},
+    geometry_library::{GeometryId, CARD_THICKNESS},
+    input::{self, Input},
+    input_recording::{InputPlayer, InputRecorder, RecordedEvent},
+    interpolation,
+    library_stats::{self, GpuMemoryStats},
+    light_lod::LightLod,
+    light_managment_system, material,
+    picking::{self, CursorWorldPosition, PlaneTarget},
+    pile::{self, PileLayout},
+    post_process::BloomSettings,
+    profile::{self, ProfileStore},
+    render_system::{
+        self, RenderInitError, RenderMode, RenderSettings, RenderState, RenderStats,
+        RenderTargetKind,
+    },
+    screenshot,
+    state_hash::{self, StateHashHistory},
+    strings::{self, LanguageChanged, Strings},
     texture_library::TextureId,
-    time::{frame_criteria, update_criteria, TimeResource},
+    time::{update_criteria, BackgroundThrottle, TimeResource},
 };
 
 pub fn run() -> Result<(), Box<dyn std::error::Error>> {
@@ -30,85 +69,284 @@ pub fn run() -> Result<(), Box<dyn std::error::Error>> {
         .build(&event_loop)
         .unwrap();
 
-    let mut game = Game::new(window);
+    // WGPU_BACKEND=vulkan,metal,dx12,dx11 or gl narrows down which graphics apis are tried
+    let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+
+    let mut game = Game::new(window, backends)?;
+
+    // --record-input <file> writes raw window input to file, --play-input <file> replays it while
+    // ignoring real input, --exclusive-fullscreen makes the fullscreen toggle change video mode and
+    // --frames <n> exits through the normal shutdown path after n frames
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record-input" => {
+                let path = args.next().ok_or("--record-input expects a file")?;
+                game.input_recorder = Some(InputRecorder::create(Path::new(&path))?);
+                log::info!("recording input to {}", path);
+            }
+            "--play-input" => {
+                let path = args.next().ok_or("--play-input expects a file")?;
+                game.input_player = Some(
+                    InputPlayer::load(Path::new(&path))
+                        .map_err(|e| format!("failed to load input recording {}: {}", path, e))?,
+                );
+                log::info!("playing back input from {}", path);
+            }
+            "--exclusive-fullscreen" => game.fullscreen_mode = FullscreenMode::Exclusive,
+            "--frames" => {
+                let frames = args.next().ok_or("--frames expects a frame count")?;
+                let frames = frames
+                    .parse()
+                    .map_err(|e| format!("invalid frame count {}: {}", frames, e))?;
+                game.world.insert_resource(FrameLimit { remaining: frames });
+            }
+            _ => log::warn!("ignoring unknown argument {}", arg),
+        }
+    }
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = game.handle_event(&event);
     });
 }
 
+// What the fullscreen toggle switches to. Exclusive takes over the display with the largest video
+// mode of the current monitor, borderless covers it at the desktop resolution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Borderless,
+    Exclusive,
+}
+
+// Resource requesting an orderly exit, inserted by any system that wants to quit the game.
+pub struct AppExit;
+
+// Resource set by --frames, the game exits once remaining frames were drawn.
+pub struct FrameLimit {
+    pub remaining: u64,
+}
+
+// Frame system counting down FrameLimit, requests the exit on the last frame.
+fn exit_after_frames(mut commands: Commands, limit: Option<ResMut<FrameLimit>>) {
+    let mut limit = match limit {
+        Some(limit) => limit,
+        None => return,
+    };
+
+    limit.remaining = limit.remaining.saturating_sub(1);
+    if limit.remaining == 0 {
+        log::info!("frame limit reached, exiting");
+        commands.insert_resource(AppExit);
+    }
+}
+
+// Systems run once when the game exits, before the gpu is released.
+fn shutdown_schedule() -> Schedule {
+    let shutdown_stage = SystemStage::single_threaded().with_system(profile::flush_profile);
+
+    let mut shutdown_schedule = Schedule::default();
+    shutdown_schedule.add_stage("shutdown", shutdown_stage);
+    shutdown_schedule
+}
+
+// Top of the table stand-in, cards lie on it.
+const TABLE_HEIGHT: f32 = -2.0;
+
+const TITLE_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// a hung shutdown step should not keep the process alive forever
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A driver reset can take a moment before a new device can be created.
+const REBUILD_ATTEMPTS: u32 = 3;
+const REBUILD_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// world is declared before window so the surface inside RenderState is dropped before the window
+// it was created from, even if the shutdown path is skipped.
 struct Game {
-    window: Window,
     world: World,
+    window: Window,
+    backends: wgpu::Backends, // kept so a rebuilt render state picks from the same apis
     frame_schedule: Schedule,
     update_schedule: Schedule,
+    shutdown_schedule: Schedule,
+    exited: bool,
+
+    input_recorder: Option<InputRecorder>,
+    input_player: Option<InputPlayer>,
+
+    bindings: Bindings,
+    fullscreen_mode: FullscreenMode,
+
+    last_title_refresh: Instant,
+    console_title: Option<String>, // shown while the console is open, there is no text rendering
+    language_changes: ManualEventReader<LanguageChanged>,
 }
 
 impl Game {
-    fn new(window: Window) -> Self {
+    fn new(window: Window, backends: wgpu::Backends) -> Result<Self, RenderInitError> {
         let mut world = World::new();
-        let render_state = RenderState::init(&window);
+        let render_state = RenderState::init(RenderTargetKind::Window(&window), backends)?;
         world.insert_resource(render_state);
+        world.insert_resource(FrameScratch::default());
+        world.insert_resource(FrameStats::default());
+        world.insert_resource(LightLod::default());
+        world.insert_resource(RenderStats::default());
+        world.insert_resource(DebugDraw::default());
+        world.insert_resource(GpuMemoryStats::default());
+        world.insert_resource(RenderSettings::default());
+        world.insert_resource(BloomSettings::default());
+        world.insert_resource(Fog::default());
+        // CARD_GAME_DEBUG_NORMALS starts out showing world space normals instead of lighting
+        world.insert_resource(match std::env::var_os("CARD_GAME_DEBUG_NORMALS") {
+            Some(_) => RenderMode::Normals,
+            None => RenderMode::Lit,
+        });
+        world.insert_resource(Events::<CameraCut>::default());
+        world.insert_resource(Events::<LanguageChanged>::default());
+        world.insert_resource(CameraDirector::default());
+        world.insert_resource(ProfileStore::load_default_location());
+        world.insert_resource(Console::default());
+        world.insert_resource(CommandRegistry::with_builtins());
+
+        let strings = Strings::load("en");
+        window.set_title(strings.get("window.title"));
+        world.insert_resource(strings);
+
         world.insert_resource(TimeResource::new(
             Duration::from_secs_f64(1.0 / 60.0),
             Duration::from_secs_f64(1.0 / 60.0),
         ));
+        world.insert_resource(BackgroundThrottle::default());
+        world.insert_resource(Input::default());
+        let mut keys = KeyBindings::defaults();
+        keys.apply(
+            &world
+                .resource::<ProfileStore>()
+                .profile()
+                .preferences
+                .key_bindings,
+        );
+        let bindings = Bindings::new(keys);
+        world.insert_resource(bindings.keys().clone());
+        // about once a second, keeping the last few minutes
+        world.insert_resource(StateHashHistory::new(60, 256));
 
         let size = window.inner_size();
         let aspect = size.width as f32 / size.height as f32;
 
+        world.insert_resource(CursorWorldPosition::new(
+            PlaneTarget::horizontal(TABLE_HEIGHT),
+            Vector2::new(size.width as f32, size.height as f32),
+        ));
+
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(3.0, 0.0, 0.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
-            .insert(Camera {
-                projection: Perspective3::new(aspect, 3.14 / 2.0, 0.05, 1000.0),
-            })
+            .insert(Camera::perspective(aspect, 3.14 / 2.0, 0.05, 1000.0))
+            .insert(CameraShake::new(0.1, 0.05, 0))
+            .insert(CameraController::new(3.0, 0.002))
             .insert(MainCamera);
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(0.0, -2.0, -5.0),
+                scale: Vector3::repeat(0.5),
                 parent: None,
                 children: vec![],
             })
             .insert(RenderGeometry::new(GeometryId::SceneTestGeometry))
-            .insert(Texture::new(TextureId::CurlyBraceTexture));
+            .insert(Texture::new(TextureId::CurlyBraceTexture))
+            .insert(DepthBias(-1)); // stands in for the table, kept behind anything lying on it
+
+        // drag_cards deals cards onto its slots
+        let board_origin = Isometry3::translation(0.0, TABLE_HEIGHT, -2.0);
+        world
+            .spawn()
+            .insert(Transform {
+                isometry: board_origin,
+                scale: Vector3::repeat(1.0),
+                parent: None,
+                children: vec![],
+            })
+            .insert(BoardGrid::new(2, 4, 1.0, board_origin));
+
+        // a deck at the side of the table, layout_piles stacks its cards
+        let sleeve = world
+            .resource::<ProfileStore>()
+            .card_sleeve(world.get_resource::<RenderState>());
+        let deck = world
+            .spawn()
+            .insert(PileLayout {
+                thickness_per_card: CARD_THICKNESS,
+                ..PileLayout::default()
+            })
+            .id();
+        let cards = (0..20)
+            .map(|_| {
+                world
+                    .spawn()
+                    .insert(Transform {
+                        isometry: Isometry3::identity(),
+                        scale: Vector3::repeat(1.0),
+                        parent: Some(deck),
+                        children: vec![],
+                    })
+                    .insert(RenderGeometry::new(GeometryId::CardGeometry))
+                    .insert(Texture::new(sleeve))
+                    .id()
+            })
+            .collect();
+        world.entity_mut(deck).insert(Transform {
+            isometry: Isometry3::translation(-3.0, TABLE_HEIGHT, -2.0),
+            scale: Vector3::repeat(1.0),
+            parent: None,
+            children: cards,
+        });
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(0.0, 0.0, -5.0),
+                scale: Vector3::new(2.0, 1.0, 1.0),
                 parent: None,
                 children: vec![],
             })
             .insert(RenderGeometry::new(GeometryId::TorusGeometry))
             .insert(Texture::new(TextureId::CrabTexture))
-            .insert(Rotate { axis: rand_vec() });
+            .insert(AnimatedTexture::new(12.0))
+            .insert(Rotate::anchored(rand_vec(), 0, UnitQuaternion::identity()));
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(3.0, 0.0, -5.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
             .insert(RenderGeometry::new(GeometryId::TorusGeometry))
             .insert(Texture::new(TextureId::CrabTexture))
-            .insert(Rotate { axis: rand_vec() });
+            .insert(NormalMap::new(TextureId::BumpNormalTexture))
+            .insert(Rotate::anchored(rand_vec(), 0, UnitQuaternion::identity()));
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(6.0, 0.0, -5.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
             .insert(RenderGeometry::new(GeometryId::TorusGeometry))
             .insert(Texture::new(TextureId::CrabTexture))
-            .insert(Rotate { axis: rand_vec() });
+            .insert(Rotate::anchored(rand_vec(), 0, UnitQuaternion::identity()));
 
+        // hue gradient around the ring so each torus can be told apart
         for i in 0..10 {
+            let tint = Tint(hue_color(i as f32 / 10.0).push(1.0));
             let tex_id = if i % 2 == 0 {
                 TextureId::CrabTexture
             } else {
@@ -119,17 +357,60 @@ impl Game {
                 .spawn()
                 .insert(Transform {
                     isometry: Isometry3::translation(i as f32, 3.0, -5.0),
+                    scale: Vector3::repeat(1.0),
                     parent: None,
                     children: vec![],
                 })
                 .insert(RenderGeometry::new(GeometryId::TorusGeometry))
                 .insert(Texture::new(tex_id))
-                .insert(Rotate { axis: rand_vec() });
+                .insert(tint)
+                .insert(Rotate::new(rand_vec()));
         }
+        // roughness from 0 on the left to 1 on the right, plain above and gold metal below
+        for i in 0..6 {
+            let roughness = i as f32 / 5.0;
+
+            for (row, metallic, tint) in [
+                (0.0, 0.0, Tint::WHITE),
+                (1.0, 1.0, Tint(Vector4::new(1.0, 0.75, 0.3, 1.0))),
+            ] {
+                world
+                    .spawn()
+                    .insert(Transform {
+                        isometry: Isometry3::translation(i as f32 * 2.0, 7.5 - row * 2.0, -5.0),
+                        scale: Vector3::repeat(0.75),
+                        parent: None,
+                        children: vec![],
+                    })
+                    .insert(RenderGeometry::new(GeometryId::TorusGeometry))
+                    .insert(tint)
+                    .insert(Material {
+                        roughness,
+                        metallic,
+                        ..Material::default()
+                    });
+            }
+        }
+        // glows far past white, shows off bloom once it's turned on with the bloom command
+        world
+            .spawn()
+            .insert(Transform {
+                isometry: Isometry3::translation(-3.0, 0.0, -5.0),
+                scale: Vector3::repeat(1.0),
+                parent: None,
+                children: vec![],
+            })
+            .insert(RenderGeometry::new(GeometryId::TorusGeometry))
+            .insert(Material {
+                emissive: Vector3::new(8.0, 3.0, 1.0),
+                ..Material::default()
+            })
+            .insert(Rotate::anchored(rand_vec(), 0, UnitQuaternion::identity()));
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(0.0, 0.0, 0.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
@@ -139,16 +420,42 @@ impl Game {
                 radius: 1.0,
             });
 
+        // a grid of dim colored lights, more than the uniform path's MAX_POINT_LIGHTS so the
+        // storage path has something to show
+        for i in 0..64 {
+            let (x, z) = ((i % 8) as f32, (i / 8) as f32);
+            let color = hue_color(i as f32 / 64.0);
+
+            world
+                .spawn()
+                .insert(Transform {
+                    isometry: Isometry3::translation(x * 3.0 - 10.5, 1.5, z * 3.0 - 15.0),
+                    scale: Vector3::repeat(1.0),
+                    parent: None,
+                    children: vec![],
+                })
+                .insert(PointLight {
+                    color: color * 0.05,
+                    power: 1.0,
+                    radius: 3.0,
+                });
+        }
+
+        world.insert_resource(AmbientLight::default());
+
         world.spawn().insert(GlobalLight {
             color: [1.0, 1.0, 1.0].into(),
             power: 100.0,
             direction: [1.0, 1.0, 1.0].into(),
         });
+        // moves the light above and the ambient light through the day from here on
+        world.insert_resource(DayNightCycle::default());
         /*
         world
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(5.0, 0.0, 0.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
@@ -161,6 +468,7 @@ impl Game {
             .spawn()
             .insert(Transform {
                 isometry: Isometry3::translation(-5.0, 0.0, 0.0),
+                scale: Vector3::repeat(1.0),
                 parent: None,
                 children: vec![],
             })
@@ -171,48 +479,401 @@ impl Game {
             });
              */
 
-        let update_stage = SystemStage::parallel()
+        // single threaded runs systems in the order they are added, the parallel executor leaves
+        // systems writing the same components in an unspecified order which would break replays
+        let update_stage = SystemStage::single_threaded()
             .with_run_criteria(update_criteria)
-            .with_system(rotate);
+            .with_system(
+                interpolation::store_previous_transforms
+                    .exclusive_system()
+                    .at_start(),
+            )
+            .with_system(debug_draw::begin_debug_tick.exclusive_system().at_start())
+            .with_system(console::run_console_commands.exclusive_system())
+            .with_system(rotate)
+            .with_system(draw_rotation_axes)
+            .with_system(animate_textures)
+            .with_system(camera_controller::camera_controller)
+            .with_system(camera_shake::decay_camera_shake)
+            .with_system(board::spawn_board_slots)
+            .with_system(board::drag_cards)
+            .with_system(board::peek_at_board)
+            .with_system(board::draw_board_slots)
+            .with_system(pile::layout_piles)
+            .with_system(day_night::advance_day_night)
+            .with_system(material::advance_dissolve)
+            .with_system(frame_stats::record_update_stats)
+            .with_system(state_hash::record_state_hash.exclusive_system().at_end())
+            .with_system(input::end_input_tick.exclusive_system().at_end())
+            .with_system(debug_draw::end_debug_tick.exclusive_system().at_end());
         let mut update_schedule = Schedule::default();
         update_schedule.add_stage("update", update_stage);
 
         let frame_stage = SystemStage::parallel()
-            .with_run_criteria(frame_criteria)
-            .with_system(render_system::render);
+            .with_system(Events::<CameraCut>::update_system)
+            .with_system(Events::<LanguageChanged>::update_system)
+            .with_system(camera_cut::direct_camera.label("camera cut"))
+            .with_system(render_system::apply_render_settings.before("render"))
+            .with_system(light_managment_system::light_assignment_prepass.before("render"))
+            .with_system(frame_scratch::reset_frame_scratch.before("debug lines"))
+            .with_system(debug_draw::draw_light_gizmos.before("debug lines"))
+            .with_system(
+                debug_draw::upload_debug_lines
+                    .label("debug lines")
+                    .before("render"),
+            )
+            .with_system(render_system::render.label("render").after("camera cut"))
+            .with_system(picking::update_cursor_world_position.after("camera cut"))
+            .with_system(strings::report_missing_strings)
+            .with_system(frame_stats::record_frame_stats)
+            .with_system(library_stats::update_memory_stats.after("render"))
+            .with_system(profile::save_profile)
+            .with_system(exit_after_frames.after("render"));
 
         let mut frame_schedule = Schedule::default();
         frame_schedule.add_stage("frame", frame_stage);
 
-        Self {
-            window,
+        Ok(Self {
             world,
+            window,
+            backends,
             update_schedule,
             frame_schedule,
+            shutdown_schedule: shutdown_schedule(),
+            exited: false,
+
+            input_recorder: None,
+            input_player: None,
+
+            bindings,
+            fullscreen_mode: FullscreenMode::Borderless,
+
+            last_title_refresh: Instant::now(),
+            console_title: None,
+            language_changes: ManualEventReader::default(),
+        })
+    }
+
+    // Replaces the whole gpu stack after the device was lost. Entities only refer to geometry and
+    // textures by id so the rest of the world is left untouched.
+    fn rebuild_render_state(&mut self) {
+        log::warn!("rebuilding render state");
+
+        // the old device goes first so both never exist at the same time, textures registered at
+        // runtime are loaded again from their files
+        let (dynamic_textures, depth_pre_pass) = self
+            .world
+            .remove_resource::<RenderState>()
+            .map(|state| (state.dynamic_textures(), state.depth_pre_pass()))
+            .unwrap_or_default();
+
+        // shaders can go missing while running, WGSL is read from the source tree, without a
+        // RenderState the only way out is to quit
+        let mut attempt = 1;
+        let mut state = loop {
+            match RenderState::init(RenderTargetKind::Window(&self.window), self.backends) {
+                Ok(state) => break state,
+                Err(e) if attempt < REBUILD_ATTEMPTS => {
+                    log::warn!("failed to rebuild render state, retrying: {}", e);
+                    thread::sleep(REBUILD_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    log::error!(
+                        "failed to rebuild render state after {} attempts: {}",
+                        attempt,
+                        e
+                    );
+                    self.world.insert_resource(AppExit);
+                    return;
+                }
+            }
+        };
+        state.restore_dynamic_textures(dynamic_textures);
+        state.set_depth_pre_pass(depth_pre_pass);
+        self.world.insert_resource(state);
+    }
+
+    fn run_action(&mut self, action: Action) {
+        let console_open = self.world.resource::<Console>().open;
+
+        match action {
+            // escape backs out of the console before it quits
+            Action::Quit if console_open => self.world.resource_mut::<Console>().toggle(),
+            Action::Quit => self.world.insert_resource(AppExit),
+            Action::ToggleConsole => self.world.resource_mut::<Console>().toggle(),
+            // grabs the cursor for mouse look, pressing it again gives it back
+            Action::ToggleCursorGrab if !console_open => {
+                let mut input = self.world.resource_mut::<Input>();
+                let grab = !input.cursor_grabbed;
+                match self.window.set_cursor_grab(grab) {
+                    Ok(()) => {
+                        self.window.set_cursor_visible(!grab);
+                        input.cursor_grabbed = grab;
+                    }
+                    Err(e) => log::warn!("failed to change cursor grab: {}", e),
+                }
+            }
+            Action::ToggleCursorGrab => (),
+            Action::ToggleFullscreen => self.toggle_fullscreen(),
+            // p is also typed into the console
+            Action::TogglePause if !console_open => {
+                let mut time = self.world.resource_mut::<TimeResource>();
+                time.paused = !time.paused;
+                log::info!("{}", if time.paused { "paused" } else { "resumed" });
+            }
+            Action::TogglePause => (),
+            Action::CycleRenderMode => {
+                let mut mode = self.world.resource_mut::<RenderMode>();
+                *mode = mode.next();
+            }
+            Action::Screenshot => self
+                .world
+                .resource_mut::<RenderState>()
+                .request_screenshot(screenshot::default_path()),
+            Action::RebuildRenderState => self.rebuild_render_state(),
         }
     }
 
+    fn toggle_fullscreen(&mut self) {
+        let fullscreen = match (self.window.fullscreen(), self.fullscreen_mode) {
+            (Some(_), _) => None,
+            (None, FullscreenMode::Borderless) => Some(Fullscreen::Borderless(None)),
+            (None, FullscreenMode::Exclusive) => match self.largest_video_mode() {
+                Some(mode) => Some(Fullscreen::Exclusive(mode)),
+                None => {
+                    log::warn!("no exclusive video mode available, using borderless fullscreen");
+                    Some(Fullscreen::Borderless(None))
+                }
+            },
+        };
+
+        log::info!("switching to {:?}", fullscreen);
+        self.window.set_fullscreen(fullscreen);
+
+        // some platforms only send Resized later or not at all when the size is unchanged
+        let size = self.window.inner_size();
+        self.resize(size);
+        apply_window_input(&mut self.world, &WindowEvent::Resized(size));
+    }
+
+    // Highest resolution first, then highest refresh rate.
+    fn largest_video_mode(&self) -> Option<VideoMode> {
+        self.window
+            .current_monitor()?
+            .video_modes()
+            .max_by_key(|mode| {
+                let size = mode.size();
+                (size.width * size.height, mode.refresh_rate())
+            })
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        self.world
+            .resource_mut::<RenderState>()
+            .resize_if_needed(&size, &self.window);
+    }
+
     fn update_as_needed(&mut self) {
         self.update_schedule.run(&mut self.world);
     }
 
+    // Shows the frame stats in the title bar, at most once a second or right after the language
+    // changed or the console closed. The open console shows its input and the newest log line.
+    fn refresh_title(&mut self) {
+        let console = self.world.resource::<Console>();
+        if console.open {
+            let log = console::scrollback(1).pop().unwrap_or_default();
+            let title = self.world.resource::<Strings>().format(
+                "window.title_console",
+                &[("input", &console.input), ("log", &log)],
+            );
+            if self.console_title.as_ref() != Some(&title) {
+                self.window.set_title(&title);
+                self.console_title = Some(title);
+            }
+            return;
+        }
+        let console_closed = self.console_title.take().is_some();
+
+        let language_changed = self
+            .language_changes
+            .iter(self.world.resource::<Events<LanguageChanged>>())
+            .count()
+            > 0;
+        if !language_changed
+            && !console_closed
+            && self.last_title_refresh.elapsed() < TITLE_REFRESH_INTERVAL
+        {
+            return;
+        }
+        self.last_title_refresh = Instant::now();
+
+        let stats = self.world.resource::<FrameStats>();
+        let gpu = self.world.resource::<RenderStats>().gpu;
+        let title = self.world.resource::<Strings>().format(
+            "window.title_stats",
+            &[
+                ("fps", &format!("{:.0}", stats.frames_per_second)),
+                ("frame_ms", &format!("{:.2}", stats.average_frame_ms())),
+                ("ups", &format!("{:.0}", stats.updates_per_second)),
+                ("draw_calls", &gpu.draw_calls),
+                ("triangles", &gpu.triangles),
+            ],
+        );
+        self.window.set_title(&title);
+    }
+
+    // Fixed updates catch up before drawing so the frame blends between the two newest ones.
     fn render(&mut self) {
-        self.frame_schedule.run(&mut self.world);
+        if self.world.resource_mut::<TimeResource>().begin_frame() {
+            self.update_as_needed();
+            self.frame_schedule.run(&mut self.world);
+        }
     }
 
+    // Runs the shutdown schedule once and releases the gpu while the window is still alive.
+    fn shutdown(&mut self) {
+        if self.exited {
+            return;
+        }
+        self.exited = true;
+
+        thread::spawn(|| {
+            thread::sleep(SHUTDOWN_TIMEOUT);
+            log::error!("shutdown did not finish in time, forcing exit");
+            std::process::exit(1);
+        });
+
+        self.shutdown_schedule.run(&mut self.world);
+
+        log::info!("{}", self.world.resource::<FrameStats>().summary());
+
+        if let Some(render_state) = self.world.remove_resource::<RenderState>() {
+            render_state.shutdown();
+        }
+
+        log::info!("shutdown complete");
+    }
+
+    // Records or replaces real window input before it reaches dispatch_event.
     fn handle_event<E>(&mut self, event: &Event<E>) -> ControlFlow {
-        self.window.request_redraw();
+        if let Event::WindowEvent { event, window_id } = event {
+            if *window_id == self.window.id() {
+                if let Some(recorded) = RecordedEvent::from_window_event(event) {
+                    if self.input_player.is_some() {
+                        return if self.exited {
+                            ControlFlow::Exit
+                        } else {
+                            ControlFlow::Poll
+                        };
+                    }
+                    if let Some(recorder) = &mut self.input_recorder {
+                        recorder.record(recorded);
+                    }
+                }
+            }
+        }
+
+        let mut control_flow = self.dispatch_event(event);
+
+        let due = match &mut self.input_player {
+            Some(player) => player.take_due(),
+            None => Vec::new(),
+        };
+        for recorded in due {
+            if control_flow == ControlFlow::Exit {
+                break;
+            }
+
+            // the window is not resizable by the user, resize it so the surface matches
+            if let RecordedEvent::Resized { width, height } = recorded {
+                self.window
+                    .set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+            }
+
+            let mut inner_size = self.window.inner_size();
+            control_flow = self.dispatch_event::<E>(&Event::WindowEvent {
+                window_id: self.window.id(),
+                event: recorded.to_window_event(&mut inner_size),
+            });
+        }
+
+        // hand input back to the real window once the recording runs out
+        if self
+            .input_player
+            .as_ref()
+            .is_some_and(InputPlayer::finished)
+        {
+            log::info!("input playback finished");
+            self.input_player = None;
+        }
+
+        control_flow
+    }
+
+    fn dispatch_event<E>(&mut self, event: &Event<E>) -> ControlFlow {
+        // winit can still deliver events after exit was requested, the world is torn down by then
+        if self.exited {
+            return ControlFlow::Exit;
+        }
+
+        // only due frames are requested, a pending redraw would keep the loop from sleeping
+        if self.world.resource::<TimeResource>().frame_due() {
+            self.window.request_redraw();
+        }
+
+        let for_window = match event {
+            Event::WindowEvent { window_id, .. } => *window_id == self.window.id(),
+            _ => true,
+        };
+        if for_window {
+            self.world.resource_mut::<Input>().handle_event(event);
+            if let Event::WindowEvent { event, .. } = event {
+                apply_window_input(&mut self.world, event);
+            }
+        }
+
         match event {
             Event::WindowEvent { event, window_id } => match event {
                 WindowEvent::Resized(size) => {
+                    if *window_id == self.window.id() {
+                        self.resize(*size);
+                    }
+                }
+                WindowEvent::KeyboardInput { input, .. } => {
+                    if *window_id == self.window.id() {
+                        if let Some(action) = self.bindings.handle_key(input) {
+                            self.run_action(action);
+                        }
+                    }
+                }
+                WindowEvent::ReceivedCharacter(c) => {
                     if *window_id == self.window.id() {
                         self.world
-                            .resource_mut::<RenderState>()
-                            .resize_if_needed(&size, &self.window);
+                            .resource_scope(|world, mut console: Mut<Console>| {
+                                if console.open {
+                                    console
+                                        .receive_character(*c, world.resource::<CommandRegistry>());
+                                }
+                            });
+                    }
+                }
+                WindowEvent::Focused(focused) => {
+                    if *window_id == self.window.id() {
+                        if !*focused {
+                            self.bindings.release_all();
+                        }
+                        self.world.resource_scope(
+                            |world, mut throttle: Mut<BackgroundThrottle>| {
+                                throttle.set_focused(*focused, &mut world.resource_mut());
+                            },
+                        );
                     }
                 }
                 WindowEvent::CloseRequested => {
                     if *window_id == self.window.id() {
+                        self.shutdown();
                         return ControlFlow::Exit;
                     }
                 }
@@ -223,16 +884,84 @@ impl Game {
         }
 
         self.update_as_needed();
+        self.refresh_title();
+
+        let lost = self
+            .world
+            .get_resource::<RenderState>()
+            .is_some_and(RenderState::is_lost);
+        if lost {
+            self.rebuild_render_state();
+        }
+
+        if self.world.contains_resource::<AppExit>() {
+            self.shutdown();
+            return ControlFlow::Exit;
+        }
 
-        ControlFlow::Poll
+        // sleep until the next frame is due instead of spinning, uncapped keeps polling
+        let time = self.world.resource::<TimeResource>();
+        if time.frame_dt.is_zero() || time.unsimulated_time >= time.update_dt {
+            ControlFlow::Poll
+        } else {
+            ControlFlow::WaitUntil(time.next_frame)
+        }
+    }
+}
+
+// The world side of window input: window size and the main camera's aspect.
+// Kept apart from the window and render state so input playback can be checked without either.
+fn apply_window_input(world: &mut World, event: &WindowEvent) {
+    match event {
+        WindowEvent::Resized(size) => {
+            world.resource_mut::<CursorWorldPosition>().window_size =
+                Vector2::new(size.width as f32, size.height as f32);
+
+            // does nothing when there is no main camera
+            let mut cameras = world.query_filtered::<&mut Camera, With<MainCamera>>();
+            for mut camera in cameras.iter_mut(world) {
+                camera.set_viewport_size(size.width, size.height);
+            }
+        }
+        _ => (),
     }
 }
 
 fn rotate(time: Res<TimeResource>, mut objects: Query<(&Rotate, &mut Transform)>) {
     let dt = time.update_dt.as_secs_f32();
-    for (Rotate { axis }, mut trans) in objects.iter_mut() {
-        let rot = UnitQuaternion::new(axis * dt);
-        trans.isometry.append_rotation_wrt_center_mut(&rot);
+    for (rotate, mut trans) in objects.iter_mut() {
+        match rotate.anchor {
+            Some((tick, rotation)) => {
+                let elapsed = time.ticks_since(tick) as f32 * dt;
+                trans.isometry.rotation = UnitQuaternion::new(rotate.axis * elapsed) * rotation;
+            }
+            None => {
+                let rot = UnitQuaternion::new(rotate.axis * dt);
+                trans.isometry.append_rotation_wrt_center_mut(&rot);
+            }
+        }
+    }
+}
+
+// Axes of everything rotate spins, only while the gizmos console toggle is on.
+fn draw_rotation_axes(mut debug: ResMut<DebugDraw>, objects: Query<&Transform, With<Rotate>>) {
+    if !debug.enabled {
+        return;
+    }
+
+    for trans in objects.iter() {
+        debug.axes(&trans.isometry, 1.0);
+    }
+}
+
+fn animate_textures(time: Res<TimeResource>, mut animated: Query<&mut AnimatedTexture>) {
+    let dt = time.update_dt.as_secs_f32();
+    for mut animated in animated.iter_mut() {
+        animated.progress += animated.fps * dt;
+
+        let frames = animated.progress.floor();
+        animated.frame = animated.frame.wrapping_add(frames as u32);
+        animated.progress -= frames;
     }
 }
 
@@ -243,3 +972,177 @@ fn rand_vec() -> Vector3<f32> {
 
     Vector3::new(r(), r(), r()).normalize()
 }
+
+// Fully saturated color at t around the color wheel, 0 and 1 are both red.
+fn hue_color(t: f32) -> Vector3<f32> {
+    use std::f32::consts::TAU;
+
+    let hue = t * TAU;
+    Vector3::new(
+        hue.cos() * 0.5 + 0.5,
+        (hue + TAU / 3.0).cos() * 0.5 + 0.5,
+        (hue + 2.0 * TAU / 3.0).cos() * 0.5 + 0.5,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_recording::{InputPlayer, InputRecorder};
+    use winit::{
+        dpi::PhysicalSize,
+        event::{ElementState, MouseButton},
+        window::WindowId,
+    };
+
+    // The parts of Game::dispatch_event that don't need a window.
+    fn input_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(Input::default());
+        world.insert_resource(CursorWorldPosition::new(
+            PlaneTarget::horizontal(0.0),
+            Vector2::new(800.0, 600.0),
+        ));
+        world
+            .spawn()
+            .insert(Camera::perspective(800.0 / 600.0, 1.0, 0.1, 100.0))
+            .insert(MainCamera);
+        world
+    }
+
+    fn dispatch(world: &mut World, recorded: &RecordedEvent) {
+        let mut inner_size = PhysicalSize::new(0, 0);
+        let event: Event<()> = Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event: recorded.to_window_event(&mut inner_size),
+        };
+
+        world.resource_mut::<Input>().handle_event(&event);
+        if let Event::WindowEvent { event, .. } = &event {
+            apply_window_input(world, event);
+        }
+    }
+
+    #[test]
+    fn input_playback_test() {
+        let path = std::env::temp_dir().join(format!("card_game_input_{}.ron", std::process::id()));
+
+        // resize while dragging, then click
+        let recording = [
+            RecordedEvent::CursorMoved { x: 100.0, y: 50.0 },
+            RecordedEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+            },
+            RecordedEvent::Resized {
+                width: 1280,
+                height: 720,
+            },
+            RecordedEvent::CursorMoved { x: 640.0, y: 360.0 },
+            RecordedEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+            },
+            RecordedEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+            },
+        ];
+        let mut recorder = InputRecorder::create(&path).unwrap();
+        for event in &recording {
+            recorder.record(event.clone());
+        }
+        drop(recorder);
+
+        let mut player = InputPlayer::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut world = input_world();
+        let mut played = 0;
+        let started = Instant::now();
+        while !player.finished() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "playback stalled"
+            );
+            for recorded in player.take_due() {
+                dispatch(&mut world, &recorded);
+                played += 1;
+            }
+        }
+        assert_eq!(played, recording.len());
+
+        let cursor = world.resource::<CursorWorldPosition>();
+        assert_eq!(cursor.window_size, Vector2::new(1280.0, 720.0));
+
+        let input = world.resource::<Input>();
+        assert_eq!(input.cursor_position(), Some(Vector2::new(640.0, 360.0)));
+        assert!(!input.mouse_buttons.pressed(MouseButton::Left));
+        assert!(input.mouse_buttons.just_released(MouseButton::Left));
+        assert!(input.mouse_buttons.pressed(MouseButton::Right));
+
+        let mut cameras = world.query_filtered::<&Camera, With<MainCamera>>();
+        let aspect = cameras.iter(&world).next().unwrap().projection.aspect();
+        assert!((aspect - 1280.0 / 720.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn resize_only_updates_main_camera_test() {
+        let resized = WindowEvent::Resized(PhysicalSize::new(1000, 500));
+
+        // no main camera at all
+        let mut world = World::new();
+        world.insert_resource(CursorWorldPosition::new(
+            PlaneTarget::horizontal(0.0),
+            Vector2::new(800.0, 600.0),
+        ));
+        apply_window_input(&mut world, &resized);
+        assert_eq!(
+            world.resource::<CursorWorldPosition>().window_size,
+            Vector2::new(1000.0, 500.0)
+        );
+
+        // a camera rendering elsewhere keeps its own aspect
+        let mut world = input_world();
+        let other = world
+            .spawn()
+            .insert(Camera::perspective(1.0, 1.0, 0.1, 100.0))
+            .id();
+        apply_window_input(&mut world, &resized);
+
+        let mut cameras = world.query_filtered::<&Camera, With<MainCamera>>();
+        let aspect = cameras.iter(&world).next().unwrap().projection.aspect();
+        assert!((aspect - 2.0).abs() < 1e-6);
+        let other = world.get::<Camera>(other).unwrap();
+        assert!((other.projection.aspect() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frame_limit_runs_shutdown_test() {
+        let directory =
+            std::env::temp_dir().join(format!("card_game_frames_{}", std::process::id()));
+        let mut world = World::new();
+        world.insert_resource(FrameLimit { remaining: 3 });
+        let mut store = ProfileStore::load(&directory);
+        store.profile_mut().name = "frames".to_owned();
+        world.insert_resource(store);
+
+        let mut frame_schedule = Schedule::default();
+        frame_schedule.add_stage(
+            "frame",
+            SystemStage::parallel().with_system(exit_after_frames),
+        );
+        for _ in 0..2 {
+            frame_schedule.run(&mut world);
+            assert!(!world.contains_resource::<AppExit>());
+        }
+        frame_schedule.run(&mut world);
+        assert!(world.contains_resource::<AppExit>());
+
+        // the unsaved change only reaches the file through flush_profile
+        shutdown_schedule().run(&mut world);
+        drop(world);
+        assert_eq!(ProfileStore::load(&directory).profile().name, "frames");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
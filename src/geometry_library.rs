@@ -1,26 +1,176 @@
-use std::{collections::HashMap, ops::Range, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use wgpu::{util::DeviceExt, BufferAddress, Device};
+use nalgebra::{Point3, Vector3, Vector4};
+use wgpu::{util::DeviceExt, Device, IndexFormat};
 
+use crate::culling::Aabb;
 use crate::data_types::Vertex as Vert;
+use crate::library_stats::{self, LibraryStats};
+use crate::primitives;
+use crate::texture_library::TextureHandle;
 
 use bytemuck::cast_slice;
 
+// Card dimensions in world units, shared by every card entity through CardGeometry.
+pub const CARD_WIDTH: f32 = 0.63;
+pub const CARD_HEIGHT: f32 = 0.88;
+pub const CARD_THICKNESS: f32 = 0.005;
+
+// Meshes built from code at load time instead of read from a file.
+#[derive(Clone, Copy, Debug)]
+pub enum GeneratedMesh {
+    Card,
+}
+
+impl GeneratedMesh {
+    fn build(self) -> (Vec<Vert>, Vec<u32>) {
+        match self {
+            GeneratedMesh::Card => primitives::rounded_card(
+                CARD_WIDTH,
+                CARD_HEIGHT,
+                0.03,
+                6,
+                CARD_THICKNESS,
+                Vert::WHITE,
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum GeometrySource {
+    File(&'static str),
+    Generated(GeneratedMesh),
+}
+
+// Meshes are expected to wind outward facing triangles counter clockwise, the obj default.
+#[derive(Clone, Copy, Debug)]
+pub struct GeometryDesc {
+    pub source: GeometrySource,
+    pub flip_winding: bool, // for legacy assets authored clockwise, ignored by generated meshes
+}
+
+impl GeometryDesc {
+    pub const fn file(path: &'static str) -> Self {
+        Self {
+            source: GeometrySource::File(path),
+            flip_winding: false,
+        }
+    }
+
+    pub const fn generated(mesh: GeneratedMesh) -> Self {
+        Self {
+            source: GeometrySource::Generated(mesh),
+            flip_winding: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub const fn flipped(self) -> Self {
+        Self {
+            flip_winding: true,
+            ..self
+        }
+    }
+}
+
 crate::macros::parallel_enum_values! {
     (
         GeometryId,
-        GEOMETRY_PATH_PAIRS,
-        str,
+        GEOMETRY_DESC_PAIRS,
+        GeometryDesc,
     )
-    TorusGeometry -> "model/torus.obj",
-    SceneTestGeometry -> "model/scene_test.obj",
+    TorusGeometry -> &GeometryDesc::file("model/torus.obj"),
+    SceneTestGeometry -> &GeometryDesc::file("model/scene_test.obj"),
+    CardGeometry -> &GeometryDesc::generated(GeneratedMesh::Card),
 }
 
-#[allow(dead_code)]
-pub struct MultiMeshData {
-    pub index_ranges: Vec<Range<BufferAddress>>,
-    pub vertices: wgpu::Buffer,
-    pub indices: wgpu::Buffer,
+// Part of a mesh drawn with one material, obj files have one per object or group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    pub indices: Range<u32>,
+    pub material: Option<usize>, // into MeshData::materials, None draws like an obj without an mtl
+}
+
+// A material from the obj's mtl file.
+#[derive(Clone, Debug)]
+pub struct MeshMaterial {
+    pub texture: Option<TextureHandle>, // the diffuse texture
+    pub diffuse: Vector3<f32>,          // multiplied into the tint of untextured materials
+}
+
+// Index types meshes can be uploaded with.
+pub trait MeshIndex: Copy + bytemuck::Pod {
+    const FORMAT: IndexFormat;
+
+    fn to_usize(self) -> usize;
+}
+
+impl MeshIndex for u16 {
+    const FORMAT: IndexFormat = IndexFormat::Uint16;
+
+    fn to_usize(self) -> usize {
+        self.into()
+    }
+}
+
+impl MeshIndex for u32 {
+    const FORMAT: IndexFormat = IndexFormat::Uint32;
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+// Bytes per index.
+pub fn index_size(format: IndexFormat) -> u64 {
+    match format {
+        IndexFormat::Uint16 => 2,
+        IndexFormat::Uint32 => 4,
+    }
+}
+
+// Index data in the smallest format holding every index, u16 halves the index memory of all but
+// the largest meshes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn new(indices: Vec<u32>) -> Self {
+        match indices.iter().all(|i| u16::try_from(*i).is_ok()) {
+            true => Self::U16(indices.iter().map(|i| *i as u16).collect()),
+            false => Self::U32(indices),
+        }
+    }
+
+    pub fn format(&self) -> IndexFormat {
+        match self {
+            Self::U16(_) => u16::FORMAT,
+            Self::U32(_) => u32::FORMAT,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U16(indices) => indices.len(),
+            Self::U32(indices) => indices.len(),
+        }
+    }
+
+    fn contents(&self) -> &[u8] {
+        match self {
+            Self::U16(indices) => cast_slice(indices),
+            Self::U32(indices) => cast_slice(indices),
+        }
+    }
 }
 
 pub struct MeshData {
@@ -28,12 +178,23 @@ pub struct MeshData {
     pub index_len: u32,
     pub vertices: wgpu::Buffer,
     pub indices: wgpu::Buffer,
+    pub index_format: IndexFormat,
+    pub bounds: Aabb, // model space
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<MeshMaterial>,
 }
 
-impl MeshData {
-    fn from_file(device: &Device, path: &Path) -> Self {
-        // TODO: use material data
-        let (models, _material) = tobj::load_obj(
+// Every model of an obj file in one vertex and index buffer, not yet on the gpu.
+struct ObjData {
+    vertices: Vec<Vert>,
+    indices: Vec<u32>, // narrowed by Indices on upload
+    submeshes: Vec<Submesh>,
+    materials: Vec<tobj::Material>,
+}
+
+impl ObjData {
+    fn load(path: &Path, flip_winding: bool) -> Result<Self, String> {
+        let (models, materials) = tobj::load_obj(
             path,
             &tobj::LoadOptions {
                 single_index: true,
@@ -42,45 +203,144 @@ impl MeshData {
                 ignore_lines: true,
             },
         )
-        .unwrap_or_else(|e| panic!("failed to open obj file {}: {}", path.display(), e));
+        .map_err(|e| format!("failed to open obj file {}: {}", path.display(), e))?;
 
-        let mesh = &models
-            .first()
-            .unwrap_or_else(|| panic!("failed to parse obj file no models {}", path.display()))
-            .mesh;
+        // the mesh still draws without its materials
+        let materials = materials.unwrap_or_else(|e| {
+            log::error!("failed to load materials of {}: {}", path.display(), e);
+            Vec::new()
+        });
 
-        let mut index_data: Vec<u16> = mesh
-            .indices
-            .iter()
-            .map(|i: &u32| {
-                (*i).try_into()
-                    .expect("obj file index out of bounds greater than 65536")
-            })
-            .collect();
+        if models.is_empty() {
+            return Err(format!(
+                "failed to parse obj file no models {}",
+                path.display()
+            ));
+        }
+
+        let mut data = Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            submeshes: Vec::new(),
+            materials,
+        };
+        for model in &models {
+            let mesh = &model.mesh;
+
+            let mut vertices = transmute_vertex_data(mesh);
+            let indices = index_data(mesh, flip_winding);
+            generate_tangents(&mut vertices, &indices);
+
+            // indices are offset into the shared vertex buffer
+            let base_vertex = data.vertices.len() as u32;
+            let start = data.indices.len() as u32;
+            data.indices.extend(indices.iter().map(|i| base_vertex + i));
+            data.vertices.extend(vertices);
+
+            data.submeshes.push(Submesh {
+                indices: start..data.indices.len() as u32,
+                material: mesh.material_id.filter(|id| *id < data.materials.len()),
+            });
+        }
 
-        reverse_indices(&mut index_data);
+        if data.vertices.is_empty() {
+            return Err(format!("obj file has no vertices {}", path.display()));
+        }
+
+        Ok(data)
+    }
+}
+
+// diffuse_texture as written in the mtl, relative to the obj.
+fn diffuse_texture_path(obj_path: &Path, material: &tobj::Material) -> Option<PathBuf> {
+    if material.diffuse_texture.is_empty() {
+        return None;
+    }
+
+    let directory = obj_path.parent().unwrap_or_else(|| Path::new(""));
+    Some(directory.join(&material.diffuse_texture))
+}
+
+impl MeshData {
+    // Size of the vertex and index buffers.
+    pub fn bytes(&self) -> u64 {
+        self.vertex_len as u64 * std::mem::size_of::<Vert>() as u64
+            + self.index_len as u64 * index_size(self.index_format)
+    }
 
-        let vertex_data: Vec<Vert> = transmute_vertex_data(mesh);
+    // Panics without vertices, a mesh always has bounds.
+    pub fn new(
+        device: &Device,
+        vertex_data: &[Vert],
+        index_data: Vec<u32>,
+        submeshes: Vec<Submesh>,
+        materials: Vec<MeshMaterial>,
+    ) -> Self {
+        let positions: Vec<Point3<f32>> = vertex_data
+            .iter()
+            .map(|v| Point3::from(v.position.xyz()))
+            .collect();
+        let bounds = Aabb::from_points(&positions).expect("mesh has no vertices");
 
         let vertices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: cast_slice(&vertex_data),
+            contents: cast_slice(vertex_data),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
+        let index_data = Indices::new(index_data);
         let indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: cast_slice(&index_data),
+            contents: index_data.contents(),
             usage: wgpu::BufferUsages::INDEX,
         });
 
         Self {
             vertices,
             indices,
+            index_format: index_data.format(),
             vertex_len: vertex_data.len() as u32,
             index_len: index_data.len() as u32,
+            bounds,
+            submeshes,
+            materials,
         }
     }
+
+    // load_texture gets the path of every diffuse texture the materials reference.
+    fn from_desc(
+        device: &Device,
+        desc: &GeometryDesc,
+        load_texture: &mut impl FnMut(&Path) -> TextureHandle,
+    ) -> Self {
+        let path = match desc.source {
+            GeometrySource::File(path) => Path::new(path),
+            GeometrySource::Generated(mesh) => {
+                let (vertices, indices) = mesh.build();
+                let submeshes = vec![Submesh {
+                    indices: 0..indices.len() as u32,
+                    material: None,
+                }];
+                return Self::new(device, &vertices, indices, submeshes, Vec::new());
+            }
+        };
+        let ObjData {
+            vertices: vertex_data,
+            indices: index_data,
+            submeshes,
+            materials,
+        } = ObjData::load(path, desc.flip_winding).unwrap_or_else(|e| panic!("{}", e));
+
+        let materials = materials
+            .iter()
+            .map(|material| MeshMaterial {
+                texture: diffuse_texture_path(path, material).map(|path| load_texture(&path)),
+                diffuse: material.diffuse.into(),
+            })
+            .collect();
+
+        Self::new(device, &vertex_data, index_data, submeshes, materials)
+    }
 }
 
 pub struct GeometryLibrary {
@@ -93,13 +353,36 @@ impl GeometryLibrary {
         todo!();
     }
 
-    pub fn load_all(device: &Device) -> Self {
-        let geometries = GEOMETRY_PATH_PAIRS
+    // load_texture is called for every texture the meshes' materials reference.
+    pub fn load_all(device: &Device, mut load_texture: impl FnMut(&Path) -> TextureHandle) -> Self {
+        let geometries = GEOMETRY_DESC_PAIRS
             .iter()
-            .map(|(id, g)| (*id, Arc::new(MeshData::from_file(device, Path::new(g)))))
+            .map(|(id, desc)| {
+                let mesh = MeshData::from_desc(device, desc, &mut load_texture);
+                (*id, Arc::new(mesh))
+            })
             .collect();
 
-        Self { geometries }
+        let library = Self { geometries };
+
+        let stats = library.stats();
+        log::info!(
+            "loaded {} meshes, {} of vertex and index buffers",
+            stats.count(),
+            library_stats::format_bytes(stats.total_bytes)
+        );
+
+        library
+    }
+
+    pub fn stats(&self) -> LibraryStats<GeometryId> {
+        LibraryStats::from_entries(self.geometries.iter().map(|(id, mesh)| (*id, mesh.bytes())))
+    }
+
+    // Swaps in a mesh built in a test for one of the ids.
+    #[cfg(test)]
+    pub fn replace(&mut self, id: GeometryId, mesh: MeshData) {
+        self.geometries.insert(id, Arc::new(mesh));
     }
 
     pub fn get(&self, id: GeometryId) -> &MeshData {
@@ -115,22 +398,221 @@ fn transmute_vertex_data(mesh: &tobj::Mesh) -> Vec<Vert> {
     // the creation of tobj mesh should create proper length data
     let p = mesh.positions.chunks(3);
     let n = mesh.normals.chunks(3);
-    let t = mesh.texcoords.chunks(2);
+    // meshes without uvs get zeros, they end up without tangents and skip normal mapping
+    let t = mesh
+        .texcoords
+        .chunks(2)
+        .chain(std::iter::repeat([0.0, 0.0].as_slice()));
+
+    // vertex colors are an obj extension, most files don't have them
+    let c = mesh
+        .vertex_color
+        .chunks(3)
+        .map(|c| [c[0], c[1], c[2], 1.0].into())
+        .chain(std::iter::repeat(Vert::WHITE));
 
     p.zip(n)
         .zip(t)
-        .map(|((p, n), t)| Vert {
+        .zip(c)
+        .map(|(((p, n), t), c)| Vert {
             position: [p[0], p[1], p[2], 1.0].into(),
             normal: [n[0], n[1], n[2], 0.0].into(),
             texture: [t[0], t[1]].into(),
+            color: c,
+            tangent: Vector4::zeros(),
         })
         .collect()
 }
 
-fn reverse_indices<T>(indices: &mut [T]) {
+// Per vertex tangents from the direction u increases in over each triangle, summed over every
+// triangle sharing the vertex and made perpendicular to its normal. w is the handedness of the
+// bitangent, the direction v increases in. Vertices whose triangles all have degenerate uvs keep
+// an all zero tangent, which the fragment shader takes as no normal mapping.
+pub fn generate_tangents<I: MeshIndex>(vertices: &mut [Vert], indices: &[I]) {
+    let mut tangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+    let mut bitangents = vec![Vector3::<f32>::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(I::to_usize);
+        let (va, vb, vc) = (&vertices[a], &vertices[b], &vertices[c]);
+
+        let e1 = (vb.position - va.position).xyz();
+        let e2 = (vc.position - va.position).xyz();
+        let d1 = vb.texture - va.texture;
+        let d2 = vc.texture - va.texture;
+
+        // infinite when the uvs collapse to a line or a point
+        let r = 1.0 / (d1.x * d2.y - d2.x * d1.y);
+        if !r.is_finite() {
+            continue;
+        }
+
+        let tangent = (e1 * d2.y - e2 * d1.y) * r;
+        let bitangent = (e2 * d1.x - e1 * d2.x) * r;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(&tangents).zip(&bitangents) {
+        let normal = vertex.normal.xyz();
+        let tangent = tangent - normal * normal.dot(tangent);
+
+        vertex.tangent = match tangent.try_normalize(1e-6) {
+            Some(tangent) => {
+                let handedness = if normal.cross(&tangent).dot(bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                tangent.push(handedness)
+            }
+            None => Vector4::zeros(),
+        };
+    }
+}
+
+// Indices as uploaded, reversing each triangle only when the asset asks for it.
+fn index_data(mesh: &tobj::Mesh, flip_winding: bool) -> Vec<u32> {
+    let mut indices = mesh.indices.clone();
+
+    if flip_winding {
+        reverse_indices(&mut indices);
+    }
+
+    indices
+}
+
+// Flips the winding of every triangle.
+pub fn reverse_indices<T>(indices: &mut [T]) {
     assert!(
         indices.len() % 3 == 0,
         "tried to reverse index data with incorrect length"
     );
     indices.chunks_mut(3).for_each(|a: &mut [T]| a.reverse());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(indices: &[u32]) -> tobj::Mesh {
+        tobj::Mesh {
+            indices: indices.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn winding_kept_by_default_test() {
+        let mesh = mesh(&[0, 1, 2, 2, 1, 3]);
+
+        assert_eq!(index_data(&mesh, false), [0, 1, 2, 2, 1, 3]);
+        assert_eq!(index_data(&mesh, true), [2, 1, 0, 3, 1, 2]);
+        assert!(GEOMETRY_DESC_PAIRS
+            .iter()
+            .all(|(_, desc)| !desc.flip_winding));
+    }
+
+    #[test]
+    fn card_mesh_size_test() {
+        let (vertices, _) = GeneratedMesh::Card.build();
+        let positions: Vec<Point3<f32>> = vertices
+            .iter()
+            .map(|v| Point3::from(v.position.xyz()))
+            .collect();
+        let bounds = Aabb::from_points(&positions).unwrap();
+
+        let size = bounds.max - bounds.min;
+        let expected = Vector3::new(CARD_WIDTH, CARD_HEIGHT, CARD_THICKNESS);
+        assert!((size - expected).norm() < 1e-5, "{}", size);
+    }
+
+    // two triangles in different objects, one with a textured material and one with a colored one
+    const TWO_MATERIALS_OBJ: &str = "mtllib two.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 0 1 1
+vt 0 0
+vt 1 0
+vt 0 1
+vn 0 0 1
+o Textured
+usemtl brick
+f 1/1/1 2/2/1 3/3/1
+o Plain
+usemtl red
+f 4/1/1 5/2/1 6/3/1
+";
+    const TWO_MATERIALS_MTL: &str = "newmtl brick
+Kd 1 1 1
+map_Kd textures/brick.png
+newmtl red
+Kd 1 0 0
+";
+
+    #[test]
+    fn submesh_per_material_test() {
+        let directory = std::env::temp_dir().join(format!("card_game_mtl_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let obj_path = directory.join("two.obj");
+        std::fs::write(&obj_path, TWO_MATERIALS_OBJ).unwrap();
+        std::fs::write(directory.join("two.mtl"), TWO_MATERIALS_MTL).unwrap();
+
+        let data = ObjData::load(&obj_path, false);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let data = data.unwrap();
+
+        assert_eq!(data.vertices.len(), 6);
+        // the second object's indices point past the first one's vertices
+        assert_eq!(data.indices, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            data.submeshes,
+            [
+                Submesh {
+                    indices: 0..3,
+                    material: Some(0),
+                },
+                Submesh {
+                    indices: 3..6,
+                    material: Some(1),
+                },
+            ]
+        );
+
+        let (brick, red) = (&data.materials[0], &data.materials[1]);
+        assert_eq!(
+            diffuse_texture_path(&obj_path, brick),
+            Some(directory.join("textures/brick.png"))
+        );
+        assert_eq!(diffuse_texture_path(&obj_path, red), None);
+        assert_eq!(red.diffuse, [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn indices_narrowed_when_they_fit_test() {
+        let small = Indices::new(vec![0, 1, 65535]);
+        assert_eq!(small, Indices::U16(vec![0, 1, 65535]));
+        assert_eq!(small.format(), IndexFormat::Uint16);
+        assert_eq!(small.contents().len(), 6);
+
+        let large = Indices::new(vec![0, 1, 65536]);
+        assert_eq!(large, Indices::U32(vec![0, 1, 65536]));
+        assert_eq!(large.format(), IndexFormat::Uint32);
+        assert_eq!(large.contents().len(), 12);
+    }
+
+    #[test]
+    fn reverse_indices_test() {
+        let mut indices = [0, 1, 2, 3, 4, 5];
+        reverse_indices(&mut indices);
+        assert_eq!(indices, [2, 1, 0, 5, 4, 3]);
+
+        reverse_indices(&mut indices);
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+    }
+}
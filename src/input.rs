@@ -0,0 +1,273 @@
+use std::{collections::HashSet, hash::Hash};
+
+use bevy_ecs::world::World;
+use nalgebra::Vector2;
+use winit::event::{
+    DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+// pixel scroll deltas from touchpads are converted to lines at this rate
+const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+
+// Held state plus the transitions since the last update tick.
+#[derive(Clone, Debug)]
+pub struct ButtonInput<T> {
+    pressed: HashSet<T>,
+    just_pressed: HashSet<T>,
+    just_released: HashSet<T>,
+}
+
+impl<T> Default for ButtonInput<T> {
+    fn default() -> Self {
+        Self {
+            pressed: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> ButtonInput<T> {
+    // Key repeat sends more presses for a held key, those don't count as new presses.
+    pub fn press(&mut self, button: T) {
+        if self.pressed.insert(button) {
+            self.just_pressed.insert(button);
+        }
+    }
+
+    pub fn release(&mut self, button: T) {
+        if self.pressed.remove(&button) {
+            self.just_released.insert(button);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.just_released.extend(self.pressed.drain());
+    }
+
+    pub fn pressed(&self, button: T) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    // Pressed since the last update tick. A press and release within one tick sets both this and
+    // just_released.
+    pub fn just_pressed(&self, button: T) -> bool {
+        self.just_pressed.contains(&button)
+    }
+
+    pub fn just_released(&self, button: T) -> bool {
+        self.just_released.contains(&button)
+    }
+
+    fn clear_transitions(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+// Resource collecting keyboard and mouse state from winit events for update systems. Fed by
+// Game::dispatch_event, transitions and deltas cover everything since the previous update tick.
+#[derive(Default)]
+pub struct Input {
+    pub keys: ButtonInput<VirtualKeyCode>,
+    pub mouse_buttons: ButtonInput<MouseButton>,
+
+    cursor_position: Option<Vector2<f32>>, // physical pixels, None outside the window
+    mouse_delta: Vector2<f32>,             // raw device motion, not affected by cursor grab
+    scroll: Vector2<f32>,                  // in lines
+
+    pub cursor_grabbed: bool,
+}
+
+impl Input {
+    pub fn handle_event<E>(&mut self, event: &Event<E>) {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } => match state {
+                    ElementState::Pressed => self.keys.press(*key),
+                    ElementState::Released => self.keys.release(*key),
+                },
+                WindowEvent::MouseInput { state, button, .. } => match state {
+                    ElementState::Pressed => self.mouse_buttons.press(*button),
+                    ElementState::Released => self.mouse_buttons.release(*button),
+                },
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = Some(Vector2::new(position.x as f32, position.y as f32));
+                }
+                WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+                WindowEvent::MouseWheel { delta, .. } => {
+                    self.scroll += match delta {
+                        MouseScrollDelta::LineDelta(x, y) => Vector2::new(*x, *y),
+                        MouseScrollDelta::PixelDelta(p) => {
+                            Vector2::new(p.x as f32, p.y as f32) / PIXELS_PER_SCROLL_LINE
+                        }
+                    };
+                }
+                // release events are never delivered to an unfocused window
+                WindowEvent::Focused(false) => {
+                    self.keys.release_all();
+                    self.mouse_buttons.release_all();
+                }
+                _ => (),
+            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                self.mouse_delta += Vector2::new(delta.0 as f32, delta.1 as f32);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys.pressed(key)
+    }
+
+    pub fn just_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys.just_pressed(key)
+    }
+
+    pub fn just_released(&self, key: VirtualKeyCode) -> bool {
+        self.keys.just_released(key)
+    }
+
+    // -1, 0 or 1 depending on which of the two keys are held.
+    pub fn axis(&self, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        self.pressed(positive) as i32 as f32 - self.pressed(negative) as i32 as f32
+    }
+
+    pub fn cursor_position(&self) -> Option<Vector2<f32>> {
+        self.cursor_position
+    }
+
+    pub fn mouse_delta(&self) -> Vector2<f32> {
+        self.mouse_delta
+    }
+
+    pub fn scroll(&self) -> Vector2<f32> {
+        self.scroll
+    }
+
+    // Called after every update tick. Anything arriving while no tick runs shows up in the next.
+    pub fn end_tick(&mut self) {
+        self.keys.clear_transitions();
+        self.mouse_buttons.clear_transitions();
+        self.mouse_delta = Vector2::zeros();
+        self.scroll = Vector2::zeros();
+    }
+}
+
+// Exclusive system, runs last in the update stage.
+pub fn end_input_tick(world: &mut World) {
+    world.resource_mut::<Input>().end_tick();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::{
+        event::{DeviceId, ModifiersState},
+        window::WindowId,
+    };
+
+    fn window_event(event: WindowEvent<'static>) -> Event<'static, ()> {
+        Event::WindowEvent {
+            window_id: unsafe { WindowId::dummy() },
+            event,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> Event<'static, ()> {
+        window_event(WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: ModifiersState::empty(),
+            },
+            is_synthetic: false,
+        })
+    }
+
+    const W: VirtualKeyCode = VirtualKeyCode::W;
+
+    #[test]
+    fn press_and_release_across_ticks_test() {
+        let mut input = Input::default();
+
+        input.handle_event(&key(W, ElementState::Pressed));
+        assert!(input.pressed(W) && input.just_pressed(W) && !input.just_released(W));
+
+        input.end_tick();
+        assert!(input.pressed(W) && !input.just_pressed(W));
+
+        // key repeat while held
+        input.handle_event(&key(W, ElementState::Pressed));
+        assert!(input.pressed(W) && !input.just_pressed(W));
+
+        input.end_tick();
+        input.handle_event(&key(W, ElementState::Released));
+        assert!(!input.pressed(W) && input.just_released(W));
+
+        input.end_tick();
+        assert!(!input.pressed(W) && !input.just_pressed(W) && !input.just_released(W));
+    }
+
+    #[test]
+    fn tap_within_one_tick_test() {
+        let mut input = Input::default();
+
+        input.handle_event(&key(W, ElementState::Pressed));
+        input.handle_event(&key(W, ElementState::Released));
+        assert!(!input.pressed(W));
+        assert!(input.just_pressed(W) && input.just_released(W));
+    }
+
+    #[test]
+    fn release_without_press_test() {
+        let mut input = Input::default();
+
+        input.handle_event(&key(W, ElementState::Released));
+        assert!(!input.just_released(W));
+    }
+
+    #[test]
+    fn focus_loss_releases_everything_test() {
+        let mut input = Input::default();
+        input.handle_event(&key(W, ElementState::Pressed));
+        input.mouse_buttons.press(MouseButton::Left);
+        input.end_tick();
+
+        input.handle_event(&window_event(WindowEvent::Focused(false)));
+        assert!(!input.pressed(W) && input.just_released(W));
+        assert!(input.mouse_buttons.just_released(MouseButton::Left));
+    }
+
+    #[test]
+    fn deltas_reset_every_tick_test() {
+        let mut input = Input::default();
+        let motion = |x, y| Event::DeviceEvent {
+            device_id: unsafe { DeviceId::dummy() },
+            event: DeviceEvent::MouseMotion { delta: (x, y) },
+        };
+
+        input.handle_event::<()>(&motion(3.0, 1.0));
+        input.handle_event::<()>(&motion(2.0, -4.0));
+        assert_eq!(input.mouse_delta(), Vector2::new(5.0, -3.0));
+
+        input.end_tick();
+        assert_eq!(input.mouse_delta(), Vector2::zeros());
+    }
+}
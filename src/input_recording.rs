@@ -0,0 +1,216 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{
+        DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+        TouchPhase, WindowEvent,
+    },
+};
+
+// Low level recording of window input for reproducing renderer and window bugs. Unlike a gameplay
+// replay this captures winit events as they arrive, one RON entry per line so a recording survives
+// the game crashing halfway through.
+
+// Serializable mirror of the WindowEvent variants worth recording.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    Resized {
+        width: u32,
+        height: u32,
+    },
+    ScaleFactorChanged {
+        scale_factor: f64,
+        width: u32,
+        height: u32,
+    },
+    Focused(bool),
+    KeyboardInput(KeyboardInput),
+    ModifiersChanged(ModifiersState),
+    ReceivedCharacter(char),
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    CursorEntered,
+    CursorLeft,
+    MouseWheel(MouseScrollDelta),
+    MouseInput {
+        state: ElementState,
+        button: MouseButton,
+    },
+}
+
+impl RecordedEvent {
+    // None for events that are not recorded, those are never suppressed during playback either.
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        Some(match event {
+            WindowEvent::Resized(size) => Self::Resized {
+                width: size.width,
+                height: size.height,
+            },
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => Self::ScaleFactorChanged {
+                scale_factor: *scale_factor,
+                width: new_inner_size.width,
+                height: new_inner_size.height,
+            },
+            WindowEvent::Focused(focused) => Self::Focused(*focused),
+            WindowEvent::KeyboardInput { input, .. } => Self::KeyboardInput(*input),
+            WindowEvent::ModifiersChanged(modifiers) => Self::ModifiersChanged(*modifiers),
+            WindowEvent::ReceivedCharacter(c) => Self::ReceivedCharacter(*c),
+            WindowEvent::CursorMoved { position, .. } => Self::CursorMoved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::CursorEntered { .. } => Self::CursorEntered,
+            WindowEvent::CursorLeft { .. } => Self::CursorLeft,
+            WindowEvent::MouseWheel { delta, .. } => Self::MouseWheel(*delta),
+            WindowEvent::MouseInput { state, button, .. } => Self::MouseInput {
+                state: *state,
+                button: *button,
+            },
+            _ => return None,
+        })
+    }
+
+    // ScaleFactorChanged borrows its size mutably, inner_size provides the storage for it.
+    #[allow(deprecated)]
+    pub fn to_window_event<'a>(&self, inner_size: &'a mut PhysicalSize<u32>) -> WindowEvent<'a> {
+        // playback doesn't distinguish between devices
+        let device_id = unsafe { DeviceId::dummy() };
+
+        match self {
+            Self::Resized { width, height } => {
+                WindowEvent::Resized(PhysicalSize::new(*width, *height))
+            }
+            Self::ScaleFactorChanged {
+                scale_factor,
+                width,
+                height,
+            } => {
+                *inner_size = PhysicalSize::new(*width, *height);
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor: *scale_factor,
+                    new_inner_size: inner_size,
+                }
+            }
+            Self::Focused(focused) => WindowEvent::Focused(*focused),
+            Self::KeyboardInput(input) => WindowEvent::KeyboardInput {
+                device_id,
+                input: *input,
+                is_synthetic: false,
+            },
+            Self::ModifiersChanged(modifiers) => WindowEvent::ModifiersChanged(*modifiers),
+            Self::ReceivedCharacter(c) => WindowEvent::ReceivedCharacter(*c),
+            Self::CursorMoved { x, y } => WindowEvent::CursorMoved {
+                device_id,
+                position: PhysicalPosition::new(*x, *y),
+                modifiers: ModifiersState::empty(),
+            },
+            Self::CursorEntered => WindowEvent::CursorEntered { device_id },
+            Self::CursorLeft => WindowEvent::CursorLeft { device_id },
+            Self::MouseWheel(delta) => WindowEvent::MouseWheel {
+                device_id,
+                delta: *delta,
+                phase: TouchPhase::Moved,
+                modifiers: ModifiersState::empty(),
+            },
+            Self::MouseInput { state, button } => WindowEvent::MouseInput {
+                device_id,
+                state: *state,
+                button: *button,
+                modifiers: ModifiersState::empty(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub offset: Duration, // wall clock time since recording started
+    pub event: RecordedEvent,
+}
+
+pub struct InputRecorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: RecordedEvent) {
+        let entry = RecordedInput {
+            offset: self.start.elapsed(),
+            event,
+        };
+
+        let result = ron::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| {
+                writeln!(self.file, "{}", line)
+                    .and_then(|_| self.file.flush())
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            log::error!("failed to record input event: {}", e);
+        }
+    }
+}
+
+pub struct InputPlayer {
+    events: VecDeque<RecordedInput>,
+    start: Option<Instant>, // set on the first take_due so loading time isn't counted
+}
+
+impl InputPlayer {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+        let events = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| ron::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            events,
+            start: None,
+        })
+    }
+
+    // Events whose recorded offset has passed, in recorded order.
+    pub fn take_due(&mut self) -> Vec<RecordedEvent> {
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+
+        let mut due = Vec::new();
+        while let Some(entry) = self.events.front() {
+            if entry.offset > elapsed {
+                break;
+            }
+            due.push(self.events.pop_front().unwrap().event);
+        }
+
+        due
+    }
+
+    pub fn finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
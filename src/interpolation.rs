@@ -0,0 +1,68 @@
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    query::{Or, With, Without},
+    system::{Commands, Query},
+};
+use nalgebra::{Isometry3, Vector3};
+
+use crate::{
+    common_component::{MainCamera, RenderGeometry, Transform},
+    math::Interpolate,
+};
+
+// Transform as of the previous fixed update, kept for entities the renderer draws or views from.
+// Removing it makes a teleported entity snap instead of sliding for one update.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct PreviousTransform {
+    pub isometry: Isometry3<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl PreviousTransform {
+    pub fn from_transform(transform: &Transform) -> Self {
+        Self {
+            isometry: transform.isometry,
+            scale: transform.scale,
+        }
+    }
+}
+
+// Pose to draw at blend (TimeResource::blend) between the previous and current update. Entities
+// that appeared this update have no previous transform and are drawn where they are.
+pub fn interpolated(
+    previous: Option<&PreviousTransform>,
+    current: &Transform,
+    blend: f32,
+) -> (Isometry3<f32>, Vector3<f32>) {
+    match previous {
+        Some(previous) => (
+            previous.isometry.interpolate(&current.isometry, blend),
+            previous.scale.interpolate(&current.scale, blend),
+        ),
+        None => (current.isometry, current.scale),
+    }
+}
+
+// Rendered entities that don't have a previous transform yet.
+type UntrackedFilter = (
+    Without<PreviousTransform>,
+    Or<(With<RenderGeometry>, With<MainCamera>)>,
+);
+
+// Runs first in every fixed update, before anything moves.
+pub fn store_previous_transforms(
+    mut commands: Commands,
+    mut tracked: Query<(&Transform, &mut PreviousTransform)>,
+    untracked: Query<(Entity, &Transform), UntrackedFilter>,
+) {
+    for (transform, mut previous) in tracked.iter_mut() {
+        *previous = PreviousTransform::from_transform(transform);
+    }
+
+    for (entity, transform) in untracked.iter() {
+        commands
+            .entity(entity)
+            .insert(PreviousTransform::from_transform(transform));
+    }
+}
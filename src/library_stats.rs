@@ -0,0 +1,147 @@
+use bevy_ecs::system::{Res, ResMut};
+
+use crate::{
+    geometry_library::GeometryId, render_system::RenderState, texture_library::TextureHandle,
+};
+
+// Gpu memory held by the entries of one library, largest first. Sizes are computed from the data
+// uploaded, drivers add their own alignment and padding on top.
+#[derive(Clone, Debug)]
+pub struct LibraryStats<K> {
+    pub total_bytes: u64,
+    pub entries: Vec<(K, u64)>,
+}
+
+impl<K> Default for LibraryStats<K> {
+    fn default() -> Self {
+        Self {
+            total_bytes: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<K> LibraryStats<K> {
+    pub fn from_entries(entries: impl Iterator<Item = (K, u64)>) -> Self {
+        let mut entries: Vec<_> = entries.collect();
+        entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Self {
+            total_bytes: entries.iter().map(|(_, bytes)| bytes).sum(),
+            entries,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+// Size of a mip chain over all layers, levels stop at 1x1 like the loaders expect.
+pub fn texture_bytes(
+    width: u32,
+    height: u32,
+    layers: u32,
+    levels: u32,
+    bytes_per_texel: u32,
+) -> u64 {
+    (0..levels)
+        .map(|level| {
+            let width = (width >> level).max(1) as u64;
+            let height = (height >> level).max(1) as u64;
+            width * height
+        })
+        .sum::<u64>()
+        * layers as u64
+        * bytes_per_texel as u64
+}
+
+// 1.5 MiB style sizes for logs.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// Resource with the current library stats for debug displays, refreshed by update_memory_stats
+// whenever the libraries change.
+#[derive(Clone, Debug, Default)]
+pub struct GpuMemoryStats {
+    pub textures: LibraryStats<TextureHandle>,
+    pub geometry: LibraryStats<GeometryId>,
+
+    texture_revision: Option<u64>, // None until the first refresh
+}
+
+impl GpuMemoryStats {
+    pub fn total_bytes(&self) -> u64 {
+        self.textures.total_bytes + self.geometry.total_bytes
+    }
+}
+
+// Textures keep arriving from the loader thread and hot reload, geometry only changes with the
+// RenderState so a new state is refreshed as well.
+pub fn update_memory_stats(state: Res<RenderState>, mut stats: ResMut<GpuMemoryStats>) {
+    let revision = state.texture_library().revision();
+    if !state.is_added() && stats.texture_revision == Some(revision) {
+        return;
+    }
+
+    stats.textures = state.texture_library().stats();
+    stats.geometry = state.geometry_library().stats();
+    stats.texture_revision = Some(revision);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_bytes_test() {
+        // 256x256 rgba8, no mips
+        assert_eq!(texture_bytes(256, 256, 1, 1, 4), 262_144);
+        // full chain down to 1x1 adds about a third
+        assert_eq!(texture_bytes(256, 256, 1, 9, 4), 4 * 87_381);
+        // every level is counted once per layer
+        assert_eq!(texture_bytes(256, 256, 6, 9, 4), 6 * 4 * 87_381);
+    }
+
+    #[test]
+    fn non_square_mip_chain_test() {
+        // 8x2, 4x1, 2x1, 1x1, the short side stops at 1
+        assert_eq!(texture_bytes(8, 2, 1, 4, 4), 4 * (16 + 4 + 2 + 1));
+        // levels past 1x1 stay 1x1
+        assert_eq!(texture_bytes(1, 1, 1, 3, 4), 4 * 3);
+    }
+
+    #[test]
+    fn entries_sorted_largest_first_test() {
+        let stats = LibraryStats::from_entries([("a", 10), ("b", 300), ("c", 20)].into_iter());
+
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.total_bytes, 330);
+        assert_eq!(stats.entries, [("b", 300), ("c", 20), ("a", 10)]);
+    }
+
+    #[test]
+    fn format_bytes_test() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(4 * 87_381), "341.3 KiB");
+        assert_eq!(format_bytes(3 << 30), "3.0 GiB");
+        // nothing past GiB
+        assert_eq!(format_bytes(2048 << 30), "2048.0 GiB");
+    }
+}
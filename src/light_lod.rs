@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+
+use bevy_ecs::entity::Entity;
+use nalgebra::{Point3, Vector3};
+
+use crate::common_component::{PointLight, SpotLight, Transform};
+use crate::culling::Frustum;
+
+// Picks which point and spot lights reach the gpu each frame. Lights that can't reach anything on
+// screen are dropped, the rest fade out between fade_start and cull_distance so they never pop, and
+// when more survive than the budget allows the most important ones are kept.
+pub struct LightLod {
+    pub fade_start: f32,
+    pub cull_distance: f32,
+
+    // importance bonus for lights submitted last frame, stops lights near the budget boundary
+    // from swapping places every frame
+    pub hysteresis: f32,
+
+    // entity ids submitted last frame, kept sorted
+    previous_point_lights: Vec<u32>,
+    previous_spot_lights: Vec<u32>,
+
+    // index of each point light entity id along a Morton curve, see light_assignment_prepass.
+    // Selected point lights are submitted in this order, lights missing from it go last.
+    point_light_rank: HashMap<u32, usize>,
+}
+
+impl Default for LightLod {
+    fn default() -> Self {
+        Self {
+            fade_start: 30.0,
+            cull_distance: 40.0,
+            hysteresis: 0.1,
+            previous_point_lights: Vec::new(),
+            previous_spot_lights: Vec::new(),
+            point_light_rank: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LightLodStats {
+    pub total: usize,
+    pub faded: usize,  // partially faded but still submitted when within budget
+    pub culled: usize, // beyond cull_distance
+    pub outside_frustum: usize, // radius doesn't reach into the view
+    pub submitted: usize,
+}
+
+pub struct LightCandidate<T> {
+    pub id: u32,
+    pub importance: f32,
+    pub data: T,
+}
+
+// Lights whose power gets scaled by the distance fade.
+pub trait FadeLight: Copy {
+    fn power_mut(&mut self) -> &mut f32;
+
+    // sphere of influence around the light's position, a spot light's cone lies inside it
+    fn radius(&self) -> f32;
+}
+
+impl FadeLight for PointLight {
+    fn power_mut(&mut self) -> &mut f32 {
+        &mut self.power
+    }
+
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+}
+
+impl FadeLight for SpotLight {
+    fn power_mut(&mut self) -> &mut f32 {
+        &mut self.power
+    }
+
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+}
+
+// Rough on screen contribution, power falls off with the square of the distance. Distances below
+// one unit are clamped so lights right at the camera don't dominate everything.
+pub fn light_importance(power: f32, distance: f32) -> f32 {
+    power / distance.max(1.0).powi(2)
+}
+
+// 1 up to fade_start, 0 from cull_distance on and smoothstepped in between.
+pub fn distance_fade(distance: f32, fade_start: f32, cull_distance: f32) -> f32 {
+    if distance >= cull_distance {
+        return 0.0;
+    }
+    if distance <= fade_start {
+        return 1.0;
+    }
+
+    let t = (cull_distance - distance) / (cull_distance - fade_start);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Keeps the budget most important candidates and writes their data to out. previous holds the
+// sorted ids submitted last frame and is replaced by this frame's selection. Ties are broken by
+// entity id so the result doesn't depend on query iteration order. With a rank the selected lights
+// are written in rank order, otherwise by importance.
+pub fn select_lights<T: Copy>(
+    candidates: &mut [LightCandidate<T>],
+    budget: usize,
+    hysteresis: f32,
+    previous: &mut Vec<u32>,
+    rank: Option<&HashMap<u32, usize>>,
+    out: &mut Vec<T>,
+) {
+    let score = |c: &LightCandidate<T>| {
+        if previous.binary_search(&c.id).is_ok() {
+            c.importance * (1.0 + hysteresis)
+        } else {
+            c.importance
+        }
+    };
+
+    candidates.sort_by(|a, b| {
+        score(b)
+            .partial_cmp(&score(a))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.id.cmp(&b.id))
+    });
+
+    let count = budget.min(candidates.len());
+    let selected = &mut candidates[..count];
+    if let Some(rank) = rank {
+        selected.sort_by_key(|c| (rank.get(&c.id).copied().unwrap_or(usize::MAX), c.id));
+    }
+
+    previous.clear();
+    previous.extend(selected.iter().map(|c| c.id));
+    previous.sort_unstable();
+
+    out.extend(selected.iter().map(|c| c.data));
+}
+
+impl LightLod {
+    pub fn set_point_light_order(&mut self, ids: impl IntoIterator<Item = u32>) {
+        self.point_light_rank.clear();
+        self.point_light_rank
+            .extend(ids.into_iter().enumerate().map(|(rank, id)| (id, rank)));
+    }
+
+    pub fn fade(&self, distance: f32) -> f32 {
+        distance_fade(distance, self.fade_start, self.cull_distance)
+    }
+
+    // Drops lights outside the frustum, fades the rest by their distance to the camera and
+    // collects the ones still visible.
+    pub fn gather<'a, L, T>(
+        &self,
+        camera: &Vector3<f32>,
+        frustum: &Frustum,
+        lights: impl Iterator<Item = (Entity, &'a L, &'a Transform)>,
+        candidates: &mut Vec<LightCandidate<T>>,
+        stats: &mut LightLodStats,
+    ) where
+        L: FadeLight + 'a,
+        T: for<'b> From<(&'b L, &'b Transform)>,
+    {
+        for (entity, light, transform) in lights {
+            stats.total += 1;
+
+            let position = Point3::from(transform.isometry.translation.vector);
+            if !frustum.intersects_sphere(&position, light.radius()) {
+                stats.outside_frustum += 1;
+                continue;
+            }
+
+            let distance = (transform.isometry.translation.vector - camera).norm();
+            let fade = self.fade(distance);
+            if fade <= 0.0 {
+                stats.culled += 1;
+                continue;
+            }
+            if fade < 1.0 {
+                stats.faded += 1;
+            }
+
+            let mut light = *light;
+            *light.power_mut() *= fade;
+
+            candidates.push(LightCandidate {
+                id: entity.id(),
+                importance: light_importance(*light.power_mut(), distance),
+                data: (&light, transform).into(),
+            });
+        }
+    }
+
+    pub fn select_point_lights<T: Copy>(
+        &mut self,
+        candidates: &mut [LightCandidate<T>],
+        budget: usize,
+        out: &mut Vec<T>,
+    ) {
+        select_lights(
+            candidates,
+            budget,
+            self.hysteresis,
+            &mut self.previous_point_lights,
+            Some(&self.point_light_rank),
+            out,
+        );
+    }
+
+    pub fn select_spot_lights<T: Copy>(
+        &mut self,
+        candidates: &mut [LightCandidate<T>],
+        budget: usize,
+        out: &mut Vec<T>,
+    ) {
+        select_lights(
+            candidates,
+            budget,
+            self.hysteresis,
+            &mut self.previous_spot_lights,
+            None,
+            out,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_types;
+    use nalgebra::{Isometry3, Matrix4};
+
+    fn candidate(id: u32, importance: f32) -> LightCandidate<u32> {
+        LightCandidate {
+            id,
+            importance,
+            data: id,
+        }
+    }
+
+    fn select(
+        importances: &[(u32, f32)],
+        budget: usize,
+        hysteresis: f32,
+        previous: &mut Vec<u32>,
+    ) -> Vec<u32> {
+        let mut candidates: Vec<_> = importances
+            .iter()
+            .map(|(id, importance)| candidate(*id, *importance))
+            .collect();
+        let mut out = Vec::new();
+        select_lights(
+            &mut candidates,
+            budget,
+            hysteresis,
+            previous,
+            None,
+            &mut out,
+        );
+        out
+    }
+
+    #[test]
+    fn importance_test() {
+        // four times the power twice as far away is just as bright
+        assert_eq!(light_importance(1.0, 2.0), light_importance(4.0, 4.0));
+        assert!(light_importance(1.0, 2.0) > light_importance(1.0, 3.0));
+        // clamped below one unit
+        assert_eq!(light_importance(1.0, 0.01), light_importance(1.0, 1.0));
+    }
+
+    #[test]
+    fn most_important_lights_selected_test() {
+        let lights = [(1, 0.5), (2, 3.0), (3, 0.1), (4, 2.0), (5, 1.0)];
+        let mut previous = Vec::new();
+
+        assert_eq!(select(&lights, 3, 0.0, &mut previous), [2, 4, 5]);
+        // previous is replaced by the selection, sorted
+        assert_eq!(previous, [2, 4, 5]);
+
+        assert_eq!(select(&lights, 10, 0.0, &mut Vec::new()).len(), 5);
+        assert!(select(&lights, 0, 0.0, &mut Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn ties_broken_by_id_test() {
+        let mut previous = Vec::new();
+        assert_eq!(
+            select(&[(7, 1.0), (3, 1.0), (5, 1.0)], 2, 0.0, &mut previous),
+            [3, 5]
+        );
+        assert_eq!(
+            select(&[(5, 1.0), (7, 1.0), (3, 1.0)], 2, 0.0, &mut Vec::new()),
+            [3, 5]
+        );
+    }
+
+    #[test]
+    fn hysteresis_keeps_previous_lights_test() {
+        let mut previous = Vec::new();
+        assert_eq!(select(&[(1, 1.0), (2, 0.9)], 1, 0.1, &mut previous), [1]);
+
+        // 2 edges ahead but not by more than the hysteresis, 1 stays
+        assert_eq!(select(&[(1, 1.0), (2, 1.05)], 1, 0.1, &mut previous), [1]);
+        // clearly ahead, it takes over and now gets the bonus itself
+        assert_eq!(select(&[(1, 1.0), (2, 1.2)], 1, 0.1, &mut previous), [2]);
+        assert_eq!(select(&[(1, 1.05), (2, 1.0)], 1, 0.1, &mut previous), [2]);
+
+        // without hysteresis the order flips every time
+        let mut previous = vec![1];
+        assert_eq!(select(&[(1, 1.0), (2, 1.05)], 1, 0.0, &mut previous), [2]);
+    }
+
+    #[test]
+    fn rank_orders_selection_test() {
+        let mut candidates: Vec<_> = [(1, 3.0), (2, 2.0), (3, 1.0), (4, 0.5)]
+            .iter()
+            .map(|(id, importance)| candidate(*id, *importance))
+            .collect();
+        // 1 is missing from the order and goes last
+        let rank = HashMap::from([(3, 0), (2, 1), (4, 2)]);
+
+        let mut out = Vec::new();
+        select_lights(
+            &mut candidates,
+            3,
+            0.0,
+            &mut Vec::new(),
+            Some(&rank),
+            &mut out,
+        );
+        assert_eq!(out, [3, 2, 1]);
+    }
+
+    #[test]
+    fn distance_fade_test() {
+        assert_eq!(distance_fade(0.0, 30.0, 40.0), 1.0);
+        assert_eq!(distance_fade(30.0, 30.0, 40.0), 1.0);
+        assert_eq!(distance_fade(35.0, 30.0, 40.0), 0.5);
+        assert_eq!(distance_fade(40.0, 30.0, 40.0), 0.0);
+        assert_eq!(distance_fade(100.0, 30.0, 40.0), 0.0);
+
+        let faded = [31.0, 33.0, 36.0, 39.0].map(|d| distance_fade(d, 30.0, 40.0));
+        assert!(faded.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn gather_fades_and_culls_test() {
+        let lod = LightLod::default();
+        // identity clip space, everything from (-1, -1, 0) to (1, 1, 1) is visible
+        let frustum = Frustum::from_view_projection(&Matrix4::identity());
+        let light = PointLight {
+            color: Vector3::repeat(1.0),
+            power: 2.0,
+            radius: 50.0,
+        };
+        let at = |z: f32| Transform {
+            isometry: Isometry3::translation(0.0, 0.0, z),
+            scale: Vector3::repeat(1.0),
+            parent: None,
+            children: vec![],
+        };
+
+        // near, fading, beyond the cull distance and outside the frustum even with its radius
+        let transforms = [at(0.5), at(35.0), at(45.0), at(-60.0)];
+        let lights = transforms
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (Entity::from_raw(i as u32), &light, t));
+
+        let mut candidates = Vec::new();
+        let mut stats = LightLodStats::default();
+        lod.gather::<_, data_types::PointLight>(
+            &Vector3::zeros(),
+            &frustum,
+            lights,
+            &mut candidates,
+            &mut stats,
+        );
+
+        assert_eq!(
+            (
+                stats.total,
+                stats.faded,
+                stats.culled,
+                stats.outside_frustum
+            ),
+            (4, 1, 1, 1)
+        );
+        let powers: Vec<_> = candidates.iter().map(|c| (c.id, c.data.color.w)).collect();
+        assert_eq!(powers, [(0, 2.0), (1, 1.0)]);
+    }
+}
@@ -1,27 +1,144 @@
-use bevy_ecs::prelude::Query;
-use nalgebra::{Matrix3, Matrix4, Vector3};
+use bevy_ecs::prelude::*;
+use nalgebra::{Matrix4, Point3};
 
-use crate::common_component::{Camera, MainCamera, PointLight, Transform};
+use crate::common_component::{PointLight, Transform};
+use crate::culling::Aabb;
+use crate::light_lod::LightLod;
+use crate::morton;
 
+// Sorts point lights along a Morton curve through their bounds and hands the order to LightLod, so
+// lights close together in the scene end up close together in the light buffer. Groundwork for
+// clustered light assignment.
 pub fn light_assignment_prepass(
-    camera: Query<(&Camera, &Transform, &MainCamera)>,
-    lights: Query<(&PointLight, &Transform)>,
+    lights: Query<(Entity, &Transform), With<PointLight>>,
+    mut light_lod: ResMut<LightLod>,
+    mut positions: Local<Vec<(u32, Point3<f32>)>>,
+    mut codes: Local<Vec<(u32, u32)>>,
 ) {
-    let bounds = lights.iter().fold(
-        (Vector3::<f32>::zeros(), Vector3::<f32>::zeros()),
-        |acc, (_pl, t)| {
-            let pos = t.isometry.translation;
-            (
-                Vector3::new(acc.0.x.max(pos.x), acc.0.y.max(pos.y), acc.0.z.max(pos.z)),
-                Vector3::new(acc.1.x.min(pos.x), acc.1.y.min(pos.y), acc.1.z.min(pos.z)),
-            )
-        },
+    positions.clear();
+    positions.extend(
+        lights
+            .iter()
+            .map(|(entity, t)| (entity.id(), Point3::from(t.isometry.translation.vector))),
     );
 
-    let t = bounds.1;
-    let s = bounds.0 - bounds.1;
+    codes.clear();
+    if let Some(bounds) = Aabb::from_points(positions.iter().map(|(_, p)| p)) {
+        let transform = normalization(&bounds);
+        codes.extend(positions.iter().map(|(id, p)| {
+            let normalized = transform.transform_point(p).coords;
+            (morton::code(&normalized), *id)
+        }));
+    }
+    // ties broken by entity id so the order doesn't depend on query iteration order
+    codes.sort_unstable();
 
-    let transform = Matrix4::new_translation(&t).append_nonuniform_scaling(&s);
+    light_lod.set_point_light_order(codes.iter().map(|(_, id)| *id));
+}
+
+// Maps the bounds onto [0, 1]^3. Flat axes, like all of them with a single light, map to 0 instead
+// of dividing by zero.
+fn normalization(bounds: &Aabb) -> Matrix4<f32> {
+    let size = bounds.max - bounds.min;
+    let inverse_size = size.map(|s| if s > 0.0 { 1.0 / s } else { 0.0 });
+
+    Matrix4::new_nonuniform_scaling(&inverse_size) * Matrix4::new_translation(&-bounds.min.coords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light_lod::LightCandidate;
+    use nalgebra::{Isometry3, Vector3};
+
+    // Ids of equally important lights in the order LightLod submits them.
+    fn submitted(world: &mut World, ids: &[u32]) -> Vec<u32> {
+        let mut candidates: Vec<_> = ids
+            .iter()
+            .map(|id| LightCandidate {
+                id: *id,
+                importance: 1.0,
+                data: *id,
+            })
+            .collect();
+
+        let mut out = Vec::new();
+        world
+            .resource_mut::<LightLod>()
+            .select_point_lights(&mut candidates, ids.len(), &mut out);
+        out
+    }
+
+    #[test]
+    fn normalization_maps_bounds_to_unit_cube_test() {
+        let bounds = Aabb::new(Point3::new(-2.0, 0.0, 1.0), Point3::new(2.0, 1.0, 5.0));
+        let transform = normalization(&bounds);
+
+        assert_eq!(transform.transform_point(&bounds.min), Point3::origin());
+        assert_eq!(
+            transform.transform_point(&bounds.max),
+            Point3::new(1.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn single_light_normalization_is_finite_test() {
+        let position = Point3::new(3.0, -1.0, 2.0);
+        let transform = normalization(&Aabb::new(position, position));
+
+        let normalized = transform.transform_point(&position);
+        assert_eq!(normalized, Point3::origin());
+        assert!(transform.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn prepass_orders_lights_test() {
+        let mut world = World::new();
+        world.insert_resource(LightLod::default());
+
+        let mut spawn = |x: f32| {
+            world
+                .spawn()
+                .insert(PointLight {
+                    color: Vector3::repeat(1.0),
+                    power: 1.0,
+                    radius: 1.0,
+                })
+                .insert(Transform {
+                    isometry: Isometry3::translation(x, 0.0, 0.0),
+                    scale: Vector3::repeat(1.0),
+                    parent: None,
+                    children: vec![],
+                })
+                .id()
+                .id()
+        };
+        let far = spawn(10.0);
+        let near = spawn(0.0);
+        let middle = spawn(4.0);
+
+        let mut system = IntoSystem::into_system(light_assignment_prepass);
+        system.initialize(&mut world);
+        system.run((), &mut world);
+
+        assert_eq!(
+            submitted(&mut world, &[far, near, middle]),
+            [near, middle, far]
+        );
+    }
+
+    #[test]
+    fn prepass_without_lights_test() {
+        let mut world = World::new();
+        let mut light_lod = LightLod::default();
+        light_lod.set_point_light_order([3, 1, 2]);
+        world.insert_resource(light_lod);
+
+        let mut system = IntoSystem::into_system(light_assignment_prepass);
+        system.initialize(&mut world);
+        system.run((), &mut world);
 
-    let morton_lights = lights.iter().map(|(_pl, t)| {});
+        // the stale order is dropped, equally important lights go out by entity id
+        assert_eq!(submitted(&mut world, &[3, 1, 2]), [1, 2, 3]);
+    }
 }
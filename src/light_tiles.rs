@@ -0,0 +1,281 @@
+use std::ops::Range;
+
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::culling::Aabb;
+
+// Screen tiles the point and spot lights are binned into, counted from the top left.
+pub const TILE_COLUMNS: u32 = 16;
+pub const TILE_ROWS: u32 = 9;
+pub const TILE_COUNT: usize = (TILE_COLUMNS * TILE_ROWS) as usize;
+
+// Lights past this many in one tile are left out of it.
+pub const MAX_LIGHTS_PER_TILE: usize = 32;
+
+// Set on the entries of spot lights, the rest of an entry is the light's index in its array.
+pub const SPOT_LIGHT_BIT: u32 = 1 << 31;
+
+// Box around a point light's sphere of influence.
+pub fn sphere_bounds(center: &Point3<f32>, radius: f32) -> Aabb {
+    let extent = Vector3::repeat(radius);
+    Aabb::new(center - extent, center + extent)
+}
+
+// Box around a spot light's cone out to radius, never larger than its sphere. Every point of the
+// cone within radius of the apex lies inside the cone of height radius, so that one is boxed: the
+// apex and the disc at its base. cut_off is the cosine of the cone's half angle.
+pub fn cone_bounds(
+    apex: &Point3<f32>,
+    direction: &Vector3<f32>,
+    cut_off: f32,
+    radius: f32,
+) -> Aabb {
+    let sphere = sphere_bounds(apex, radius);
+    let cut_off = cut_off.clamp(-1.0, 1.0);
+    if cut_off <= 0.0 {
+        return sphere;
+    }
+
+    let direction = direction.normalize();
+    let base = apex + direction * radius;
+    let base_radius = radius * (1.0 - cut_off * cut_off).sqrt() / cut_off;
+    // a disc reaches along each axis as far as its normal is off that axis
+    let extent = direction.map(|d| base_radius * (1.0 - d * d).max(0.0).sqrt());
+
+    Aabb::new(
+        apex.inf(&(base - extent)).sup(&sphere.min),
+        apex.sup(&(base + extent)).inf(&sphere.max),
+    )
+}
+
+// Columns and rows of the tiles bounds covers as seen through view_projection, None when it is
+// entirely off screen. A box reaching behind the camera can project anywhere so it covers every
+// tile, unless all of it is behind.
+pub fn covered_tiles(
+    view_projection: &Matrix4<f32>,
+    bounds: &Aabb,
+) -> Option<(Range<u32>, Range<u32>)> {
+    let mut min = [f32::MAX; 2];
+    let mut max = [f32::MIN; 2];
+    let mut behind = 0;
+    for i in 0..8 {
+        let pick = |bit: usize, axis: usize| {
+            if i & bit == 0 {
+                bounds.min[axis]
+            } else {
+                bounds.max[axis]
+            }
+        };
+        let corner = Point3::new(pick(1, 0), pick(2, 1), pick(4, 2));
+        let clip = view_projection * corner.to_homogeneous();
+        if clip.w <= f32::EPSILON {
+            behind += 1;
+            continue;
+        }
+        for axis in 0..2 {
+            min[axis] = min[axis].min(clip[axis] / clip.w);
+            max[axis] = max[axis].max(clip[axis] / clip.w);
+        }
+    }
+
+    match behind {
+        8 => return None,
+        0 => {}
+        _ => return Some((0..TILE_COLUMNS, 0..TILE_ROWS)),
+    }
+    if max[0] < -1.0 || min[0] > 1.0 || max[1] < -1.0 || min[1] > 1.0 {
+        return None;
+    }
+
+    // ndc y points up, rows count down from the top
+    let tile = |ndc: f32, count: u32| {
+        (((ndc + 1.0) * 0.5 * count as f32).floor() as i64).clamp(0, count as i64 - 1) as u32
+    };
+    let columns = tile(min[0], TILE_COLUMNS)..tile(max[0], TILE_COLUMNS) + 1;
+    let rows = TILE_ROWS - 1 - tile(max[1], TILE_ROWS)..TILE_ROWS - tile(min[1], TILE_ROWS);
+    Some((columns, rows))
+}
+
+// Per tile lists of the lights reaching into it, laid out the way the fragment shader reads them:
+// for every tile, row by row, the number of lights followed by MAX_LIGHTS_PER_TILE entries.
+pub struct LightTiles {
+    data: Vec<u32>,
+    pub overflowed: usize, // entries left out of full tiles
+}
+
+impl Default for LightTiles {
+    fn default() -> Self {
+        Self {
+            data: vec![0; TILE_COUNT * Self::TILE_STRIDE],
+            overflowed: 0,
+        }
+    }
+}
+
+impl LightTiles {
+    pub const TILE_STRIDE: usize = 1 + MAX_LIGHTS_PER_TILE;
+
+    pub fn clear(&mut self) {
+        for tile in self.data.chunks_mut(Self::TILE_STRIDE) {
+            tile[0] = 0;
+        }
+        self.overflowed = 0;
+    }
+
+    // Adds entry to every tile bounds covers.
+    pub fn insert(&mut self, entry: u32, view_projection: &Matrix4<f32>, bounds: &Aabb) {
+        let (columns, rows) = match covered_tiles(view_projection, bounds) {
+            Some(tiles) => tiles,
+            None => return,
+        };
+
+        for row in rows {
+            for column in columns.clone() {
+                let start = (row * TILE_COLUMNS + column) as usize * Self::TILE_STRIDE;
+                let tile = &mut self.data[start..start + Self::TILE_STRIDE];
+                let count = tile[0] as usize;
+                if count == MAX_LIGHTS_PER_TILE {
+                    self.overflowed += 1;
+                    continue;
+                }
+                tile[1 + count] = entry;
+                tile[0] += 1;
+            }
+        }
+    }
+
+    // Entries binned into one tile.
+    #[cfg(test)]
+    pub fn tile(&self, column: u32, row: u32) -> &[u32] {
+        let start = (row * TILE_COLUMNS + column) as usize * Self::TILE_STRIDE;
+        let count = self.data[start] as usize;
+        &self.data[start + 1..start + 1 + count]
+    }
+
+    pub fn data(&self) -> &[u32] {
+        &self.data
+    }
+
+    pub fn average_lights_per_tile(&self) -> f32 {
+        let total: u32 = self
+            .data
+            .chunks(Self::TILE_STRIDE)
+            .map(|tile| tile[0])
+            .sum();
+        total as f32 / TILE_COUNT as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Isometry3, Perspective3};
+
+    const EPSILON: f32 = 1e-4;
+
+    // 90 degree view from the origin down -z, 16:9 like the tile grid so every tile is square
+    fn view_projection() -> Matrix4<f32> {
+        let projection = Perspective3::new(16.0 / 9.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        projection.as_matrix() * Isometry3::identity().to_homogeneous()
+    }
+
+    // the tiles a light covers, as (column, row) pairs
+    fn tiles_of(bounds: &Aabb) -> Vec<(u32, u32)> {
+        let mut tiles = LightTiles::default();
+        tiles.insert(7, &view_projection(), bounds);
+
+        let mut covered = Vec::new();
+        for row in 0..TILE_ROWS {
+            for column in 0..TILE_COLUMNS {
+                match tiles.tile(column, row) {
+                    [] => {}
+                    [7] => covered.push((column, row)),
+                    other => panic!("unexpected entries {:?}", other),
+                }
+            }
+        }
+        covered
+    }
+
+    #[test]
+    fn sphere_in_the_middle_test() {
+        // at depth 10 the view is 20 units tall and 16 / 9 times that wide, so every tile is 20 / 9
+        // units across. The center is between two columns and in the middle of a row.
+        let bounds = sphere_bounds(&Point3::new(0.0, 0.0, -10.0), 0.5);
+        assert_eq!(tiles_of(&bounds), [(7, 4), (8, 4)]);
+
+        // a little higher reaches into the row above
+        let tile = 20.0 / 9.0;
+        let bounds = sphere_bounds(&Point3::new(0.0, 0.5 * tile, -10.0), 0.5);
+        assert_eq!(tiles_of(&bounds), [(7, 3), (8, 3), (7, 4), (8, 4)]);
+
+        // off to the top left
+        let bounds = sphere_bounds(&Point3::new(-7.5 * tile, 4.0 * tile, -10.0), 0.2);
+        assert_eq!(tiles_of(&bounds), [(0, 0)]);
+    }
+
+    #[test]
+    fn off_screen_and_behind_test() {
+        assert!(tiles_of(&sphere_bounds(&Point3::new(50.0, 0.0, -10.0), 1.0)).is_empty());
+        assert!(tiles_of(&sphere_bounds(&Point3::new(0.0, 0.0, 10.0), 1.0)).is_empty());
+
+        // around the camera it can reach anywhere on screen
+        let around = tiles_of(&sphere_bounds(&Point3::new(0.0, 0.0, 0.0), 1.0));
+        assert_eq!(around.len(), TILE_COUNT);
+    }
+
+    #[test]
+    fn cone_bounds_test() {
+        // a narrow cone pointing down only reaches a little to the sides
+        let apex = Point3::new(0.0, 5.0, 0.0);
+        let cut_off = 30f32.to_radians().cos();
+        let bounds = cone_bounds(&apex, &-Vector3::y(), cut_off, 4.0);
+        let side = 4.0 * 30f32.to_radians().tan();
+        assert!((bounds.min - Point3::new(-side, 1.0, -side)).norm() < EPSILON);
+        assert!((bounds.max - Point3::new(side, 5.0, side)).norm() < EPSILON);
+
+        // wide cones are limited to the sphere
+        let wide = cone_bounds(&apex, &-Vector3::y(), 80f32.to_radians().cos(), 4.0);
+        assert_eq!(wide.min, Point3::new(-4.0, 1.0, -4.0));
+        assert_eq!(wide.max, Point3::new(4.0, 5.0, 4.0));
+        let backwards = cone_bounds(&apex, &-Vector3::y(), -0.5, 4.0);
+        assert_eq!(backwards.min, sphere_bounds(&apex, 4.0).min);
+    }
+
+    #[test]
+    fn spot_cone_covers_less_than_its_sphere_test() {
+        // pointing right from the middle of the view, the cone never reaches the left half
+        let apex = Point3::new(0.0, 0.0, -10.0);
+        let cone = cone_bounds(&apex, &Vector3::x(), 20f32.to_radians().cos(), 8.0);
+        let covered = tiles_of(&cone);
+
+        assert!(covered.iter().all(|(column, _)| *column >= 7));
+        let sphere = tiles_of(&sphere_bounds(&apex, 8.0));
+        assert!(sphere.iter().any(|(column, _)| *column < 7));
+        assert!(covered.len() < sphere.len());
+        assert!(covered.iter().all(|tile| sphere.contains(tile)));
+    }
+
+    #[test]
+    fn tile_budget_test() {
+        let mut tiles = LightTiles::default();
+        // the middle of tile (8, 4)
+        let bounds = sphere_bounds(&Point3::new(10.0 / 9.0, 0.0, -10.0), 0.1);
+        for i in 0..MAX_LIGHTS_PER_TILE as u32 + 3 {
+            tiles.insert(i | SPOT_LIGHT_BIT, &view_projection(), &bounds);
+        }
+
+        let tile = tiles.tile(8, 4);
+        assert_eq!(tile.len(), MAX_LIGHTS_PER_TILE);
+        assert_eq!(tile[0], SPOT_LIGHT_BIT);
+        assert_eq!(tiles.overflowed, 3);
+        assert_eq!(
+            tiles.average_lights_per_tile(),
+            MAX_LIGHTS_PER_TILE as f32 / TILE_COUNT as f32
+        );
+
+        tiles.clear();
+        assert!(tiles.tile(8, 4).is_empty());
+        assert_eq!(tiles.overflowed, 0);
+    }
+}
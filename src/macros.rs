@@ -2,7 +2,7 @@
 // should be possible to remove this, but the saving might not be worth the explicitness.
 macro_rules! parallel_enum_values {
     (($enum_name:ident, $const_name:ident, $const_type:ty $(,)?) $($name:ident -> $value:expr),* $(,)?) => {
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
         pub enum $enum_name {
             $($name,)*
         }
@@ -1,17 +1,51 @@
+mod bindings;
+mod board;
+mod camera_controller;
+mod camera_cut;
+mod camera_shake;
 mod common_component;
+mod console;
+mod culling;
 mod data_types;
+mod day_night;
+mod debug_draw;
+mod fog;
+mod frame_scratch;
+mod frame_stats;
 mod game;
 mod geometry_library;
+mod input;
+mod input_recording;
+mod interpolation;
+mod library_stats;
+mod light_lod;
+mod light_managment_system;
+mod light_tiles;
 mod macros;
+mod material;
+mod math;
+mod morton;
+mod picking;
+mod pile;
+mod post_process;
+mod primitives;
+mod profile;
 mod render_system;
+mod screenshot;
 mod shader_library;
+mod shadow;
+mod shadow_atlas;
+mod spot_cookies;
+mod state_hash;
+mod strings;
 mod texture_library;
 mod tile_world;
 mod time;
+mod tonemap;
 mod util;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    simple_logger::init_with_level(log::Level::Info).unwrap();
+    console::init_logger(log::Level::Info).unwrap();
 
     game::run()
 }
@@ -0,0 +1,86 @@
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    system::{Commands, Query, Res},
+};
+use nalgebra::Vector3;
+
+use crate::{common_component::MaterialParams, time::TimeResource};
+
+// Typed views over MaterialParams so game code never indexes the raw slots. Each view documents the
+// slots it owns, they have to match the params comment in the fragment shader.
+
+// Slots 0 and 1 for the amount and edge width, 4 to 6 for the edge color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DissolveParams {
+    pub amount: f32,     // 0 is fully visible, 1 is fully dissolved
+    pub edge_width: f32, // in noise units, 0 disables the glowing edge
+    pub edge_color: Vector3<f32>,
+}
+
+impl DissolveParams {
+    pub fn write_into(&self, params: &mut MaterialParams) {
+        params.0[0] = self.amount;
+        params.0[1] = self.edge_width;
+        params.0[4..7].copy_from_slice(self.edge_color.as_slice());
+    }
+
+    pub fn read_from(params: &MaterialParams) -> Self {
+        Self {
+            amount: params.0[0],
+            edge_width: params.0[1],
+            edge_color: Vector3::from_column_slice(&params.0[4..7]),
+        }
+    }
+}
+
+// Dissolves an entity over duration seconds, then despawns it. Used for destroyed cards.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct Dissolve {
+    pub duration: f32,
+    pub elapsed: f32,
+    pub edge_width: f32,
+    pub edge_color: Vector3<f32>,
+}
+
+impl Dissolve {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            elapsed: 0.0,
+            edge_width: 0.05,
+            edge_color: Vector3::new(4.0, 1.5, 0.3),
+        }
+    }
+}
+
+pub fn advance_dissolve(
+    mut commands: Commands,
+    time: Res<TimeResource>,
+    mut dissolving: Query<(Entity, &mut Dissolve, Option<&mut MaterialParams>)>,
+) {
+    let dt = time.update_dt.as_secs_f32();
+
+    for (entity, mut dissolve, params) in dissolving.iter_mut() {
+        dissolve.elapsed += dt;
+        if dissolve.elapsed >= dissolve.duration {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let dissolve_params = DissolveParams {
+            amount: dissolve.elapsed / dissolve.duration,
+            edge_width: dissolve.edge_width,
+            edge_color: dissolve.edge_color,
+        };
+
+        match params {
+            Some(mut params) => dissolve_params.write_into(&mut params),
+            None => {
+                let mut params = MaterialParams::default();
+                dissolve_params.write_into(&mut params);
+                commands.entity(entity).insert(params);
+            }
+        }
+    }
+}
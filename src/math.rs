@@ -0,0 +1,404 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::{Add, Mul, Sub},
+};
+
+use nalgebra::{Isometry3, Matrix4, UnitQuaternion, Vector2, Vector3, Vector4};
+
+// Smoothing helpers that give the same result whether a duration is covered in one step or many.
+// lerp(a, b, k * dt) does not have this property and should be avoided for per frame smoothing.
+
+pub trait Interpolate: Copy {
+    fn interpolate(&self, target: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        self + (target - self) * t
+    }
+}
+
+impl Interpolate for Vector2<f32> {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        self.lerp(target, t)
+    }
+}
+
+impl Interpolate for Vector3<f32> {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        self.lerp(target, t)
+    }
+}
+
+impl Interpolate for Vector4<f32> {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        self.lerp(target, t)
+    }
+}
+
+// try_slerp flips the target when needed so rotation always takes the short way around. It only
+// fails when both rotations are practically identical.
+impl Interpolate for UnitQuaternion<f32> {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        self.try_slerp(target, t, f32::EPSILON).unwrap_or(*target)
+    }
+}
+
+// Translation lerps and rotation slerps independently.
+impl Interpolate for Isometry3<f32> {
+    fn interpolate(&self, target: &Self, t: f32) -> Self {
+        Isometry3::from_parts(
+            self.translation
+                .vector
+                .interpolate(&target.translation.vector, t)
+                .into(),
+            self.rotation.interpolate(&target.rotation, t),
+        )
+    }
+}
+
+// Moves current towards target, closing the fraction 1 - e^(-decay_rate * dt) of the gap.
+// decay_rate is in 1/seconds, higher values converge faster.
+pub fn exp_decay<T: Interpolate>(current: T, target: T, decay_rate: f32, dt: f32) -> T {
+    current.interpolate(&target, 1.0 - (-decay_rate * dt).exp())
+}
+
+// Critically damped spring following a target without overshoot. Uses the closed form solution
+// so the result only depends on the total time passed, as long as the target holds still.
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothDamped<T> {
+    pub value: T,
+    pub velocity: T,
+    pub smooth_time: f32, // roughly the time it takes to reach the target
+}
+
+impl<T> SmoothDamped<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    pub fn new(value: T, smooth_time: f32) -> Self {
+        Self {
+            value,
+            velocity: value * 0.0,
+            smooth_time,
+        }
+    }
+
+    pub fn update(&mut self, target: T, dt: f32) -> T {
+        let omega = 2.0 / self.smooth_time.max(f32::EPSILON);
+        let decay = (-omega * dt).exp();
+
+        let offset = self.value - target;
+        let slope = self.velocity + offset * omega;
+
+        self.value = target + (offset + slope * dt) * decay;
+        self.velocity = (self.velocity - slope * (omega * dt)) * decay;
+
+        self.value
+    }
+}
+
+// Wraps into (-PI, PI].
+pub fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = (angle + PI).rem_euclid(TAU) - PI;
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+// Signed shortest rotation from one angle to another.
+pub fn angle_difference(from: f32, to: f32) -> f32 {
+    wrap_angle(to - from)
+}
+
+// exp_decay for angles such as yaw, always turns the short way around.
+pub fn exp_decay_angle(current: f32, target: f32, decay_rate: f32, dt: f32) -> f32 {
+    let target = current + angle_difference(current, target);
+    wrap_angle(exp_decay(current, target, decay_rate, dt))
+}
+
+// Easing curves mapping linear progress in [0, 1] to eased progress, also in [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    SmoothStep,
+    EaseInOutCubic,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::SmoothStep => t * t * (3.0 - 2.0 * t),
+            Easing::EaseInOutCubic => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+// Deterministic pseudo random value in [-1, 1] for an integer and a seed.
+pub fn hash_noise(seed: u32, i: i32) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9e37_79b1) ^ seed.wrapping_mul(0x85eb_ca77);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+
+    (h as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+// Same result as isometry.to_matrix(), written straight into out column by column. nalgebra goes
+// through a rotation matrix and a homogeneous conversion which doesn't vectorize well, and this
+// runs once per drawn object. The nalgebra_matrices feature switches back to isometry.to_matrix()
+// for comparing the two.
+pub fn write_isometry_matrix(isometry: &Isometry3<f32>, out: &mut Matrix4<f32>) {
+    let q = isometry.rotation.quaternion();
+    let (x, y, z, w) = (q.i, q.j, q.k, q.w);
+    let t = &isometry.translation.vector;
+
+    let (x2, y2, z2) = (x + x, y + y, z + z);
+    let (xx, yy, zz) = (x * x2, y * y2, z * z2);
+    let (xy, xz, yz) = (x * y2, x * z2, y * z2);
+    let (wx, wy, wz) = (w * x2, w * y2, w * z2);
+
+    // column major
+    let m = out.as_mut_slice();
+    m[0] = 1.0 - (yy + zz);
+    m[1] = xy + wz;
+    m[2] = xz - wy;
+    m[3] = 0.0;
+
+    m[4] = xy - wz;
+    m[5] = 1.0 - (xx + zz);
+    m[6] = yz + wx;
+    m[7] = 0.0;
+
+    m[8] = xz + wy;
+    m[9] = yz - wx;
+    m[10] = 1.0 - (xx + yy);
+    m[11] = 0.0;
+
+    m[12] = t.x;
+    m[13] = t.y;
+    m[14] = t.z;
+    m[15] = 1.0;
+}
+
+#[cfg(not(feature = "nalgebra_matrices"))]
+pub fn isometry_matrix(isometry: &Isometry3<f32>) -> Matrix4<f32> {
+    let mut out = Matrix4::zeros();
+    write_isometry_matrix(isometry, &mut out);
+    out
+}
+
+#[cfg(feature = "nalgebra_matrices")]
+pub fn isometry_matrix(isometry: &Isometry3<f32>) -> Matrix4<f32> {
+    isometry.to_matrix()
+}
+
+// isometry * scaling, the scale is applied in local space first.
+pub fn transform_matrix(isometry: &Isometry3<f32>, scale: &Vector3<f32>) -> Matrix4<f32> {
+    let mut out = isometry_matrix(isometry);
+    for (i, s) in scale.iter().enumerate() {
+        out.column_mut(i).scale_mut(*s);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    // One 0.1s step against ten 0.01s steps.
+    fn one_and_ten_steps<T: Copy>(start: T, mut step: impl FnMut(T, f32) -> T) -> (T, T) {
+        let one = step(start, 0.1);
+        let ten = (0..10).fold(start, |value, _| step(value, 0.01));
+        (one, ten)
+    }
+
+    #[test]
+    fn exp_decay_scalar_frame_rate_independent_test() {
+        let (one, ten) = one_and_ten_steps(0.0, |v, dt| exp_decay(v, 10.0, 8.0, dt));
+        assert!((one - ten).abs() < EPSILON, "{} vs {}", one, ten);
+        // 1 - e^(-0.8) of the way
+        assert!((one - 10.0 * (1.0 - (-0.8f32).exp())).abs() < EPSILON);
+    }
+
+    #[test]
+    fn exp_decay_vector_frame_rate_independent_test() {
+        let target = Vector3::new(3.0, -2.0, 5.0);
+        let (one, ten) = one_and_ten_steps(Vector3::zeros(), |v, dt| exp_decay(v, target, 5.0, dt));
+        assert!((one - ten).norm() < EPSILON, "{} vs {}", one, ten);
+    }
+
+    #[test]
+    fn exp_decay_quaternion_frame_rate_independent_test() {
+        let target = UnitQuaternion::from_euler_angles(0.3, 1.2, -0.5);
+        let (one, ten) = one_and_ten_steps(UnitQuaternion::identity(), |q, dt| {
+            exp_decay(q, target, 6.0, dt)
+        });
+        assert!(one.angle_to(&ten) < 1e-3, "{} vs {}", one, ten);
+    }
+
+    #[test]
+    fn quaternion_takes_short_way_test() {
+        let from = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 170f32.to_radians());
+        let to = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), -170f32.to_radians());
+        // 20 degrees apart through 180, not 340 through 0
+        let halfway = from.interpolate(&to, 0.5);
+        assert!((halfway.angle() - PI).abs() < 1e-3, "{}", halfway.angle());
+
+        // the same rotation with a negated quaternion must not spin all the way around either
+        let negated = UnitQuaternion::new_unchecked(-from.into_inner());
+        assert!(from.interpolate(&negated, 0.5).angle_to(&from) < 1e-3);
+    }
+
+    #[test]
+    fn smooth_damped_frame_rate_independent_test() {
+        let mut one = SmoothDamped::new(Vector3::zeros(), 0.3);
+        let mut ten = one;
+        let target = Vector3::new(1.0, 2.0, -1.0);
+
+        // a few frames of each so the velocity carries over too
+        for _ in 0..3 {
+            one.update(target, 0.1);
+            for _ in 0..10 {
+                ten.update(target, 0.01);
+            }
+            assert!((one.value - ten.value).norm() < 1e-4);
+            assert!((one.velocity - ten.velocity).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn smooth_damped_does_not_overshoot_test() {
+        let mut spring = SmoothDamped::new(0.0, 0.2);
+        let mut previous = 0.0;
+        for _ in 0..200 {
+            let value = spring.update(1.0, 1.0 / 60.0);
+            assert!(value >= previous && value <= 1.0, "{}", value);
+            previous = value;
+        }
+        assert!((previous - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn wrap_angle_test() {
+        assert!((wrap_angle(0.5) - 0.5).abs() < EPSILON);
+        assert!((wrap_angle(TAU + 0.5) - 0.5).abs() < EPSILON);
+        assert!((wrap_angle(-TAU - 0.5) + 0.5).abs() < EPSILON);
+        assert_eq!(wrap_angle(PI), PI);
+        assert_eq!(wrap_angle(-PI), PI);
+
+        assert!((angle_difference(3.0, -3.0) - (TAU - 6.0)).abs() < EPSILON);
+        assert!((angle_difference(-3.0, 3.0) + (TAU - 6.0)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn exp_decay_angle_test() {
+        // from just below PI to just above -PI, crossing PI instead of going through 0
+        let angle = exp_decay_angle(3.0, -3.0, 100.0, 1.0);
+        assert!((angle + 3.0).abs() < 1e-3, "{}", angle);
+        let halfway = exp_decay_angle(3.0, -3.0, 2f32.ln(), 1.0);
+        assert!(halfway.abs() > 3.0, "{}", halfway);
+
+        let (one, ten) = one_and_ten_steps(3.0, |a, dt| exp_decay_angle(a, -3.0, 4.0, dt));
+        assert!(angle_difference(one, ten).abs() < EPSILON);
+    }
+
+    #[test]
+    fn easing_test() {
+        for easing in [Easing::Linear, Easing::SmoothStep, Easing::EaseInOutCubic] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert!((easing.apply(0.5) - 0.5).abs() < EPSILON);
+            // clamped outside [0, 1]
+            assert_eq!(easing.apply(-1.0), 0.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+    }
+
+    // Deterministic isometries covering every rotation axis and sign.
+    fn random_isometries(count: usize) -> Vec<Isometry3<f32>> {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(470);
+        (0..count)
+            .map(|_| {
+                let translation = Vector3::from_fn(|_, _| rng.gen_range(-100.0..100.0));
+                let axis_angle = Vector3::from_fn(|_, _| rng.gen_range(-PI..PI));
+                Isometry3::new(translation, axis_angle)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn isometry_matrix_matches_nalgebra_test() {
+        let mut out = Matrix4::zeros();
+        for isometry in random_isometries(1000) {
+            write_isometry_matrix(&isometry, &mut out);
+            let expected = isometry.to_homogeneous();
+
+            let difference = (out - expected).abs().max();
+            assert!(difference < 1e-5, "{} vs {}", out, expected);
+        }
+    }
+
+    #[test]
+    fn transform_matrix_applies_scale_first_test() {
+        let isometry = random_isometries(1)[0];
+        let scale = Vector3::new(2.0, 0.5, 3.0);
+
+        let expected = isometry.to_homogeneous() * Matrix4::new_nonuniform_scaling(&scale);
+        assert!((transform_matrix(&isometry, &scale) - expected).abs().max() < 1e-4);
+    }
+
+    // Not a correctness check, compares both paths over 10k transforms. Run it with
+    // cargo test --release isometry_matrix_speed -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn isometry_matrix_speed_test() {
+        use std::{hint::black_box, time::Instant};
+
+        let isometries = random_isometries(10_000);
+        let mut out = vec![Matrix4::zeros(); isometries.len()];
+        let time = |f: &mut dyn FnMut()| {
+            let start = Instant::now();
+            for _ in 0..100 {
+                f();
+            }
+            start.elapsed() / 100
+        };
+
+        let nalgebra = time(&mut || {
+            for (isometry, out) in isometries.iter().zip(&mut out) {
+                *out = black_box(isometry).to_homogeneous();
+            }
+            black_box(&out);
+        });
+        let hand_written = time(&mut || {
+            for (isometry, out) in isometries.iter().zip(&mut out) {
+                write_isometry_matrix(black_box(isometry), out);
+            }
+            black_box(&out);
+        });
+
+        println!(
+            "10k isometries: nalgebra {:?}, write_isometry_matrix {:?}, {:.2}x",
+            nalgebra,
+            hand_written,
+            nalgebra.as_secs_f64() / hand_written.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use nalgebra::Vector3;
+
+// Codes interleave this many bits of each axis, 30 bits in total.
+pub const BITS_PER_AXIS: u32 = 10;
+const GRID_MAX: u32 = (1 << BITS_PER_AXIS) - 1;
+
+// Spreads the low 10 bits of v out so two zero bits follow each one.
+fn spread_bits(v: u32) -> u32 {
+    let mut v = v & GRID_MAX;
+    v = (v | (v << 16)) & 0x0300_00ff;
+    v = (v | (v << 8)) & 0x0300_f00f;
+    v = (v | (v << 4)) & 0x030c_30c3;
+    v = (v | (v << 2)) & 0x0924_9249;
+    v
+}
+
+// x lands in the lowest bit of every group of three, then y, then z.
+pub fn encode(x: u32, y: u32, z: u32) -> u32 {
+    spread_bits(x) | (spread_bits(y) << 1) | (spread_bits(z) << 2)
+}
+
+// Grid cell of a position in [0, 1]^3, positions outside are clamped onto the edge.
+pub fn quantize(normalized: &Vector3<f32>) -> [u32; 3] {
+    let cell = |v: f32| (v.clamp(0.0, 1.0) * GRID_MAX as f32).round() as u32;
+    [cell(normalized.x), cell(normalized.y), cell(normalized.z)]
+}
+
+// Positions close together in [0, 1]^3 mostly get codes close together.
+pub fn code(normalized: &Vector3<f32>) -> u32 {
+    let [x, y, z] = quantize(normalized);
+    encode(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_interleaves_test() {
+        assert_eq!(encode(1, 0, 0), 0b001);
+        assert_eq!(encode(0, 1, 0), 0b010);
+        assert_eq!(encode(0, 0, 1), 0b100);
+        assert_eq!(encode(0b11, 0, 0), 0b001_001);
+        assert_eq!(encode(0b10, 0b01, 0b11), 0b101_110);
+        assert_eq!(encode(GRID_MAX, GRID_MAX, GRID_MAX), (1 << 30) - 1);
+    }
+
+    #[test]
+    fn encode_ignores_high_bits_test() {
+        assert_eq!(encode(GRID_MAX + 1, 0, 0), 0);
+    }
+
+    #[test]
+    fn quantize_clamps_test() {
+        assert_eq!(quantize(&Vector3::new(0.0, 0.5, 1.0)), [0, 512, GRID_MAX]);
+        assert_eq!(
+            quantize(&Vector3::new(-1.0, 2.0, f32::NAN)),
+            [0, GRID_MAX, 0]
+        );
+    }
+
+    #[test]
+    fn nearby_positions_sort_together_test() {
+        let mut positions = [
+            Vector3::new(0.9, 0.9, 0.9),
+            Vector3::new(0.1, 0.1, 0.1),
+            Vector3::new(0.85, 0.9, 0.9),
+            Vector3::new(0.1, 0.15, 0.1),
+        ];
+        positions.sort_by_key(code);
+
+        // the two corners' pairs stay next to each other
+        assert!(positions[0].x < 0.5 && positions[1].x < 0.5);
+        assert!(positions[2].x > 0.5 && positions[3].x > 0.5);
+    }
+}
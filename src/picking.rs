@@ -0,0 +1,218 @@
+use bevy_ecs::{
+    prelude::Component,
+    query::{With, Without},
+    system::{Query, Res, ResMut},
+};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+
+use crate::camera_cut::CameraDirector;
+use crate::common_component::{Camera, MainCamera, Transform};
+use crate::input::Input;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>, // normalized
+}
+
+impl Ray {
+    // Builds the world space ray through a window position given in physical pixels. None for an
+    // empty window, a minimized one reports a size of zero.
+    pub fn from_screen(
+        cursor: &Vector2<f32>,
+        window_size: &Vector2<f32>,
+        view_projection: &Matrix4<f32>,
+    ) -> Option<Self> {
+        if !(window_size.x > 0.0 && window_size.y > 0.0) {
+            return None;
+        }
+        let inverse = view_projection.try_inverse()?;
+
+        // window y grows downwards, ndc y grows upwards
+        let x = 2.0 * cursor.x / window_size.x - 1.0;
+        let y = 1.0 - 2.0 * cursor.y / window_size.y;
+
+        // both depths are inside the clip volume for either depth convention
+        let near = inverse.transform_point(&Point3::new(x, y, 0.0));
+        let far = inverse.transform_point(&Point3::new(x, y, 1.0));
+
+        Some(Self {
+            origin: near,
+            direction: (far - near).try_normalize(f32::EPSILON)?,
+        })
+    }
+}
+
+// Plane made of all points p where normal.dot(p) == offset.
+#[derive(Clone, Copy, Debug)]
+pub struct PlaneTarget {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+impl PlaneTarget {
+    // Horizontal plane at the given height, the table is laid out on one of these.
+    pub fn horizontal(height: f32) -> Self {
+        Self {
+            normal: Vector3::y(),
+            offset: height,
+        }
+    }
+
+    // Returns None when the ray runs parallel to the plane or the plane is behind the ray origin.
+    pub fn intersect(&self, ray: &Ray) -> Option<Point3<f32>> {
+        let denom = self.normal.dot(&ray.direction);
+        if denom.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let t = (self.offset - self.normal.dot(&ray.origin.coords)) / denom;
+        if t < 0.0 {
+            return None;
+        }
+
+        Some(ray.origin + ray.direction * t)
+    }
+}
+
+// Resource tracking where the cursor meets the primary plane. The window size is fed by
+// Game::handle_event, world is recalculated every frame from the cursor position in Input.
+#[derive(Clone, Debug)]
+pub struct CursorWorldPosition {
+    pub plane: PlaneTarget,
+
+    pub window_size: Vector2<f32>,
+
+    pub world: Option<Point3<f32>>,
+}
+
+impl CursorWorldPosition {
+    pub fn new(plane: PlaneTarget, window_size: Vector2<f32>) -> Self {
+        Self {
+            plane,
+            window_size,
+            world: None,
+        }
+    }
+}
+
+// Debug marker, entities with this component are moved to the cursor's plane intersection.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct CursorMarker;
+
+// Markers are excluded from the camera query so the two transform borrows never overlap.
+pub fn update_cursor_world_position(
+    mut cursor: ResMut<CursorWorldPosition>,
+    input: Res<Input>,
+    camera: Query<(&Camera, &Transform, &MainCamera)>,
+    director: Option<Res<CameraDirector>>,
+    mut markers: Query<&mut Transform, (With<CursorMarker>, Without<MainCamera>)>,
+) {
+    cursor.world = match (input.cursor_position(), camera.get_single()) {
+        (Some(screen), Ok((cam, cam_pos, _))) => {
+            // picks from the presented view, which differs from the camera during a cut
+            let (cam, isometry) = match &director {
+                Some(director) => director.presented(cam, &cam_pos.isometry),
+                None => (cam.clone(), cam_pos.isometry),
+            };
+
+            Ray::from_screen(
+                &screen,
+                &cursor.window_size,
+                &cam.view_projection(&isometry),
+            )
+            .and_then(|ray| cursor.plane.intersect(&ray))
+        }
+        _ => None,
+    };
+
+    if let Some(world) = cursor.world {
+        for mut t in markers.iter_mut() {
+            t.isometry.translation.vector = world.coords;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Isometry3, Perspective3};
+
+    const WINDOW: Vector2<f32> = Vector2::new(800.0, 600.0);
+
+    // Camera at (0, 5, 5) looking at the origin.
+    fn view_projection() -> Matrix4<f32> {
+        let view = Isometry3::look_at_rh(
+            &Point3::new(0.0, 5.0, 5.0),
+            &Point3::origin(),
+            &Vector3::y(),
+        );
+        let projection =
+            Perspective3::new(WINDOW.x / WINDOW.y, std::f32::consts::FRAC_PI_3, 0.1, 100.0);
+
+        projection.as_matrix() * view.to_homogeneous()
+    }
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).norm() < 1e-3, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn ray_through_center_test() {
+        let ray = Ray::from_screen(&(WINDOW / 2.0), &WINDOW, &view_projection()).unwrap();
+        let hit = PlaneTarget::horizontal(0.0).intersect(&ray).unwrap();
+        assert_close(hit, Point3::origin());
+    }
+
+    #[test]
+    fn screen_round_trip_test() {
+        let point = Point3::new(1.5, 0.0, -2.0);
+        let ndc = view_projection().transform_point(&point);
+        let screen = Vector2::new(
+            (ndc.x + 1.0) * 0.5 * WINDOW.x,
+            (1.0 - ndc.y) * 0.5 * WINDOW.y,
+        );
+        let ray = Ray::from_screen(&screen, &WINDOW, &view_projection()).unwrap();
+        assert_close(PlaneTarget::horizontal(0.0).intersect(&ray).unwrap(), point);
+    }
+
+    #[test]
+    fn empty_window_test() {
+        let cursor = Vector2::new(0.0, 0.0);
+        for size in [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(800.0, 0.0),
+            Vector2::new(0.0, 600.0),
+            Vector2::new(-800.0, 600.0),
+            Vector2::new(f32::NAN, 600.0),
+        ] {
+            assert!(
+                Ray::from_screen(&cursor, &size, &view_projection()).is_none(),
+                "ray for window size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn plane_intersection_test() {
+        let plane = PlaneTarget::horizontal(1.0);
+        let down = Ray {
+            origin: Point3::new(2.0, 3.0, -1.0),
+            direction: -Vector3::y(),
+        };
+        assert_close(plane.intersect(&down).unwrap(), Point3::new(2.0, 1.0, -1.0));
+
+        // parallel to the plane, then pointing away from it
+        let along = Ray {
+            direction: Vector3::x(),
+            ..down
+        };
+        let up = Ray {
+            direction: Vector3::y(),
+            ..down
+        };
+        assert!(plane.intersect(&along).is_none());
+        assert!(plane.intersect(&up).is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    system::{Commands, Query},
+};
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+use crate::{
+    common_component::{Transform, Visibility},
+    math::hash_noise,
+};
+
+// Lays out the children of a deck or discard zone as a physical pile. The first child is the
+// bottom card.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct PileLayout {
+    pub thickness_per_card: f32,
+    pub jitter: f32,       // largest sideways offset in world units
+    pub jitter_angle: f32, // largest turn around the pile axis in radians
+    pub max_visible: usize,
+}
+
+impl Default for PileLayout {
+    fn default() -> Self {
+        Self {
+            thickness_per_card: 0.005,
+            jitter: 0.01,
+            jitter_angle: 0.03,
+            max_visible: 8,
+        }
+    }
+}
+
+// Pose of a card relative to its zone and whether it should be drawn. Jitter is seeded by the card
+// id so a card keeps its exact pose frame to frame. Cards lie face up, their meshes face +z like
+// primitives::rounded_card.
+pub fn pile_card_pose(
+    pile: &PileLayout,
+    index: usize,
+    count: usize,
+    card_id: u32,
+) -> (Isometry3<f32>, bool) {
+    let offset = Vector3::new(
+        hash_noise(card_id, 0) * pile.jitter,
+        (index as f32 + 0.5) * pile.thickness_per_card,
+        hash_noise(card_id, 1) * pile.jitter,
+    );
+    let angle = hash_noise(card_id, 2) * pile.jitter_angle;
+
+    let rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle)
+        * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2);
+
+    // only the top of the pile can be seen, the rest are hidden to save draws
+    let visible = index + pile.max_visible >= count;
+
+    (
+        Isometry3::from_parts(Translation3::from(offset), rotation),
+        visible,
+    )
+}
+
+pub fn layout_piles(
+    mut commands: Commands,
+    piles: Query<(Entity, &PileLayout)>,
+    mut transforms: Query<&mut Transform>,
+    mut visibilities: Query<&mut Visibility>,
+) {
+    for (zone, pile) in piles.iter() {
+        let (zone_isometry, cards) = match transforms.get(zone) {
+            Ok(t) => (t.isometry, t.children.clone()),
+            Err(_) => continue,
+        };
+
+        for (index, card) in cards.iter().enumerate() {
+            let (pose, visible) = pile_card_pose(pile, index, cards.len(), card.id());
+
+            if let Ok(mut transform) = transforms.get_mut(*card) {
+                transform.isometry = zone_isometry * pose;
+            }
+
+            match visibilities.get_mut(*card) {
+                Ok(mut visibility) => visibility.visible = visible,
+                Err(_) => {
+                    commands.entity(*card).insert(Visibility { visible });
+                }
+            }
+        }
+    }
+}
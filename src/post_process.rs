@@ -0,0 +1,386 @@
+use bevy_ecs::world::World;
+use wgpu::Device;
+
+use crate::data_types;
+use crate::render_system::{DrawStats, HDR_FORMAT};
+use crate::shader_library::{ShaderId, ShaderLibrary, ShaderStage};
+
+// Resource, apply_render_settings passes changes on to the RenderState. The bloom passes are only
+// recorded while enabled so it costs nothing when off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    pub threshold: f32, // brightest channel of the linear color above which pixels bloom
+    pub intensity: f32, // scale of the blurred result added before tonemapping
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 1.0,
+            intensity: 0.3,
+        }
+    }
+}
+
+pub fn bloom_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let parse = |value: &str| {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|v| *v >= 0.0)
+            .ok_or_else(|| format!("expected a number of at least 0, got {}", value))
+    };
+
+    let mut settings = world.resource_mut::<BloomSettings>();
+    match args {
+        ["on"] => settings.enabled = true,
+        ["off"] => settings.enabled = false,
+        ["threshold", value] => settings.threshold = parse(value)?,
+        ["intensity", value] => settings.intensity = parse(value)?,
+        _ => return Err("expected on, off, threshold <value> or intensity <value>".to_string()),
+    }
+
+    Ok(())
+}
+
+// The bloom chain has at most this many mips, the first is half the frame size.
+const MAX_BLOOM_MIPS: u32 = 6;
+// mips smaller than this add little but blockiness
+const MIN_BLOOM_MIP_SIZE: u32 = 4;
+
+// Number of mips in the chain for a frame size, at least one.
+pub fn bloom_mip_count(width: u32, height: u32) -> u32 {
+    let mut count = 1;
+    let mut size = width.min(height) / 2;
+    while count < MAX_BLOOM_MIPS && size / 2 >= MIN_BLOOM_MIP_SIZE {
+        size /= 2;
+        count += 1;
+    }
+    count
+}
+
+// The bloom texture and what reads from it, all sized to the frame.
+struct BloomTargets {
+    _texture: wgpu::Texture,
+    mip_views: Vec<wgpu::TextureView>,
+    // the prefilter reads the HDR target, every other pass reads one of the mips
+    prefilter_bind_group: wgpu::BindGroup,
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+// Passes run on the HDR target between the main pass and tonemapping. Owns its pipelines and
+// intermediate textures, RenderState recreates the textures when the frame size changes.
+pub struct PostProcess {
+    bloom: BloomSettings,
+    bloom_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+
+    targets: BloomTargets,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Process Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: data_types::Bloom::BINDING_SIZE,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let bloom_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Buffer"),
+            size: data_types::Bloom::BINDING_SIZE.unwrap().into(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        // the blur relies on bilinear taps averaging four texels each
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = shaders.get(ShaderId::BloomWgsl);
+        let create_pipeline = |entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Bloom Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: shader.handle(),
+                    entry_point: shader.entry_point(ShaderStage::Vertex),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader.handle(),
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        let prefilter_pipeline = create_pipeline("fs_prefilter", None);
+        let downsample_pipeline = create_pipeline("fs_downsample", None);
+        let upsample_pipeline = create_pipeline(
+            "fs_upsample",
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+        );
+
+        let targets = create_bloom_targets(
+            device,
+            &bind_group_layout,
+            &bloom_buffer,
+            &sampler,
+            hdr_view,
+            width,
+            height,
+        );
+
+        Self {
+            bloom: BloomSettings::default(),
+            bloom_buffer,
+            bind_group_layout,
+            sampler,
+
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+
+            targets,
+        }
+    }
+
+    // hdr_view is the new HDR target of the given size.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        hdr_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.targets = create_bloom_targets(
+            device,
+            &self.bind_group_layout,
+            &self.bloom_buffer,
+            &self.sampler,
+            hdr_view,
+            width,
+            height,
+        );
+    }
+
+    pub fn bloom(&self) -> BloomSettings {
+        self.bloom
+    }
+
+    pub fn set_bloom(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.bloom = settings;
+
+        let data = data_types::Bloom {
+            threshold: settings.threshold,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.bloom_buffer, 0, bytemuck::cast_slice(&[data]));
+    }
+
+    // The bloom result the tonemap pass adds, black until bloom ran.
+    pub fn bloom_view(&self) -> &wgpu::TextureView {
+        &self.targets.mip_views[0]
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    // Downsamples the HDR target through the mip chain, then blurs back up into the first mip.
+    pub fn render_bloom(&self, encoder: &mut wgpu::CommandEncoder, gpu: &mut DrawStats) {
+        let targets = &self.targets;
+        let last = targets.mip_views.len() - 1;
+
+        let mut pass = |pipeline, bind_group, target, load| {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            rpass.set_pipeline(pipeline);
+            rpass.set_bind_group(0, bind_group, &[]);
+            gpu.bind_group();
+            rpass.draw(0..3, 0..1);
+            gpu.draw_fullscreen();
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        pass(
+            &self.prefilter_pipeline,
+            &targets.prefilter_bind_group,
+            &targets.mip_views[0],
+            clear,
+        );
+        for mip in 1..=last {
+            pass(
+                &self.downsample_pipeline,
+                &targets.mip_bind_groups[mip - 1],
+                &targets.mip_views[mip],
+                clear,
+            );
+        }
+        // each mip keeps its downsampled content and gets the blurred smaller mips added on top
+        for mip in (1..=last).rev() {
+            pass(
+                &self.upsample_pipeline,
+                &targets.mip_bind_groups[mip],
+                &targets.mip_views[mip - 1],
+                wgpu::LoadOp::Load,
+            );
+        }
+    }
+}
+
+fn create_bloom_targets(
+    device: &Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    hdr_view: &wgpu::TextureView,
+    width: u32,
+    height: u32,
+) -> BloomTargets {
+    let mip_count = bloom_mip_count(width, height);
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Bloom Texture"),
+        size: wgpu::Extent3d {
+            width: (width / 2).max(1),
+            height: (height / 2).max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: mip_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+
+    // one view per mip, a pass draws into one mip while reading its neighbour
+    let mip_views: Vec<_> = (0..mip_count)
+        .map(|mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Bloom Mip View"),
+                base_mip_level: mip,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    let create_bind_group = |source| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    };
+
+    BloomTargets {
+        prefilter_bind_group: create_bind_group(hdr_view),
+        mip_bind_groups: mip_views.iter().map(create_bind_group).collect(),
+        mip_views,
+        _texture: texture,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_mip_count_test() {
+        // 400 high: 200, 100, 50, 25, 12, 6
+        assert_eq!(bloom_mip_count(1920, 400), 6);
+        // 64: 32, 16, 8, 4
+        assert_eq!(bloom_mip_count(64, 64), 4);
+        assert_eq!(bloom_mip_count(8, 8), 1);
+        assert_eq!(bloom_mip_count(1, 1), 1);
+    }
+}
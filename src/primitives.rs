@@ -0,0 +1,239 @@
+use std::f32::consts::FRAC_PI_2;
+
+use nalgebra::{Vector2, Vector4};
+
+use crate::{data_types::Vertex, geometry_library::generate_tangents};
+
+// Generated meshes use counter clockwise winding for outward facing triangles, the same as the
+// obj files and the pipelines expect.
+
+// Card shaped slab centered on the origin. The front face points along +z and the back along -z.
+// Both faces get their own half of the texture, u in [0, 0.5] for the front and [0.5, 1] for the
+// back, mirrored so the back reads correctly when the card is flipped. The edge strip gets its own
+// hard normals pointing away from the outline. color is applied to every vertex, pass Vertex::WHITE
+// to leave the texture untinted. Indices are u32 so any corner_segments fits, MeshData narrows them
+// to u16 when possible.
+pub fn rounded_card(
+    width: f32,
+    height: f32,
+    corner_radius: f32,
+    corner_segments: u16,
+    thickness: f32,
+    color: Vector4<f32>,
+) -> (Vec<Vertex>, Vec<u32>) {
+    let radius = corner_radius.max(0.0).min(width / 2.0).min(height / 2.0);
+    let outline = rounded_rect_outline(width, height, radius, corner_segments);
+    let n = outline.len() as u32;
+    let half_thickness = thickness / 2.0;
+
+    let mut vertices = Vec::with_capacity(4 * outline.len() + 2);
+    let mut indices = Vec::with_capacity(12 * outline.len());
+
+    let face_uv = |p: &Vector2<f32>, back: bool| {
+        let u = (p.x / width + 0.5) * 0.5;
+        let v = 0.5 - p.y / height;
+        if back {
+            Vector2::new(1.0 - u, v)
+        } else {
+            Vector2::new(u, v)
+        }
+    };
+
+    for (back, z) in [(false, half_thickness), (true, -half_thickness)] {
+        let normal_z = if back { -1.0 } else { 1.0 };
+        let center = vertices.len() as u32;
+
+        vertices.push(Vertex {
+            position: [0.0, 0.0, z, 1.0].into(),
+            normal: [0.0, 0.0, normal_z, 0.0].into(),
+            texture: face_uv(&Vector2::zeros(), back),
+            color,
+            tangent: Vector4::zeros(),
+        });
+        vertices.extend(outline.iter().map(|(p, _)| Vertex {
+            position: [p.x, p.y, z, 1.0].into(),
+            normal: [0.0, 0.0, normal_z, 0.0].into(),
+            texture: face_uv(p, back),
+            color,
+            tangent: Vector4::zeros(),
+        }));
+
+        // triangle fan around the center, flipped for the back so it faces -z
+        for i in 0..n {
+            let a = center + 1 + i;
+            let b = center + 1 + (i + 1) % n;
+            if back {
+                indices.extend_from_slice(&[center, b, a]);
+            } else {
+                indices.extend_from_slice(&[center, a, b]);
+            }
+        }
+    }
+
+    let edge = vertices.len() as u32;
+    for (i, (p, normal)) in outline.iter().enumerate() {
+        let v = i as f32 / n as f32;
+        for z in [half_thickness, -half_thickness] {
+            vertices.push(Vertex {
+                position: [p.x, p.y, z, 1.0].into(),
+                normal: Vector4::new(normal.x, normal.y, 0.0, 0.0),
+                texture: Vector2::new(0.5, v),
+                color,
+                tangent: Vector4::zeros(),
+            });
+        }
+    }
+
+    // closed strip, the last quad connects back to the first outline point
+    for i in 0..n {
+        let front_a = edge + 2 * i;
+        let back_a = front_a + 1;
+        let front_b = edge + 2 * ((i + 1) % n);
+        let back_b = front_b + 1;
+
+        indices.extend_from_slice(&[front_a, back_a, back_b, front_a, back_b, front_b]);
+    }
+
+    // the edge strip has a constant u so only the faces get tangents
+    generate_tangents(&mut vertices, &indices);
+
+    (vertices, indices)
+}
+
+// Counter clockwise outline of a rounded rectangle as (position, outward normal) pairs, starting at
+// the top right corner. Every corner contributes corner_segments + 1 points along its arc.
+fn rounded_rect_outline(
+    width: f32,
+    height: f32,
+    radius: f32,
+    corner_segments: u16,
+) -> Vec<(Vector2<f32>, Vector2<f32>)> {
+    let segments = corner_segments.max(1);
+    let inner_x = width / 2.0 - radius;
+    let inner_y = height / 2.0 - radius;
+
+    let centers = [
+        Vector2::new(inner_x, inner_y),
+        Vector2::new(-inner_x, inner_y),
+        Vector2::new(-inner_x, -inner_y),
+        Vector2::new(inner_x, -inner_y),
+    ];
+
+    centers
+        .iter()
+        .enumerate()
+        .flat_map(|(corner, center)| {
+            (0..=segments).map(move |s| {
+                let angle = (corner as f32 + s as f32 / segments as f32) * FRAC_PI_2;
+                let normal = Vector2::new(angle.cos(), angle.sin());
+                (center + normal * radius, normal)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const WIDTH: f32 = 0.63;
+    const HEIGHT: f32 = 0.88;
+    const RADIUS: f32 = 0.03;
+
+    fn card(corner_segments: u16) -> (Vec<Vertex>, Vec<u32>) {
+        rounded_card(WIDTH, HEIGHT, RADIUS, corner_segments, 0.01, Vertex::WHITE)
+    }
+
+    #[test]
+    fn vertex_and_index_counts_test() {
+        let (vertices, indices) = card(4);
+
+        // 4 corners of 5 points each, two faces with a center each and two rings for the edge
+        let n = 4 * 5;
+        assert_eq!(vertices.len(), 2 * (n + 1) + 2 * n);
+        // a fan per face and two triangles per edge quad
+        assert_eq!(indices.len(), 2 * 3 * n + 6 * n);
+        assert!(indices.iter().all(|i| (*i as usize) < vertices.len()));
+    }
+
+    #[test]
+    fn corners_lie_on_arc_test() {
+        let (vertices, _) = card(6);
+        let (inner_x, inner_y) = (WIDTH / 2.0 - RADIUS, HEIGHT / 2.0 - RADIUS);
+
+        // skip the front face center, every other front face vertex sits on a corner arc
+        for vertex in &vertices[1..1 + 4 * 7] {
+            let p = vertex.position;
+            let center = Vector2::new(inner_x.copysign(p.x), inner_y.copysign(p.y));
+            let distance = (Vector2::new(p.x, p.y) - center).norm();
+            assert!((distance - RADIUS).abs() < 1e-5, "{} off the arc", distance);
+        }
+    }
+
+    #[test]
+    fn faces_point_outwards_test() {
+        let (vertices, indices) = card(3);
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|i| vertices[i as usize].position.xyz());
+            let face_normal = (b - a).cross(&(c - a));
+            let normal = vertices[triangle[0] as usize].normal.xyz();
+            // counter clockwise around the vertex normal
+            assert!(face_normal.dot(&normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn edge_strip_is_watertight_test() {
+        let (vertices, indices) = card(4);
+        let n = 4 * 5;
+        let strip = &indices[2 * 3 * n..];
+
+        // edges inside the strip are shared by two triangles walking them in opposite directions,
+        // only the front and back rims are left open where the faces attach
+        let mut edges: HashMap<(u32, u32), i32> = HashMap::new();
+        for triangle in strip.chunks_exact(3) {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let (a, b) = (triangle[a], triangle[b]);
+                *edges.entry((a.min(b), a.max(b))).or_default() += if a < b { 1 } else { -1 };
+            }
+        }
+        let mut counts: HashMap<(u32, u32), usize> = HashMap::new();
+        for triangle in strip.chunks_exact(3) {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let (a, b) = (triangle[a], triangle[b]);
+                *counts.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+
+        let open: Vec<_> = counts.iter().filter(|(_, c)| **c == 1).collect();
+        assert_eq!(open.len(), 2 * n, "only the rims may be open");
+        for (edge, count) in &counts {
+            assert!(*count <= 2);
+            if *count == 2 {
+                assert_eq!(edges[edge], 0, "{:?} is walked the same way twice", edge);
+            }
+        }
+
+        // the rims line up with the face outlines
+        let position = |i: u32| vertices[i as usize].position;
+        let edge = 2 * (n + 1) as u32;
+        for i in 0..n as u32 {
+            assert_eq!(position(edge + 2 * i), position(1 + i));
+            assert_eq!(position(edge + 2 * i + 1), position(n as u32 + 2 + i));
+        }
+    }
+
+    #[test]
+    fn many_segments_past_u16_test() {
+        // 80004 outline points, far more vertices than u16 indices can reach
+        let (vertices, indices) = card(20_000);
+        assert!(vertices.len() > u16::MAX as usize);
+        assert_eq!(
+            indices.iter().max().copied(),
+            Some(vertices.len() as u32 - 1)
+        );
+    }
+}
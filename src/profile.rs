@@ -0,0 +1,374 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bevy_ecs::{system::ResMut, world::World};
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::{
+    bindings::Action,
+    render_system::RenderState,
+    texture_library::{TextureHandle, TextureId},
+};
+
+const PROFILE_FILE: &str = "profile.ron";
+const LOCK_FILE: &str = "profile.lock";
+
+// changes are written at most this often, the rest is caught by the save on exit
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerProfile {
+    pub name: String,
+    pub stats: ProfileStats,
+    pub preferences: ProfilePreferences,
+    pub unlocks: BTreeSet<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileStats {
+    pub games_played: u32,
+    pub games_won: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfilePreferences {
+    pub card_sleeve_texture: Option<String>,
+    pub key_bindings: BTreeMap<VirtualKeyCode, Option<Action>>, // applied over the defaults
+}
+
+// Resource owning the loaded profile. All changes go through profile_mut so saving stays in one place.
+pub struct ProfileStore {
+    profile: PlayerProfile,
+    directory: PathBuf,
+
+    dirty: bool,
+    last_save: Instant,
+
+    _lock: Option<ProfileLock>,
+}
+
+impl ProfileStore {
+    pub fn load_default_location() -> Self {
+        let directory = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(env!("CARGO_PKG_NAME"));
+
+        Self::load(&directory)
+    }
+
+    // Never fails, anything that goes wrong is logged and the defaults are used instead.
+    pub fn load(directory: &Path) -> Self {
+        if let Err(e) = fs::create_dir_all(directory) {
+            log::error!(
+                "failed to create profile directory {}: {}",
+                directory.display(),
+                e
+            );
+        }
+
+        let lock = ProfileLock::acquire(&directory.join(LOCK_FILE));
+        let profile = read_profile(&directory.join(PROFILE_FILE));
+
+        Self {
+            profile,
+            directory: directory.to_owned(),
+            dirty: false,
+            last_save: Instant::now(),
+            _lock: lock,
+        }
+    }
+
+    pub fn profile(&self) -> &PlayerProfile {
+        &self.profile
+    }
+
+    pub fn profile_mut(&mut self) -> &mut PlayerProfile {
+        self.dirty = true;
+        &mut self.profile
+    }
+
+    // Texture for newly spawned cards. The sleeve preference names a registered or builtin texture,
+    // without one or when it isn't loaded the uv grid shows where the two faces' regions are. state
+    // is None without a gpu.
+    pub fn card_sleeve(&self, state: Option<&RenderState>) -> TextureHandle {
+        self.profile
+            .preferences
+            .card_sleeve_texture
+            .as_deref()
+            .zip(state)
+            .and_then(|(name, state)| state.texture_handle(name))
+            .unwrap_or_else(|| TextureId::UvGridTexture.into())
+    }
+
+    pub fn save(&mut self) {
+        let path = self.directory.join(PROFILE_FILE);
+        match write_profile(&path, &self.profile) {
+            Ok(()) => self.dirty = false,
+            Err(e) => log::error!("failed to save profile {}: {}", path.display(), e),
+        }
+        self.last_save = Instant::now();
+    }
+
+    pub fn save_if_dirty(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+}
+
+// Shutdown system, writes any changes the debounced save has not picked up yet.
+pub fn flush_profile(mut store: ResMut<ProfileStore>) {
+    store.save_if_dirty();
+}
+
+pub fn save_profile(mut store: ResMut<ProfileStore>) {
+    if store.dirty && store.last_save.elapsed() >= SAVE_INTERVAL {
+        store.save();
+    }
+}
+
+// Changes are written by save_profile like any other profile change.
+pub fn profile_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let mut store = world.resource_mut::<ProfileStore>();
+    match args {
+        [] => {
+            let profile = store.profile();
+            log::info!(
+                "{:?}, {} games played, {} won, card sleeve {:?}",
+                profile.name,
+                profile.stats.games_played,
+                profile.stats.games_won,
+                profile.preferences.card_sleeve_texture
+            );
+        }
+        ["name", name] => store.profile_mut().name = name.to_string(),
+        ["sleeve", "none"] => store.profile_mut().preferences.card_sleeve_texture = None,
+        ["sleeve", texture] => {
+            store.profile_mut().preferences.card_sleeve_texture = Some(texture.to_string())
+        }
+        _ => return Err("expected name or sleeve".to_owned()),
+    }
+
+    Ok(())
+}
+
+fn read_profile(path: &Path) -> PlayerProfile {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return PlayerProfile::default(),
+        Err(e) => {
+            log::error!("failed to read profile {}: {}", path.display(), e);
+            return PlayerProfile::default();
+        }
+    };
+
+    match ron::from_str(&contents) {
+        Ok(profile) => profile,
+        Err(e) => {
+            // keep the broken file around so it can be recovered by hand
+            let backup = path.with_extension(format!("ron.corrupt-{}", unix_time()));
+            log::error!(
+                "profile {} is corrupt ({}), moving it to {} and using defaults",
+                path.display(),
+                e,
+                backup.display()
+            );
+            if let Err(e) = fs::rename(path, &backup) {
+                log::error!("failed to back up corrupt profile: {}", e);
+            }
+
+            PlayerProfile::default()
+        }
+    }
+}
+
+// Written to a temporary file first so a crash mid write can't corrupt the existing profile.
+fn write_profile(path: &Path, profile: &PlayerProfile) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = ron::ser::to_string_pretty(profile, ron::ser::PrettyConfig::default())?;
+
+    let temporary = path.with_extension("ron.tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Lock file marking the profile as in use. A second instance only gets a warning, the profile
+// still loads but both instances will overwrite each other's saves. A lock left behind by a crash
+// is taken over once its pid is no longer running.
+struct ProfileLock {
+    path: PathBuf,
+}
+
+impl ProfileLock {
+    fn acquire(path: &Path) -> Option<Self> {
+        match Self::create(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            result => return Self::created(path, result),
+        }
+
+        let owner = fs::read_to_string(path).unwrap_or_default();
+        match owner.trim().parse() {
+            Ok(pid) if process_running(pid) => {
+                log::warn!(
+                    "profile is locked by another instance (pid {}), saves from this instance may be lost. remove {} if no other instance is running",
+                    pid,
+                    path.display()
+                );
+                return None;
+            }
+            _ => log::info!(
+                "taking over stale profile lock {} (pid {})",
+                path.display(),
+                owner.trim()
+            ),
+        }
+
+        // another instance taking it over at the same time makes one of the creates fail
+        if let Err(e) = fs::remove_file(path) {
+            log::error!(
+                "failed to remove stale profile lock {}: {}",
+                path.display(),
+                e
+            );
+            return None;
+        }
+        Self::created(path, Self::create(path))
+    }
+
+    fn create(path: &Path) -> std::io::Result<File> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(file)
+    }
+
+    fn created(path: &Path, result: std::io::Result<File>) -> Option<Self> {
+        match result {
+            Ok(_) => Some(Self {
+                path: path.to_owned(),
+            }),
+            Err(e) => {
+                log::error!("failed to create profile lock {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+// Without a portable way to ask, other platforms assume the owner is running and keep warning.
+#[cfg(target_os = "linux")]
+fn process_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+impl Drop for ProfileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("card_game_{}_{}.lock", name, std::process::id()))
+    }
+
+    #[test]
+    fn profile_command_test() {
+        let directory =
+            std::env::temp_dir().join(format!("card_game_command_{}", std::process::id()));
+        let mut world = World::new();
+        world.insert_resource(ProfileStore::load(&directory));
+
+        profile_command(&mut world, &["name", "player"]).unwrap();
+        profile_command(&mut world, &["sleeve", "red"]).unwrap();
+        profile_command(&mut world, &[]).unwrap();
+        assert!(profile_command(&mut world, &["sleeve"]).is_err());
+
+        let mut store = world.remove_resource::<ProfileStore>().unwrap();
+        assert!(store.dirty);
+        store.save_if_dirty();
+        drop(store);
+
+        let profile = ProfileStore::load(&directory).profile().clone();
+        assert_eq!(profile.name, "player");
+        assert_eq!(
+            profile.preferences.card_sleeve_texture.as_deref(),
+            Some("red")
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn lock_held_and_released_test() {
+        let path = lock_path("held");
+
+        let lock = ProfileLock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+        // this process is running, so a second instance must not take it
+        assert!(ProfileLock::acquire(&path).is_none());
+        assert!(path.exists());
+
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stale_lock_taken_over_test() {
+        let path = lock_path("stale");
+        // above the largest pid linux hands out
+        fs::write(&path, u32::MAX.to_string()).unwrap();
+
+        let lock = ProfileLock::acquire(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unreadable_lock_taken_over_test() {
+        // a crash between creating the lock and writing the pid leaves it empty
+        for (i, contents) in ["", "not a pid"].iter().enumerate() {
+            let path = lock_path(&format!("unreadable{}", i));
+            fs::write(&path, contents).unwrap();
+
+            let lock = ProfileLock::acquire(&path);
+            assert!(lock.is_some(), "{:?} was not taken over", contents);
+            drop(lock);
+            assert!(!path.exists());
+        }
+    }
+}
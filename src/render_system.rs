@@ -1,564 +1,3973 @@
-use bevy_ecs::system::{Query, ResMut};
-use nalgebra::{Matrix4, Vector4};
+use bevy_ecs::{
+    change_detection::DetectChanges,
+    entity::Entity,
+    system::{Local, Query, Res, ResMut, SystemParam},
+};
+use std::{
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    num::NonZeroU32,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use bytemuck::Zeroable;
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
 use wgpu::{Adapter, Device, Instance, Queue, Surface};
 
 use winit::{dpi::PhysicalSize, window::Window};
 
+use crate::camera_cut::CameraDirector;
+use crate::camera_shake::CameraShake;
 use crate::common_component::{
-    Camera, GlobalLight, MainCamera, PointLight, RenderGeometry, SpotLight, Texture, Transform,
+    AmbientLight, AnimatedTexture, Camera, DepthBias, GlobalLight, MainCamera, Material,
+    MaterialParams, NormalMap, PointLight, RenderGeometry, RenderLayers, SortKey, SpotLight,
+    Texture, Tint, Transform, Visibility,
 };
+use crate::culling::Frustum;
+use crate::fog::Fog;
+use crate::frame_scratch::FrameScratch;
 use crate::geometry_library::{GeometryId, GeometryLibrary};
-use crate::shader_library::{ShaderId, ShaderLibrary};
+use crate::interpolation::{self, PreviousTransform};
+use crate::light_lod::{LightLod, LightLodStats};
+use crate::light_tiles::{self, LightTiles, SPOT_LIGHT_BIT, TILE_COLUMNS, TILE_COUNT, TILE_ROWS};
+use crate::material::DissolveParams;
+use crate::math;
+use crate::post_process::{BloomSettings, PostProcess};
+use crate::screenshot::{self, PendingScreenshot, SaveProgress};
+use crate::shader_library::{
+    Shader, ShaderError, ShaderFlags, ShaderHandle, ShaderId, ShaderLibrary, ShaderStage,
+    ShaderVariant,
+};
+use crate::shadow::{self, SHADOW_CASTER_MARGIN, SHADOW_DISTANCE, SHADOW_MAP_SIZE};
+use crate::shadow_atlas::{ShadowAtlas, ShadowTile, SHADOW_ATLAS_HEIGHT, SHADOW_ATLAS_WIDTH};
+use crate::spot_cookies::SpotCookies;
 
 use crate::data_types::{
-    self, GlobalLight as GlobalLightData, PointLight as PointLightData, SpotLight as SpotLightData,
-    Vertex,
+    self, pack_fixed, AmbientLight as AmbientLightData, GlobalLight as GlobalLightData,
+    Instance as InstanceData, LightCounts, LightTileHeader, LineVertex,
+    PointLight as PointLightData, Shadow as ShadowData, SpotLight as SpotLightData, Vertex,
 };
-use crate::texture_library::{TextureId, TextureLibrary};
+use crate::texture_library::{self, DynamicTextures, TextureHandle, TextureLibrary, VolumeTexture};
+use crate::time::{BackgroundThrottle, TimeResource};
+use crate::tonemap::{ColorGrading, TonemapSettings};
 use crate::util::BlockOn;
 
-const PUSH_CONSTANT_SIZE: u32 = std::mem::size_of::<Matrix4<f32>>() as u32;
-
 const MAX_GLOBAL_LIGHTS: usize = 8;
 const MAX_POINT_LIGHTS: usize = 8;
 const MAX_SPOT_LIGHTS: usize = 8;
+// point lights when they live in a storage buffer, see PointLightPath
+const MAX_STORAGE_POINT_LIGHTS: usize = 1024;
+
+// DepthBias values are clamped to this many levels on either side of zero, each level gets its own
+// pipeline.
+const MAX_DEPTH_BIAS_LEVEL: i32 = 2;
+const DEPTH_BIAS_LEVELS: usize = (2 * MAX_DEPTH_BIAS_LEVEL + 1) as usize;
+const DEPTH_BIAS_CONSTANT_STEP: i32 = 4;
+const DEPTH_BIAS_SLOPE_STEP: f32 = 1.0;
+
+// Index into the per bias pipelines, level 0 is the unbiased pipeline.
+pub fn depth_bias_level(bias: Option<&DepthBias>) -> usize {
+    let level = bias.map_or(0, |b| {
+        b.0.clamp(-MAX_DEPTH_BIAS_LEVEL, MAX_DEPTH_BIAS_LEVEL)
+    });
+
+    (level + MAX_DEPTH_BIAS_LEVEL) as usize
+}
 
-// Render System
-pub fn render(
-    mut state: ResMut<RenderState>,
-    camera: Query<(&Camera, &Transform, &MainCamera)>,
-    objects: Query<(&RenderGeometry, &Transform, Option<&Texture>)>,
-    global_lights: Query<&GlobalLight>,
-    point_lights: Query<(&PointLight, &Transform)>,
-    spot_lights: Query<(&SpotLight, &Transform)>,
-) {
-    match camera.get_single() {
-        Ok((cam, cam_pos, _)) => {
-            // grab transformation matrices for push constants
-            let mut objects = objects
-                .iter()
-                .map(|(RenderGeometry { geom_type }, pos, texture)| {
-                    let t_id = match texture {
-                        Some(s) => Some(s.texture_id),
-                        None => None,
-                    };
+// Less depth is closer to the camera so positive DepthBias values become negative hardware bias.
+fn depth_bias_state(level: usize) -> wgpu::DepthBiasState {
+    let level = level as i32 - MAX_DEPTH_BIAS_LEVEL;
+
+    wgpu::DepthBiasState {
+        constant: -level * DEPTH_BIAS_CONSTANT_STEP,
+        slope_scale: -level as f32 * DEPTH_BIAS_SLOPE_STEP,
+        clamp: 0.0,
+    }
+}
 
-                    (*geom_type, pos.isometry.to_matrix(), t_id)
-                });
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    pub geometry: GeometryId,
+    pub submesh: usize, // index into the mesh's submeshes
+    pub model: Matrix4<f32>,
+    pub scale: Vector3<f32>,
+    pub params: MaterialParams,
+    pub tint: Tint,
+    pub material: Material,
+    pub texture: Option<TextureHandle>,
+    pub normal_map: Option<TextureHandle>,
+    pub layer: u32,
+    pub bias_level: usize,
+    pub sort_key: i32,
+}
 
-            let view_projection: Matrix4<f32> =
-                cam.projection.as_matrix() * cam_pos.isometry.inverse().to_matrix();
+impl DrawItem {
+    // Fragments can be discarded, such draws can't have their depth written ahead by the depth
+    // pre pass.
+    pub fn discards(&self) -> bool {
+        DissolveParams::read_from(&self.params).amount > 0.0
+    }
+}
 
-            let p = cam_pos.isometry.translation.vector;
+// Run of instances sharing a pipeline, mesh and texture, drawn with one call.
+#[derive(Clone, Debug)]
+pub struct DrawBatch {
+    pub geometry: GeometryId,
+    pub submesh: usize,
+    pub texture: Option<TextureHandle>,
+    pub normal_map: Option<TextureHandle>,
+    pub bias_level: usize,
+    pub discards: bool,
+    pub instances: Range<u32>,
+}
 
-            let cam = data_types::Camera {
-                view_projection,
-                position: Vector4::new(p.x, p.y, p.z, 1.0),
-            };
+// What the shadow passes draw this frame. batches are every caster before culling, drawn into the
+// global shadow map when global is set and into each spot light's atlas tile. spot_tiles is
+// indexed like the submitted spot lights.
+#[derive(Clone, Copy)]
+pub struct ShadowPasses<'a> {
+    pub batches: &'a [DrawBatch],
+    pub global: bool,
+    pub spot_tiles: &'a [Option<ShadowTile>],
+}
 
-            let global_lights: Box<[GlobalLightData]> = global_lights
-                .iter()
-                .map(|tuple| tuple.into())
-                .take(MAX_GLOBAL_LIGHTS)
-                .collect();
+// Groups draws by pipeline, then by textures and then by mesh, so they can be instanced and
+// texture bind groups change as rarely as possible. Stable so objects sharing all of those keep
+// query order. SortKey comes right after the pipeline and decides the order of everything else.
+pub fn sort_draws(draws: &mut [DrawItem]) {
+    draws.sort_by_key(|draw| {
+        (
+            draw.bias_level,
+            draw.sort_key,
+            draw.texture,
+            draw.normal_map,
+            draw.geometry,
+            draw.submesh,
+        )
+    });
+}
 
-            let point_lights: Box<[PointLightData]> = point_lights
-                .iter()
-                .map(|tuple| tuple.into())
-                .take(MAX_POINT_LIGHTS)
-                .collect();
+// Merges neighbouring draws into batches, draws must already be sorted so matching ones are next
+// to each other.
+pub fn batch_draws(
+    draws: &[DrawItem],
+    instances: &mut Vec<InstanceData>,
+    batches: &mut Vec<DrawBatch>,
+) {
+    for draw in draws {
+        let index = instances.len() as u32;
+        instances.push(InstanceData {
+            model: draw.model,
+            params: draw.params.0,
+            scale: draw.scale.push(draw.layer as f32),
+            tint: draw.tint.0,
+            material: (&draw.material).into(),
+        });
 
-            let spot_lights: Box<[SpotLightData]> = spot_lights
-                .iter()
-                .map(|tuple| tuple.into())
-                .take(MAX_POINT_LIGHTS)
-                .collect();
-
-            let mut point_light_data = [PointLightData::default(); MAX_POINT_LIGHTS];
-
-            assert!(point_lights.len() <= MAX_POINT_LIGHTS); // This assert probably isn't needed
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    point_lights.as_ptr(),
-                    point_light_data.as_mut_ptr(),
-                    point_lights.len(),
-                )
+        match batches.last_mut() {
+            Some(batch)
+                if batch.geometry == draw.geometry
+                    && batch.submesh == draw.submesh
+                    && batch.texture == draw.texture
+                    && batch.normal_map == draw.normal_map
+                    && batch.bias_level == draw.bias_level
+                    && batch.discards == draw.discards() =>
+            {
+                batch.instances.end = index + 1;
             }
+            _ => batches.push(DrawBatch {
+                geometry: draw.geometry,
+                submesh: draw.submesh,
+                texture: draw.texture,
+                normal_map: draw.normal_map,
+                bias_level: draw.bias_level,
+                discards: draw.discards(),
+                instances: index..index + 1,
+            }),
+        }
+    }
+}
 
-            state
-                .queue
-                .write_buffer(&state.camera_buffer, 0, bytemuck::cast_slice(&[cam]));
-            state.queue.write_buffer(
-                &state.light_buffer,
-                0,
-                bytemuck::cast_slice(&point_light_data),
-            );
+// Where the fragment shader reads point lights from. Storage lifts the MAX_POINT_LIGHTS cap but
+// needs storage buffers in fragment shaders, which downlevel devices like WebGL lack. It also
+// culls point and spot lights per screen tile, in a second storage buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointLightPath {
+    Uniform,
+    Storage,
+}
+
+impl PointLightPath {
+    // CARD_GAME_UNIFORM_LIGHTS forces the uniform path on devices that could do storage.
+    pub fn choose(downlevel: &wgpu::DownlevelCapabilities, limits: &wgpu::Limits) -> Self {
+        let storage_size =
+            (std::mem::size_of::<PointLightData>() * MAX_STORAGE_POINT_LIGHTS) as u64;
+
+        if std::env::var_os("CARD_GAME_UNIFORM_LIGHTS").is_none()
+            && downlevel
+                .flags
+                .contains(wgpu::DownlevelFlags::FRAGMENT_STORAGE)
+            && limits.max_storage_buffers_per_shader_stage >= 2
+            && limits.max_storage_buffer_binding_size as u64 >= storage_size
+        {
+            PointLightPath::Storage
+        } else {
+            PointLightPath::Uniform
+        }
+    }
 
-            state.render(&mut objects);
+    pub fn capacity(self) -> usize {
+        match self {
+            PointLightPath::Uniform => MAX_POINT_LIGHTS,
+            PointLightPath::Storage => MAX_STORAGE_POINT_LIGHTS,
         }
-        Err(e) => log::error!("failed to access main camera entity for render call: {}", e),
     }
 }
 
-pub struct RenderState {
-    _instance: Instance,
-    surface: Surface,
-    surface_config: wgpu::SurfaceConfiguration,
-    _adapter: Adapter,
-    device: Device,
-    queue: Queue,
+// Counters from the last rendered frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub drawn_objects: usize,
+    pub culled_objects: usize, // outside the view frustum
+    pub hidden_objects: usize, // by their Visibility, not counted as culled
+    pub hidden_lights: usize,  // of every type, not counted in the light stats
+    pub lights_per_tile: f32,  // average over the screen tiles, 0 without tiled light culling
+    pub point_lights: LightLodStats,
+    pub spot_lights: LightLodStats,
+    pub gpu: DrawStats,
+    pub scratch_peak_bytes: usize, // FrameScratch high-water mark over every frame so far
+}
 
-    render_pipeline: wgpu::RenderPipeline,
+// Work recorded for the gpu in one frame, over every pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: usize,
+    pub instances: usize,
+    pub triangles: usize, // of every instance, lines are not counted
+    pub bind_group_switches: usize,
+    pub bytes_written: usize, // through queue.write_buffer, texture uploads are not counted
+}
 
-    /*
-    light_assignment_pipeline: wgpu::ComputePipeline,
-    light_assignment_bind_group: wgpu::BindGroup,
-     */
-    camera_bind_group: wgpu::BindGroup,
-    camera_buffer: wgpu::Buffer,
+impl DrawStats {
+    pub fn draw_indexed(&mut self, index_len: u32, instances: &Range<u32>) {
+        let instance_count = instances.len();
+        self.draw_calls += 1;
+        self.instances += instance_count;
+        self.triangles += index_len as usize / 3 * instance_count;
+    }
 
-    light_bind_group: wgpu::BindGroup,
-    light_buffer: wgpu::Buffer,
+    pub fn draw_lines(&mut self) {
+        self.draw_calls += 1;
+        self.instances += 1;
+    }
 
-    _depth_stencil_texture: wgpu::Texture,
-    depth_stencil_view: wgpu::TextureView,
-    _depth_stencil_sampler: wgpu::Sampler,
+    // A single triangle covering the screen.
+    pub fn draw_fullscreen(&mut self) {
+        self.draw_calls += 1;
+        self.instances += 1;
+        self.triangles += 1;
+    }
 
-    texture_library: TextureLibrary,
+    pub fn bind_group(&mut self) {
+        self.bind_group_switches += 1;
+    }
 
-    _shader_library: ShaderLibrary,
-    geometry_library: GeometryLibrary,
+    pub fn write(&mut self, queue: &Queue, buffer: &wgpu::Buffer, offset: u64, data: &[u8]) {
+        self.bytes_written += data.len();
+        queue.write_buffer(buffer, offset, data);
+    }
 }
 
-impl RenderState {
-    pub fn init(window: &Window) -> Self {
-        let size = window.inner_size();
-        let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
-        let surface = unsafe { instance.create_surface(&window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .block_on()
-            .expect("failed to find appropriate adapter");
+type CameraItem = (
+    &'static Camera,
+    &'static Transform,
+    Option<&'static PreviousTransform>,
+    Option<&'static CameraShake>,
+    &'static MainCamera,
+);
+
+type ObjectItem = (
+    &'static RenderGeometry,
+    &'static Transform,
+    Option<&'static Texture>,
+    Option<&'static DepthBias>,
+    Option<&'static SortKey>,
+    Option<&'static Visibility>,
+    Option<&'static MaterialParams>,
+    Option<&'static PreviousTransform>,
+    Option<&'static AnimatedTexture>,
+    Option<&'static Tint>,
+    Option<&'static Material>,
+    Option<&'static NormalMap>,
+    Option<&'static RenderLayers>,
+);
+
+type GlobalLightItem = (
+    &'static GlobalLight,
+    Option<&'static Visibility>,
+    Option<&'static RenderLayers>,
+);
+
+// Point and spot lights, L is the light component.
+type LocalLightItem<L> = (
+    Entity,
+    &'static L,
+    &'static Transform,
+    Option<&'static Visibility>,
+    Option<&'static RenderLayers>,
+);
+
+// The main camera and the view presented in its place during a cut.
+#[derive(SystemParam)]
+pub struct RenderCamera<'w, 's> {
+    query: Query<'w, 's, CameraItem>,
+    director: Option<Res<'w, CameraDirector>>,
+}
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::PUSH_CONSTANTS,
-                    limits: wgpu::Limits {
-                        max_push_constant_size: PUSH_CONSTANT_SIZE,
-                        ..Default::default()
-                    }
-                    .using_resolution(adapter.limits()), //wgpu::Limits::downlevel_defaults(),
-                },
-                None,
-            )
-            .block_on()
-            .expect("failed to create appropriate device");
+// last_culling holds the counts last logged, they are only logged again once they change.
+#[derive(SystemParam)]
+pub struct RenderObjects<'w, 's> {
+    query: Query<'w, 's, ObjectItem>,
+    last_culling: Local<'s, (usize, usize, usize)>,
+}
 
-        let shader_library = ShaderLibrary::load_all(&device);
+#[derive(SystemParam)]
+pub struct RenderLights<'w, 's> {
+    global: Query<'w, 's, GlobalLightItem>,
+    point: Query<'w, 's, LocalLightItem<PointLight>>,
+    spot: Query<'w, 's, LocalLightItem<SpotLight>>,
+    ambient: Option<Res<'w, AmbientLight>>,
+    lod: ResMut<'w, LightLod>,
+    global_overflow: Local<'s, bool>, // warned about too many global lights
+    shadow_atlas: Local<'s, ShadowAtlas>,
+    tiles: Local<'s, LightTiles>,
+}
 
-        //let light_assignment_shader = shader_library.get(ShaderId::LightAssignment).clone();
-        let fragment_shader = shader_library.get(ShaderId::FragmentShader).clone();
-        let vertex_shader = shader_library.get(ShaderId::VertexShader).clone();
+#[derive(SystemParam)]
+pub struct FrameContext<'w, 's> {
+    throttle: Option<Res<'w, BackgroundThrottle>>,
+    time: Res<'w, TimeResource>,
+    tonemap: Option<Res<'w, TonemapSettings>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
 
-        let geometry_library = GeometryLibrary::load_all(&device);
+// Render System
+pub fn render(
+    mut state: ResMut<RenderState>,
+    camera: RenderCamera,
+    objects: RenderObjects,
+    lights: RenderLights,
+    mut scratch: ResMut<FrameScratch>,
+    mut stats: ResMut<RenderStats>,
+    frame: FrameContext,
+) {
+    let RenderCamera {
+        query: camera,
+        director,
+    } = camera;
+    let RenderObjects {
+        query: objects,
+        mut last_culling,
+    } = objects;
+    let RenderLights {
+        global: global_lights,
+        point: point_lights,
+        spot: spot_lights,
+        ambient: ambient_light,
+        lod: mut light_lod,
+        global_overflow: mut global_light_overflow,
+        mut shadow_atlas,
+        tiles: mut light_tiles,
+    } = lights;
+    let FrameContext {
+        throttle,
+        time,
+        tonemap,
+        ..
+    } = frame;
+
+    *stats = RenderStats::default();
+
+    if throttle.is_some_and(|t| t.rendering_suspended()) {
+        return;
+    }
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Camera Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::all(),
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: data_types::Camera::BINDING_SIZE,
-                    },
-                    count: None,
-                }],
-            });
+    match camera.get_single() {
+        Ok((cam, cam_pos, cam_previous, shake, _)) => {
+            let scratch = &mut *scratch;
+            let stats = &mut *stats;
+            let blend = time.blend();
+            let texture_library = &state.texture_library;
+            let geometry_library = &state.geometry_library;
+
+            // objects and lights on none of the camera's layers don't exist as far as this frame
+            // goes, they aren't counted as hidden or culled either
+            let on_camera_layers =
+                |layers: Option<&RenderLayers>| RenderLayers::of(layers).intersects(&cam.layers);
+
+            // grab transformation matrices for the instance buffer
+            scratch.draws.extend(
+                objects
+                    .iter()
+                    .filter(|(.., layers)| on_camera_layers(*layers))
+                    .filter(|(_, _, _, _, _, visibility, ..)| {
+                        let visible = Visibility::is_visible(*visibility);
+                        stats.hidden_objects += !visible as usize;
+                        visible
+                    })
+                    .flat_map(
+                        |(
+                            RenderGeometry { geom_type },
+                            pos,
+                            texture,
+                            bias,
+                            sort_key,
+                            _,
+                            params,
+                            previous,
+                            animated,
+                            tint,
+                            material,
+                            normal_map,
+                            _,
+                        )| {
+                            let (isometry, scale) =
+                                interpolation::interpolated(previous, pos, blend);
+                            let model = math::transform_matrix(&isometry, &scale);
+                            let tint = tint.copied().unwrap_or_default();
+                            let mesh = geometry_library.get(*geom_type);
+
+                            // one draw per submesh, a Texture component replaces the texture of
+                            // every mtl material
+                            mesh.submeshes
+                                .iter()
+                                .enumerate()
+                                .map(move |(index, submesh)| {
+                                    let mesh_material =
+                                        submesh.material.map(|m| &mesh.materials[m]);
+                                    let texture = texture
+                                        .map(|t| t.handle)
+                                        .or_else(|| mesh_material.and_then(|m| m.texture));
+                                    let tint = match mesh_material {
+                                        Some(m) if m.texture.is_none() => {
+                                            Tint(tint.0.component_mul(&m.diffuse.push(1.0)))
+                                        }
+                                        _ => tint,
+                                    };
+                                    let layer = animated.map_or(0, |animated| {
+                                        animated.frame
+                                            % texture_library.get_or_default(texture).layers
+                                    });
+
+                                    DrawItem {
+                                        geometry: *geom_type,
+                                        submesh: index,
+                                        model,
+                                        scale,
+                                        params: params.copied().unwrap_or_default(),
+                                        tint,
+                                        material: material.copied().unwrap_or_default(),
+                                        texture,
+                                        normal_map: normal_map.map(|n| n.handle),
+                                        layer,
+                                        bias_level: depth_bias_level(bias),
+                                        sort_key: sort_key.map_or(0, |k| k.0),
+                                    }
+                                })
+                        },
+                    ),
+            );
 
-        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Camera Buffer"),
-            size: data_types::Camera::BINDING_SIZE.unwrap().into(),
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-            mapped_at_creation: false,
-        });
+            // an active camera cut replaces the gameplay pose for this frame only
+            let (cam_pose, _) = interpolation::interpolated(cam_previous, cam_pos, blend);
+            let (cam, mut cam_isometry) = match &director {
+                Some(director) => director.presented(cam, &cam_pose),
+                None => (cam.clone(), cam_pose),
+            };
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Camera Bind Group"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &camera_buffer,
-                    offset: 0,
-                    size: data_types::Camera::BINDING_SIZE,
-                }),
-            }],
-        });
+            // shake only affects the view, the camera's transform stays untouched
+            if let Some(shake) = shake {
+                cam_isometry = shake.apply(&cam_isometry, blend);
+            }
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Texture Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+            let view_projection = cam.view_projection(&cam_isometry);
 
-        let texture_library = TextureLibrary::load_all(&device, &queue, &texture_bind_group_layout);
+            sort_draws(&mut scratch.draws);
 
-        let light_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Light Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
+            let visible_global_lights = || {
+                global_lights
+                    .iter()
+                    .filter(|(_, visibility, layers)| {
+                        Visibility::is_visible(*visibility) && on_camera_layers(*layers)
+                    })
+                    .map(|(light, ..)| light)
+            };
+
+            // only the first global light casts shadows, and spot lights given a tile of the shadow
+            // atlas. Casters outside the camera's view still cast into it so the shadow passes get
+            // their batches before culling
+            let light_view_projection = visible_global_lights().next().map(|light| {
+                let corners =
+                    shadow::frustum_corners(&cam.projection, &cam_isometry, SHADOW_DISTANCE);
+                shadow::light_view_projection(&light.direction, &corners, SHADOW_CASTER_MARGIN)
+            });
+            if light_view_projection.is_some() || !spot_lights.is_empty() {
+                batch_draws(
+                    &scratch.draws,
+                    &mut scratch.instances,
+                    &mut scratch.shadow_batches,
+                );
+            }
+
+            let frustum = Frustum::from_view_projection(&view_projection);
+            let object_count = scratch.draws.len();
+            scratch.draws.retain(|draw| {
+                let bounds = geometry_library.get(draw.geometry).bounds;
+                frustum.intersects_aabb(&bounds.transformed(&draw.model))
             });
+            stats.drawn_objects = scratch.draws.len();
+            stats.culled_objects = object_count - stats.drawn_objects;
 
-        let global_light_size = (std::mem::size_of::<GlobalLightData>() * 8) as u64;
-        let point_light_size = (std::mem::size_of::<PointLightData>() * 8) as u64;
-        let spot_light_size = (std::mem::size_of::<SpotLightData>() * 8) as u64;
+            let culling = (
+                stats.drawn_objects,
+                stats.culled_objects,
+                stats.hidden_objects,
+            );
+            if *last_culling != culling {
+                log::debug!(
+                    "drawing {} objects, {} culled, {} hidden",
+                    culling.0,
+                    culling.1,
+                    culling.2
+                );
+                *last_culling = culling;
+            }
 
-        let global_light_offset = 0;
-        let point_light_offset = global_light_size;
-        let spot_light_offset = point_light_offset + point_light_size;
+            batch_draws(&scratch.draws, &mut scratch.instances, &mut scratch.batches);
 
-        let light_buffer_size: u64 = global_light_size + point_light_size + spot_light_size;
+            let p = cam_isometry.translation.vector;
 
-        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Light Buffer"),
-            size: light_buffer_size,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
-            mapped_at_creation: false,
-        });
+            let cam = data_types::Camera {
+                view_projection,
+                position: p,
+                forward: cam_isometry.rotation * -Vector3::z(),
+                ..state.fog.uniform_fields()
+            };
 
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Light Bind Group"),
-            layout: &light_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &light_buffer,
-                        offset: global_light_offset,
-                        size: None,
-                    }),
+            // debug views show their values as is
+            let tonemap = match state.render_mode.debug_flags() {
+                Some(_) => TonemapSettings::default(),
+                None => tonemap.map_or_else(TonemapSettings::default, |t| *t),
+            };
+            let compare = tonemap.compare.unwrap_or(tonemap.operator);
+            let tonemap_data = data_types::Tonemap {
+                op: tonemap.operator.shader_id(),
+                compare_op: compare.shader_id(),
+                exposure: tonemap.exposure_for(tonemap.operator),
+                compare_exposure: tonemap.exposure_for(compare),
+                split_x: match tonemap.compare {
+                    Some(_) => state.surface_config.width as f32 / 2.0,
+                    None => 0.0,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &light_buffer,
-                        offset: point_light_offset,
-                        size: None,
-                    }),
+                manual_gamma: state.needs_manual_gamma.0 as u32,
+                bloom_intensity: match state.bloom_active() {
+                    true => state.post_process.bloom().intensity,
+                    false => 0.0,
                 },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                        buffer: &light_buffer,
-                        offset: spot_light_offset,
-                        size: None,
-                    }),
+                grading_strength: match state.render_mode.debug_flags() {
+                    Some(_) => 0.0,
+                    None => state.color_grading.strength,
                 },
-            ],
-        });
-
-        let depth_stencil_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: size.width,
-                height: size.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-        });
+            };
 
-        let depth_stencil_view =
-            depth_stencil_texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let depth_stencil_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            ..Default::default()
-        });
+            // global lights have no lod to pick the important ones, extras are just dropped
+            let global_light_count = visible_global_lights().count();
+            stats.hidden_lights = global_lights
+                .iter()
+                .filter(|(_, visibility, layers)| {
+                    !Visibility::is_visible(*visibility) && on_camera_layers(*layers)
+                })
+                .count();
+            if global_light_count > MAX_GLOBAL_LIGHTS && !*global_light_overflow {
+                log::warn!(
+                    "{} global lights in the scene, only the first {} are drawn",
+                    global_light_count,
+                    MAX_GLOBAL_LIGHTS
+                );
+            }
+            *global_light_overflow = global_light_count > MAX_GLOBAL_LIGHTS;
 
-        /*
-        let light_assignment_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Light Assignment Bind Group Layout"),
-                entries: &[],
-            });
+            scratch.global_lights.extend(
+                visible_global_lights()
+                    .map(GlobalLightData::from)
+                    .take(MAX_GLOBAL_LIGHTS),
+            );
 
-        let light_assignment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Light Assignment Bind Group"),
-            layout: &light_assignment_bind_group_layout,
-            entries: &[],
-        });
+            let mut hidden_lights = 0;
+            let mut visible = |visibility: Option<&Visibility>| {
+                let visible = Visibility::is_visible(visibility);
+                hidden_lights += !visible as usize;
+                visible
+            };
 
-        let light_assignment_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Assignment Pipeline Layout"),
-                bind_group_layouts: &[&global_light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+            light_lod.gather(
+                &p,
+                &frustum,
+                point_lights
+                    .iter()
+                    .filter(|(.., layers)| on_camera_layers(*layers))
+                    .filter(|(.., visibility, _)| visible(*visibility))
+                    .map(|(entity, light, transform, ..)| (entity, light, transform)),
+                &mut scratch.point_light_candidates,
+                &mut stats.point_lights,
+            );
+            light_lod.select_point_lights(
+                &mut scratch.point_light_candidates,
+                state.point_light_path.capacity(),
+                &mut scratch.point_lights,
+            );
+            stats.point_lights.submitted = scratch.point_lights.len();
+
+            light_lod.gather(
+                &p,
+                &frustum,
+                spot_lights
+                    .iter()
+                    .filter(|(.., layers)| on_camera_layers(*layers))
+                    .filter(|(.., visibility, _)| visible(*visibility))
+                    .map(|(entity, light, transform, ..)| (entity, light, transform)),
+                &mut scratch.spot_light_candidates,
+                &mut stats.spot_lights,
+            );
+            light_lod.select_spot_lights(
+                &mut scratch.spot_light_candidates,
+                MAX_SPOT_LIGHTS,
+                &mut scratch.spot_lights,
+            );
+            stats.spot_lights.submitted = scratch.spot_lights.len();
+            stats.hidden_lights += hidden_lights;
+
+            let mut gpu = DrawStats::default();
+
+            // the submitted spot lights are the first candidates, a light's index is also the slot
+            // of its shadow camera
+            // fades follow game time so they stop while paused and follow time_scale
+            let now = time.ingame_time;
+            let dt = shadow_atlas
+                .last_update
+                .map_or(0.0, |last| now.saturating_sub(last).as_secs_f32());
+            shadow_atlas.last_update = Some(now);
+            shadow_atlas.update(
+                &scratch.spot_light_candidates[..scratch.spot_lights.len()],
+                |light| light.cast_shadows,
+                dt,
+                &mut scratch.spot_shadows,
+            );
+            for (slot, (light, tile)) in scratch
+                .spot_lights
+                .iter_mut()
+                .map(|light| &mut light.data)
+                .zip(&scratch.spot_shadows)
+                .enumerate()
+            {
+                // cookies are projected with it too, shadowed or not
+                light.shadow_view_projection = shadow::spot_light_view_projection(
+                    &Point3::from(light.position.xyz()),
+                    &light.direction.xyz(),
+                    light.direction.w,
+                    light.position.w,
+                );
+                let tile = match tile {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                light.shadow_rect = tile.tile.uv_rect();
+                light.shadow_strength = tile.strength;
+
+                let shadow_cam = data_types::Camera {
+                    view_projection: light.shadow_view_projection,
+                    ..Zeroable::zeroed()
+                };
+                gpu.write(
+                    &state.queue,
+                    &state.spot_shadow_camera_buffer,
+                    slot as u64 * state.spot_shadow_camera_stride,
+                    bytemuck::cast_slice(&[shadow_cam]),
+                );
+            }
 
-        let light_assignment_pipeline =
-            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("Light Assignment Pipeline"),
-                layout: Some(&light_assignment_pipeline_layout),
-                module: light_assignment_shader.handle(),
-                entry_point: light_assignment_shader.entry_point(),
-            });
-        */
+            let RenderState {
+                device,
+                queue,
+                texture_library,
+                spot_cookies,
+                ..
+            } = &mut *state;
+            spot_cookies.update(device, queue, texture_library, &mut scratch.spot_lights);
+
+            // each fragment only goes through the lights binned into its screen tile
+            if let Some(buffer) = &state.light_tile_buffer {
+                light_tiles.clear();
+                for (i, light) in scratch.point_lights.iter().enumerate() {
+                    let center = Point3::from(light.position.xyz());
+                    let bounds = light_tiles::sphere_bounds(&center, light.position.w);
+                    light_tiles.insert(i as u32, &view_projection, &bounds);
+                }
+                for (i, light) in scratch.spot_lights.iter().map(|l| &l.data).enumerate() {
+                    let bounds = light_tiles::cone_bounds(
+                        &Point3::from(light.position.xyz()),
+                        &light.direction.xyz(),
+                        light.direction.w,
+                        light.position.w,
+                    );
+                    light_tiles.insert(i as u32 | SPOT_LIGHT_BIT, &view_projection, &bounds);
+                }
+                stats.lights_per_tile = light_tiles.average_lights_per_tile();
+
+                let config = &state.surface_config;
+                let header = LightTileHeader {
+                    screen_size: Vector2::new(config.width as f32, config.height as f32),
+                    columns: TILE_COLUMNS,
+                    rows: TILE_ROWS,
+                };
+                gpu.write(&state.queue, buffer, 0, bytemuck::cast_slice(&[header]));
+                gpu.write(
+                    &state.queue,
+                    buffer,
+                    std::mem::size_of::<LightTileHeader>() as u64,
+                    bytemuck::cast_slice(light_tiles.data()),
+                );
+            }
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            let ambient_light: AmbientLightData = match ambient_light {
+                Some(al) => (&*al).into(),
+                None => (&AmbientLight::default()).into(),
+            };
+
+            // the shader only reads the first LightCounts slots of each array
+            let (global_light_data, global_count): ([GlobalLightData; MAX_GLOBAL_LIGHTS], _) =
+                pack_fixed(scratch.global_lights.iter().copied());
+            let (spot_light_data, spot_count): ([SpotLightData; MAX_SPOT_LIGHTS], _) =
+                pack_fixed(scratch.spot_lights.iter().map(|light| light.data));
+
+            let light_counts = LightCounts {
+                global: global_count as u32,
+                point: scratch.point_lights.len() as u32,
+                spot: spot_count as u32,
+                _padding: 0,
+            };
+
+            // the shadow pass draws with the regular vertex shader, which only reads the camera's
+            // view projection
+            let shadow = ShadowData {
+                view_projection: light_view_projection.unwrap_or_else(Matrix4::identity),
+                enabled: light_view_projection.is_some() as u32,
+                _padding: [0; 3],
+            };
+            let shadow_cam = data_types::Camera {
+                view_projection: shadow.view_projection,
+                ..Zeroable::zeroed()
+            };
+
+            gpu.write(
+                &state.queue,
+                &state.camera_buffer,
+                0,
+                bytemuck::cast_slice(&[cam]),
+            );
+            gpu.write(
+                &state.queue,
+                &state.tonemap_buffer,
+                0,
+                bytemuck::cast_slice(&[tonemap_data]),
+            );
+            gpu.write(
+                &state.queue,
+                &state.shadow_camera_buffer,
+                0,
+                bytemuck::cast_slice(&[shadow_cam]),
+            );
+            gpu.write(
+                &state.queue,
+                &state.shadow_buffer,
+                0,
+                bytemuck::cast_slice(&[shadow]),
+            );
+            gpu.write(
+                &state.queue,
+                &state.light_buffer,
+                state.light_layout.global,
+                bytemuck::cast_slice(&global_light_data),
+            );
+            // storage only needs the lights in use, the uniform array is written whole
+            match &state.point_light_storage {
+                Some(buffer) if !scratch.point_lights.is_empty() => {
+                    gpu.write(
+                        &state.queue,
+                        buffer,
+                        0,
+                        bytemuck::cast_slice(&scratch.point_lights),
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    let (point_light_data, _): ([PointLightData; MAX_POINT_LIGHTS], _) =
+                        pack_fixed(scratch.point_lights.iter().copied());
+                    gpu.write(
+                        &state.queue,
+                        &state.light_buffer,
+                        state.light_layout.point,
+                        bytemuck::cast_slice(&point_light_data),
+                    );
+                }
+            }
+            gpu.write(
+                &state.queue,
+                &state.light_buffer,
+                state.light_layout.spot,
+                bytemuck::cast_slice(&spot_light_data),
+            );
+            gpu.write(
+                &state.queue,
+                &state.light_buffer,
+                state.light_layout.ambient,
+                bytemuck::cast_slice(&[ambient_light]),
+            );
+            gpu.write(
+                &state.queue,
+                &state.light_buffer,
+                state.light_layout.counts,
+                bytemuck::cast_slice(&[light_counts]),
+            );
+
+            state.render(
+                &scratch.instances,
+                &scratch.batches,
+                ShadowPasses {
+                    batches: &scratch.shadow_batches,
+                    global: light_view_projection.is_some(),
+                    spot_tiles: &scratch.spot_shadows,
+                },
+                &scratch.debug_lines,
+                &mut gpu,
+            );
+            stats.gpu = gpu;
+            stats.scratch_peak_bytes = scratch.peak_bytes();
+        }
+        Err(e) => log::error!("failed to access main camera entity for render call: {}", e),
+    }
+}
+
+// Must match the surface size, recreated whenever it changes.
+fn create_depth_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Depth32Float,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
+}
+
+// Color target the main pass draws into when multisampling, it is resolved into the HDR target.
+// None without multisampling.
+fn create_msaa_target(
+    device: &Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<(wgpu::Texture, wgpu::TextureView)> {
+    if sample_count <= 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Color Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Some((texture, view))
+}
+
+// The main pass draws the scene into this, unclamped and linear. The tonemap pass maps it into the
+// frame.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Must match the surface size like the depth texture, always single sampled since msaa resolves
+// into it.
+fn create_hdr_target(
+    device: &Device,
+    width: u32,
+    height: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("HDR Color Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
+}
+
+// Recreated with the HDR target and whenever the grading LUT changes, post_process has to be
+// resized first.
+fn create_tonemap_bind_group(
+    device: &Device,
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    hdr_view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+    post_process: &PostProcess,
+    lut: &VolumeTexture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Tonemap Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(post_process.bloom_view()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(post_process.sampler()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&lut.view),
+            },
+        ],
+    })
+}
+
+pub const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
+// Runtime render options, apply_render_settings passes changes on to the RenderState.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub sample_count: u32,  // msaa samples per pixel, 1 disables multisampling
+    pub light_gizmos: bool, // read by debug_draw::draw_light_gizmos instead
+    pub color_grading: ColorGrading,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: DEFAULT_SAMPLE_COUNT,
+            light_gizmos: cfg!(debug_assertions),
+            color_grading: ColorGrading::default(),
+        }
+    }
+}
+
+// What the forward pass shows. Everything but Lit is for diagnosing geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    Lit,
+    Wireframe, // needs Features::POLYGON_MODE_LINE, drawn lit otherwise
+    Normals,   // world space normal after normal mapping
+    Depth,
+    Uvs,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 5] = [
+        RenderMode::Lit,
+        RenderMode::Wireframe,
+        RenderMode::Normals,
+        RenderMode::Depth,
+        RenderMode::Uvs,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    // The WGSL forward variant showing the mode, None for the lit shaders.
+    fn debug_flags(self) -> Option<ShaderFlags> {
+        match self {
+            RenderMode::Lit | RenderMode::Wireframe => None,
+            RenderMode::Normals => Some(ShaderFlags::DEBUG_NORMALS),
+            RenderMode::Depth => Some(ShaderFlags::DEBUG_DEPTH),
+            RenderMode::Uvs => Some(ShaderFlags::DEBUG_UVS),
+        }
+    }
+
+    fn polygon_mode(self) -> wgpu::PolygonMode {
+        match self {
+            RenderMode::Wireframe => wgpu::PolygonMode::Line,
+            _ => wgpu::PolygonMode::Fill,
+        }
+    }
+}
+
+pub fn apply_render_settings(
+    settings: Res<RenderSettings>,
+    mode: Res<RenderMode>,
+    bloom: Res<BloomSettings>,
+    fog: Res<Fog>,
+    mut state: ResMut<RenderState>,
+) {
+    // a rebuilt RenderState starts out with the defaults
+    if settings.is_changed() || state.is_added() {
+        state.set_sample_count(settings.sample_count);
+        state.set_color_grading(settings.color_grading);
+    }
+    if mode.is_changed() || state.is_added() {
+        state.set_render_mode(*mode);
+    }
+    if bloom.is_changed() || state.is_added() {
+        state.set_bloom(*bloom);
+    }
+    if fog.is_changed() || state.is_added() {
+        state.set_fog(*fog);
+    }
+}
+
+// wgpu only tells whether a format can be multisampled at all, not with which counts. 4 samples
+// work with every format that can be, so 4 is the only count offered besides 1. Formats are checked
+// against both the adapter and what wgpu guarantees, the device validates against one of the two.
+pub fn choose_sample_count(
+    requested: u32,
+    adapter: &Adapter,
+    color_format: wgpu::TextureFormat,
+) -> u32 {
+    if requested <= 1 {
+        return 1;
+    }
+
+    let flags = |format: wgpu::TextureFormat| {
+        adapter.get_texture_format_features(format).flags
+            & format.describe().guaranteed_format_features.flags
+    };
+    let supported = flags(color_format).contains(
+        wgpu::TextureFormatFeatureFlags::MULTISAMPLE
+            | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE,
+    ) && flags(wgpu::TextureFormat::Depth32Float)
+        .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE);
+
+    if requested != 4 || !supported {
+        log::warn!(
+            "{} msaa samples are not supported with {:?}, multisampling is disabled",
+            requested,
+            color_format
+        );
+        return 1;
+    }
+
+    requested
+}
+
+#[derive(Clone, Copy)]
+struct ForwardShaders {
+    vertex: ShaderHandle,
+    fragments: [ShaderHandle; 2], // untextured then textured
+}
+
+// Shaders and layouts the forward pipelines are built from, kept so the pipelines can be rebuilt
+// when the sample count or render mode changes.
+struct ForwardPipelineSource {
+    lit: ForwardShaders,
+    // those of the current render mode, lit or a debug view
+    shaders: ForwardShaders,
+    polygon_mode: wgpu::PolygonMode,
+    // STORAGE_LIGHTS when the lit shaders read point lights from storage, debug views use it too
+    // so they match the layout
+    light_flags: ShaderFlags,
+    layout: wgpu::PipelineLayout,
+    // only the vertex stage runs, which reads nothing but the camera
+    depth_pre_pass_layout: wgpu::PipelineLayout,
+    debug_lines: ShaderHandle,   // reads only the camera too
+    format: wgpu::TextureFormat, // HDR_FORMAT, what the scene is drawn into
+
+    tonemap: ShaderHandle,
+    tonemap_layout: wgpu::PipelineLayout,
+    output_format: wgpu::TextureFormat, // of the surface or offscreen target
+}
+
+struct ForwardPipelines {
+    // untextured then textured, each indexed by depth bias level
+    render: [Vec<wgpu::RenderPipeline>; 2],
+    // same as render without depth writes, for draws the depth pre pass already covered
+    pre_passed: [Vec<wgpu::RenderPipeline>; 2],
+    depth_pre_pass: Vec<wgpu::RenderPipeline>, // indexed by depth bias level
+    debug_lines: wgpu::RenderPipeline,
+}
+
+impl ForwardPipelineSource {
+    fn build(
+        &self,
+        device: &Device,
+        shaders: &ShaderLibrary,
+        sample_count: u32,
+    ) -> ForwardPipelines {
+        let vertex_shader = shaders.get(self.shaders.vertex);
+        let vertex = wgpu::VertexState {
+            module: vertex_shader.handle(),
+            entry_point: vertex_shader.entry_point(ShaderStage::Vertex),
+            buffers: &[Vertex::desc(), InstanceData::desc()],
+        };
+        let multisample = wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        };
+
+        // the depth pre pass has to rasterize exactly like the main pass so the depths match
+        let primitive = wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: self.polygon_mode,
+            conservative: false,
+        };
+        let depth_stencil = |depth_write_enabled, bias| wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled,
+            // equal depth passes so coplanar objects drawn later in the sort end up on top, and so
+            // the main pass draws over the depth the pre pass left
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias,
+        };
+
+        let create_render_pipeline = |fragment_shader: &Shader, depth_write_enabled, bias| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                 label: None,
-                bind_group_layouts: &[
-                    &camera_bind_group_layout,
-                    &texture_bind_group_layout,
-                    &light_bind_group_layout,
-                ],
-                push_constant_ranges: &[wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::all(),
-                    range: 0..PUSH_CONSTANT_SIZE,
-                }],
-            });
+                layout: Some(&self.layout),
+                vertex: vertex.clone(),
+                fragment: Some(wgpu::FragmentState {
+                    module: fragment_shader.handle(),
+                    entry_point: fragment_shader.entry_point(ShaderStage::Fragment),
+                    targets: &[Some(self.format.into())],
+                }),
+                primitive,
+                depth_stencil: Some(depth_stencil(depth_write_enabled, bias)),
+                multisample,
+                multiview: None,
+            })
+        };
+
+        let create_render_pipelines = |depth_write_enabled| {
+            self.shaders.fragments.map(|handle| {
+                let fragment_shader = shaders.get(handle);
+                (0..DEPTH_BIAS_LEVELS)
+                    .map(|level| {
+                        create_render_pipeline(
+                            fragment_shader,
+                            depth_write_enabled,
+                            depth_bias_state(level),
+                        )
+                    })
+                    .collect()
+            })
+        };
 
-        let swapchain_format = surface.get_supported_formats(&adapter)[0];
+        let depth_pre_pass = (0..DEPTH_BIAS_LEVELS)
+            .map(|level| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Depth Pre Pass Pipeline"),
+                    layout: Some(&self.depth_pre_pass_layout),
+                    vertex: vertex.clone(),
+                    fragment: None,
+                    primitive,
+                    depth_stencil: Some(depth_stencil(true, depth_bias_state(level))),
+                    multisample,
+                    multiview: None,
+                })
+            })
+            .collect();
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&render_pipeline_layout),
+        // depth tested against the scene but not written, lines never hide each other
+        let debug_lines_shader = shaders.get(self.debug_lines);
+        let debug_lines = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&self.depth_pre_pass_layout),
             vertex: wgpu::VertexState {
-                module: &vertex_shader.handle(),
-                entry_point: vertex_shader.entry_point(),
-                buffers: &[Vertex::desc()],
+                module: debug_lines_shader.handle(),
+                entry_point: debug_lines_shader.entry_point(ShaderStage::Vertex),
+                buffers: &[LineVertex::desc()],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &fragment_shader.handle(),
-                entry_point: fragment_shader.entry_point(),
-                targets: &[Some(swapchain_format.into())],
+                module: debug_lines_shader.handle(),
+                entry_point: debug_lines_shader.entry_point(ShaderStage::Fragment),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
             }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil(false, wgpu::DepthBiasState::default())),
+            multisample,
+            multiview: None,
+        });
+
+        ForwardPipelines {
+            render: create_render_pipelines(true),
+            pre_passed: create_render_pipelines(false),
+            depth_pre_pass,
+            debug_lines,
+        }
+    }
+
+    // Depth only like the pre pass, drawn with the shadow camera bind group into the single
+    // sampled shadow map. Nothing is culled since cards are seen from both sides by the light.
+    fn build_shadow(&self, device: &Device, shaders: &ShaderLibrary) -> wgpu::RenderPipeline {
+        let vertex_shader = shaders.get(self.lit.vertex);
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&self.depth_pre_pass_layout),
+            vertex: wgpu::VertexState {
+                module: vertex_shader.handle(),
+                entry_point: vertex_shader.entry_point(ShaderStage::Vertex),
+                buffers: &[Vertex::desc(), InstanceData::desc()],
+            },
+            fragment: None,
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Front),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
+                cull_mode: None,
+                ..Default::default()
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_compare: wgpu::CompareFunction::LessEqual,
                 stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+                // keeps lit surfaces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
             }),
             multisample: wgpu::MultisampleState::default(),
             multiview: None,
-        });
-
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: swapchain_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Mailbox,
-        };
+        })
+    }
 
-        surface.configure(&device, &surface_config);
+    // Fullscreen triangle reading the HDR target, independent of the sample count and render mode.
+    fn build_tonemap(&self, device: &Device, shaders: &ShaderLibrary) -> wgpu::RenderPipeline {
+        let shader = shaders.get(self.tonemap);
 
-        Self {
-            _instance: instance,
-            surface,
-            surface_config,
-            _adapter: adapter,
-            device,
-            queue,
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&self.tonemap_layout),
+            vertex: wgpu::VertexState {
+                module: shader.handle(),
+                entry_point: shader.entry_point(ShaderStage::Vertex),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader.handle(),
+                entry_point: shader.entry_point(ShaderStage::Fragment),
+                targets: &[Some(self.output_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+}
 
-            render_pipeline,
+const INITIAL_INSTANCE_CAPACITY: usize = 256;
 
-            /*
-            light_assignment_pipeline,
-            light_assignment_bind_group,
-             */
-            camera_bind_group,
-            camera_buffer,
+fn create_instance_buffer(device: &Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Instance Buffer"),
+        size: (capacity * std::mem::size_of::<InstanceData>()) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    })
+}
 
-            light_bind_group,
-            light_buffer,
+const INITIAL_DEBUG_LINE_CAPACITY: usize = 1024; // in vertices
 
-            _depth_stencil_texture: depth_stencil_texture,
-            depth_stencil_view,
-            _depth_stencil_sampler: depth_stencil_sampler,
+fn create_debug_line_buffer(device: &Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Debug Line Buffer"),
+        size: (capacity * std::mem::size_of::<LineVertex>()) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    })
+}
 
-            texture_library,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NeedsManualGamma(pub bool);
+
+// Prefers an srgb surface so the hardware encodes the shader output. When only linear formats are
+// available the first one is used and the tonemap pass has to apply gamma itself.
+// supported must not be empty.
+pub fn choose_surface_format(
+    supported: &[wgpu::TextureFormat],
+) -> (wgpu::TextureFormat, NeedsManualGamma) {
+    const PREFERRED: [wgpu::TextureFormat; 2] = [
+        wgpu::TextureFormat::Bgra8UnormSrgb,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+    ];
+
+    if let Some(format) = PREFERRED.iter().find(|f| supported.contains(f)) {
+        return (*format, NeedsManualGamma(false));
+    }
 
-            _shader_library: shader_library,
-            geometry_library,
-        }
+    match supported.iter().find(|f| f.describe().srgb) {
+        Some(format) => (*format, NeedsManualGamma(false)),
+        None => (supported[0], NeedsManualGamma(true)),
     }
+}
 
-    pub fn render(
-        &mut self,
-        objects: &mut dyn Iterator<Item = (GeometryId, Matrix4<f32>, Option<TextureId>)>,
-    ) {
-        let frame = match self.surface.get_current_texture() {
-            Ok(frame) => frame,
-            Err(wgpu::SurfaceError::Outdated) => return, // Redraw is sometimes sent before resize
-            Err(e) => panic!("failed to acquire next swap chain texture: {}", e),
-        };
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+// Lowest latency first. Fifo is the only mode every surface has to support so it's the last resort.
+const PRESENT_MODE_PREFERENCE: [wgpu::PresentMode; 3] = [
+    wgpu::PresentMode::Mailbox,
+    wgpu::PresentMode::Immediate,
+    wgpu::PresentMode::Fifo,
+];
+
+pub fn choose_present_mode(supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+    PRESENT_MODE_PREFERENCE
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(wgpu::PresentMode::Fifo)
+}
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+// What RenderState::init draws into. Offscreen needs no window, frames are kept in a texture of
+// their own that read_back_frame copies out, for tests and screenshots.
+#[derive(Clone, Copy)]
+pub enum RenderTargetKind<'a> {
+    Window(&'a Window),
+    #[allow(dead_code)] // only the headless test renders offscreen so far
+    Offscreen {
+        width: u32,
+        height: u32,
+    },
+}
 
-        /* This would be the depth pre pass but as of now it is not implemented
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Depth Pre Pass"),
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_stencil_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-        }
-        */
+enum RenderTarget {
+    Surface(Surface),
+    Offscreen(wgpu::Texture),
+}
 
-        /*
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Light Assignment Pass"),
-            });
+// Always srgb so the hardware encodes the output like on most surfaces.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Color target that can be copied from, for offscreen rendering and screenshots.
+fn create_offscreen_target(
+    device: &Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Color Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    })
+}
 
-            cpass.set_pipeline(&self.light_assignment_pipeline);
-            cpass.set_bind_group(0, &self.light_assignment_bind_group, &[]);
-            cpass.dispatch_workgroups(8, 8, 8);
-        }
-         */
+// Byte offsets of the sections in the light uniform buffer. Every section is bound separately so
+// each starts on the uniform offset alignment, the arrays are sized for their MAX_* lights.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct LightBufferLayout {
+    global: wgpu::BufferAddress,
+    point: wgpu::BufferAddress,
+    spot: wgpu::BufferAddress,
+    ambient: wgpu::BufferAddress,
+    counts: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+}
 
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_stencil_view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
+impl LightBufferLayout {
+    fn new(offset_alignment: wgpu::BufferAddress) -> Self {
+        let global_size = (std::mem::size_of::<GlobalLightData>() * MAX_GLOBAL_LIGHTS) as u64;
+        let point_size = (std::mem::size_of::<PointLightData>() * MAX_POINT_LIGHTS) as u64;
+        let spot_size = (std::mem::size_of::<SpotLightData>() * MAX_SPOT_LIGHTS) as u64;
+        let ambient_size = std::mem::size_of::<AmbientLightData>() as u64;
+        let counts_size = std::mem::size_of::<LightCounts>() as u64;
 
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_bind_group(0, &self.camera_bind_group, &[]);
-            rpass.set_bind_group(2, &self.light_bind_group, &[]);
+        let global = 0;
+        let point = wgpu::util::align_to(global + global_size, offset_alignment);
+        let spot = wgpu::util::align_to(point + point_size, offset_alignment);
+        let ambient = wgpu::util::align_to(spot + spot_size, offset_alignment);
+        let counts = wgpu::util::align_to(ambient + ambient_size, offset_alignment);
 
-            // Draw geometry
-            for (id, model_t, tex) in objects {
-                rpass.set_bind_group(1, &self.texture_library.get(tex).bind_group, &[]);
-
-                let mesh = self.geometry_library.get(id);
-                rpass.set_push_constants(
-                    wgpu::ShaderStages::all(),
-                    0,
-                    bytemuck::cast_slice(&[model_t]),
-                );
-                rpass.set_vertex_buffer(0, mesh.vertices.slice(..));
-                rpass.set_index_buffer(mesh.indices.slice(..), wgpu::IndexFormat::Uint16);
-                rpass.draw_indexed(0..mesh.index_len, 0, 0..1);
-            }
+        Self {
+            global,
+            point,
+            spot,
+            ambient,
+            counts,
+            size: counts + counts_size,
         }
-
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
     }
+}
+
+pub struct RenderState {
+    _instance: Instance,
+    target: RenderTarget,
+    // offscreen targets keep their size and format here too, the present mode is unused
+    surface_config: wgpu::SurfaceConfiguration,
+    supported_present_modes: Vec<wgpu::PresentMode>,
+    adapter: Adapter,
+    adapter_info: wgpu::AdapterInfo,
+    device: Device,
+    queue: Queue,
+
+    pipeline_source: ForwardPipelineSource,
+    pipelines: ForwardPipelines,
+    depth_pre_pass: bool,
+    sample_count: u32,
+    render_mode: RenderMode,
+    msaa_target: Option<(wgpu::Texture, wgpu::TextureView)>,
+    // Set once the device is unusable, the owner should replace the whole RenderState.
+    device_lost: Arc<AtomicBool>,
+    needs_manual_gamma: NeedsManualGamma,
+
+    // the scene is drawn into the HDR target, the tonemap pass writes it into the frame
+    _hdr_texture: wgpu::Texture,
+    hdr_view: wgpu::TextureView,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    tonemap_bind_group_layout: wgpu::BindGroupLayout,
+    tonemap_bind_group: wgpu::BindGroup,
+    tonemap_buffer: wgpu::Buffer,
+    tonemap_sampler: wgpu::Sampler,
+    color_grading: ColorGrading,
+    // grading LUT and library revision the tonemap bind group was created with, the revision is
+    // only tracked while a LUT is set
+    tonemap_lut: (Option<TextureHandle>, Option<u64>),
+    post_process: PostProcess,
+    fog: Fog,
+
+    /*
+    light_assignment_pipeline: wgpu::ComputePipeline,
+    light_assignment_bind_group: wgpu::BindGroup,
+     */
+    camera_bind_group: wgpu::BindGroup,
+    camera_buffer: wgpu::Buffer,
+
+    light_bind_group: wgpu::BindGroup,
+    light_buffer: wgpu::Buffer,
+    light_layout: LightBufferLayout,
+    point_light_path: PointLightPath,
+    point_light_storage: Option<wgpu::Buffer>, // replaces the point light section on Storage
+    light_tile_buffer: Option<wgpu::Buffer>,   // LightTileHeader then the tiles, only on Storage
+    spot_cookies: SpotCookies,
+
+    // shadow map of the first global light, drawn before the main pass every frame
+    shadow_pipeline: wgpu::RenderPipeline,
+    shadow_camera_bind_group: wgpu::BindGroup,
+    shadow_camera_buffer: wgpu::Buffer,
+    shadow_buffer: wgpu::Buffer,
+    _shadow_texture: wgpu::Texture,
+    shadow_view: wgpu::TextureView,
+    _shadow_sampler: wgpu::Sampler,
+
+    // spot light shadows, one atlas tile and camera slot per spot light with a tile. The atlas is
+    // sampled with the global shadow sampler.
+    spot_shadow_camera_buffer: wgpu::Buffer,
+    spot_shadow_camera_stride: u64,
+    spot_shadow_camera_bind_groups: Vec<wgpu::BindGroup>,
+    _spot_shadow_texture: wgpu::Texture,
+    spot_shadow_view: wgpu::TextureView,
+
+    _depth_stencil_texture: wgpu::Texture,
+    depth_stencil_view: wgpu::TextureView,
+    _depth_stencil_sampler: wgpu::Sampler,
+
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_library: TextureLibrary,
+
+    shader_library: ShaderLibrary,
+    geometry_library: GeometryLibrary,
+
+    // per frame instance data, grown as needed
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+
+    // DebugDraw lines of the current frame, the buffer is grown like the instance buffer
+    debug_line_count: u32,
+    debug_line_buffer: wgpu::Buffer,
+    debug_line_capacity: usize,
+
+    // taken by the next render, then saved once the gpu copy is mapped
+    screenshot_request: Option<PathBuf>,
+    pending_screenshots: Vec<PendingScreenshot>,
+}
+
+#[derive(Debug)]
+pub enum RenderInitError {
+    NoAdapter(wgpu::Backends),
+    Device(wgpu::RequestDeviceError),
+    IncompatibleSurface, // the adapter can't present to the window's surface
+    Shader(ShaderError),
+}
+
+impl fmt::Display for RenderInitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderInitError::NoAdapter(backends) => {
+                write!(f, "failed to find an adapter for backends {:?}", backends)
+            }
+            RenderInitError::Device(error) => write!(f, "failed to create a device: {}", error),
+            RenderInitError::IncompatibleSurface => {
+                write!(f, "surface is incompatible with the adapter")
+            }
+            RenderInitError::Shader(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for RenderInitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RenderInitError::NoAdapter(_) | RenderInitError::IncompatibleSurface => None,
+            RenderInitError::Device(error) => Some(error),
+            RenderInitError::Shader(error) => Some(error),
+        }
+    }
+}
+
+impl From<ShaderError> for RenderInitError {
+    fn from(error: ShaderError) -> Self {
+        RenderInitError::Shader(error)
+    }
+}
+
+impl RenderState {
+    // backends limits which graphics apis are tried, the best adapter among them is used. Only
+    // borrows the window so a lost device can be replaced by calling this again.
+    pub fn init(
+        target: RenderTargetKind,
+        backends: wgpu::Backends,
+    ) -> Result<Self, RenderInitError> {
+        let instance = wgpu::Instance::new(backends);
+        let (surface, size) = match target {
+            RenderTargetKind::Window(window) => (
+                Some(unsafe { instance.create_surface(window) }),
+                window.inner_size(),
+            ),
+            RenderTargetKind::Offscreen { width, height } => {
+                (None, PhysicalSize::new(width, height))
+            }
+        };
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: surface.as_ref(),
+            })
+            .block_on()
+            .ok_or(RenderInitError::NoAdapter(backends))?;
+
+        let adapter_info = adapter.get_info();
+        log::info!(
+            "using adapter {} ({:?}, {:?} backend)",
+            adapter_info.name,
+            adapter_info.device_type,
+            adapter_info.backend
+        );
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    // wireframe is a debug view, it is drawn lit where lines aren't supported
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+                    limits: wgpu::Limits::default().using_resolution(adapter.limits()), //wgpu::Limits::downlevel_defaults(),
+                },
+                None,
+            )
+            .block_on()
+            .map_err(RenderInitError::Device)?;
+
+        // Out of memory is how a lost device shows up, everything else keeps the default behaviour
+        // of panicking.
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.on_uncaptured_error(move |e| match e {
+            wgpu::Error::OutOfMemory { .. } => {
+                log::error!("gpu out of memory, treating the device as lost: {}", e);
+                lost.store(true, Ordering::Relaxed);
+            }
+            _ => panic!("wgpu error: {}", e),
+        });
+
+        let point_light_path =
+            PointLightPath::choose(&adapter.get_downlevel_capabilities(), &device.limits());
+        log::info!("reading point lights from {:?} buffers", point_light_path);
+
+        let mut shader_library = ShaderLibrary::load_all(&device)?;
+
+        //let light_assignment_shader = shader_library.get(ShaderId::LightAssignment).clone();
+        // CARD_GAME_WGSL draws with the WGSL copy of the forward shaders, which has untextured and
+        // textured variants. The SPIR-V pair can't be preprocessed so both use the same shader,
+        // and only the WGSL shader can read point lights from storage
+        let use_wgsl = std::env::var_os("CARD_GAME_WGSL").is_some()
+            || point_light_path == PointLightPath::Storage;
+        let light_flags = match point_light_path {
+            PointLightPath::Storage => ShaderFlags::STORAGE_LIGHTS,
+            PointLightPath::Uniform => ShaderFlags::NONE,
+        };
+        let lit_shaders = if use_wgsl {
+            let mut variant = |flags| {
+                shader_library.variant(&device, ShaderVariant::new(ShaderId::ForwardWgsl, flags))
+            };
+            ForwardShaders {
+                vertex: ShaderId::ForwardWgsl.into(),
+                fragments: [
+                    variant(light_flags)?,
+                    variant(ShaderFlags::TEXTURED | light_flags)?,
+                ],
+            }
+        } else {
+            ForwardShaders {
+                vertex: ShaderId::VertexShader.into(),
+                fragments: [ShaderId::FragmentShader.into(); 2],
+            }
+        };
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::all(),
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: data_types::Camera::BINDING_SIZE,
+                    },
+                    count: None,
+                }],
+            });
+
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Camera Buffer"),
+            size: data_types::Camera::BINDING_SIZE.unwrap().into(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &camera_buffer,
+                    offset: 0,
+                    size: data_types::Camera::BINDING_SIZE,
+                }),
+            }],
+        });
+
+        // seen from the first global light for the shadow pass
+        let shadow_camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Camera Buffer"),
+            size: data_types::Camera::BINDING_SIZE.unwrap().into(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let shadow_camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &shadow_camera_buffer,
+                    offset: 0,
+                    size: data_types::Camera::BINDING_SIZE,
+                }),
+            }],
+        });
+
+        // one camera per spot light for its tile of the shadow atlas, bound at an offset
+        let spot_shadow_camera_stride = wgpu::util::align_to(
+            u64::from(data_types::Camera::BINDING_SIZE.unwrap()),
+            device.limits().min_uniform_buffer_offset_alignment as u64,
+        );
+        let spot_shadow_camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Spot Shadow Camera Buffer"),
+            size: spot_shadow_camera_stride * MAX_SPOT_LIGHTS as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+        let spot_shadow_camera_bind_groups = (0..MAX_SPOT_LIGHTS as u64)
+            .map(|slot| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Spot Shadow Camera Bind Group"),
+                    layout: &camera_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &spot_shadow_camera_buffer,
+                            offset: slot * spot_shadow_camera_stride,
+                            size: data_types::Camera::BINDING_SIZE,
+                        }),
+                    }],
+                })
+            })
+            .collect();
+
+        let texture_bind_group_layout = texture_library::bind_group_layout(&device);
+
+        let mut texture_library =
+            TextureLibrary::load_as_needed(&device, &queue, &texture_bind_group_layout);
+        let geometry_library = GeometryLibrary::load_all(&device, |path| {
+            texture_library.load_referenced(&device, &queue, &texture_bind_group_layout, path)
+        });
+
+        let mut light_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: match point_light_path {
+                        PointLightPath::Uniform => wgpu::BufferBindingType::Uniform,
+                        PointLightPath::Storage => {
+                            wgpu::BufferBindingType::Storage { read_only: true }
+                        }
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 7,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 11,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        // the per tile light lists only exist on the storage path, the uniform path has few enough
+        // lights to go through all of them
+        if point_light_path == PointLightPath::Storage {
+            light_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 9,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+        }
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Light Bind Group Layout"),
+                entries: &light_layout_entries,
+            });
+
+        let light_layout =
+            LightBufferLayout::new(device.limits().min_uniform_buffer_offset_alignment as u64);
+
+        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: light_layout.size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        // the uniform section for point lights is left unused then, it is only a few hundred bytes
+        let point_light_storage = match point_light_path {
+            PointLightPath::Uniform => None,
+            PointLightPath::Storage => Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Point Light Storage Buffer"),
+                size: (std::mem::size_of::<PointLightData>() * MAX_STORAGE_POINT_LIGHTS) as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })),
+        };
+        let light_tile_buffer = match point_light_path {
+            PointLightPath::Uniform => None,
+            PointLightPath::Storage => Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Light Tile Buffer"),
+                size: (std::mem::size_of::<LightTileHeader>()
+                    + std::mem::size_of::<u32>() * LightTiles::TILE_STRIDE * TILE_COUNT)
+                    as u64,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })),
+        };
+
+        let shadow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Buffer"),
+            size: std::mem::size_of::<ShadowData>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let (shadow_texture, shadow_view) =
+            create_depth_texture(&device, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, 1);
+        let (spot_shadow_texture, spot_shadow_view) =
+            create_depth_texture(&device, SHADOW_ATLAS_WIDTH, SHADOW_ATLAS_HEIGHT, 1);
+        // compares against the stored depth, linear filtering blends neighbouring results on top
+        // of the pcf taps in the shader
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let spot_cookies = SpotCookies::new(&device, &queue, MAX_SPOT_LIGHTS);
+
+        let mut light_entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &light_buffer,
+                    offset: light_layout.global,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: match &point_light_storage {
+                    Some(buffer) => buffer.as_entire_binding(),
+                    None => wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &light_buffer,
+                        offset: light_layout.point,
+                        size: None,
+                    }),
+                },
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &light_buffer,
+                    offset: light_layout.spot,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &light_buffer,
+                    offset: light_layout.ambient,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &light_buffer,
+                    offset: light_layout.counts,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: shadow_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&shadow_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&shadow_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&spot_shadow_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(&spot_cookies.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::Sampler(&spot_cookies.sampler),
+            },
+        ];
+        if let Some(buffer) = &light_tile_buffer {
+            light_entries.push(wgpu::BindGroupEntry {
+                binding: 9,
+                resource: buffer.as_entire_binding(),
+            });
+        }
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Bind Group"),
+            layout: &light_bind_group_layout,
+            entries: &light_entries,
+        });
+
+        let depth_stencil_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        /*
+        let light_assignment_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Light Assignment Bind Group Layout"),
+                entries: &[],
+            });
+
+        let light_assignment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Assignment Bind Group"),
+            layout: &light_assignment_bind_group_layout,
+            entries: &[],
+        });
+
+        let light_assignment_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Assignment Pipeline Layout"),
+                bind_group_layouts: &[&global_light_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let light_assignment_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Light Assignment Pipeline"),
+                layout: Some(&light_assignment_pipeline_layout),
+                module: light_assignment_shader.handle(),
+                entry_point: light_assignment_shader.entry_point(ShaderStage::Compute),
+            });
+        */
+
+        let tonemap_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Tonemap Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: data_types::Tonemap::BINDING_SIZE,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // the bloom result, sampled with its own sampler since it is half size
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // the color grading LUT, sampled with the bloom sampler for trilinear filtering
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let tonemap_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tonemap Buffer"),
+            size: data_types::Tonemap::BINDING_SIZE.unwrap().into(),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        // the HDR target matches the frame size so every pixel is sampled exactly
+        let tonemap_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Tonemap Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &light_bind_group_layout,
+                    // the normal map, a texture bind group of its own so any texture can be one
+                    &texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let (swapchain_format, needs_manual_gamma) = match &surface {
+            Some(surface) => {
+                let supported_formats = surface.get_supported_formats(&adapter);
+                if supported_formats.is_empty() {
+                    return Err(RenderInitError::IncompatibleSurface);
+                }
+                choose_surface_format(&supported_formats)
+            }
+            None => (OFFSCREEN_FORMAT, NeedsManualGamma(false)),
+        };
+        log::info!(
+            "using surface format {:?}, manual gamma {}",
+            swapchain_format,
+            needs_manual_gamma.0
+        );
+
+        let pipeline_source = ForwardPipelineSource {
+            lit: lit_shaders,
+            shaders: lit_shaders,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            light_flags,
+            layout: render_pipeline_layout,
+            depth_pre_pass_layout: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Depth Pre Pass Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+            debug_lines: ShaderId::DebugLinesWgsl.into(),
+            format: HDR_FORMAT,
+
+            tonemap: ShaderId::TonemapWgsl.into(),
+            tonemap_layout: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Tonemap Pipeline Layout"),
+                bind_group_layouts: &[&tonemap_bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+            output_format: swapchain_format,
+        };
+
+        let sample_count = choose_sample_count(DEFAULT_SAMPLE_COUNT, &adapter, HDR_FORMAT);
+        log::info!("using {} msaa samples", sample_count);
+        let pipelines = pipeline_source.build(&device, &shader_library, sample_count);
+        let shadow_pipeline = pipeline_source.build_shadow(&device, &shader_library);
+        let tonemap_pipeline = pipeline_source.build_tonemap(&device, &shader_library);
+
+        let (depth_stencil_texture, depth_stencil_view) =
+            create_depth_texture(&device, size.width, size.height, sample_count);
+        let msaa_target =
+            create_msaa_target(&device, HDR_FORMAT, size.width, size.height, sample_count);
+        let (hdr_texture, hdr_view) = create_hdr_target(&device, size.width, size.height);
+        let post_process =
+            PostProcess::new(&device, &shader_library, &hdr_view, size.width, size.height);
+        let tonemap_bind_group = create_tonemap_bind_group(
+            &device,
+            &tonemap_bind_group_layout,
+            &tonemap_buffer,
+            &hdr_view,
+            &tonemap_sampler,
+            &post_process,
+            texture_library.lut_or_identity(None),
+        );
+
+        // offscreen targets never present, no mode is supported
+        let supported_present_modes = surface
+            .as_ref()
+            .map_or_else(Vec::new, |surface| surface.get_supported_modes(&adapter));
+        let present_mode = choose_present_mode(&supported_present_modes);
+        if surface.is_some() {
+            log::info!(
+                "using present mode {:?}, supported {:?}",
+                present_mode,
+                supported_present_modes
+            );
+        }
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: swapchain_format,
+            width: size.width,
+            height: size.height,
+            present_mode,
+        };
+
+        let target = match surface {
+            Some(surface) => {
+                surface.configure(&device, &surface_config);
+                RenderTarget::Surface(surface)
+            }
+            None => RenderTarget::Offscreen(create_offscreen_target(
+                &device,
+                OFFSCREEN_FORMAT,
+                size.width,
+                size.height,
+            )),
+        };
+
+        let instance_buffer = create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
+        let debug_line_buffer = create_debug_line_buffer(&device, INITIAL_DEBUG_LINE_CAPACITY);
+
+        Ok(Self {
+            _instance: instance,
+            target,
+            surface_config,
+            supported_present_modes,
+            adapter,
+            adapter_info,
+            device,
+            queue,
+
+            pipeline_source,
+            pipelines,
+            depth_pre_pass: false,
+            sample_count,
+            render_mode: RenderMode::Lit,
+            msaa_target,
+            device_lost,
+            needs_manual_gamma,
+
+            _hdr_texture: hdr_texture,
+            hdr_view,
+            tonemap_pipeline,
+            tonemap_bind_group_layout,
+            tonemap_bind_group,
+            tonemap_buffer,
+            tonemap_sampler,
+            color_grading: ColorGrading::default(),
+            tonemap_lut: (None, None),
+            post_process,
+            fog: Fog::default(),
+
+            /*
+            light_assignment_pipeline,
+            light_assignment_bind_group,
+             */
+            camera_bind_group,
+            camera_buffer,
+
+            light_bind_group,
+            light_buffer,
+            light_layout,
+            point_light_path,
+            point_light_storage,
+            light_tile_buffer,
+            spot_cookies,
+
+            shadow_pipeline,
+            shadow_camera_bind_group,
+            shadow_camera_buffer,
+            shadow_buffer,
+            _shadow_texture: shadow_texture,
+            shadow_view,
+            _shadow_sampler: shadow_sampler,
+            spot_shadow_camera_buffer,
+            spot_shadow_camera_stride,
+            spot_shadow_camera_bind_groups,
+            _spot_shadow_texture: spot_shadow_texture,
+            spot_shadow_view,
+
+            _depth_stencil_texture: depth_stencil_texture,
+            depth_stencil_view,
+            _depth_stencil_sampler: depth_stencil_sampler,
+
+            texture_bind_group_layout,
+            texture_library,
+
+            shader_library,
+            geometry_library,
+
+            instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+
+            debug_line_count: 0,
+            debug_line_buffer,
+            debug_line_capacity: INITIAL_DEBUG_LINE_CAPACITY,
+
+            screenshot_request: None,
+            pending_screenshots: Vec::new(),
+        })
+    }
+
+    // Grows the instance buffer to fit, the old contents are not kept.
+    fn reserve_instances(&mut self, count: usize) {
+        if count <= self.instance_capacity {
+            return;
+        }
+
+        self.instance_capacity = count.next_power_of_two();
+        self.instance_buffer = create_instance_buffer(&self.device, self.instance_capacity);
+    }
+
+    fn reserve_debug_lines(&mut self, count: usize) {
+        if count <= self.debug_line_capacity {
+            return;
+        }
+
+        self.debug_line_capacity = count.next_power_of_two();
+        self.debug_line_buffer = create_debug_line_buffer(&self.device, self.debug_line_capacity);
+    }
+
+    // batches must be sorted by bias level and index into instances. debug_lines are pairs of line
+    // ends. What gets recorded is added to gpu.
+    pub fn render(
+        &mut self,
+        instances: &[InstanceData],
+        batches: &[DrawBatch],
+        shadows: ShadowPasses,
+        debug_lines: &[LineVertex],
+        gpu: &mut DrawStats,
+    ) {
+        self.texture_library.poll_loaded(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+        );
+        self.update_grading_lut();
+
+        self.reserve_instances(instances.len());
+        gpu.write(
+            &self.queue,
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(instances),
+        );
+
+        self.debug_line_count = debug_lines.len() as u32;
+        if !debug_lines.is_empty() {
+            self.reserve_debug_lines(debug_lines.len());
+            gpu.write(
+                &self.queue,
+                &self.debug_line_buffer,
+                0,
+                bytemuck::cast_slice(debug_lines),
+            );
+        }
+
+        self.save_screenshots();
+        let screenshot_request = self.screenshot_request.take();
+
+        let surface = match &self.target {
+            RenderTarget::Surface(surface) => surface,
+            RenderTarget::Offscreen(texture) => {
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                self.render_into(&view, batches, shadows, gpu);
+                if let Some(path) = screenshot_request {
+                    let pending = self.capture_screenshot(texture, path);
+                    self.pending_screenshots.push(pending);
+                }
+                return;
+            }
+        };
+
+        let frame = match surface.get_current_texture() {
+            Ok(frame) => frame,
+            // Redraw is sometimes sent before resize, exclusive fullscreen switches can also leave
+            // the swapchain outdated without a size change so reconfigure either way
+            Err(wgpu::SurfaceError::Outdated) => {
+                surface.configure(&self.device, &self.surface_config);
+                return;
+            }
+            Err(wgpu::SurfaceError::Lost) => {
+                log::warn!("surface lost, reconfiguring");
+                surface.configure(&self.device, &self.surface_config);
+                return;
+            }
+            Err(wgpu::SurfaceError::Timeout) => {
+                log::warn!("timed out acquiring the next frame, skipping it");
+                return;
+            }
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                log::error!("out of memory acquiring the next frame, treating the device as lost");
+                self.device_lost.store(true, Ordering::Relaxed);
+                return;
+            }
+        };
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.render_into(&view, batches, shadows, gpu);
+        frame.present();
+
+        // Surface textures can't be copied from on every backend, the frame is drawn a second time
+        // into a texture that can. It's left out of the stats since it isn't part of a normal frame.
+        if let Some(path) = screenshot_request {
+            let texture = create_offscreen_target(
+                &self.device,
+                self.surface_config.format,
+                self.surface_config.width,
+                self.surface_config.height,
+            );
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.render_into(&view, batches, shadows, &mut DrawStats::default());
+            let pending = self.capture_screenshot(&texture, path);
+            self.pending_screenshots.push(pending);
+        }
+    }
+
+    // Draws the shadow casters as seen from the light camera. Like the depth pre pass it skips
+    // draws that discard fragments, dissolving objects cast no shadow.
+    fn draw_shadow_casters<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        camera: &'a wgpu::BindGroup,
+        shadows: ShadowPasses,
+        gpu: &mut DrawStats,
+    ) {
+        rpass.set_pipeline(&self.shadow_pipeline);
+        rpass.set_bind_group(0, camera, &[]);
+        gpu.bind_group();
+        rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+        let mut bound_geometry = None;
+        for draw in shadows.batches.iter().filter(|draw| !draw.discards) {
+            let mesh = self.geometry_library.get(draw.geometry);
+            if bound_geometry != Some(draw.geometry) {
+                rpass.set_vertex_buffer(0, mesh.vertices.slice(..));
+                rpass.set_index_buffer(mesh.indices.slice(..), mesh.index_format);
+                bound_geometry = Some(draw.geometry);
+            }
+            let indices = mesh.submeshes[draw.submesh].indices.clone();
+            gpu.draw_indexed(indices.len() as u32, &draw.instances);
+            rpass.draw_indexed(indices, 0, draw.instances.clone());
+        }
+    }
+
+    // Saves a png of the next rendered frame to path. The file is written on a worker thread a few
+    // frames later.
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        if screenshot::swaps_red_blue(self.surface_config.format).is_none() {
+            log::error!(
+                "screenshots are not supported for surface format {:?}",
+                self.surface_config.format
+            );
+            return;
+        }
+
+        self.screenshot_request = Some(path);
+    }
+
+    // Copies texture, which must hold the rendered frame, into a buffer that gets saved once it's
+    // mapped.
+    fn capture_screenshot(&self, texture: &wgpu::Texture, path: PathBuf) -> PendingScreenshot {
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let buffer = self.copy_to_read_back_buffer(texture, width, height);
+        let swap_red_blue = screenshot::swaps_red_blue(self.surface_config.format).unwrap_or(false);
+
+        PendingScreenshot::new(buffer, width, height, swap_red_blue, path)
+    }
+
+    // Polls without blocking so mapping progresses, finished screenshots are handed to a worker.
+    // The workers are detached unless the caller joins them.
+    fn save_screenshots(&mut self) -> Vec<JoinHandle<()>> {
+        if self.pending_screenshots.is_empty() {
+            return Vec::new();
+        }
+
+        self.device.poll(wgpu::Maintain::Poll);
+        let mut writers = Vec::new();
+        self.pending_screenshots
+            .retain(|pending| match pending.try_save() {
+                SaveProgress::Waiting => true,
+                SaveProgress::Saving(writer) => {
+                    writers.push(writer);
+                    false
+                }
+                SaveProgress::Failed => false,
+            });
+
+        writers
+    }
+
+    // Records and submits every pass, view is the frame's color target.
+    fn render_into(
+        &self,
+        view: &wgpu::TextureView,
+        batches: &[DrawBatch],
+        shadows: ShadowPasses,
+        gpu: &mut DrawStats,
+    ) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+
+        if shadows.global {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.shadow_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            self.draw_shadow_casters(&mut rpass, &self.shadow_camera_bind_group, shadows, gpu);
+        }
+
+        // One pass per spot light tile, limited to the tile by the viewport. The first one clears
+        // the whole atlas, the shader never reads tiles outside the ones assigned this frame.
+        let spot_tiles = shadows
+            .spot_tiles
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, tile)| tile.map(|tile| (slot, tile.tile)));
+        for (i, (slot, tile)) in spot_tiles.enumerate() {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Spot Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.spot_shadow_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: if i == 0 {
+                            wgpu::LoadOp::Clear(1.0)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            let size = tile.size as f32;
+            rpass.set_viewport(tile.x as f32, tile.y as f32, size, size, 0.0, 1.0);
+            rpass.set_scissor_rect(tile.x, tile.y, tile.size, tile.size);
+            self.draw_shadow_casters(
+                &mut rpass,
+                &self.spot_shadow_camera_bind_groups[slot],
+                shadows,
+                gpu,
+            );
+        }
+
+        // Fills the depth buffer first so the main pass only shades the closest fragment of each
+        // pixel. Draws that can discard fragments are left to the main pass.
+        if self.depth_pre_pass {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Pre Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_stencil_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            let mut bias_level = None;
+            let mut bound_geometry = None;
+
+            rpass.set_bind_group(0, &self.camera_bind_group, &[]);
+            gpu.bind_group();
+            rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+            for draw in batches.iter().filter(|draw| !draw.discards) {
+                if bias_level != Some(draw.bias_level) {
+                    rpass.set_pipeline(&self.pipelines.depth_pre_pass[draw.bias_level]);
+                    bias_level = Some(draw.bias_level);
+                }
+
+                let mesh = self.geometry_library.get(draw.geometry);
+                if bound_geometry != Some(draw.geometry) {
+                    rpass.set_vertex_buffer(0, mesh.vertices.slice(..));
+                    rpass.set_index_buffer(mesh.indices.slice(..), mesh.index_format);
+                    bound_geometry = Some(draw.geometry);
+                }
+                let indices = mesh.submeshes[draw.submesh].indices.clone();
+                gpu.draw_indexed(indices.len() as u32, &draw.instances);
+                rpass.draw_indexed(indices, 0, draw.instances.clone());
+            }
+        }
+
+        /*
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Light Assignment Pass"),
+            });
+
+            cpass.set_pipeline(&self.light_assignment_pipeline);
+            cpass.set_bind_group(0, &self.light_assignment_bind_group, &[]);
+            cpass.dispatch_workgroups(8, 8, 8);
+        }
+         */
+
+        {
+            // with msaa the samples are drawn into msaa_target and resolved into the HDR target
+            let (color_view, resolve_target) = match &self.msaa_target {
+                Some((_, msaa_view)) => (msaa_view, Some(&self.hdr_view)),
+                None => (&self.hdr_view, None),
+            };
+
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color_view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.clear_color()),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_stencil_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: match self.depth_pre_pass {
+                            true => wgpu::LoadOp::Load,
+                            false => wgpu::LoadOp::Clear(1.0),
+                        },
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            let mut pipeline = None;
+            // only rebound when a batch uses a different one, the draw sort keeps those together
+            let mut bound_texture = None;
+            let mut bound_normal_map = None;
+            let mut bound_geometry = None;
+
+            rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+            // Draw geometry
+            for draw in batches {
+                // bind groups stay bound across pipeline switches as all pipelines share a layout
+                let textured = draw.texture.is_some();
+                let pre_passed = self.depth_pre_pass && !draw.discards;
+                if pipeline != Some((pre_passed, textured, draw.bias_level)) {
+                    let pipelines = match pre_passed {
+                        true => &self.pipelines.pre_passed,
+                        false => &self.pipelines.render,
+                    };
+                    rpass.set_pipeline(&pipelines[textured as usize][draw.bias_level]);
+                    if pipeline.is_none() {
+                        rpass.set_bind_group(0, &self.camera_bind_group, &[]);
+                        rpass.set_bind_group(2, &self.light_bind_group, &[]);
+                        gpu.bind_group();
+                        gpu.bind_group();
+                    }
+                    pipeline = Some((pre_passed, textured, draw.bias_level));
+                }
+
+                if bound_texture != Some(draw.texture) {
+                    rpass.set_bind_group(
+                        1,
+                        &self.texture_library.get_or_default(draw.texture).bind_group,
+                        &[],
+                    );
+                    gpu.bind_group();
+                    bound_texture = Some(draw.texture);
+                }
+                if bound_normal_map != Some(draw.normal_map) {
+                    rpass.set_bind_group(
+                        3,
+                        &self
+                            .texture_library
+                            .normal_map_or_flat(draw.normal_map)
+                            .bind_group,
+                        &[],
+                    );
+                    gpu.bind_group();
+                    bound_normal_map = Some(draw.normal_map);
+                }
+
+                let mesh = self.geometry_library.get(draw.geometry);
+                if bound_geometry != Some(draw.geometry) {
+                    rpass.set_vertex_buffer(0, mesh.vertices.slice(..));
+                    rpass.set_index_buffer(mesh.indices.slice(..), mesh.index_format);
+                    bound_geometry = Some(draw.geometry);
+                }
+                let indices = mesh.submeshes[draw.submesh].indices.clone();
+                gpu.draw_indexed(indices.len() as u32, &draw.instances);
+                rpass.draw_indexed(indices, 0, draw.instances.clone());
+            }
+
+            if self.debug_line_count > 0 {
+                rpass.set_pipeline(&self.pipelines.debug_lines);
+                rpass.set_bind_group(0, &self.camera_bind_group, &[]);
+                gpu.bind_group();
+                rpass.set_vertex_buffer(0, self.debug_line_buffer.slice(..));
+                rpass.draw(0..self.debug_line_count, 0..1);
+                gpu.draw_lines();
+            }
+        }
+
+        if self.bloom_active() {
+            self.post_process.render_bloom(&mut encoder, gpu);
+        }
+
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Tonemap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // every pixel is overwritten
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            rpass.set_pipeline(&self.tonemap_pipeline);
+            rpass.set_bind_group(0, &self.tonemap_bind_group, &[]);
+            gpu.bind_group();
+            rpass.draw(0..3, 0..1);
+            gpu.draw_fullscreen();
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    // The old textures are dropped here, wgpu frees them once the gpu is done with them.
+    fn create_render_targets(&mut self) {
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+
+        let (texture, view) = create_depth_texture(&self.device, width, height, self.sample_count);
+        self._depth_stencil_texture = texture;
+        self.depth_stencil_view = view;
+
+        self.msaa_target =
+            create_msaa_target(&self.device, HDR_FORMAT, width, height, self.sample_count);
+
+        let (texture, view) = create_hdr_target(&self.device, width, height);
+        self.post_process.resize(&self.device, &view, width, height);
+        self.tonemap_bind_group = create_tonemap_bind_group(
+            &self.device,
+            &self.tonemap_bind_group_layout,
+            &self.tonemap_buffer,
+            &view,
+            &self.tonemap_sampler,
+            &self.post_process,
+            self.grading_lut(),
+        );
+        self._hdr_texture = texture;
+        self.hdr_view = view;
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // Falls back to 1 when the count isn't supported, see choose_sample_count. Rebuilds the
+    // pipelines and render targets when the count changes.
+    pub fn set_sample_count(&mut self, requested: u32) {
+        let sample_count = choose_sample_count(requested, &self.adapter, HDR_FORMAT);
+        if sample_count == self.sample_count {
+            return;
+        }
+
+        log::info!("switching to {} msaa samples", sample_count);
+        self.sample_count = sample_count;
+        self.pipelines =
+            self.pipeline_source
+                .build(&self.device, &self.shader_library, sample_count);
+        self.create_render_targets();
+    }
+
+    pub fn set_bloom(&mut self, settings: BloomSettings) {
+        self.post_process.set_bloom(&self.queue, settings);
+    }
+
+    // Bloom is left out of debug views so they show their values as is.
+    fn bloom_active(&self) -> bool {
+        self.post_process.bloom().enabled && self.render_mode.debug_flags().is_none()
+    }
+
+    // The LUT is picked up by the next frame, one that isn't registered grades with the identity
+    // LUT until it is.
+    pub fn set_color_grading(&mut self, grading: ColorGrading) {
+        if let Some(lut) = grading.lut {
+            if self.texture_library.lut(lut).is_none() {
+                log::warn!("{:?} is not a registered color grading LUT", lut);
+            }
+        }
+        self.color_grading = grading;
+    }
+
+    fn grading_lut(&self) -> &VolumeTexture {
+        self.texture_library.lut_or_identity(self.color_grading.lut)
+    }
+
+    // Registering, replacing or dropping the LUT in use needs a new tonemap bind group.
+    fn update_grading_lut(&mut self) {
+        let lut = self.color_grading.lut;
+        let key = (lut, lut.map(|_| self.texture_library.revision()));
+        if key == self.tonemap_lut {
+            return;
+        }
+
+        self.tonemap_lut = key;
+        self.tonemap_bind_group = create_tonemap_bind_group(
+            &self.device,
+            &self.tonemap_bind_group_layout,
+            &self.tonemap_buffer,
+            &self.hdr_view,
+            &self.tonemap_sampler,
+            &self.post_process,
+            self.grading_lut(),
+        );
+    }
+
+    pub fn set_fog(&mut self, fog: Fog) {
+        self.fog = fog;
+    }
+
+    // Debug views keep the black background, fog is applied by the lit shaders only.
+    fn clear_color(&self) -> wgpu::Color {
+        match self.render_mode.debug_flags() {
+            Some(_) => wgpu::Color::BLACK,
+            None => self.fog.clear_color(),
+        }
+    }
+
+    // Debug views always draw with the WGSL shaders since the SPIR-V pair has no variants. A mode
+    // that can't be shown leaves the current one in place.
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        if mode == self.render_mode {
+            return;
+        }
+
+        if mode == RenderMode::Wireframe
+            && !self
+                .device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+        {
+            log::warn!("wireframe is not supported by {}", self.adapter_info.name);
+            return;
+        }
+
+        let shaders = match mode.debug_flags() {
+            Some(flags) => {
+                let variant = ShaderVariant::new(
+                    ShaderId::ForwardWgsl,
+                    flags | self.pipeline_source.light_flags,
+                );
+                match self.shader_library.variant(&self.device, variant) {
+                    // debug views ignore the texture so both slots share one shader
+                    Ok(fragment) => ForwardShaders {
+                        vertex: ShaderId::ForwardWgsl.into(),
+                        fragments: [fragment; 2],
+                    },
+                    Err(e) => {
+                        log::error!("failed to build the {:?} view: {}", mode, e);
+                        return;
+                    }
+                }
+            }
+            None => self.pipeline_source.lit,
+        };
+
+        log::info!("switching to {:?} render mode", mode);
+        self.render_mode = mode;
+        self.pipeline_source.shaders = shaders;
+        self.pipeline_source.polygon_mode = mode.polygon_mode();
+        self.pipelines =
+            self.pipeline_source
+                .build(&self.device, &self.shader_library, self.sample_count);
+    }
+
+    pub fn depth_pre_pass(&self) -> bool {
+        self.depth_pre_pass
+    }
+
+    // Off by default, it only pays off with enough overdraw to outweigh drawing everything twice.
+    pub fn set_depth_pre_pass(&mut self, enabled: bool) {
+        self.depth_pre_pass = enabled;
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    // Waits for all submitted work before the gpu objects are released.
+    pub fn shutdown(mut self) {
+        self.device.poll(wgpu::Maintain::Wait);
+        // everything is mapped after the wait, screenshots taken just before quitting get saved
+        for writer in self.save_screenshots() {
+            let _ = writer.join();
+        }
+    }
+
+    pub fn register_texture(&mut self, name: &str, path: &Path) -> Result<TextureHandle, String> {
+        self.texture_library.register(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            name,
+            path,
+        )
+    }
+
+    pub fn register_lut(&mut self, name: &str, path: &Path) -> Result<TextureHandle, String> {
+        self.texture_library.register_lut(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            name,
+            path,
+        )
+    }
+
+    pub fn unregister_texture(&mut self, handle: TextureHandle) -> Result<(), String> {
+        self.texture_library.unregister(handle)
+    }
+
+    pub fn texture_handle(&self, name: &str) -> Option<TextureHandle> {
+        self.texture_library.handle_by_name(name)
+    }
+
+    pub fn dynamic_textures(&self) -> DynamicTextures {
+        self.texture_library.dynamic_textures().clone()
+    }
+
+    // For carrying registered textures over to a RenderState replacing a lost one.
+    pub fn restore_dynamic_textures(&mut self, dynamic: DynamicTextures) {
+        self.texture_library.restore_dynamic_textures(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            dynamic,
+        );
+    }
+
+    pub fn texture_library(&self) -> &TextureLibrary {
+        &self.texture_library
+    }
+
+    pub fn geometry_library(&self) -> &GeometryLibrary {
+        &self.geometry_library
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.surface_config.present_mode
+    }
+
+    pub fn supported_present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.supported_present_modes
+    }
+
+    // Reconfigures the surface right away, unsupported modes are rejected and leave it untouched.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> Result<(), String> {
+        if !self.supported_present_modes.contains(&mode) {
+            return Err(format!(
+                "present mode {:?} is not supported, expected one of {:?}",
+                mode, self.supported_present_modes
+            ));
+        }
+
+        self.surface_config.present_mode = mode;
+        self.configure_target();
+        log::info!("using present mode {:?}", mode);
+
+        Ok(())
+    }
+
+    // Zero sizes come from minimized windows and are ignored, the surface keeps its old size.
+    pub fn resize_if_needed(&mut self, size: &PhysicalSize<u32>, window: &Window) -> () {
+        let unchanged =
+            size.width == self.surface_config.width && size.height == self.surface_config.height;
+
+        if size.width > 0 && size.height > 0 && !unchanged {
+            self.surface_config.width = size.width;
+            self.surface_config.height = size.height;
+            self.configure_target();
+
+            self.create_render_targets();
 
-    pub fn resize_if_needed(&mut self, size: &PhysicalSize<u32>, window: &Window) -> () {
-        if size.width > 0 && size.height > 0 {
-            self.surface_config.width = size.width;
-            self.surface_config.height = size.height;
-            self.surface.configure(&self.device, &self.surface_config);
-
             window.request_redraw();
         }
     }
+
+    // Applies surface_config, an offscreen target is recreated at the new size.
+    fn configure_target(&mut self) {
+        match &mut self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.surface_config),
+            RenderTarget::Offscreen(texture) => {
+                *texture = create_offscreen_target(
+                    &self.device,
+                    OFFSCREEN_FORMAT,
+                    self.surface_config.width,
+                    self.surface_config.height,
+                )
+            }
+        }
+    }
+
+    // RGBA pixels of the last rendered frame, rows top to bottom. Blocks until the gpu is done.
+    // Only offscreen targets can be read, surface textures are gone once presented.
+    #[allow(dead_code)]
+    pub fn read_back_frame(&self) -> Vec<u8> {
+        let texture = match &self.target {
+            RenderTarget::Offscreen(texture) => texture,
+            RenderTarget::Surface(_) => panic!("only offscreen frames can be read back"),
+        };
+
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let buffer = self.copy_to_read_back_buffer(texture, width, height);
+
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("failed to map the frame read back buffer")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+
+        let pixels = screenshot::unpad_rows(&slice.get_mapped_range(), width);
+        pixels
+    }
+
+    // Submits a copy of texture into a new mappable buffer, rows padded to the copy alignment.
+    fn copy_to_read_back_buffer(
+        &self,
+        texture: &wgpu::Texture,
+        width: u32,
+        height: u32,
+    ) -> wgpu::Buffer {
+        let padded_row_bytes = screenshot::padded_bytes_per_row(width);
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Read Back Buffer"),
+            size: padded_row_bytes as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        query::With,
+        system::{IntoSystem, System},
+        world::World,
+    };
+    use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector4};
+    use std::{path::Path, process, time::Duration};
+
+    use super::*;
+    use crate::{
+        fog::FogFalloff,
+        geometry_library::{GeometryId, MeshData, Submesh},
+        shadow_atlas::SHADOW_FADE_SECONDS,
+        texture_library::TextureId,
+        tonemap::TonemapOperator,
+    };
+
+    fn draw_item(geometry: GeometryId, texture: TextureId) -> DrawItem {
+        DrawItem {
+            geometry,
+            submesh: 0,
+            model: Matrix4::identity(),
+            scale: Vector3::repeat(1.0),
+            params: MaterialParams::default(),
+            tint: Tint::default(),
+            material: Material::default(),
+            texture: Some(texture.into()),
+            normal_map: None,
+            layer: 0,
+            bias_level: depth_bias_level(None),
+            sort_key: 0,
+        }
+    }
+
+    #[test]
+    fn light_buffer_layout_test() {
+        for alignment in [16, 64, 256] {
+            let layout = LightBufferLayout::new(alignment);
+            let sections = [
+                (
+                    layout.global,
+                    size_of::<GlobalLightData>() * MAX_GLOBAL_LIGHTS,
+                ),
+                (layout.point, size_of::<PointLightData>() * MAX_POINT_LIGHTS),
+                (layout.spot, size_of::<SpotLightData>() * MAX_SPOT_LIGHTS),
+                (layout.ambient, size_of::<AmbientLightData>()),
+                (layout.counts, size_of::<LightCounts>()),
+            ];
+
+            assert_eq!(layout.global, 0);
+            for (offset, _) in sections {
+                assert_eq!(offset % alignment, 0, "{:?}", layout);
+            }
+            // each section ends before the next one starts
+            for pair in sections.windows(2) {
+                assert!(pair[0].0 + pair[0].1 as u64 <= pair[1].0, "{:?}", layout);
+            }
+            assert_eq!(layout.size, layout.counts + size_of::<LightCounts>() as u64);
+        }
+    }
+
+    #[test]
+    fn packed_lights_fill_their_sections_test() {
+        let layout = LightBufferLayout::new(256);
+
+        let global = GlobalLight {
+            color: Vector3::new(1.0, 0.5, 0.25),
+            power: 2.0,
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let spots: Vec<SpotLightData> = (0..3)
+            .map(|i| {
+                let light = SpotLight {
+                    color: Vector3::new(0.0, 1.0, 0.0),
+                    power: i as f32 + 1.0,
+                    radius: 5.0,
+                    direction: Vector3::new(0.0, -1.0, 0.0),
+                    cut_off: 0.9,
+                    cookie: None,
+                    cast_shadows: true,
+                };
+                let transform = transform(Isometry3::translation(i as f32, 3.0, 0.0));
+                (&light, &transform).into()
+            })
+            .collect();
+
+        let (globals, global_count): ([GlobalLightData; MAX_GLOBAL_LIGHTS], _) =
+            pack_fixed(std::iter::once(GlobalLightData::from(&global)));
+        let (spot_data, spot_count): ([SpotLightData; MAX_SPOT_LIGHTS], _) =
+            pack_fixed(spots.iter().copied());
+        assert_eq!((global_count, spot_count), (1, 3));
+
+        // the whole array is written, and it fits in front of the next section
+        let global_bytes: &[u8] = bytemuck::cast_slice(&globals);
+        let spot_bytes: &[u8] = bytemuck::cast_slice(&spot_data);
+        assert_eq!(
+            global_bytes.len(),
+            size_of::<GlobalLightData>() * MAX_GLOBAL_LIGHTS
+        );
+        assert_eq!(
+            spot_bytes.len(),
+            size_of::<SpotLightData>() * MAX_SPOT_LIGHTS
+        );
+        assert!(layout.global + global_bytes.len() as u64 <= layout.point);
+        assert!(layout.spot + spot_bytes.len() as u64 <= layout.ambient);
+
+        // lights in use come first, in order, the rest is padding the shader skips
+        assert_eq!(
+            &global_bytes[..size_of::<GlobalLightData>()],
+            bytemuck::bytes_of(&GlobalLightData::from(&global))
+        );
+        let spot_size = size_of::<SpotLightData>();
+        for (i, spot) in spots.iter().enumerate() {
+            assert_eq!(
+                &spot_bytes[i * spot_size..(i + 1) * spot_size],
+                bytemuck::bytes_of(spot)
+            );
+        }
+        let unused = bytemuck::bytes_of(&SpotLightData::default()).repeat(MAX_SPOT_LIGHTS - 3);
+        assert_eq!(&spot_bytes[3 * spot_size..], &unused[..]);
+    }
+
+    #[test]
+    fn choose_surface_format_test() {
+        use wgpu::TextureFormat::*;
+
+        // srgb preferred wherever it appears in the list
+        assert_eq!(
+            choose_surface_format(&[Bgra8Unorm, Rgba8UnormSrgb, Bgra8UnormSrgb]),
+            (Bgra8UnormSrgb, NeedsManualGamma(false))
+        );
+        assert_eq!(
+            choose_surface_format(&[Rgba8Unorm, Rgba8UnormSrgb]),
+            (Rgba8UnormSrgb, NeedsManualGamma(false))
+        );
+        // any other srgb format before falling back to linear
+        assert_eq!(
+            choose_surface_format(&[Rgba16Float, Bc1RgbaUnormSrgb]),
+            (Bc1RgbaUnormSrgb, NeedsManualGamma(false))
+        );
+        // linear only, the tonemap pass applies gamma
+        assert_eq!(
+            choose_surface_format(&[Rgba16Float, Bgra8Unorm]),
+            (Rgba16Float, NeedsManualGamma(true))
+        );
+    }
+
+    #[test]
+    fn sorted_draws_batch_by_texture_test() {
+        // ten objects alternating between two textures and two meshes
+        let mut draws: Vec<_> = (0..10)
+            .map(|i| {
+                let texture = match i % 2 {
+                    0 => TextureId::CheckerTexture,
+                    _ => TextureId::UvGridTexture,
+                };
+                let geometry = match i % 4 < 2 {
+                    true => GeometryId::TorusGeometry,
+                    false => GeometryId::SceneTestGeometry,
+                };
+                draw_item(geometry, texture)
+            })
+            .collect();
+
+        sort_draws(&mut draws);
+        let (mut instances, mut batches) = (Vec::new(), Vec::new());
+        batch_draws(&draws, &mut instances, &mut batches);
+
+        assert_eq!(instances.len(), 10);
+        let keys: Vec<_> = batches
+            .iter()
+            .map(|batch| (batch.texture, batch.geometry))
+            .collect();
+        // one texture switch, the meshes alternate within each texture
+        assert_eq!(
+            keys,
+            [
+                (
+                    Some(TextureId::CheckerTexture.into()),
+                    GeometryId::TorusGeometry
+                ),
+                (
+                    Some(TextureId::CheckerTexture.into()),
+                    GeometryId::SceneTestGeometry
+                ),
+                (
+                    Some(TextureId::UvGridTexture.into()),
+                    GeometryId::TorusGeometry
+                ),
+                (
+                    Some(TextureId::UvGridTexture.into()),
+                    GeometryId::SceneTestGeometry
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn draw_stats_test() {
+        let mut stats = DrawStats::default();
+
+        // a quad drawn 3 times, then a single cube
+        stats.draw_indexed(6, &(0..3));
+        stats.bind_group();
+        stats.draw_indexed(36, &(3..4));
+        stats.draw_lines();
+
+        assert_eq!(
+            stats,
+            DrawStats {
+                draw_calls: 3,
+                instances: 5,
+                triangles: 2 * 3 + 12,
+                bind_group_switches: 1,
+                bytes_written: 0,
+            }
+        );
+    }
+
+    const HEADLESS_SIZE: u32 = 64;
+    const HEADLESS_REFERENCE: &str = "tests/reference/headless_torus.png";
+
+    // None without a usable adapter, the test is skipped like the shader tests
+    fn headless_state(width: u32, height: u32) -> Option<RenderState> {
+        let target = RenderTargetKind::Offscreen { width, height };
+        match RenderState::init(target, wgpu::Backends::all()) {
+            Ok(state) => Some(state),
+            Err(RenderInitError::NoAdapter(_) | RenderInitError::Device(_)) => {
+                eprintln!("no adapter available, skipping");
+                None
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn transform(isometry: Isometry3<f32>) -> Transform {
+        Transform {
+            isometry,
+            scale: Vector3::repeat(1.0),
+            parent: None,
+            children: vec![],
+        }
+    }
+
+    // Resources the render system needs plus a main camera and a global light shining down and
+    // away from the camera.
+    fn scene_world(mut state: RenderState, camera: Isometry3<f32>, light_brightness: f32) -> World {
+        // msaa resolves differ the most between adapters
+        state.set_sample_count(1);
+
+        let mut world = World::new();
+        world.insert_resource(state);
+        world.insert_resource(FrameScratch::default());
+        world.insert_resource(LightLod::default());
+        world.insert_resource(RenderStats::default());
+        world.insert_resource(TimeResource::new(
+            Duration::from_secs_f64(1.0 / 60.0),
+            Duration::from_secs_f64(1.0 / 60.0),
+        ));
+        world.insert_resource(AmbientLight::default());
+        world.insert_resource(TonemapSettings::default());
+
+        world
+            .spawn()
+            .insert(transform(camera))
+            .insert(Camera::perspective(
+                1.0,
+                std::f32::consts::FRAC_PI_2,
+                0.05,
+                100.0,
+            ))
+            .insert(MainCamera);
+        world.spawn().insert(GlobalLight {
+            color: Vector3::repeat(light_brightness),
+            power: 3.0,
+            direction: [-1.0, -1.0, -1.0].into(),
+        });
+
+        world
+    }
+
+    fn run_render(world: &mut World) {
+        let mut system = IntoSystem::into_system(render);
+        system.initialize(world);
+        system.run((), world);
+    }
+
+    // One frame of a lit torus facing the camera, the state is returned to read the frame back.
+    fn render_torus_scene(
+        state: RenderState,
+        light_brightness: f32,
+        tonemap: TonemapSettings,
+    ) -> RenderState {
+        let mut world = scene_world(state, Isometry3::identity(), light_brightness);
+        world.insert_resource(tonemap);
+        world
+            .spawn()
+            .insert(transform(Isometry3::from_parts(
+                Vector3::new(0.0, 0.0, -2.0).into(),
+                UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::FRAC_PI_2),
+            )))
+            .insert(RenderGeometry::new(GeometryId::TorusGeometry));
+        run_render(&mut world);
+
+        let stats = *world.resource::<RenderStats>();
+        assert_eq!(stats.drawn_objects, 1);
+
+        world.remove_resource::<RenderState>().unwrap()
+    }
+
+    // Compares RGBA pixels against a checked in png. UPDATE_REFERENCE=1 writes the pixels as the new
+    // reference instead, check it in after looking at it.
+    fn assert_matches_reference(pixels: &[u8], width: u32, height: u32, path: &str) {
+        if std::env::var("UPDATE_REFERENCE").as_deref() == Ok("1") {
+            image::save_buffer(path, pixels, width, height, image::ColorType::Rgba8).unwrap();
+            eprintln!("updated reference {}", path);
+            return;
+        }
+
+        let reference = match image::open(path) {
+            Ok(reference) => reference.to_rgba8(),
+            Err(e) => panic!(
+                "missing reference {} ({}), run with UPDATE_REFERENCE=1 to write it",
+                path, e
+            ),
+        };
+        assert_eq!(
+            reference.dimensions(),
+            (width, height),
+            "{} has the wrong size",
+            path
+        );
+
+        // adapters rasterize and filter slightly differently, only the average difference counts
+        let difference = pixels
+            .iter()
+            .zip(reference.as_raw())
+            .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as f64)
+            .sum::<f64>()
+            / pixels.len() as f64;
+        assert!(
+            difference < 4.0,
+            "frame differs from {} by {} on average, run with UPDATE_REFERENCE=1 if the change is \
+             intended",
+            path,
+            difference
+        );
+    }
+
+    #[test]
+    fn headless_render_test() {
+        let state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let pixels = render_torus_scene(state, 1.0, TonemapSettings::default()).read_back_frame();
+        assert_eq!(pixels.len(), (HEADLESS_SIZE * HEADLESS_SIZE * 4) as usize);
+        assert!(
+            pixels.chunks(4).any(|p| p[..3].iter().any(|c| *c > 32)),
+            "the frame is all black"
+        );
+
+        assert_matches_reference(&pixels, HEADLESS_SIZE, HEADLESS_SIZE, HEADLESS_REFERENCE);
+    }
+
+    #[test]
+    fn screenshot_matches_frame_test() {
+        // rows of an odd width need padding in the copy
+        let (width, height) = (37, 29);
+        let mut state = match headless_state(width, height) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let path = std::env::temp_dir().join(format!("card_game_screenshot_{}.png", process::id()));
+        state.request_screenshot(path.clone());
+        let state = render_torus_scene(state, 1.0, TonemapSettings::default());
+
+        let pixels = state.read_back_frame();
+        // waits for the png to be written
+        state.shutdown();
+
+        let saved = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved.dimensions(), (width, height));
+        assert!(
+            saved.as_raw() == &pixels,
+            "screenshot differs from the frame"
+        );
+    }
+
+    #[test]
+    fn tonemap_rolls_off_bright_light_test() {
+        let saturated_pixels = |operator| {
+            let state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+                Some(state) => state,
+                None => return None,
+            };
+            let tonemap = TonemapSettings {
+                operator,
+                ..Default::default()
+            };
+            let pixels = render_torus_scene(state, 50.0, tonemap).read_back_frame();
+            Some(pixels.chunks(4).filter(|p| p[..3].contains(&255)).count())
+        };
+
+        let (clipped, reinhard) = match (
+            saturated_pixels(TonemapOperator::None),
+            saturated_pixels(TonemapOperator::Reinhard),
+        ) {
+            (Some(clipped), Some(reinhard)) => (clipped, reinhard),
+            _ => return,
+        };
+        assert!(clipped > 0, "the light is not bright enough to clip");
+        // reinhard only reaches 1 at infinity
+        assert_eq!(reinhard, 0, "{} of {} clipped pixels", reinhard, clipped);
+    }
+
+    #[test]
+    fn color_grading_test() {
+        // a 2x2x2 LUT turning every color red
+        let path = std::env::temp_dir().join(format!("card_game_red_lut_{}.png", process::id()));
+        let red = [255, 0, 0, 255].repeat(8);
+        image::save_buffer(&path, &red, 4, 2, image::ColorType::Rgba8).unwrap();
+
+        let render = |red_strength: Option<f32>, grading: ColorGrading| {
+            let mut state = headless_state(HEADLESS_SIZE, HEADLESS_SIZE)?;
+            let grading = match red_strength {
+                Some(strength) => ColorGrading {
+                    lut: Some(state.register_lut("red", &path).unwrap()),
+                    strength,
+                },
+                None => grading,
+            };
+            state.set_color_grading(grading);
+            Some(render_torus_scene(state, 1.0, TonemapSettings::default()).read_back_frame())
+        };
+        let ungraded = ColorGrading {
+            lut: None,
+            strength: 0.0,
+        };
+        let frames = (
+            render(None, ungraded),
+            render(None, ColorGrading::default()),
+            render(Some(1.0), ungraded),
+            render(Some(0.5), ungraded),
+        );
+        std::fs::remove_file(&path).unwrap();
+        let (ungraded, identity, red, half_red) = match frames {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => return,
+        };
+
+        // the identity LUT the pass uses by default leaves the frame as it was
+        let identity_error = ungraded
+            .iter()
+            .zip(&identity)
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(
+            identity_error <= 2,
+            "identity LUT is off by {}",
+            identity_error
+        );
+
+        assert!(red.chunks(4).all(|p| p == [255, 0, 0, 255]));
+
+        // strength blends display colors, the black background goes half way to red
+        let corner = &half_red[..4];
+        assert!(
+            (corner[0] as i32 - 128).abs() <= 3 && corner[1..3] == [0, 0],
+            "{:?}",
+            corner
+        );
+    }
+
+    #[test]
+    fn bloom_spreads_bright_light_test() {
+        let render = |enabled| {
+            let mut state = headless_state(HEADLESS_SIZE, HEADLESS_SIZE)?;
+            state.set_bloom(BloomSettings {
+                enabled,
+                threshold: 1.0,
+                intensity: 1.0,
+            });
+            let tonemap = TonemapSettings {
+                operator: TonemapOperator::Reinhard,
+                ..Default::default()
+            };
+            Some(render_torus_scene(state, 50.0, tonemap).read_back_frame())
+        };
+
+        let (plain, bloom) = match (render(false), render(true)) {
+            (Some(plain), Some(bloom)) => (plain, bloom),
+            _ => return,
+        };
+        // the glow reaches past the torus onto the black background
+        let lit_background = plain
+            .chunks(4)
+            .zip(bloom.chunks(4))
+            .filter(|(plain, bloom)| plain[..3] == [0; 3] && bloom[..3].iter().any(|c| *c > 8))
+            .count();
+        assert!(lit_background > 0, "bloom did not spread past the torus");
+    }
+
+    #[test]
+    fn missing_mtl_texture_uses_unknown_texture_test() {
+        let mut state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+            Some(state) => state,
+            None => return,
+        };
+
+        let path = Path::new("texture/does-not-exist.png");
+        let handle = state.texture_library.load_referenced(
+            &state.device,
+            &state.queue,
+            &state.texture_bind_group_layout,
+            path,
+        );
+        assert_eq!(handle, TextureId::UnknownTexture.into());
+    }
+
+    // A flat square facing +y with size * size vertices, one unit across like the torus.
+    fn grid_mesh(device: &Device, size: u32) -> MeshData {
+        let vertices: Vec<Vertex> = (0..size * size)
+            .map(|i| {
+                let (x, z) = (i % size, i / size);
+                let u = x as f32 / (size - 1) as f32;
+                let v = z as f32 / (size - 1) as f32;
+                Vertex {
+                    position: Vector4::new(u - 0.5, 0.0, v - 0.5, 1.0),
+                    normal: Vector4::new(0.0, 1.0, 0.0, 0.0),
+                    texture: [u, v].into(),
+                    color: Vertex::WHITE,
+                    tangent: Vector4::zeros(),
+                }
+            })
+            .collect();
+
+        // counter clockwise seen from above
+        let mut indices = Vec::new();
+        for z in 0..size - 1 {
+            for x in 0..size - 1 {
+                let a = z * size + x;
+                let (b, c, d) = (a + 1, a + size, a + size + 1);
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+
+        let submeshes = vec![Submesh {
+            indices: 0..indices.len() as u32,
+            material: None,
+        }];
+        MeshData::new(device, &vertices, indices, submeshes, Vec::new())
+    }
+
+    #[test]
+    fn large_mesh_uses_u32_indices_test() {
+        let mut state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+            Some(state) => state,
+            None => return,
+        };
+
+        // 70225 vertices, past what u16 indices can reach
+        let mesh = grid_mesh(&state.device, 265);
+        assert_eq!(mesh.vertex_len, 70225);
+        assert_eq!(mesh.index_format, wgpu::IndexFormat::Uint32);
+        state
+            .geometry_library
+            .replace(GeometryId::TorusGeometry, mesh);
+
+        // the grid covers the middle of the frame and leaves the corners empty
+        let pixels = render_torus_scene(state, 1.0, TonemapSettings::default()).read_back_frame();
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * HEADLESS_SIZE + x) * 4) as usize;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+        let center = HEADLESS_SIZE / 2;
+        assert_ne!(pixel(center, center), [0; 3], "the grid was not drawn");
+        assert_eq!(pixel(0, 0), [0; 3]);
+    }
+
+    const STACK_REFERENCE: &str = "tests/reference/headless_stack.png";
+
+    // Red, green and blue cards lying in the same plane, stacked in that order by DepthBias and
+    // SortKey like a discard pile. Seen from eye, looking at their center.
+    fn render_stack_scene(mut state: RenderState, eye: Point3<f32>) -> RenderState {
+        let card = grid_mesh(&state.device, 2);
+        state
+            .geometry_library
+            .replace(GeometryId::TorusGeometry, card);
+
+        // up is only used to pick the roll, -z keeps it defined when looking straight down
+        let view = Isometry3::look_at_rh(&eye, &Point3::origin(), &-Vector3::z());
+        let mut world = scene_world(state, view.inverse(), 1.0);
+
+        let colors = [Vector3::x(), Vector3::y(), Vector3::z()];
+        for (i, color) in colors.iter().enumerate() {
+            world
+                .spawn()
+                .insert(transform(Isometry3::identity()))
+                .insert(RenderGeometry::new(GeometryId::TorusGeometry))
+                .insert(Tint(color.push(1.0)))
+                .insert(DepthBias(i as i32))
+                .insert(SortKey(i as i32));
+        }
+        run_render(&mut world);
+
+        let stats = *world.resource::<RenderStats>();
+        assert_eq!(stats.drawn_objects, 3);
+
+        world.remove_resource::<RenderState>().unwrap()
+    }
+
+    #[test]
+    fn coplanar_stack_shows_top_card_test() {
+        // straight down, tilted and close to grazing
+        let eyes = [
+            Point3::new(0.0, 1.2, 0.0),
+            Point3::new(0.0, 1.0, 1.0),
+            Point3::new(1.0, 0.8, -0.6),
+            Point3::new(-0.9, 0.5, 0.4),
+            Point3::new(0.3, 0.25, 1.0),
+        ];
+
+        for (i, eye) in eyes.iter().enumerate() {
+            let state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+                Some(state) => state,
+                None => return,
+            };
+            let pixels = render_stack_scene(state, *eye).read_back_frame();
+
+            // any red or green showing through the blue top card is z-fighting
+            let covered: Vec<_> = pixels.chunks(4).filter(|p| p[..3] != [0; 3]).collect();
+            assert!(!covered.is_empty(), "the stack was not drawn from {}", eye);
+            let fighting = covered
+                .iter()
+                .filter(|p| p[2] <= p[0] || p[2] <= p[1])
+                .count();
+            assert_eq!(
+                fighting,
+                0,
+                "{} of {} pixels show a lower card from {}",
+                fighting,
+                covered.len(),
+                eye
+            );
+
+            if i == 0 {
+                assert_matches_reference(&pixels, HEADLESS_SIZE, HEADLESS_SIZE, STACK_REFERENCE);
+            }
+        }
+    }
+
+    #[test]
+    fn dense_fog_hides_scene_test() {
+        let mut state = match headless_state(HEADLESS_SIZE, HEADLESS_SIZE) {
+            Some(state) => state,
+            None => return,
+        };
+        state.set_fog(Fog {
+            enabled: true,
+            color: Vector3::new(1.0, 0.0, 0.0),
+            falloff: FogFalloff::Exponential { density: 100.0 },
+            clear_to_fog_color: true,
+        });
+
+        // the torus is fully covered and the background cleared to the same color
+        let pixels = render_torus_scene(state, 1.0, TonemapSettings::default()).read_back_frame();
+        let unfogged = pixels.chunks(4).filter(|p| p[..3] != [255, 0, 0]).count();
+        assert_eq!(unfogged, 0, "{} pixels show through the fog", unfogged);
+    }
+
+    fn remove_global_lights(world: &mut World) {
+        let global_lights: Vec<_> = world
+            .query_filtered::<Entity, With<GlobalLight>>()
+            .iter(world)
+            .collect();
+        for light in global_lights {
+            world.despawn(light);
+        }
+    }
+
+    // scene_world with a grid card in place of the torus and no global light, seen from eye looking
+    // at the origin. Also returns the camera's view projection to find points on screen.
+    fn scene_with_grid(eye: Point3<f32>) -> Option<(World, Matrix4<f32>)> {
+        let mut state = headless_state(HEADLESS_SIZE, HEADLESS_SIZE)?;
+        let card = grid_mesh(&state.device, 2);
+        state
+            .geometry_library
+            .replace(GeometryId::TorusGeometry, card);
+
+        let view = Isometry3::look_at_rh(&eye, &Point3::origin(), &Vector3::y());
+        let mut world = scene_world(state, view.inverse(), 1.0);
+        remove_global_lights(&mut world);
+
+        let camera = Camera::perspective(1.0, std::f32::consts::FRAC_PI_2, 0.05, 100.0);
+        Some((world, camera.view_projection(&view.inverse())))
+    }
+
+    // Sum of the color channels of a pixel in a HEADLESS_SIZE frame.
+    fn brightness(pixels: &[u8], x: u32, y: u32) -> u32 {
+        let i = ((y * HEADLESS_SIZE + x) * 4) as usize;
+        pixels[i..i + 3].iter().map(|c| *c as u32).sum()
+    }
+
+    // The pixel point lands on in a HEADLESS_SIZE frame.
+    fn pixel_of(view_projection: &Matrix4<f32>, point: Point3<f32>) -> (u32, u32) {
+        let ndc = view_projection.transform_point(&point);
+        let x = ((ndc.x * 0.5 + 0.5) * HEADLESS_SIZE as f32) as u32;
+        let y = ((0.5 - ndc.y * 0.5) * HEADLESS_SIZE as f32) as u32;
+        (x, y)
+    }
+
+    #[test]
+    fn spot_light_shadow_test() {
+        // a small card hanging between a spot light and the floor, seen from the side so the
+        // floor below it is in view
+        let (mut world, view_projection) = match scene_with_grid(Point3::new(0.0, 3.0, 3.0)) {
+            Some(scene) => scene,
+            None => return,
+        };
+
+        for (height, scale) in [(0.0, 4.0), (1.0, 0.5)] {
+            world
+                .spawn()
+                .insert(Transform {
+                    scale: Vector3::repeat(scale),
+                    ..transform(Isometry3::translation(0.0, height, 0.0))
+                })
+                .insert(RenderGeometry::new(GeometryId::TorusGeometry));
+        }
+        world
+            .spawn()
+            .insert(transform(Isometry3::translation(0.0, 3.0, 0.0)))
+            .insert(SpotLight {
+                color: Vector3::repeat(2.0),
+                power: 1.0,
+                radius: 10.0,
+                direction: -Vector3::y(),
+                cut_off: 40f32.to_radians().cos(),
+                cookie: None,
+                cast_shadows: true,
+            });
+
+        let below = pixel_of(&view_projection, Point3::origin());
+        let beside = pixel_of(&view_projection, Point3::new(1.2, 0.0, 0.0));
+
+        let mut system = IntoSystem::into_system(render);
+        system.initialize(&mut world);
+
+        // the tile is assigned right away but the shadow fades in from nothing
+        system.run((), &mut world);
+        let unshadowed = world.resource::<RenderState>().read_back_frame();
+        assert_eq!(world.resource::<RenderStats>().spot_lights.submitted, 1);
+
+        world.resource_mut::<TimeResource>().ingame_time +=
+            Duration::from_secs_f32(SHADOW_FADE_SECONDS * 1.5);
+        world.resource_mut::<FrameScratch>().reset();
+        system.run((), &mut world);
+        let shadowed = world.resource::<RenderState>().read_back_frame();
+
+        assert!(
+            brightness(&shadowed, below.0, below.1) * 2 < brightness(&unshadowed, below.0, below.1),
+            "the floor below the card is not in shadow"
+        );
+        let (lit, unshadowed_lit) = (
+            brightness(&shadowed, beside.0, beside.1),
+            brightness(&unshadowed, beside.0, beside.1),
+        );
+        assert!(lit > 0 && lit.abs_diff(unshadowed_lit) <= 6);
+    }
+
+    #[test]
+    fn tiled_light_culling_test() {
+        // a floor with a small point light above its left side
+        let (mut world, view_projection) = match scene_with_grid(Point3::new(0.0, 3.0, 3.0)) {
+            Some(scene) => scene,
+            None => return,
+        };
+        if world.resource::<RenderState>().light_tile_buffer.is_none() {
+            eprintln!("no storage buffers, lights are not culled per tile");
+            return;
+        }
+        world
+            .spawn()
+            .insert(Transform {
+                scale: Vector3::repeat(4.0),
+                ..transform(Isometry3::identity())
+            })
+            .insert(RenderGeometry::new(GeometryId::TorusGeometry));
+        world
+            .spawn()
+            .insert(transform(Isometry3::translation(-1.5, 0.3, 0.0)))
+            .insert(PointLight {
+                color: Vector3::repeat(2.0),
+                power: 1.0,
+                radius: 1.0,
+            });
+        run_render(&mut world);
+
+        // the light reaches a few tiles and only lights the floor within its radius
+        let stats = *world.resource::<RenderStats>();
+        assert!(stats.lights_per_tile > 0.0 && stats.lights_per_tile < 0.5);
+
+        let pixels = world.resource::<RenderState>().read_back_frame();
+        let brightness = |point: Point3<f32>| {
+            let (x, y) = pixel_of(&view_projection, point);
+            brightness(&pixels, x, y)
+        };
+        let below = brightness(Point3::new(-1.5, 0.0, 0.0));
+        let across = brightness(Point3::new(1.5, 0.0, 0.0));
+        let beside = brightness(Point3::new(-1.5, 0.0, 1.5));
+        assert!(below > across * 2, "{} is not lit above {}", below, across);
+        assert_eq!(across, beside);
+    }
 }
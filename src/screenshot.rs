@@ -0,0 +1,182 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::{ImageBuffer, Rgba};
+use wgpu::{BufferAsyncError, TextureFormat};
+
+// Texture to buffer copies need every row aligned to 256 bytes. Shared by screenshots and
+// RenderState::read_back_frame.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    wgpu::util::align_to(width * 4, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+// Drops the padding at the end of every row, leaving width * 4 bytes per row.
+pub fn unpad_rows(padded: &[u8], width: u32) -> Vec<u8> {
+    let row_bytes = width as usize * 4;
+    padded
+        .chunks(padded_bytes_per_row(width) as usize)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect()
+}
+
+// Whether pixels of the format have to swap red and blue to become rgba. None for formats that
+// aren't 8 bit per channel, those can't be saved.
+//
+// Srgb and linear formats are read the same way. Srgb textures store encoded bytes and the shader
+// encodes by hand for linear surfaces, either way the bytes are what the screen shows and png
+// expects srgb.
+pub fn swaps_red_blue(format: TextureFormat) -> Option<bool> {
+    match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => Some(false),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => Some(true),
+        _ => None,
+    }
+}
+
+// screenshot-<unix time in milliseconds>.png in the pictures directory.
+pub fn default_path() -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+
+    dirs::picture_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(env!("CARGO_PKG_NAME"))
+        .join(format!("screenshot-{}.png", millis))
+}
+
+// A frame copied into a buffer that is waiting to be mapped.
+pub struct PendingScreenshot {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    swap_red_blue: bool,
+    path: PathBuf,
+    mapped: Mutex<Receiver<Result<(), BufferAsyncError>>>, // the lock only makes it Sync
+}
+
+impl PendingScreenshot {
+    // Starts mapping the buffer, the copy into it has to be submitted already.
+    pub fn new(
+        buffer: wgpu::Buffer,
+        width: u32,
+        height: u32,
+        swap_red_blue: bool,
+        path: PathBuf,
+    ) -> Self {
+        let (sender, mapped) = mpsc::channel();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                // the receiver is gone when the render state was dropped first
+                let _ = sender.send(result);
+            });
+
+        Self {
+            buffer,
+            width,
+            height,
+            swap_red_blue,
+            path,
+            mapped: Mutex::new(mapped),
+        }
+    }
+
+    // Mapping only progresses while the device is polled. Once mapped the pixels are copied out
+    // and the png is written on a worker thread, the screenshot can be dropped after that.
+    pub fn try_save(&self) -> SaveProgress {
+        let mapped = self.mapped.lock().unwrap().try_recv();
+        match mapped {
+            Err(TryRecvError::Empty) => return SaveProgress::Waiting,
+            Err(TryRecvError::Disconnected) => {
+                log::error!("screenshot {} was never mapped", self.path.display());
+                return SaveProgress::Failed;
+            }
+            Ok(Err(e)) => {
+                log::error!("failed to map screenshot {}: {}", self.path.display(), e);
+                return SaveProgress::Failed;
+            }
+            Ok(Ok(())) => (),
+        }
+
+        let mut pixels = unpad_rows(&self.buffer.slice(..).get_mapped_range(), self.width);
+        self.buffer.unmap();
+        if self.swap_red_blue {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        let (width, height, path) = (self.width, self.height, self.path.clone());
+        SaveProgress::Saving(thread::spawn(move || {
+            match save_png(&path, width, height, pixels) {
+                Ok(()) => log::info!("saved screenshot {}", path.display()),
+                Err(e) => log::error!("failed to save screenshot {}: {}", path.display(), e),
+            }
+        }))
+    }
+}
+
+pub enum SaveProgress {
+    Waiting,
+    Saving(JoinHandle<()>),
+    Failed,
+}
+
+fn save_png(path: &Path, width: u32, height: u32, pixels: Vec<u8>) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    }
+
+    let image = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, pixels)
+        .ok_or_else(|| "pixel data does not match the frame size".to_string())?;
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpad_odd_width_test() {
+        // 3 pixels are 12 bytes, padded to a full 256 byte row
+        let width = 3;
+        let padded_row = padded_bytes_per_row(width) as usize;
+        assert_eq!(padded_row, 256);
+
+        let mut padded = vec![0xff; padded_row * 2];
+        for row in 0..2 {
+            for i in 0..12 {
+                padded[row * padded_row + i] = (row * 12 + i) as u8;
+            }
+        }
+
+        let pixels = unpad_rows(&padded, width);
+        assert_eq!(pixels, (0..24).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn aligned_width_is_not_padded_test() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+    }
+
+    #[test]
+    fn swaps_red_blue_test() {
+        assert_eq!(swaps_red_blue(TextureFormat::Bgra8UnormSrgb), Some(true));
+        assert_eq!(swaps_red_blue(TextureFormat::Rgba8Unorm), Some(false));
+        assert_eq!(swaps_red_blue(TextureFormat::Rgba16Float), None);
+    }
+}
@@ -1,13 +1,20 @@
 #![allow(dead_code)]
 use std::borrow::Cow;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::collections::{hash_map::Entry, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::BitOr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use wgpu::{Device, ShaderModule};
 
+use crate::util::BlockOn;
+
+mod preprocess;
+pub use preprocess::{preprocess, PreprocessError};
+
 crate::macros::parallel_enum_values!(
     (
         ShaderId,
@@ -17,58 +24,314 @@ crate::macros::parallel_enum_values!(
     LightAssignment -> "shader/light_assignment.comp.spv",
     VertexShader -> "shader/vertex_shader.vert.spv",
     FragmentShader -> "shader/fragment_shader.frag.spv",
+    // both stages in one file, see ShaderStage::wgsl_entry_point
+    ForwardWgsl -> "wgsl/forward.wgsl",
+    DebugLinesWgsl -> "wgsl/debug_lines.wgsl",
+    TonemapWgsl -> "wgsl/tonemap.wgsl",
+    BloomWgsl -> "wgsl/bloom.wgsl", // one fragment entry point per pass, see PostProcess
 );
 
+pub fn shader_path(id: ShaderId) -> &'static str {
+    SHADER_PATH_PAIRS
+        .iter()
+        .find(|(pair_id, _)| *pair_id == id)
+        .map(|(_, path)| *path)
+        .expect("shader id missing from SHADER_PATH_PAIRS")
+}
+
+// Optional features of a shader, each flag is defined by name for the preprocessor so WGSL sources
+// can test it with #ifdef.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderFlags(u32);
+
+impl ShaderFlags {
+    pub const NONE: Self = Self(0);
+    pub const TEXTURED: Self = Self(1 << 0);
+    pub const DEBUG_NORMALS: Self = Self(1 << 1); // shade with the world space normal instead
+    pub const STORAGE_LIGHTS: Self = Self(1 << 2); // point lights in a runtime sized storage array
+    pub const DEBUG_DEPTH: Self = Self(1 << 3); // distance from the camera
+    pub const DEBUG_UVS: Self = Self(1 << 4); // texture coordinates in red and green
+
+    const NAMES: &'static [(ShaderFlags, &'static str)] = &[
+        (Self::TEXTURED, "TEXTURED"),
+        (Self::DEBUG_NORMALS, "DEBUG_NORMALS"),
+        (Self::STORAGE_LIGHTS, "STORAGE_LIGHTS"),
+        (Self::DEBUG_DEPTH, "DEBUG_DEPTH"),
+        (Self::DEBUG_UVS, "DEBUG_UVS"),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .iter()
+            .filter(move |(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+}
+
+impl BitOr for ShaderFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for ShaderFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+        f.write_str(&self.names().collect::<Vec<_>>().join("|"))
+    }
+}
+
+// A builtin shader preprocessed with a set of flags, only WGSL sources can have variants since
+// SPIR-V is compiled ahead of time by build.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderVariant {
+    pub id: ShaderId,
+    pub flags: ShaderFlags,
+}
+
+impl ShaderVariant {
+    pub fn new(id: ShaderId, flags: ShaderFlags) -> Self {
+        Self { id, flags }
+    }
+}
+
+// SPIR-V is compiled into OUT_DIR by build.rs, WGSL is loaded from the source tree as is so it can
+// be changed without a rebuild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderFormat {
+    SpirV,
+    Wgsl,
+}
+
+impl ShaderFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("wgsl") => ShaderFormat::Wgsl,
+            _ => ShaderFormat::SpirV,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    // A WGSL file can hold every stage so each gets its own default entry point, SPIR-V compiled
+    // from glsl always uses main.
+    pub fn default_entry_point(self, format: ShaderFormat) -> &'static str {
+        match (format, self) {
+            (ShaderFormat::SpirV, _) => "main",
+            (ShaderFormat::Wgsl, ShaderStage::Vertex) => "vs_main",
+            (ShaderFormat::Wgsl, ShaderStage::Fragment) => "fs_main",
+            (ShaderFormat::Wgsl, ShaderStage::Compute) => "cs_main",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Shader {
     name: String,
     source_path: PathBuf,
+    format: ShaderFormat,
 
-    entry_point: String,
+    entry_point: Option<String>, // None uses the default for the stage
 
     handle: ShaderModule,
 }
 
-impl Shader {
-    pub fn new(device: &Device, source_path: &Path) -> Self {
-        ShaderBuilder::new(source_path).build(device)
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Empty {
+        path: PathBuf,
+    },
+    BadAlignment {
+        path: PathBuf,
+        len: usize,
+    }, // SPIR-V is a stream of 32 bit words
+    BadMagic {
+        path: PathBuf,
+        magic: u32,
+    },
+    InvalidUtf8 {
+        path: PathBuf,
+    },
+    Preprocess {
+        path: PathBuf,
+        error: PreprocessError,
+    },
+    DefinesOnSpirV {
+        path: PathBuf,
+    }, // defines only apply to WGSL sources
+    Invalid {
+        path: PathBuf,
+        message: String,
+    }, // rejected by wgpu's validation
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // SPIR-V files only exist once build.rs compiled them
+            ShaderError::Io { path, error } => write!(
+                f,
+                "failed to read shader file {}: {}, SPIR-V shaders are compiled into OUT_DIR by \
+                 the build script so a clean rebuild may restore it",
+                path.display(),
+                error
+            ),
+            ShaderError::Empty { path } => write!(f, "shader file {} is empty", path.display()),
+            ShaderError::BadAlignment { path, len } => write!(
+                f,
+                "shader file {} is {} bytes, SPIR-V must be a multiple of 4, the file is likely \
+                 truncated",
+                path.display(),
+                len
+            ),
+            ShaderError::BadMagic { path, magic } => write!(
+                f,
+                "shader file {} starts with {:#010x} instead of the SPIR-V magic number {:#010x}, \
+                 is it a glsl source file?",
+                path.display(),
+                magic,
+                SPIRV_MAGIC
+            ),
+            ShaderError::InvalidUtf8 { path } => {
+                write!(f, "shader file {} is not valid utf-8", path.display())
+            }
+            ShaderError::Preprocess { path, error } => {
+                write!(
+                    f,
+                    "failed to preprocess shader file {}, {}",
+                    path.display(),
+                    error
+                )
+            }
+            ShaderError::DefinesOnSpirV { path } => write!(
+                f,
+                "shader file {} is SPIR-V, only WGSL shaders can be preprocessed with defines",
+                path.display()
+            ),
+            ShaderError::Invalid { path, message } => {
+                write!(f, "shader file {} is invalid: {}", path.display(), message)
+            }
+        }
     }
+}
 
-    pub fn all(device: &Device, source_path: &Path, name: &str, entry_point: &str) -> Self {
-        let mut file = File::open(source_path).unwrap_or_else(|e| {
-            panic!(
-                "failed to open shader file {}: {}",
-                source_path.display(),
-                e
-            )
-        });
+impl Error for ShaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShaderError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
 
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents).unwrap_or_else(|e| {
-            panic!(
-                "failed to read shader file {}: {}",
-                source_path.display(),
-                e
-            )
-        });
+impl Shader {
+    pub fn new(device: &Device, source_path: &Path) -> Result<Self, ShaderError> {
+        ShaderBuilder::new(source_path).build(device)
+    }
 
-        assert!(
-            contents.len() % 4 == 0,
-            "shader source file missing alignment possibly wrong filepath"
-        );
-        let data = bytemuck::cast_slice(&contents);
+    pub fn all(
+        device: &Device,
+        source_path: &Path,
+        name: &str,
+        entry_point: Option<&str>,
+        defines: &[&str],
+    ) -> Result<Self, ShaderError> {
+        let path = || source_path.to_owned();
+
+        let contents = std::fs::read(source_path).map_err(|error| ShaderError::Io {
+            path: path(),
+            error,
+        })?;
+        if contents.is_empty() {
+            return Err(ShaderError::Empty { path: path() });
+        }
 
+        let format = ShaderFormat::from_path(source_path);
+        let source = match format {
+            ShaderFormat::SpirV => {
+                if !defines.is_empty() {
+                    return Err(ShaderError::DefinesOnSpirV { path: path() });
+                }
+                if contents.len() % 4 != 0 {
+                    return Err(ShaderError::BadAlignment {
+                        path: path(),
+                        len: contents.len(),
+                    });
+                }
+
+                let magic =
+                    u32::from_le_bytes([contents[0], contents[1], contents[2], contents[3]]);
+                if magic != SPIRV_MAGIC {
+                    return Err(ShaderError::BadMagic {
+                        path: path(),
+                        magic,
+                    });
+                }
+
+                // the Vec<u8> isn't guaranteed to be 4 byte aligned so the words are copied out
+                let words = contents
+                    .chunks_exact(4)
+                    .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+                    .collect();
+                wgpu::ShaderSource::SpirV(Cow::Owned(words))
+            }
+            // wgpu reports wgsl errors with the offending source line
+            ShaderFormat::Wgsl => {
+                let text = String::from_utf8(contents)
+                    .map_err(|_| ShaderError::InvalidUtf8 { path: path() })?;
+                let text = preprocess(&text, defines).map_err(|error| ShaderError::Preprocess {
+                    path: path(),
+                    error,
+                })?;
+                wgpu::ShaderSource::Wgsl(Cow::Owned(text))
+            }
+        };
+
+        // validation errors would otherwise go to the uncaptured error handler, which panics
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
         let handle = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some(name),
-            source: wgpu::ShaderSource::SpirV(Cow::Borrowed(data)),
+            source,
         });
+        if let Some(error) = device.pop_error_scope().block_on() {
+            return Err(ShaderError::Invalid {
+                path: path(),
+                message: error.to_string(),
+            });
+        }
 
-        Self {
+        Ok(Self {
             name: name.to_string(),
             source_path: source_path.to_owned(),
-            entry_point: entry_point.to_string(),
+            format,
+            entry_point: entry_point.map(str::to_string),
             handle,
-        }
+        })
     }
 
     pub fn handle(&self) -> &ShaderModule {
@@ -83,8 +346,14 @@ impl Shader {
         &self.source_path
     }
 
-    pub fn entry_point(&self) -> &str {
-        &self.entry_point
+    pub fn format(&self) -> ShaderFormat {
+        self.format
+    }
+
+    pub fn entry_point(&self, stage: ShaderStage) -> &str {
+        self.entry_point
+            .as_deref()
+            .unwrap_or_else(|| stage.default_entry_point(self.format))
     }
 }
 
@@ -92,7 +361,8 @@ pub struct ShaderBuilder {
     name: String,
     source_path: PathBuf,
 
-    entry_point: String,
+    entry_point: Option<String>,
+    defines: Vec<String>,
 }
 
 impl ShaderBuilder {
@@ -103,11 +373,11 @@ impl ShaderBuilder {
             .to_str()
             .expect("failed to convert os string to string")
             .to_string();
-        let entry_point = "main".to_string();
         Self {
             name,
             source_path: source_path.to_owned(),
-            entry_point,
+            entry_point: None,
+            defines: Vec::new(),
         }
     }
 
@@ -121,155 +391,407 @@ impl ShaderBuilder {
         self
     }
 
+    // Overrides the default for every stage.
     pub fn entry_point(mut self, entry_point: &str) -> Self {
-        self.entry_point = entry_point.to_string();
+        self.entry_point = Some(entry_point.to_string());
+        self
+    }
+
+    // Defines a name for the preprocessor, WGSL only.
+    pub fn define(mut self, name: &str) -> Self {
+        self.defines.push(name.to_string());
         self
     }
 
-    pub fn build(self, device: &Device) -> Shader {
+    pub fn flags(self, flags: ShaderFlags) -> Self {
+        flags.names().fold(self, ShaderBuilder::define)
+    }
+
+    pub fn build(self, device: &Device) -> Result<Shader, ShaderError> {
         let ShaderBuilder {
             name,
             source_path,
             entry_point,
+            defines,
         } = self;
-        Shader::all(device, &source_path, &name, &entry_point)
+        let defines: Vec<&str> = defines.iter().map(String::as_str).collect();
+        Shader::all(
+            device,
+            &source_path,
+            &name,
+            entry_point.as_deref(),
+            &defines,
+        )
     }
 }
 
-#[derive(Default)]
-pub struct ShaderLibrary {
-    shaders: HashMap<ShaderId, Arc<Shader>>,
+// Either a shader from the ShaderId table, a preprocessed variant of one, or one added through
+// ShaderLibraryBuilder::add or ShaderLibrary::register, for experiments that shouldn't need a table
+// entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShaderHandle {
+    Builtin(ShaderId),
+    Variant(ShaderVariant),
+    Dynamic(u32),
 }
 
-impl ShaderLibrary {
-    // TODO: implement on the fly shader loading and unloading.
-    pub fn load_as_needed() -> Self {
-        todo!();
+impl From<ShaderId> for ShaderHandle {
+    fn from(id: ShaderId) -> Self {
+        ShaderHandle::Builtin(id)
+    }
+}
+
+impl From<ShaderVariant> for ShaderHandle {
+    fn from(variant: ShaderVariant) -> Self {
+        ShaderHandle::Variant(variant)
+    }
+}
+
+// Table paths are relative, SPIR-V is looked up in OUT_DIR and WGSL in the source tree.
+pub fn builtin_path(path: &str) -> PathBuf {
+    match ShaderFormat::from_path(Path::new(path)) {
+        ShaderFormat::SpirV => Path::new(env!("OUT_DIR")).join(path),
+        ShaderFormat::Wgsl => PathBuf::from(path),
     }
+}
 
-    pub fn load_all(device: &Device) -> Self {
-        let build_out_dir = Path::new(&env!("OUT_DIR"));
+// Collects shaders to load together, nothing touches the device until build.
+#[derive(Default)]
+pub struct ShaderLibraryBuilder {
+    shaders: Vec<(ShaderHandle, ShaderBuilder)>,
+    next_id: u32,
+}
+
+impl ShaderLibraryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        let shaders = SHADER_PATH_PAIRS
+    pub fn with_builtins() -> Self {
+        let mut builder = Self::new();
+        builder.shaders = SHADER_PATH_PAIRS
             .iter()
             .map(|(id, s)| {
                 (
-                    *id,
-                    Arc::new(ShaderBuilder::new(&build_out_dir.join(s)).build(device)),
+                    ShaderHandle::from(*id),
+                    ShaderBuilder::new(&builtin_path(s)),
                 )
             })
             .collect();
+        builder
+    }
+
+    pub fn add(&mut self, source_path: &Path) -> ShaderHandle {
+        self.add_shader(ShaderBuilder::new(source_path))
+    }
+
+    // For shaders that need a name or entry point of their own.
+    pub fn add_shader(&mut self, shader: ShaderBuilder) -> ShaderHandle {
+        let handle = ShaderHandle::Dynamic(self.next_id);
+        self.next_id += 1;
+        self.shaders.push((handle, shader));
+        handle
+    }
+
+    // Fails on the first shader that can't be loaded.
+    pub fn build(self, device: &Device) -> Result<ShaderLibrary, ShaderError> {
+        let shaders = self
+            .shaders
+            .into_iter()
+            .map(|(handle, shader)| Ok((handle, Arc::new(shader.build(device)?))))
+            .collect::<Result<_, ShaderError>>()?;
+
+        Ok(ShaderLibrary {
+            shaders,
+            next_id: self.next_id,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct ShaderLibrary {
+    shaders: HashMap<ShaderHandle, Arc<Shader>>,
+    next_id: u32, // for register, continues after the ids handed out by the builder
+}
+
+impl ShaderLibrary {
+    // TODO: implement on the fly shader loading and unloading.
+    pub fn load_as_needed() -> Self {
+        todo!();
+    }
+
+    pub fn load_all(device: &Device) -> Result<Self, ShaderError> {
+        ShaderLibraryBuilder::with_builtins().build(device)
+    }
+
+    pub fn register(
+        &mut self,
+        device: &Device,
+        shader: ShaderBuilder,
+    ) -> Result<ShaderHandle, ShaderError> {
+        let shader = shader.build(device)?;
+
+        let handle = ShaderHandle::Dynamic(self.next_id);
+        self.next_id += 1;
+        self.shaders.insert(handle, Arc::new(shader));
+
+        Ok(handle)
+    }
+
+    // Builds the variant the first time it is asked for, after that it comes from the cache.
+    pub fn variant(
+        &mut self,
+        device: &Device,
+        variant: ShaderVariant,
+    ) -> Result<ShaderHandle, ShaderError> {
+        let handle = ShaderHandle::from(variant);
+        if let Entry::Vacant(entry) = self.shaders.entry(handle) {
+            let path = builtin_path(shader_path(variant.id));
+            let name = format!("{:?} {}", variant.id, variant.flags);
+            let shader = ShaderBuilder::new(&path)
+                .name(&name)
+                .flags(variant.flags)
+                .build(device)?;
+            entry.insert(Arc::new(shader));
+        }
 
-        Self { shaders }
+        Ok(handle)
     }
 
-    pub fn get(&self, id: ShaderId) -> &Shader {
+    pub fn get(&self, handle: impl Into<ShaderHandle>) -> &Shader {
         &self
             .shaders
-            .get(&id)
+            .get(&handle.into())
             .expect("tried to access shader with bad id")
     }
 }
 
-// tests are outdated shaders use features that aren't requested
-// TODO: update test to either not actually create module or use wgpu features
-
-/*
 #[cfg(test)]
 mod tests {
-
-    use crate::util::BlockOn;
+    use crate::util::test_device;
 
     use super::*;
 
-    use wgpu::{Adapter, Device, Instance, Queue};
+    use wgpu::Device;
 
-    async fn init_wgpu() -> (Instance, Adapter, Device, Queue) {
-        let instance = Instance::new(wgpu::Backends::all());
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: None,
-            })
-            .await
-            .expect("failed to find appropriate adapter");
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
-                },
-                None,
-            )
-            .await
-            .expect("failed to create appropriate device");
+    fn device() -> Option<Device> {
+        test_device().map(|(device, _)| device)
+    }
 
-        (instance, adapter, device, queue)
+    fn vertex_shader_path() -> PathBuf {
+        builtin_path("shader/vertex_shader.vert.spv")
     }
 
     #[test]
     fn shader_builder_generic_test() {
-        let (_instance, _adapter, device, _queue) = init_wgpu().block_on();
-
-        let path = PathBuf::from("shader/vertex_shader.vsspirv");
-        let builder = ShaderBuilder::new(&path);
-        let shader = builder.build(&device);
-
-        assert_eq!(
-            shader.source_path.to_str().unwrap(),
-            "shader/vertex_shader.vs"
-        );
-
-        assert_eq!(shader.name, "vertex_shader.vsspirv");
-        assert_eq!(shader.entry_point, "main");
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let path = vertex_shader_path();
+        let shader = ShaderBuilder::new(&path).build(&device).unwrap();
+
+        assert_eq!(shader.source_path(), path);
+        assert_eq!(shader.name(), "vertex_shader.vert.spv");
+        assert_eq!(shader.format(), ShaderFormat::SpirV);
+        assert_eq!(shader.entry_point(ShaderStage::Vertex), "main");
     }
 
     #[test]
     fn shader_builder_specific_test() {
-        let (_instance, _adapter, device, _queue) = init_wgpu().block_on();
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
 
-        let path = PathBuf::from("shader/vertex_shader.vsspirv");
-        let builder = ShaderBuilder::new(&path)
+        let path = vertex_shader_path();
+        let shader = ShaderBuilder::new(&path)
             .name("Joblin")
-            .entry_point("main2");
-        let shader = builder.build(&device);
-
-        assert_eq!(
-            shader.source_path.to_str().unwrap(),
-            "shader/vertex_shader.vs"
-        );
-
-        assert_eq!(shader.name, "Joblin");
-        assert_eq!(shader.entry_point, "main2");
+            .entry_point("main2")
+            .build(&device)
+            .unwrap();
+
+        assert_eq!(shader.source_path(), path);
+        assert_eq!(shader.name(), "Joblin");
+        assert_eq!(shader.entry_point(ShaderStage::Vertex), "main2");
+        assert_eq!(shader.entry_point(ShaderStage::Fragment), "main2");
     }
 
     #[test]
     fn shader_library_builder_generic_test() {
-        let (_instance, _adapter, device, _queue) = init_wgpu().block_on();
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
 
-        let path = PathBuf::from("shader/vertex_shader.vsspirv");
-        let path2 = PathBuf::from("shader/fragment_shader.fsspirv");
+        let path = vertex_shader_path();
+        let path2 = builtin_path("shader/fragment_shader.frag.spv");
 
-        let mut builder = ShaderLibraryBuilder::new();
+        let mut builder = ShaderLibraryBuilder::with_builtins();
         let shader = builder.add(&path);
         let shader2 = builder.add(&path2);
-        let library = builder.build(&device);
+        let mut library = builder.build(&device).unwrap();
+
+        assert_eq!(library.get(shader).source_path(), path);
+        assert_eq!(library.get(shader2).source_path(), path2);
+        assert_eq!(library.get(ShaderId::VertexShader).source_path(), path);
+
+        let shader3 = library
+            .register(&device, ShaderBuilder::new(&path))
+            .unwrap();
+        assert!(shader3 != shader && shader3 != shader2);
+    }
+
+    #[test]
+    fn wgsl_entry_points_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let shader = ShaderBuilder::new(&builtin_path("wgsl/forward.wgsl"))
+            .build(&device)
+            .unwrap();
+
+        assert_eq!(shader.format(), ShaderFormat::Wgsl);
+        assert_eq!(shader.entry_point(ShaderStage::Vertex), "vs_main");
+        assert_eq!(shader.entry_point(ShaderStage::Fragment), "fs_main");
+    }
+
+    #[test]
+    fn forward_pairs_load_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        // the SPIR-V pair from OUT_DIR and its WGSL port straight from the source tree
+        let library = ShaderLibrary::load_all(&device).unwrap();
+        let vertex = library.get(ShaderId::VertexShader);
+        let fragment = library.get(ShaderId::FragmentShader);
+        let wgsl = library.get(ShaderId::ForwardWgsl);
+
+        assert_eq!(vertex.format(), ShaderFormat::SpirV);
+        assert_eq!(fragment.format(), ShaderFormat::SpirV);
+        assert_eq!(vertex.entry_point(ShaderStage::Vertex), "main");
+        assert_eq!(fragment.entry_point(ShaderStage::Fragment), "main");
+
+        assert_eq!(wgsl.format(), ShaderFormat::Wgsl);
+        assert_eq!(wgsl.source_path(), Path::new("wgsl/forward.wgsl"));
+        assert_eq!(wgsl.entry_point(ShaderStage::Vertex), "vs_main");
+        assert_eq!(wgsl.entry_point(ShaderStage::Fragment), "fs_main");
+    }
+
+    #[test]
+    fn shader_variant_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let mut library = ShaderLibrary::default();
+        let textured = ShaderVariant::new(ShaderId::ForwardWgsl, ShaderFlags::TEXTURED);
+        let handle = library.variant(&device, textured).unwrap();
+        assert_eq!(handle, ShaderHandle::Variant(textured));
+        assert_eq!(library.variant(&device, textured).unwrap(), handle);
+        assert_eq!(library.get(textured).name(), "ForwardWgsl TEXTURED");
+
+        let untextured = ShaderVariant::new(ShaderId::ForwardWgsl, ShaderFlags::NONE);
+        assert!(library.variant(&device, untextured).unwrap() != handle);
+
+        let spirv = ShaderVariant::new(ShaderId::VertexShader, ShaderFlags::TEXTURED);
+        assert!(matches!(
+            library.variant(&device, spirv),
+            Err(ShaderError::DefinesOnSpirV { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_shader_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let result = ShaderBuilder::new(Path::new("shader/does_not_exist.spv")).build(&device);
+        assert!(matches!(result, Err(ShaderError::Io { .. })));
+    }
+
+    #[test]
+    fn misaligned_shader_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let path = std::env::temp_dir().join("card_game_misaligned_shader.spv");
+        std::fs::write(&path, [0x03, 0x02, 0x23, 0x07, 0x00]).unwrap();
+
+        let result = ShaderBuilder::new(&path).build(&device);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(ShaderError::BadAlignment { len: 5, .. })
+        ));
+    }
 
-        let shader = library.get(shader);
+    // Builds a shader from the given file contents, written to a temporary file for the duration.
+    fn build_from_contents(
+        device: &Device,
+        name: &str,
+        contents: &[u8],
+    ) -> Result<Shader, ShaderError> {
+        let path = std::env::temp_dir().join(format!("card_game_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+
+        let result = ShaderBuilder::new(&path).build(device);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
 
-        assert_eq!(
-            shader.source_path.to_str().unwrap(),
-            "shader/vertex_shader.vsspirv"
-        );
+    #[test]
+    fn empty_shader_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let result = build_from_contents(&device, "empty.spv", &[]);
+        assert!(matches!(result, Err(ShaderError::Empty { .. })));
+    }
 
-        let shader = library.get(shader2);
-        assert_eq!(
-            shader.source_path.to_str().unwrap(),
-            "shader/fragment_shader.fsspirv"
-        );
+    #[test]
+    fn bad_magic_shader_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        // a word aligned file that isn't SPIR-V, like a glsl source given the wrong extension
+        let result = build_from_contents(&device, "bad_magic.spv", b"#version 450\n\n\n\n");
+        match result {
+            Err(e @ ShaderError::BadMagic { magic, .. }) => {
+                assert_eq!(magic, u32::from_le_bytes(*b"#ver"));
+                assert!(e.to_string().contains("bad_magic.spv"), "{}", e);
+            }
+            other => panic!(
+                "expected BadMagic, got {:?}",
+                other.map(|s| s.name().to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn valid_spirv_module_test() {
+        let device = match device() {
+            Some(device) => device,
+            None => return,
+        };
+
+        let contents = std::fs::read(vertex_shader_path()).unwrap();
+        let shader = build_from_contents(&device, "valid.spv", &contents).unwrap();
+        assert_eq!(shader.format(), ShaderFormat::SpirV);
     }
 }
-*/
@@ -0,0 +1,195 @@
+use std::fmt;
+
+// Line based conditionals for WGSL sources, which have no preprocessor of their own:
+//
+//     #ifdef NAME / #ifndef NAME
+//     #else
+//     #endif
+//
+// Conditions nest and names that aren't defined count as undefined. Directives and skipped lines
+// are replaced by empty lines so line numbers in wgpu's errors still match the file.
+pub fn preprocess(source: &str, defines: &[&str]) -> Result<String, PreprocessError> {
+    // one entry per open #ifdef, whether its current branch is taken and whether #else was seen
+    let mut stack: Vec<(bool, bool)> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |kind| PreprocessError {
+            line: index + 1,
+            kind,
+        };
+        let active = stack.iter().all(|(taken, _)| *taken);
+
+        let directive = match line.trim_start().strip_prefix('#') {
+            Some(directive) => directive,
+            None => {
+                if active {
+                    output.push_str(line);
+                }
+                output.push('\n');
+                continue;
+            }
+        };
+
+        let mut words = directive.split_whitespace();
+        match words.next() {
+            Some(keyword @ ("ifdef" | "ifndef")) => {
+                let name = words
+                    .next()
+                    .ok_or_else(|| error(PreprocessErrorKind::MissingName))?;
+                let defined = defines.contains(&name);
+                stack.push((defined == (keyword == "ifdef"), false));
+            }
+            Some("else") => match stack.last_mut() {
+                Some((_, true)) => return Err(error(PreprocessErrorKind::DuplicateElse)),
+                Some((taken, seen_else)) => {
+                    *taken = !*taken;
+                    *seen_else = true;
+                }
+                None => return Err(error(PreprocessErrorKind::Unmatched("else"))),
+            },
+            Some("endif") => {
+                if stack.pop().is_none() {
+                    return Err(error(PreprocessErrorKind::Unmatched("endif")));
+                }
+            }
+            _ => {
+                return Err(error(PreprocessErrorKind::UnknownDirective(
+                    directive.trim().to_string(),
+                )))
+            }
+        }
+        output.push('\n');
+    }
+
+    if !stack.is_empty() {
+        return Err(PreprocessError {
+            line: source.lines().count(),
+            kind: PreprocessErrorKind::MissingEndif,
+        });
+    }
+
+    Ok(output)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PreprocessError {
+    pub line: usize, // 1 based
+    pub kind: PreprocessErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PreprocessErrorKind {
+    MissingName,
+    DuplicateElse,
+    Unmatched(&'static str),
+    MissingEndif,
+    UnknownDirective(String),
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            PreprocessErrorKind::MissingName => write!(f, "#ifdef and #ifndef need a name"),
+            PreprocessErrorKind::DuplicateElse => write!(f, "second #else for the same #ifdef"),
+            PreprocessErrorKind::Unmatched(directive) => {
+                write!(f, "#{} without an #ifdef", directive)
+            }
+            PreprocessErrorKind::MissingEndif => write!(f, "missing #endif at end of file"),
+            PreprocessErrorKind::UnknownDirective(directive) => {
+                write!(f, "unknown directive #{}", directive)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // skipped lines stay as empty lines, trimmed here so expectations stay readable
+    fn lines(source: &str) -> Vec<&str> {
+        source.lines().filter(|line| !line.is_empty()).collect()
+    }
+
+    #[test]
+    fn no_directives_test() {
+        let source = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(preprocess(source, &[]).unwrap(), source);
+        assert_eq!(preprocess(source, &["TEXTURED"]).unwrap(), source);
+    }
+
+    #[test]
+    fn ifdef_else_test() {
+        let source = "a\n#ifdef TEXTURED\nb\n#else\nc\n#endif\nd\n";
+
+        let textured = preprocess(source, &["TEXTURED"]).unwrap();
+        assert_eq!(lines(&textured), ["a", "b", "d"]);
+        assert_eq!(textured.lines().count(), source.lines().count());
+
+        let untextured = preprocess(source, &[]).unwrap();
+        assert_eq!(lines(&untextured), ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn nested_test() {
+        let source = "\
+#ifdef A
+a
+    #ifndef B
+    not b
+    #else
+    b
+    #endif
+#else
+not a
+    #ifdef B
+    unreachable with A
+    #endif
+#endif
+";
+
+        assert_eq!(
+            lines(&preprocess(source, &["A"]).unwrap()),
+            ["a", "    not b"]
+        );
+        assert_eq!(
+            lines(&preprocess(source, &["A", "B"]).unwrap()),
+            ["a", "    b"]
+        );
+        assert_eq!(
+            lines(&preprocess(source, &["B"]).unwrap()),
+            ["not a", "    unreachable with A"]
+        );
+        assert_eq!(lines(&preprocess(source, &[]).unwrap()), ["not a"]);
+    }
+
+    #[test]
+    fn unknown_flag_test() {
+        // undefined names are false, defines nothing refers to are ignored
+        let source = "#ifdef NOT_A_FLAG\na\n#endif\n#ifndef NOT_A_FLAG\nb\n#endif\n";
+        assert_eq!(lines(&preprocess(source, &["UNUSED"]).unwrap()), ["b"]);
+    }
+
+    #[test]
+    fn malformed_test() {
+        let kind = |source| preprocess(source, &[]).unwrap_err().kind;
+
+        assert_eq!(kind("#ifdef\n#endif\n"), PreprocessErrorKind::MissingName);
+        assert_eq!(kind("#endif\n"), PreprocessErrorKind::Unmatched("endif"));
+        assert_eq!(kind("#else\n"), PreprocessErrorKind::Unmatched("else"));
+        assert_eq!(
+            kind("#ifdef A\n#else\n#else\n#endif\n"),
+            PreprocessErrorKind::DuplicateElse
+        );
+        assert_eq!(kind("#ifdef A\na\n"), PreprocessErrorKind::MissingEndif);
+        assert_eq!(
+            kind("#if A\n#endif\n"),
+            PreprocessErrorKind::UnknownDirective("if A".to_string())
+        );
+
+        let error = preprocess("a\n\n#endif\n", &[]).unwrap_err();
+        assert_eq!(error.line, 3);
+    }
+}
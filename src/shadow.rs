@@ -0,0 +1,279 @@
+use nalgebra::{Isometry3, Matrix4, Point3, Vector3};
+
+use crate::common_component::Projection;
+
+// Resolution of the square shadow map for the first GlobalLight.
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+// Only this much of the camera's view gets shadows, the whole far plane would spread the map so thin
+// that nothing close to the camera keeps a usable shadow.
+pub const SHADOW_DISTANCE: f32 = 40.0;
+
+// Casters up to this far beyond the shadowed region towards the light still cast into it.
+pub const SHADOW_CASTER_MARGIN: f32 = 50.0;
+
+// World space corners of the camera's view between its near plane and max_distance, near corners
+// first. The camera looks down its local -z like nalgebra's projections expect.
+pub fn frustum_corners(
+    projection: &Projection,
+    isometry: &Isometry3<f32>,
+    max_distance: f32,
+) -> [Point3<f32>; 8] {
+    let near = projection.znear();
+    let far = max_distance.min(projection.zfar()).max(near);
+
+    // left, right, bottom and top of the view at a depth
+    let bounds = |depth: f32| match projection {
+        Projection::Perspective(p) => {
+            let half_height = depth * (p.fovy() / 2.0).tan();
+            let half_width = half_height * p.aspect();
+            (-half_width, half_width, -half_height, half_height)
+        }
+        Projection::Orthographic(o) => (o.left(), o.right(), o.bottom(), o.top()),
+    };
+
+    let mut corners = [Point3::origin(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        let depth = if i < 4 { near } else { far };
+        let (left, right, bottom, top) = bounds(depth);
+
+        let x = if i % 2 == 0 { left } else { right };
+        let y = if (i / 2) % 2 == 0 { bottom } else { top };
+        *corner = isometry * Point3::new(x, y, -depth);
+    }
+
+    corners
+}
+
+// Orthographic view projection looking along direction that fits every corner. Depth runs from 0
+// on the side facing the light to 1, the clip range wgpu uses. margin moves the near plane towards
+// the light so casters outside the corners' bounds still land in the map.
+pub fn light_view_projection(
+    direction: &Vector3<f32>,
+    corners: &[Point3<f32>],
+    margin: f32,
+) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    // look_at needs an up vector that isn't parallel to the view direction
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    };
+
+    let center = corners
+        .iter()
+        .fold(Vector3::zeros(), |sum, corner| sum + corner.coords)
+        / corners.len() as f32;
+    let center = Point3::from(center);
+    let view = Isometry3::look_at_rh(&(center - direction), &center, &up);
+
+    let mut min = Vector3::repeat(f32::MAX);
+    let mut max = Vector3::repeat(f32::MIN);
+    for corner in corners {
+        let corner = view * corner;
+        min = min.inf(&corner.coords);
+        max = max.sup(&corner.coords);
+    }
+
+    // the view looks down -z, the corner with the largest z is closest to the light
+    let near = -max.z - margin;
+    let far = -min.z;
+
+    let width = max.x - min.x;
+    let height = max.y - min.y;
+    let depth = far - near;
+    #[rustfmt::skip]
+    let projection = Matrix4::new(
+        2.0 / width, 0.0,          0.0,          -(max.x + min.x) / width,
+        0.0,         2.0 / height, 0.0,          -(max.y + min.y) / height,
+        0.0,         0.0,          -1.0 / depth, -near / depth,
+        0.0,         0.0,          0.0,          1.0,
+    );
+
+    projection * view.to_homogeneous()
+}
+
+// Near plane of spot light shadows, closer casters are clipped.
+pub const SPOT_SHADOW_NEAR: f32 = 0.05;
+
+// Perspective view projection from a spot light covering its cone out to range. cut_off is the
+// cosine of the cone's half angle like on SpotLight, very wide cones are narrowed to 80 degrees and
+// leave the rim unshadowed. Depth runs from 0 at the near plane to 1 at range like the global one.
+pub fn spot_light_view_projection(
+    position: &Point3<f32>,
+    direction: &Vector3<f32>,
+    cut_off: f32,
+    range: f32,
+) -> Matrix4<f32> {
+    let direction = direction.normalize();
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::z()
+    } else {
+        Vector3::y()
+    };
+    let view = Isometry3::look_at_rh(position, &(position + direction), &up);
+
+    let half_angle = cut_off
+        .clamp(-1.0, 1.0)
+        .acos()
+        .clamp(1f32.to_radians(), 80f32.to_radians());
+    let f = 1.0 / half_angle.tan();
+    let near = SPOT_SHADOW_NEAR;
+    let far = range.max(near * 2.0);
+    #[rustfmt::skip]
+    let projection = Matrix4::new(
+        f,   0.0, 0.0,                 0.0,
+        0.0, f,   0.0,                 0.0,
+        0.0, 0.0, far / (near - far),  near * far / (near - far),
+        0.0, 0.0, -1.0,                0.0,
+    );
+
+    projection * view.to_homogeneous()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Orthographic3, Perspective3};
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    // 90 degree square view from the origin down -z
+    fn camera() -> (Projection, Isometry3<f32>) {
+        let projection = Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0);
+        (Projection::Perspective(projection), Isometry3::identity())
+    }
+
+    fn project(view_projection: &Matrix4<f32>, point: &Point3<f32>) -> Point3<f32> {
+        view_projection.transform_point(point)
+    }
+
+    #[test]
+    fn frustum_corners_test() {
+        let (projection, isometry) = camera();
+        let corners = frustum_corners(&projection, &isometry, 10.0);
+
+        for corner in &corners[..4] {
+            assert!((corner.z + 1.0).abs() < EPSILON);
+            assert!((corner.x.abs() - 1.0).abs() < EPSILON);
+            assert!((corner.y.abs() - 1.0).abs() < EPSILON);
+        }
+        for corner in &corners[4..] {
+            assert!((corner.z + 10.0).abs() < EPSILON);
+            assert!((corner.x.abs() - 10.0).abs() < EPSILON);
+            assert!((corner.y.abs() - 10.0).abs() < EPSILON);
+        }
+
+        // moving the camera moves the corners with it
+        let moved = frustum_corners(&projection, &Isometry3::translation(0.0, 5.0, 0.0), 10.0);
+        for (corner, moved) in corners.iter().zip(&moved) {
+            assert!((moved - corner - Vector3::new(0.0, 5.0, 0.0)).norm() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn orthographic_frustum_corners_test() {
+        let projection = Orthographic3::new(-4.0, 4.0, -2.0, 2.0, 1.0, 100.0);
+        let corners = frustum_corners(
+            &Projection::Orthographic(projection),
+            &Isometry3::identity(),
+            10.0,
+        );
+
+        // the same rectangle at both depths
+        for (near, far) in corners[..4].iter().zip(&corners[4..]) {
+            assert!((near.x - far.x).abs() < EPSILON && (near.y - far.y).abs() < EPSILON);
+            assert!((near.x.abs() - 4.0).abs() < EPSILON);
+            assert!((near.y.abs() - 2.0).abs() < EPSILON);
+            assert!((near.z + 1.0).abs() < EPSILON);
+            assert!((far.z + 10.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn light_from_above_test() {
+        let (projection, isometry) = camera();
+        let corners = frustum_corners(&projection, &isometry, 10.0);
+        let view_projection = light_view_projection(&-Vector3::y(), &corners, 5.0);
+
+        // every corner lands in clip space and the fit is tight on both axes
+        let projected: Vec<_> = corners
+            .iter()
+            .map(|c| project(&view_projection, c))
+            .collect();
+        for p in &projected {
+            assert!(p.x >= -1.0 - EPSILON && p.x <= 1.0 + EPSILON);
+            assert!(p.y >= -1.0 - EPSILON && p.y <= 1.0 + EPSILON);
+            assert!(p.z >= -EPSILON && p.z <= 1.0 + EPSILON);
+        }
+        let max_x = projected.iter().map(|p| p.x.abs()).fold(0.0, f32::max);
+        let max_y = projected.iter().map(|p| p.y.abs()).fold(0.0, f32::max);
+        assert!((max_x - 1.0).abs() < EPSILON);
+        assert!((max_y - 1.0).abs() < EPSILON);
+
+        // the lowest corners are farthest from the light
+        let bottom = project(&view_projection, &Point3::new(0.0, -10.0, -10.0));
+        assert!((bottom.z - 1.0).abs() < EPSILON);
+
+        // higher points are closer to the light, a caster within the margin above the view is kept
+        let low = project(&view_projection, &Point3::new(0.0, -1.0, -5.0));
+        let high = project(&view_projection, &Point3::new(0.0, 1.0, -5.0));
+        let caster = project(&view_projection, &Point3::new(0.0, 14.0, -5.0));
+        assert!(high.z < low.z);
+        assert!(caster.z >= 0.0 && caster.z < high.z);
+    }
+
+    #[test]
+    fn light_at_an_angle_test() {
+        let (projection, isometry) = camera();
+        let corners = frustum_corners(&projection, &isometry, 20.0);
+        let direction = Vector3::new(1.0, -1.0, 1.0);
+        let view_projection = light_view_projection(&direction, &corners, 0.0);
+
+        for corner in &corners {
+            let p = project(&view_projection, corner);
+            assert!(p.x.abs() <= 1.0 + EPSILON && p.y.abs() <= 1.0 + EPSILON);
+            assert!(p.z >= -EPSILON && p.z <= 1.0 + EPSILON);
+        }
+
+        // moving a point along the light direction only changes its depth
+        let point = Point3::new(2.0, -3.0, -8.0);
+        let a = project(&view_projection, &point);
+        let b = project(&view_projection, &(point + direction.normalize()));
+        assert!((a.x - b.x).abs() < EPSILON && (a.y - b.y).abs() < EPSILON);
+        assert!(b.z > a.z);
+    }
+
+    #[test]
+    fn spot_light_test() {
+        let position = Point3::new(0.0, 5.0, 0.0);
+        let cut_off = 30f32.to_radians().cos();
+        let view_projection = spot_light_view_projection(&position, &-Vector3::y(), cut_off, 10.0);
+
+        // straight down lands in the center, the near plane and range at depth 0 and 1
+        let center = project(
+            &view_projection,
+            &Point3::new(0.0, 5.0 - SPOT_SHADOW_NEAR, 0.0),
+        );
+        assert!(center.x.abs() < EPSILON && center.y.abs() < EPSILON && center.z.abs() < EPSILON);
+        let far = project(&view_projection, &Point3::new(0.0, -5.0, 0.0));
+        assert!((far.z - 1.0).abs() < EPSILON);
+
+        // the cone's rim touches the edge of clip space, closer points have less depth
+        let rim = project(
+            &view_projection,
+            &Point3::new(4.0 * 30f32.to_radians().tan(), 1.0, 0.0),
+        );
+        assert!((rim.x.abs().max(rim.y.abs()) - 1.0).abs() < EPSILON);
+        let near = project(&view_projection, &Point3::new(0.0, 3.0, 0.0));
+        assert!(near.z > 0.0 && near.z < far.z);
+
+        // a horizontal light still gets a valid view
+        let sideways = spot_light_view_projection(&position, &Vector3::x(), cut_off, 10.0);
+        let ahead = project(&sideways, &Point3::new(5.0, 5.0, 0.0));
+        assert!(ahead.x.abs() < EPSILON && ahead.y.abs() < EPSILON);
+        assert!(ahead.z > 0.0 && ahead.z < 1.0);
+    }
+}
@@ -0,0 +1,451 @@
+use std::time::Duration;
+
+use nalgebra::Vector4;
+
+use crate::light_lod::LightCandidate;
+
+// One depth texture shared by every shadowed spot light. The left square holds the single largest
+// tile, the right square is split into three medium tiles and four small ones, one per spot light.
+pub const SHADOW_ATLAS_WIDTH: u32 = 2048;
+pub const SHADOW_ATLAS_HEIGHT: u32 = 1024;
+
+// Seconds for a light's shadow to fade in after getting a tile, or out after losing it.
+pub const SHADOW_FADE_SECONDS: f32 = 0.25;
+
+// Square region of the atlas in texels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasTile {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+impl AtlasTile {
+    // Offset and size in texture coordinates, the shader maps the light's [0, 1] uv into it.
+    pub fn uv_rect(&self) -> Vector4<f32> {
+        let (width, height) = (SHADOW_ATLAS_WIDTH as f32, SHADOW_ATLAS_HEIGHT as f32);
+        Vector4::new(
+            self.x as f32 / width,
+            self.y as f32 / height,
+            self.size as f32 / width,
+            self.size as f32 / height,
+        )
+    }
+}
+
+// Tile of one light this frame. strength scales the shadow from 0, unshadowed, to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowTile {
+    pub tile: AtlasTile,
+    pub strength: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Holder {
+    id: u32,
+    strength: f32,
+    wanted: bool, // false while fading out, the slot can be handed to another light then
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    tile: AtlasTile,
+    tier: usize, // 0 is the largest
+    holder: Option<Holder>,
+}
+
+// Hands out atlas tiles to spot lights by importance, the most important light gets the largest
+// tile and lights below min_importance get none. Assignments are kept across frames so a light only
+// changes tiles when its tier changes, and shadows fade instead of popping when a light gains or
+// loses its tile.
+pub struct ShadowAtlas {
+    pub min_importance: f32,
+    // importance bonus per tier for lights holding a tile, stops lights near a tier boundary from
+    // trading tiles every frame. Compounds so a light is favored over the lights below its tier.
+    pub hysteresis: f32,
+    pub last_update: Option<Duration>, // game time for the fade's dt, set by the render system
+
+    slots: Vec<Slot>,
+    tier_capacity: Vec<usize>,
+
+    // per update, reused between frames
+    order: Vec<usize>,
+    tiers: Vec<Option<usize>>,
+    carried: Vec<f32>, // strength of lights changing tiers
+}
+
+impl Default for ShadowAtlas {
+    fn default() -> Self {
+        let half = SHADOW_ATLAS_HEIGHT;
+        let quarter = half / 2;
+        let eighth = half / 4;
+
+        let mut tiles = vec![(
+            0,
+            AtlasTile {
+                x: 0,
+                y: 0,
+                size: half,
+            },
+        )];
+        for (x, y) in [(0, 0), (quarter, 0), (0, quarter)] {
+            tiles.push((
+                1,
+                AtlasTile {
+                    x: half + x,
+                    y,
+                    size: quarter,
+                },
+            ));
+        }
+        for (x, y) in [(0, 0), (eighth, 0), (0, eighth), (eighth, eighth)] {
+            tiles.push((
+                2,
+                AtlasTile {
+                    x: half + quarter + x,
+                    y: quarter + y,
+                    size: eighth,
+                },
+            ));
+        }
+
+        Self::new(tiles)
+    }
+}
+
+impl ShadowAtlas {
+    // tiles pairs every tile with its tier, lower tiers go to more important lights.
+    pub fn new(tiles: impl IntoIterator<Item = (usize, AtlasTile)>) -> Self {
+        let slots: Vec<_> = tiles
+            .into_iter()
+            .map(|(tier, tile)| Slot {
+                tile,
+                tier,
+                holder: None,
+            })
+            .collect();
+
+        let tier_count = slots.iter().map(|s| s.tier + 1).max().unwrap_or(0);
+        let tier_capacity = (0..tier_count)
+            .map(|tier| slots.iter().filter(|s| s.tier == tier).count())
+            .collect();
+
+        Self {
+            min_importance: 0.01,
+            hysteresis: 0.1,
+            last_update: None,
+            slots,
+            tier_capacity,
+            order: Vec::new(),
+            tiers: Vec::new(),
+            carried: Vec::new(),
+        }
+    }
+
+    fn slot_of(&self, id: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| matches!(s.holder, Some(h) if h.id == id))
+    }
+
+    // lights are every light that may cast a shadow this frame, casts_shadow leaves out the ones
+    // that opted out, which fade out like lights ranked below every tile. Writes each light's tile
+    // to out in the same order, None for lights without one. dt advances the fades. Lights missing
+    // from lights give up their tile right away.
+    pub fn update<T>(
+        &mut self,
+        lights: &[LightCandidate<T>],
+        casts_shadow: impl Fn(&T) -> bool,
+        dt: f32,
+        out: &mut Vec<Option<ShadowTile>>,
+    ) {
+        let step = dt / SHADOW_FADE_SECONDS;
+
+        // rank by importance, ties broken by id so the result doesn't depend on the input order
+        let mut order = std::mem::take(&mut self.order);
+        order.clear();
+        order.extend(0..lights.len());
+        let tier_count = self.tier_capacity.len();
+        let score = |i: usize| {
+            let LightCandidate { id, importance, .. } = lights[i];
+            match self.slot_of(id) {
+                Some(slot) => {
+                    let tiers_above_none = (tier_count - self.slots[slot].tier) as i32;
+                    importance * (1.0 + self.hysteresis).powi(tiers_above_none)
+                }
+                None => importance,
+            }
+        };
+        order.sort_by(|a, b| {
+            score(*b)
+                .partial_cmp(&score(*a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(lights[*a].id.cmp(&lights[*b].id))
+        });
+
+        // fill the tiers from the largest down
+        self.tiers.clear();
+        self.tiers.resize(lights.len(), None);
+        let mut tier = 0;
+        let mut left = self.tier_capacity.first().copied().unwrap_or(0);
+        for &i in &order {
+            while left == 0 && tier + 1 < self.tier_capacity.len() {
+                tier += 1;
+                left = self.tier_capacity[tier];
+            }
+            if left == 0
+                || lights[i].importance < self.min_importance
+                || !casts_shadow(&lights[i].data)
+            {
+                continue;
+            }
+            self.tiers[i] = Some(tier);
+            left -= 1;
+        }
+
+        // lights staying in their tier keep their tile, lights moving to another tier give theirs
+        // up but keep their strength and lights without a tier start fading out
+        self.carried.clear();
+        self.carried.resize(lights.len(), 0.0);
+        for slot in &mut self.slots {
+            let mut holder = match slot.holder {
+                Some(holder) => holder,
+                None => continue,
+            };
+            slot.holder = match lights.iter().position(|light| light.id == holder.id) {
+                Some(i) if self.tiers[i] == Some(slot.tier) => {
+                    holder.wanted = true;
+                    Some(holder)
+                }
+                Some(i) if self.tiers[i].is_some() => {
+                    self.carried[i] = holder.strength;
+                    None
+                }
+                Some(_) => {
+                    holder.wanted = false;
+                    Some(holder)
+                }
+                None => None,
+            };
+        }
+
+        // lights without a tile in their tier take a free one, or the weakest still fading out
+        for &i in &order {
+            let id = lights[i].id;
+            let tier = match self.tiers[i] {
+                Some(tier) => tier,
+                None => continue,
+            };
+            if self.slot_of(id).is_some() {
+                continue;
+            }
+
+            let strength = self.carried[i];
+            let slot = self
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.tier == tier && !matches!(s.holder, Some(h) if h.wanted))
+                .min_by(|(_, a), (_, b)| {
+                    let strength = |s: &Slot| s.holder.map_or(-1.0, |h| h.strength);
+                    strength(a)
+                        .partial_cmp(&strength(b))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .map(|(slot, _)| slot)
+                .expect("every tier has room for the lights ranked into it");
+            self.slots[slot].holder = Some(Holder {
+                id,
+                strength,
+                wanted: true,
+            });
+        }
+
+        for slot in &mut self.slots {
+            if let Some(holder) = &mut slot.holder {
+                if holder.wanted {
+                    holder.strength = (holder.strength + step).min(1.0);
+                } else {
+                    holder.strength -= step;
+                    if holder.strength <= 0.0 {
+                        slot.holder = None;
+                    }
+                }
+            }
+        }
+
+        out.extend(lights.iter().map(|light| {
+            self.slot_of(light.id).map(|slot| ShadowTile {
+                tile: self.slots[slot].tile,
+                strength: self.slots[slot].holder.map_or(0.0, |h| h.strength),
+            })
+        }));
+
+        self.order = order;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(atlas: &mut ShadowAtlas, lights: &[(u32, f32)], dt: f32) -> Vec<Option<ShadowTile>> {
+        let candidates: Vec<_> = lights
+            .iter()
+            .map(|(id, importance)| LightCandidate {
+                id: *id,
+                importance: *importance,
+                data: true,
+            })
+            .collect();
+        let mut out = Vec::new();
+        atlas.update(&candidates, |casts| *casts, dt, &mut out);
+        out
+    }
+
+    fn sizes(tiles: &[Option<ShadowTile>]) -> Vec<u32> {
+        tiles.iter().map(|t| t.map_or(0, |t| t.tile.size)).collect()
+    }
+
+    #[test]
+    fn layout_test() {
+        let atlas = ShadowAtlas::default();
+        let tiles: Vec<_> = atlas.slots.iter().map(|s| s.tile).collect();
+        assert_eq!(tiles.len(), 8);
+
+        for (i, a) in tiles.iter().enumerate() {
+            assert!(a.x + a.size <= SHADOW_ATLAS_WIDTH && a.y + a.size <= SHADOW_ATLAS_HEIGHT);
+            for b in &tiles[i + 1..] {
+                let apart = a.x + a.size <= b.x
+                    || b.x + b.size <= a.x
+                    || a.y + a.size <= b.y
+                    || b.y + b.size <= a.y;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+
+        let rect = tiles[0].uv_rect();
+        assert_eq!(rect, Vector4::new(0.0, 0.0, 0.5, 1.0));
+    }
+
+    #[test]
+    fn tiles_by_importance_test() {
+        let mut atlas = ShadowAtlas::default();
+        let lights: Vec<_> = (0..8).map(|id| (id, 1.0 + id as f32)).collect();
+        let tiles = update(&mut atlas, &lights, 0.1);
+
+        // the most important light gets the largest tile, the next three medium ones
+        assert_eq!(sizes(&tiles), [256, 256, 256, 256, 512, 512, 512, 1024]);
+
+        // lights below min_importance get nothing even with tiles to spare
+        let tiles = update(&mut ShadowAtlas::default(), &[(1, 1.0), (2, 0.001)], 0.1);
+        assert_eq!(sizes(&tiles), [1024, 0]);
+    }
+
+    #[test]
+    fn lights_without_shadows_test() {
+        let mut atlas = ShadowAtlas::default();
+        let mut candidates: Vec<_> = [(1, 1.0, false), (2, 0.5, true)]
+            .into_iter()
+            .map(|(id, importance, casts)| LightCandidate {
+                id,
+                importance,
+                data: casts,
+            })
+            .collect();
+        let mut tiles = Vec::new();
+
+        // the most important light opted out, the next one gets the largest tile
+        atlas.update(&candidates, |casts| *casts, 0.1, &mut tiles);
+        assert_eq!(sizes(&tiles), [0, 1024]);
+
+        // turning shadows off fades the shadow out instead of dropping it
+        candidates[1].data = false;
+        tiles.clear();
+        atlas.update(&candidates, |casts| *casts, 0.05, &mut tiles);
+        assert_eq!(sizes(&tiles), [0, 1024]);
+        tiles.clear();
+        atlas.update(&candidates, |casts| *casts, SHADOW_FADE_SECONDS, &mut tiles);
+        assert_eq!(sizes(&tiles), [0, 0]);
+    }
+
+    #[test]
+    fn stable_assignment_test() {
+        let mut atlas = ShadowAtlas::default();
+        let lights = [(1, 1.0), (2, 0.5), (3, 0.4)];
+        let first = update(&mut atlas, &lights, 0.1);
+
+        // the same lights in a different order keep their tiles
+        let reordered = update(&mut atlas, &[(3, 0.4), (1, 1.0), (2, 0.5)], 0.1);
+        assert_eq!(reordered[1].unwrap().tile, first[0].unwrap().tile);
+        assert_eq!(reordered[2].unwrap().tile, first[1].unwrap().tile);
+        assert_eq!(reordered[0].unwrap().tile, first[2].unwrap().tile);
+
+        // a small swap in importance stays within the hysteresis
+        let swapped = update(&mut atlas, &[(1, 0.95), (2, 1.0), (3, 0.4)], 0.1);
+        assert_eq!(swapped[0].unwrap().tile, first[0].unwrap().tile);
+        assert_eq!(swapped[1].unwrap().tile, first[1].unwrap().tile);
+
+        // a large one moves the lights between tiers
+        let swapped = update(&mut atlas, &[(1, 0.5), (2, 1.0), (3, 0.4)], 0.1);
+        assert_eq!(swapped[1].unwrap().tile, first[0].unwrap().tile);
+        assert_eq!(swapped[0].unwrap().tile.size, 512);
+    }
+
+    #[test]
+    fn fade_test() {
+        let mut atlas = ShadowAtlas::default();
+        let dt = SHADOW_FADE_SECONDS / 4.0;
+
+        // new tiles fade in
+        let tiles = update(&mut atlas, &[(1, 1.0)], dt);
+        assert_eq!(tiles[0].unwrap().strength, 0.25);
+        for _ in 0..10 {
+            update(&mut atlas, &[(1, 1.0)], dt);
+        }
+        let tiles = update(&mut atlas, &[(1, 1.0)], dt);
+        assert_eq!(tiles[0].unwrap().strength, 1.0);
+
+        // dropping below min_importance keeps the tile while the shadow fades out
+        let tiles = update(&mut atlas, &[(1, 0.001)], dt);
+        assert_eq!(tiles[0].unwrap().strength, 0.75);
+        for _ in 0..2 {
+            update(&mut atlas, &[(1, 0.001)], dt);
+        }
+        let tiles = update(&mut atlas, &[(1, 0.001)], dt);
+        assert_eq!(tiles[0], None);
+
+        // a light that isn't submitted anymore loses its tile at once
+        update(&mut atlas, &[(1, 1.0)], dt);
+        update(&mut atlas, &[], dt);
+        assert_eq!(atlas.slot_of(1), None);
+    }
+
+    #[test]
+    fn eviction_test() {
+        let mut atlas = ShadowAtlas::default();
+        let dt = SHADOW_FADE_SECONDS / 4.0;
+        let first = update(&mut atlas, &[(1, 1.0)], dt)[0].unwrap().tile;
+
+        // a light pushed out of the largest tile hands it over right away, the new holder fades
+        // in from nothing while the old one moves down a tier with its strength
+        let tiles = update(&mut atlas, &[(1, 1.0), (2, 5.0)], dt);
+        assert_eq!(tiles[1].unwrap().tile, first);
+        assert_eq!(tiles[1].unwrap().strength, 0.25);
+        assert_eq!(tiles[0].unwrap().tile.size, 512);
+        assert_eq!(tiles[0].unwrap().strength, 0.5);
+
+        // with every tile taken the least important light fades out, and its tile goes to the
+        // next light needing one
+        let mut lights: Vec<_> = (10..18).map(|id| (id, 1.0 + id as f32)).collect();
+        update(&mut atlas, &lights, SHADOW_FADE_SECONDS);
+        lights[0].1 = 0.001;
+        let evicted = update(&mut atlas, &lights, dt)[0].unwrap();
+        assert_eq!(evicted.strength, 0.75);
+
+        lights.push((20, 2.0));
+        let tiles = update(&mut atlas, &lights, dt);
+        assert_eq!(tiles[0], None);
+        assert_eq!(tiles[8].unwrap().tile, evicted.tile);
+    }
+}
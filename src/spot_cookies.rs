@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+
+use wgpu::{Device, Queue};
+
+use crate::data_types::ExtractedSpotLight;
+use crate::texture_library::{ColorSpace, TextureHandle, TextureLibrary};
+
+// Side of every layer of the cookie array in texels. Cookie textures need a mip level this size.
+pub const COOKIE_SIZE: u32 = 256;
+
+const COOKIE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Which cookie each layer after the white one holds.
+struct CookieLayers {
+    cookies: Vec<Option<TextureHandle>>,
+    in_use: Vec<bool>, // per assign, reused between frames
+}
+
+impl CookieLayers {
+    fn new(count: usize) -> Self {
+        Self {
+            cookies: vec![None; count],
+            in_use: Vec::new(),
+        }
+    }
+
+    fn position(&self, cookie: TextureHandle) -> Option<usize> {
+        self.cookies.iter().position(|c| *c == Some(cookie))
+    }
+
+    // Points every light at the layer of its cookie, 0 without one. Cookies keep the layer they
+    // had, new ones take a layer no light uses this frame and are pushed to fills with it.
+    fn assign(&mut self, lights: &mut [ExtractedSpotLight], fills: &mut Vec<(u32, TextureHandle)>) {
+        self.in_use.clear();
+        self.in_use.resize(self.cookies.len(), false);
+        for cookie in lights.iter().filter_map(|light| light.cookie) {
+            if let Some(i) = self.position(cookie) {
+                self.in_use[i] = true;
+            }
+        }
+
+        for light in lights {
+            let cookie = match light.cookie {
+                Some(cookie) => cookie,
+                None => {
+                    light.data.cookie_layer = 0;
+                    continue;
+                }
+            };
+
+            let i = match self.position(cookie) {
+                Some(i) => i,
+                None => {
+                    // only runs out with more lights than layers
+                    let i = match self.in_use.iter().position(|used| !used) {
+                        Some(i) => i,
+                        None => {
+                            light.data.cookie_layer = 0;
+                            continue;
+                        }
+                    };
+                    self.cookies[i] = Some(cookie);
+                    self.in_use[i] = true;
+                    fills.push((i as u32 + 1, cookie));
+                    i
+                }
+            };
+            light.data.cookie_layer = i as u32 + 1;
+        }
+    }
+}
+
+// Texture array holding the cookies of the submitted spot lights, bound with the lights. Layer 0
+// is white for lights without a cookie. Cookies are copied out of the texture library when a light
+// starts using them and again whenever the library changes, a cookie still loading or unusable as
+// one leaves its lights on the white layer.
+pub struct SpotCookies {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+
+    layers: CookieLayers,
+    revision: Option<u64>, // of the texture library when the layers were filled
+    fills: Vec<(u32, TextureHandle)>,
+    unusable: HashSet<TextureHandle>, // not tried again until the library changes
+    reported: HashSet<TextureHandle>, // warned about once each
+}
+
+impl SpotCookies {
+    // Room for a different cookie on each of lights lights.
+    pub fn new(device: &Device, queue: &Queue, lights: usize) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Spot Cookies"),
+            size: wgpu::Extent3d {
+                width: COOKIE_SIZE,
+                height: COOKIE_SIZE,
+                depth_or_array_layers: lights as u32 + 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: COOKIE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &vec![255; (4 * COOKIE_SIZE * COOKIE_SIZE) as usize],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * COOKIE_SIZE),
+                rows_per_image: std::num::NonZeroU32::new(COOKIE_SIZE),
+            },
+            wgpu::Extent3d {
+                width: COOKIE_SIZE,
+                height: COOKIE_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // cookies end at the edge of the cone's square, clamping keeps them from wrapping around
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Spot Cookie Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            layers: CookieLayers::new(lights),
+            revision: None,
+            fills: Vec::new(),
+            unusable: HashSet::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    // Sets the cookie layer of every light, copying in the cookies that aren't in the array yet.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        library: &TextureLibrary,
+        lights: &mut [ExtractedSpotLight],
+    ) {
+        // a loaded or hot reloaded texture may be a cookie already in a layer, or one that
+        // couldn't be used before
+        if self.revision != Some(library.revision()) {
+            self.revision = Some(library.revision());
+            self.layers.cookies.fill(None);
+            self.unusable.clear();
+        }
+        for light in lights.iter_mut() {
+            if light.cookie.is_some_and(|c| self.unusable.contains(&c)) {
+                light.cookie = None;
+            }
+        }
+
+        self.fills.clear();
+        self.layers.assign(lights, &mut self.fills);
+        if self.fills.is_empty() {
+            return;
+        }
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for &(layer, cookie) in &self.fills {
+            let copied = library
+                .get(cookie)
+                .map(|texture| match texture.color_space {
+                    ColorSpace::Srgb => {
+                        texture.copy_level_to_layer(&mut encoder, COOKIE_SIZE, &self.texture, layer)
+                    }
+                    ColorSpace::Linear => Err("cookies need an srgb texture".to_string()),
+                });
+            match copied {
+                Some(Ok(())) => continue,
+                Some(Err(e)) if self.reported.insert(cookie) => {
+                    log::warn!("texture {:?} can't be a spot light cookie, {}", cookie, e);
+                }
+                // already reported, still loading or never registered
+                _ => {}
+            }
+
+            self.unusable.insert(cookie);
+            self.layers.cookies[layer as usize - 1] = None;
+            for light in lights.iter_mut().filter(|l| l.data.cookie_layer == layer) {
+                light.data.cookie_layer = 0;
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture_library::TextureId;
+
+    fn lights(cookies: &[Option<TextureId>]) -> Vec<ExtractedSpotLight> {
+        cookies
+            .iter()
+            .map(|cookie| ExtractedSpotLight {
+                cookie: cookie.map(TextureHandle::from),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn layers_of(lights: &[ExtractedSpotLight]) -> Vec<u32> {
+        lights.iter().map(|l| l.data.cookie_layer).collect()
+    }
+
+    #[test]
+    fn cookie_layers_test() {
+        let (checker, grid, white) = (
+            TextureId::CheckerTexture,
+            TextureId::UvGridTexture,
+            TextureId::WhiteTexture,
+        );
+        let mut layers = CookieLayers::new(2);
+        let mut fills = Vec::new();
+
+        // lights sharing a cookie share its layer, lights without one use the white layer
+        let mut frame = lights(&[Some(checker), None, Some(checker), Some(grid)]);
+        layers.assign(&mut frame, &mut fills);
+        assert_eq!(layers_of(&frame), [1, 0, 1, 2]);
+        assert_eq!(fills, [(1, checker.into()), (2, grid.into())]);
+
+        // cookies in use keep their layer, the new one takes the free layer
+        fills.clear();
+        let mut frame = lights(&[Some(white), Some(grid)]);
+        layers.assign(&mut frame, &mut fills);
+        assert_eq!(layers_of(&frame), [1, 2]);
+        assert_eq!(fills, [(1, white.into())]);
+
+        // past the last layer lights go without their cookie
+        fills.clear();
+        let mut frame = lights(&[Some(checker), Some(white), Some(grid)]);
+        layers.assign(&mut frame, &mut fills);
+        assert_eq!(layers_of(&frame), [0, 1, 2]);
+        assert!(fills.is_empty());
+    }
+}
@@ -0,0 +1,405 @@
+use std::{collections::VecDeque, fs, path::Path};
+
+use bevy_ecs::{entity::Entity, world::World};
+use nalgebra::Isometry3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{BoardGrid, BoardSlot},
+    common_component::Transform,
+    tile_world::{Tile, TileChunkGeneric},
+    time::TimeResource,
+};
+
+// Stable hashing of gameplay state for spotting desyncs between replays or peers. Hashes have to
+// match across runs, builds and platforms, so std's randomly seeded hashers can't be used.
+//
+// Floats are hashed by bit pattern after canonicalizing: -0.0 hashes as 0.0 so values that compare
+// equal hash equal, and NaN is a bug in gameplay state so it debug asserts. In release builds every
+// NaN hashes as the same quiet NaN.
+
+// 64 bit FNV-1a.
+#[derive(Clone, Copy, Debug)]
+pub struct StateHasher {
+    state: u64,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl StateHasher {
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(canonical_f32_bits(value));
+    }
+
+    pub fn write_entity(&mut self, entity: Entity) {
+        self.write_u64(entity.to_bits());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+pub fn canonical_f32_bits(value: f32) -> u32 {
+    debug_assert!(!value.is_nan(), "NaN in hashed game state");
+
+    if value == 0.0 {
+        0
+    } else if value.is_nan() {
+        f32::NAN.to_bits()
+    } else {
+        value.to_bits()
+    }
+}
+
+pub trait HashState {
+    fn hash_state(&self, hasher: &mut StateHasher);
+}
+
+impl HashState for Isometry3<f32> {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        for value in self
+            .translation
+            .vector
+            .iter()
+            .chain(self.rotation.coords.iter())
+        {
+            hasher.write_f32(*value);
+        }
+    }
+}
+
+impl HashState for Transform {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        self.isometry.hash_state(hasher);
+        for value in self.scale.iter() {
+            hasher.write_f32(*value);
+        }
+
+        hasher.write_u64(self.parent.map_or(u64::MAX, Entity::to_bits));
+        hasher.write_u32(self.children.len() as u32);
+        // child order is meaningful, piles lay cards out bottom to top
+        for child in &self.children {
+            hasher.write_entity(*child);
+        }
+    }
+}
+
+impl HashState for BoardSlot {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_entity(self.grid);
+        hasher.write_u32(self.coord.row);
+        hasher.write_u32(self.coord.column);
+    }
+}
+
+impl HashState for TimeResource {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.tick);
+    }
+}
+
+impl<const L: usize> HashState for TileChunkGeneric<L, Tile> {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        for tile in self.tiles.iter().flatten().flatten() {
+            hasher.write_u32(tile.id);
+            hasher.write_f32(tile.temperature);
+        }
+    }
+}
+
+// Categories are hashed separately so a diff can say what diverged and not just when.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashCategory {
+    Time,
+    Transforms,
+    Board,
+}
+
+impl HashCategory {
+    pub const ALL: [HashCategory; 3] = [Self::Time, Self::Transforms, Self::Board];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateHash {
+    pub tick: u64,
+    pub categories: [u64; HashCategory::ALL.len()],
+}
+
+impl StateHash {
+    pub fn category(&self, category: HashCategory) -> u64 {
+        self.categories[category as usize]
+    }
+
+    pub fn combined(&self) -> u64 {
+        let mut hasher = StateHasher::default();
+        for hash in self.categories {
+            hasher.write_u64(hash);
+        }
+        hasher.finish()
+    }
+}
+
+// Query iteration order depends on archetype layout rather than gameplay, so entities are hashed
+// in id order.
+fn hash_components<T: HashState + bevy_ecs::component::Component>(world: &mut World) -> u64 {
+    let mut entries: Vec<(Entity, u64)> = world
+        .query::<(Entity, &T)>()
+        .iter(world)
+        .map(|(entity, component)| {
+            let mut hasher = StateHasher::default();
+            component.hash_state(&mut hasher);
+            (entity, hasher.finish())
+        })
+        .collect();
+    entries.sort_unstable_by_key(|(entity, _)| entity.to_bits());
+
+    let mut hasher = StateHasher::default();
+    for (entity, hash) in entries {
+        hasher.write_entity(entity);
+        hasher.write_u64(hash);
+    }
+    hasher.finish()
+}
+
+pub fn hash_world(world: &mut World) -> StateHash {
+    let time = world.resource::<TimeResource>();
+    let tick = time.tick;
+
+    let mut time_hasher = StateHasher::default();
+    time.hash_state(&mut time_hasher);
+
+    let mut board_hasher = StateHasher::default();
+    board_hasher.write_u64(hash_components::<BoardGrid>(world));
+    board_hasher.write_u64(hash_components::<BoardSlot>(world));
+
+    StateHash {
+        tick,
+        categories: [
+            time_hasher.finish(),
+            hash_components::<Transform>(world),
+            board_hasher.finish(),
+        ],
+    }
+}
+
+// Resource keeping the most recent hashes. An interval of 0 only hashes when asked to.
+pub struct StateHashHistory {
+    pub interval: u64,
+    pub capacity: usize,
+    pub hashes: VecDeque<StateHash>,
+
+    requested: bool,
+}
+
+impl StateHashHistory {
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            interval,
+            capacity,
+            hashes: VecDeque::with_capacity(capacity),
+            requested: false,
+        }
+    }
+
+    // Hashes on the next fixed update regardless of interval.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    fn push(&mut self, hash: StateHash) {
+        if self.hashes.len() == self.capacity {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(hash);
+    }
+
+    pub fn at_tick(&self, tick: u64) -> Option<&StateHash> {
+        self.hashes.iter().find(|hash| hash.tick == tick)
+    }
+}
+
+// Exclusive system, runs last in the fixed update so the hash describes the finished tick.
+pub fn record_state_hash(world: &mut World) {
+    let time = world.resource::<TimeResource>();
+
+    let due = match world.get_resource::<StateHashHistory>() {
+        Some(history) => history.requested || time.every_n_ticks(history.interval),
+        None => return,
+    };
+    if !due {
+        return;
+    }
+
+    let hash = hash_world(world);
+    let mut history = world.resource_mut::<StateHashHistory>();
+    if history.requested {
+        log::info!(
+            "state hash at tick {}: {:016x}, time {:016x}, transforms {:016x}, board {:016x}",
+            hash.tick,
+            hash.combined(),
+            hash.category(HashCategory::Time),
+            hash.category(HashCategory::Transforms),
+            hash.category(HashCategory::Board)
+        );
+    }
+    history.requested = false;
+    history.push(hash);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub tick: u64,
+    pub categories: Vec<HashCategory>,
+}
+
+// First tick present in both histories whose hashes differ, ticks only one side recorded are skipped.
+pub fn first_divergence(a: &StateHashHistory, b: &StateHashHistory) -> Option<Divergence> {
+    a.hashes.iter().find_map(|hash_a| {
+        let hash_b = b.at_tick(hash_a.tick)?;
+
+        let categories: Vec<_> = HashCategory::ALL
+            .iter()
+            .copied()
+            .filter(|c| hash_a.category(*c) != hash_b.category(*c))
+            .collect();
+
+        (!categories.is_empty()).then_some(Divergence {
+            tick: hash_a.tick,
+            categories,
+        })
+    })
+}
+
+// One RON hash per line, oldest first.
+fn save_history(history: &StateHashHistory, path: &Path) -> Result<(), String> {
+    let mut contents = String::new();
+    for hash in &history.hashes {
+        contents += &ron::to_string(hash).map_err(|e| e.to_string())?;
+        contents.push('\n');
+    }
+
+    fs::write(path, contents).map_err(|e| e.to_string())
+}
+
+fn load_history(path: &Path) -> Result<StateHashHistory, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    let hashes: VecDeque<StateHash> = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| ron::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect::<Result<_, _>>()?;
+
+    let mut history = StateHashHistory::new(0, hashes.len());
+    history.hashes = hashes;
+    Ok(history)
+}
+
+// Without arguments the next tick is hashed and logged. Saving the history of one run and diffing
+// it from another finds the first tick they disagree on.
+pub fn hash_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    let mut history = world
+        .get_resource_mut::<StateHashHistory>()
+        .ok_or_else(|| "state hashing is off".to_string())?;
+
+    match args {
+        [] => {
+            history.request();
+            Ok(())
+        }
+        ["save", path] => save_history(&history, Path::new(path)),
+        ["diff", path] => {
+            let other = load_history(Path::new(path))?;
+            match first_divergence(&history, &other) {
+                Some(divergence) => log::info!(
+                    "diverged at tick {} in {:?}",
+                    divergence.tick,
+                    divergence.categories
+                ),
+                None => log::info!("no divergence in the ticks both histories recorded"),
+            }
+            Ok(())
+        }
+        _ => Err("expected save <path> or diff <path>".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    #[test]
+    fn hash_command_test() {
+        let mut world = World::new();
+        world.insert_resource(TimeResource::new(Duration::from_millis(10), Duration::ZERO));
+        world.insert_resource(StateHashHistory::new(0, 4));
+        let card = world
+            .spawn()
+            .insert(Transform {
+                isometry: Isometry3::translation(1.0, 0.0, 0.0),
+                scale: Vector3::repeat(1.0),
+                parent: None,
+                children: vec![],
+            })
+            .id();
+
+        // an interval of 0 only hashes on request
+        record_state_hash(&mut world);
+        assert!(world.resource::<StateHashHistory>().hashes.is_empty());
+        hash_command(&mut world, &[]).unwrap();
+        record_state_hash(&mut world);
+        let recorded = world.resource::<StateHashHistory>().hashes[0];
+
+        let path = std::env::temp_dir().join(format!("card_game_hash_{}", std::process::id()));
+        let path_arg = path.to_str().unwrap();
+        hash_command(&mut world, &["save", path_arg]).unwrap();
+        let saved = load_history(&path).unwrap();
+        assert_eq!(saved.at_tick(recorded.tick), Some(&recorded));
+        hash_command(&mut world, &["diff", path_arg]).unwrap();
+        assert_eq!(first_divergence(world.resource(), &saved), None);
+
+        // moving a card at the same tick only changes the transforms
+        world.get_mut::<Transform>(card).unwrap().isometry = Isometry3::identity();
+        hash_command(&mut world, &[]).unwrap();
+        record_state_hash(&mut world);
+        let mut history = world.resource_mut::<StateHashHistory>();
+        history.hashes.pop_front();
+        assert_eq!(
+            first_divergence(&history, &saved),
+            Some(Divergence {
+                tick: recorded.tick,
+                categories: vec![HashCategory::Transforms],
+            })
+        );
+
+        assert!(hash_command(&mut world, &["diff"]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
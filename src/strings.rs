@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{Display, Write},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::{event::Events, system::Res, world::World};
+
+const LANGUAGE_DIRECTORY: &str = "lang";
+const FALLBACK_LANGUAGE: &str = "en";
+
+// missing keys are collected and reported together at most this often instead of once per lookup
+const MISSING_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Sent after the language was switched at runtime, anything built from Strings should look its text
+// up again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguageChanged {
+    pub language: String,
+}
+
+pub struct Strings {
+    directory: PathBuf,
+    language: String,
+    selected: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+
+    missing: Mutex<MissingKeys>,
+}
+
+struct MissingKeys {
+    keys: BTreeSet<String>,
+    last_report: Instant,
+}
+
+impl Strings {
+    pub fn load(language: &str) -> Self {
+        Self::load_from(Path::new(LANGUAGE_DIRECTORY), language)
+    }
+
+    // Tables are read from <directory>/<language>.ron.
+    pub fn load_from(directory: &Path, language: &str) -> Self {
+        let mut strings = Self {
+            directory: directory.to_owned(),
+            language: String::new(),
+            selected: HashMap::new(),
+            fallback: load_table(directory, FALLBACK_LANGUAGE),
+            missing: Mutex::new(MissingKeys {
+                keys: BTreeSet::new(),
+                last_report: Instant::now(),
+            }),
+        };
+        strings.set_language(language);
+
+        strings
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    // The fallback table is always loaded so switching back to it is just dropping the selected table.
+    // Use switch_language at runtime so text gets rebuilt.
+    pub fn set_language(&mut self, language: &str) {
+        self.selected = if language == FALLBACK_LANGUAGE {
+            HashMap::new()
+        } else {
+            load_table(&self.directory, language)
+        };
+        self.language = language.to_string();
+
+        log::info!("using language {}", language);
+    }
+
+    // Lookup order is selected language, then the fallback language, then the key itself.
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        match self.selected.get(key).or_else(|| self.fallback.get(key)) {
+            Some(s) => s,
+            None => {
+                self.missing.lock().unwrap().keys.insert(key.to_string());
+                key
+            }
+        }
+    }
+
+    // Same as get but replaces {name} placeholders with the matching argument.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        substitute(self.get(key), args)
+    }
+}
+
+fn load_table(directory: &Path, language: &str) -> HashMap<String, String> {
+    let path = directory.join(format!("{}.ron", language));
+
+    let table = File::open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| ron::de::from_reader(file).map_err(|e| e.to_string()));
+
+    match table {
+        Ok(table) => table,
+        Err(e) => {
+            log::error!("failed to load string table {}: {}", path.display(), e);
+            HashMap::new()
+        }
+    }
+}
+
+// Placeholders without a matching argument and unterminated braces are left in the output as is.
+fn substitute(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let end = match after.find('}') {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+
+        let name = &after[..end];
+        match args.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => write!(out, "{}", value).unwrap(),
+            None => out.push_str(&rest[start..start + end + 2]),
+        }
+
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+// Changes the language of the Strings resource and sends LanguageChanged.
+pub fn switch_language(world: &mut World, language: &str) {
+    world.resource_mut::<Strings>().set_language(language);
+    world
+        .resource_mut::<Events<LanguageChanged>>()
+        .send(LanguageChanged {
+            language: language.to_string(),
+        });
+}
+
+pub fn language_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    match args {
+        [language] => {
+            switch_language(world, language);
+            Ok(())
+        }
+        _ => Err(format!(
+            "expected a language code, the current language is {}",
+            world.resource::<Strings>().language()
+        )),
+    }
+}
+
+pub fn report_missing_strings(strings: Res<Strings>) {
+    let mut missing = strings.missing.lock().unwrap();
+    if missing.keys.is_empty() || missing.last_report.elapsed() < MISSING_REPORT_INTERVAL {
+        return;
+    }
+
+    let keys: Vec<String> = std::mem::take(&mut missing.keys).into_iter().collect();
+    missing.last_report = Instant::now();
+
+    log::warn!(
+        "{} string keys missing for language {}: {}",
+        keys.len(),
+        strings.language,
+        keys.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // en has every key, de only some
+    fn language_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("card_game_lang_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("en.ron"),
+            r#"{ "card.draw": "Draw {count} cards", "menu.quit": "Quit" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("de.ron"),
+            r#"{ "card.draw": "Ziehe {count} Karten" }"#,
+        )
+        .unwrap();
+
+        directory
+    }
+
+    #[test]
+    fn substitute_test() {
+        let args: &[(&str, &dyn Display)] = &[("count", &3), ("name", &"Ace")];
+        assert_eq!(substitute("Draw {count}", args), "Draw 3");
+        assert_eq!(substitute("{name}{name} {count}", args), "AceAce 3");
+        // unknown placeholders and unterminated braces stay
+        assert_eq!(substitute("{other} {count}", args), "{other} 3");
+        assert_eq!(substitute("{count} {name", args), "3 {name");
+        assert_eq!(substitute("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn fallback_test() {
+        let directory = language_directory("fallback");
+        let strings = Strings::load_from(&directory, "de");
+
+        assert_eq!(
+            strings.format("card.draw", &[("count", &2)]),
+            "Ziehe 2 Karten"
+        );
+        assert_eq!(strings.get("menu.quit"), "Quit");
+        assert_eq!(strings.get("menu.missing"), "menu.missing");
+        assert!(strings
+            .missing
+            .lock()
+            .unwrap()
+            .keys
+            .contains("menu.missing"));
+
+        // a language without a file falls back to english for everything
+        let strings = Strings::load_from(&directory, "fr");
+        assert_eq!(strings.get("card.draw"), "Draw {count} cards");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn language_switch_test() {
+        let directory = language_directory("switch");
+        let mut world = World::new();
+        world.insert_resource(Strings::load_from(&directory, "en"));
+        world.insert_resource(Events::<LanguageChanged>::default());
+        let mut reader = world.resource::<Events<LanguageChanged>>().get_reader();
+
+        language_command(&mut world, &["de"]).unwrap();
+        assert_eq!(world.resource::<Strings>().language(), "de");
+        assert_eq!(
+            world.resource::<Strings>().get("card.draw"),
+            "Ziehe {count} Karten"
+        );
+
+        switch_language(&mut world, "en");
+        assert_eq!(
+            world.resource::<Strings>().get("card.draw"),
+            "Draw {count} cards"
+        );
+
+        let events = world.resource::<Events<LanguageChanged>>();
+        let languages: Vec<_> = reader.iter(events).map(|e| e.language.as_str()).collect();
+        assert_eq!(languages, ["de", "en"]);
+
+        assert!(language_command(&mut world, &[]).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
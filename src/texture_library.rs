@@ -1,76 +1,407 @@
-use ktx2::Reader;
-use std::{collections::HashMap, fs::File, io::Read, path::Path, sync::Arc};
+use ktx2::{Reader, SupercompressionScheme};
+use procedural::{Image, ProceduralTexture};
+use sampler::{SamplerCache, SamplerKey};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+};
 use wgpu::{BindGroupLayout, Device, Queue};
 
+use crate::library_stats::{self, LibraryStats};
+
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+pub mod procedural;
+pub mod sampler;
+
+// Procedural textures are generated at load time so placeholders never depend on the asset folder.
+#[derive(Clone, Copy, Debug)]
+pub enum TextureSource {
+    File(&'static str),
+    Procedural(ProceduralTexture),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    pub source: TextureSource,
+    pub sampler: SamplerKey,
+}
+
+impl TextureDesc {
+    pub const fn file(path: &'static str) -> Self {
+        Self {
+            source: TextureSource::File(path),
+            sampler: SamplerKey::DEFAULT,
+        }
+    }
+
+    pub const fn procedural(procedural: ProceduralTexture) -> Self {
+        Self {
+            source: TextureSource::Procedural(procedural),
+            sampler: SamplerKey::DEFAULT,
+        }
+    }
+
+    pub const fn with_sampler(self, sampler: SamplerKey) -> Self {
+        Self { sampler, ..self }
+    }
+}
+
 crate::macros::parallel_enum_values! {
     (
         TextureId,
-        TEXTURE_PATH_PAIRS,
-        str,
+        TEXTURE_DESC_PAIRS,
+        TextureDesc,
     )
-    UnknownTexture -> "texture/crabdance-seamless-tile.ktx2",
-    CrabTexture -> "texture/crabdance-seamless-tile.ktx2",
-    CurlyBraceTexture -> "texture/curly-brace.ktx2",
+    // loud enough to spot wherever it is used on purpose
+    UnknownTexture -> &TextureDesc::procedural(ProceduralTexture::Checker)
+        .with_sampler(SamplerKey::NEAREST),
+    CheckerTexture -> &TextureDesc::procedural(ProceduralTexture::Checker)
+        .with_sampler(SamplerKey::NEAREST),
+    UvGridTexture -> &TextureDesc::procedural(ProceduralTexture::UvGrid),
+    WhiteTexture -> &TextureDesc::procedural(ProceduralTexture::White),
+    FlatNormalTexture -> &TextureDesc::procedural(ProceduralTexture::FlatNormal),
+    BumpNormalTexture -> &TextureDesc::procedural(ProceduralTexture::BumpNormal),
+    CrabTexture -> &TextureDesc::file("texture/crabdance-seamless-tile.ktx2"),
+    CurlyBraceTexture -> &TextureDesc::file("texture/curly-brace.ktx2"),
+}
+
+// How the shader should read the stored bytes. Color textures are stored srgb encoded and decoded
+// to linear by the sampler since lighting is done in linear space, data such as normals is used
+// as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
 }
 
-// Each texture uses it's own internal texture, view, sampler, and bind group.
-// Some of this may be redundent. TODO: reduce redundency in samplers.
+impl ColorSpace {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+// Each texture uses it's own internal texture, view and bind group. Samplers are shared through
+// the SamplerCache, the bind group keeps the one it was created with alive.
+//
+// Every texture is viewed as an array so one bind group layout and shader cover both, textures
+// with a single layer are drawn with layer 0.
 pub struct Texture {
-    pub handle: wgpu::Texture,
-    pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    handle: wgpu::Texture,
+    _view: wgpu::TextureView,
     pub bind_group: wgpu::BindGroup,
+    pub width: u32,
+    pub height: u32,
+    pub levels: u32,
+    pub layers: u32, // frames of an AnimatedTexture
+    pub color_space: ColorSpace,
+    pub bytes: u64, // gpu memory of all levels and layers
 }
 
-impl Texture {
-    pub fn from_file(
-        device: &Device,
-        queue: &Queue,
-        layout: &BindGroupLayout,
-        path: &Path,
-    ) -> Self {
+// Tightly packed rgba8 texture data, not yet on the gpu. Decoding needs no device so it can happen
+// on the loader thread.
+//
+// levels is the mip chain, largest level first. Each level halves the previous size rounding down
+// but never below 1, the chain may stop before reaching 1x1. Each level holds every layer of that
+// size back to back, rows top to bottom.
+pub struct DecodedTexture {
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+    pub levels: Vec<Vec<u8>>,
+    pub color_space: ColorSpace,
+}
+
+impl DecodedTexture {
+    // A single level and layer.
+    pub fn rgba8(width: u32, height: u32, data: Vec<u8>, color_space: ColorSpace) -> Self {
+        Self {
+            width,
+            height,
+            layers: 1,
+            levels: vec![data],
+            color_space,
+        }
+    }
+
+    // Checks the size is not empty and the levels form a mip chain as described above, no longer
+    // than the one down to 1x1.
+    pub fn verify_mip_chain(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 || self.layers == 0 {
+            return Err(format!(
+                "empty texture of {}x{} with {} layers",
+                self.width, self.height, self.layers
+            ));
+        }
+
+        let full_chain = 32 - self.width.max(self.height).leading_zeros();
+        if self.levels.is_empty() || self.levels.len() > full_chain as usize {
+            return Err(format!(
+                "{} levels, a {}x{} texture has 1 to {}",
+                self.levels.len(),
+                self.width,
+                self.height,
+                full_chain
+            ));
+        }
+
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected =
+                4 * (self.width >> level).max(1) * (self.height >> level).max(1) * self.layers;
+            if data.len() != expected as usize {
+                return Err(format!(
+                    "level {} is {} bytes, expected {}",
+                    level,
+                    data.len(),
+                    expected
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Errors mention the path, nothing in here should panic on a bad file.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
         let mut file = File::open(path)
-            .unwrap_or_else(|e| panic!("failed to open texture file {}: {}", path.display(), e));
+            .map_err(|e| format!("failed to open texture file {}: {}", path.display(), e))?;
 
         let mut contents = Vec::new();
-        file.read_to_end(&mut contents).unwrap_or_else(|e| {
-            panic!(
+        file.read_to_end(&mut contents).map_err(|e| {
+            format!(
                 "failed to read contents of texture file into buffer {}: {}",
                 path.display(),
                 e
             )
-        });
+        })?;
+
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
 
+        let decoded = match extension.as_deref() {
+            Some("ktx2") => Self::from_ktx2(path, contents)?,
+            // png and jpeg carry no reliable color space, they are treated as color textures
+            Some("png" | "jpg" | "jpeg") => {
+                let image = decode_image(&contents).map_err(|e| {
+                    format!("failed to decode texture file {}: {}", path.display(), e)
+                })?;
+
+                Self::rgba8(image.width, image.height, image.data, ColorSpace::Srgb)
+            }
+            _ => {
+                return Err(format!(
+                    "unsupported texture file {}: expected a .ktx2, .png or .jpg extension",
+                    path.display()
+                ))
+            }
+        };
+
+        decoded
+            .verify_mip_chain()
+            .map_err(|e| format!("invalid texture file {}: {}", path.display(), e))?;
+
+        Ok(decoded)
+    }
+
+    fn from_ktx2(path: &Path, contents: Vec<u8>) -> Result<Self, String> {
         let reader = Reader::new(contents)
-            .unwrap_or_else(|e| panic!("failed to parse texture file {}: {}", path.display(), e));
+            .map_err(|e| format!("failed to parse texture file {}: {}", path.display(), e))?;
 
         let header = reader.header();
 
-        assert_eq!(header.format, Some(ktx2::Format::R8G8B8A8_SRGB));
-        assert_eq!(header.pixel_depth, 0);
-        assert_eq!(header.level_count, 1);
-        assert_eq!(header.supercompression_scheme, None);
+        let color_space = match header.format {
+            Some(ktx2::Format::R8G8B8A8_SRGB) => ColorSpace::Srgb,
+            Some(ktx2::Format::R8G8B8A8_UNORM) => ColorSpace::Linear,
+            _ => {
+                return Err(format!(
+                "unsupported texture file {}: expected R8G8B8A8_SRGB or R8G8B8A8_UNORM, found {:?}",
+                path.display(),
+                header.format
+            ))
+            }
+        };
+        if header.pixel_depth != 0 {
+            return Err(format!(
+                "unsupported texture file {}: expected a 2d texture, found depth {}",
+                path.display(),
+                header.pixel_depth
+            ));
+        }
+
+        if header.face_count != 1 {
+            return Err(format!(
+                "unsupported texture file {}: cube maps are not supported",
+                path.display()
+            ));
+        }
 
         let width = header.pixel_width;
         let height = header.pixel_height;
+        // a layer count of 0 means it isn't an array texture, same as a single layer
+        let layers = header.layer_count.max(1);
 
         //let dfd = reader.data_format_descriptors().next();
 
-        let texture_data = reader.levels().next().unwrap();
+        // a level count of 0 still stores the base level
+        let levels = reader
+            .levels()
+            .enumerate()
+            .map(|(level, data)| {
+                decompress_level(header.supercompression_scheme, data)
+                    .map(Cow::into_owned)
+                    .map_err(|e| {
+                        format!(
+                            "failed to decompress level {} of texture file {}: {}",
+                            level,
+                            path.display(),
+                            e
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let texture_size = wgpu::Extent3d {
+        Ok(Self {
             width,
             height,
-            depth_or_array_layers: 1,
+            layers,
+            levels,
+            color_space,
+        })
+    }
+}
+
+// Edge length of the identity color grading LUT. 255 / 15 is whole so every entry is exact in 8 bits.
+pub const IDENTITY_LUT_SIZE: u32 = 16;
+
+// Tightly packed rgba8 volume, slices front to back, each slice's rows top to bottom. Color
+// grading LUTs are cubes indexed by the color they map, red along x, green along y and blue along
+// the slices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedVolume {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub data: Vec<u8>,
+}
+
+impl DecodedVolume {
+    // LUT mapping every color to itself.
+    pub fn identity_lut(size: u32) -> Self {
+        let value = |i: u32| ((i * 255 + (size - 1) / 2) / (size - 1)) as u8;
+
+        let mut data = Vec::with_capacity((4 * size * size * size) as usize);
+        for blue in 0..size {
+            for green in 0..size {
+                for red in 0..size {
+                    data.extend_from_slice(&[value(red), value(green), value(blue), 255]);
+                }
+            }
+        }
+
+        Self {
+            width: size,
+            height: size,
+            depth: size,
+            data,
+        }
+    }
+
+    // LUTs are saved as a strip of N slices of NxN, side by side (N*N x N) or stacked (N x N*N),
+    // blue increasing from the first slice to the last. Only the base level is used.
+    pub fn from_lut_strip(strip: &DecodedTexture) -> Result<Self, String> {
+        let (width, height) = (strip.width, strip.height);
+        let horizontal = width == height * height;
+        let vertical = height == width * width;
+        let size = if horizontal { height } else { width };
+
+        if !(horizontal || vertical) || size < 2 || strip.layers != 1 {
+            return Err(format!(
+                "a {}x{} strip with {} layers does not form a LUT cube, expected a single layer \
+                 of N*N x N or N x N*N with N at least 2",
+                width, height, strip.layers
+            ));
+        }
+        let pixels = match strip.levels.first() {
+            Some(pixels) if pixels.len() == (4 * width * height) as usize => pixels,
+            _ => return Err(format!("the {}x{} strip has no base level", width, height)),
+        };
+
+        // stacked slices already are in volume order
+        let data = if vertical {
+            pixels.clone()
+        } else {
+            let row_bytes = 4 * size as usize;
+            let mut data = Vec::with_capacity(pixels.len());
+            for slice in 0..size as usize {
+                for row in 0..size as usize {
+                    let start = (row * size as usize + slice) * row_bytes;
+                    data.extend_from_slice(&pixels[start..start + row_bytes]);
+                }
+            }
+            data
         };
 
+        Ok(Self {
+            width: size,
+            height: size,
+            depth: size,
+            data,
+        })
+    }
+
+    pub fn lut_from_file(path: &Path) -> Result<Self, String> {
+        let strip = DecodedTexture::from_file(path)?;
+        Self::from_lut_strip(&strip)
+            .map_err(|e| format!("invalid color grading LUT {}: {}", path.display(), e))
+    }
+}
+
+// A single level linear rgba8 3D texture, bound on its own rather than through a texture bind
+// group since only the tonemap pass reads one.
+pub struct VolumeTexture {
+    _handle: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub bytes: u64,
+}
+
+impl VolumeTexture {
+    pub fn from_decoded(device: &Device, queue: &Queue, decoded: &DecodedVolume) -> Self {
+        let DecodedVolume {
+            width,
+            height,
+            depth,
+            ref data,
+        } = *decoded;
+        assert_eq!(
+            data.len(),
+            (4 * width * height * depth) as usize,
+            "volume data does not match its size"
+        );
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: depth,
+        };
         let handle = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("texture"),
-            size: texture_size,
+            label: Some("volume texture"),
+            size,
             mip_level_count: 1,
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
+            dimension: wgpu::TextureDimension::D3,
+            format: ColorSpace::Linear.texture_format(),
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
@@ -81,29 +412,126 @@ impl Texture {
                 origin: wgpu::Origin3d::ZERO,
                 aspect: wgpu::TextureAspect::All,
             },
-            &texture_data,
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * texture_size.width),
-                rows_per_image: std::num::NonZeroU32::new(4 * texture_size.height),
+                bytes_per_row: std::num::NonZeroU32::new(4 * width),
+                rows_per_image: std::num::NonZeroU32::new(height),
             },
-            texture_size,
+            size,
         );
 
-        let view = handle.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        let view = handle.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D3),
+            ..Default::default()
+        });
+
+        Self {
+            _handle: handle,
+            view,
+            bytes: data.len() as u64,
+        }
+    }
+}
+
+impl Texture {
+    pub fn from_file(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        path: &Path,
+    ) -> Result<Self, String> {
+        let decoded = DecodedTexture::from_file(path)?;
+        Ok(Self::from_decoded(device, queue, layout, sampler, &decoded))
+    }
+
+    pub fn from_procedural(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        procedural: ProceduralTexture,
+    ) -> Self {
+        let image = procedural.generate();
+        let decoded = DecodedTexture::rgba8(
+            image.width,
+            image.height,
+            image.data,
+            procedural.color_space(),
+        );
+
+        Self::from_decoded(device, queue, layout, sampler, &decoded)
+    }
+
+    // Panics unless decoded passes DecodedTexture::verify_mip_chain, files are checked while
+    // decoding.
+    pub fn from_decoded(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        decoded: &DecodedTexture,
+    ) -> Self {
+        if let Err(e) = decoded.verify_mip_chain() {
+            panic!("invalid texture data: {}", e);
+        }
+        let DecodedTexture {
+            width,
+            height,
+            layers,
+            ref levels,
+            color_space,
+        } = *decoded;
+
+        let handle = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: color_space.texture_format(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+
+        for (level, texture_data) in levels.iter().enumerate() {
+            let level_size = wgpu::Extent3d {
+                width: (width >> level).max(1),
+                height: (height >> level).max(1),
+                depth_or_array_layers: layers,
+            };
+
+            queue.write_texture(
+                wgpu::ImageCopyTextureBase {
+                    texture: &handle,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                texture_data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * level_size.width),
+                    rows_per_image: std::num::NonZeroU32::new(level_size.height),
+                },
+                level_size,
+            );
+        }
+
+        let view = handle.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
             ..Default::default()
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("texture bind group"),
-            layout: layout,
+            layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -111,55 +539,971 @@ impl Texture {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
             ],
         });
 
         Self {
             handle,
-            view,
-            sampler,
+            _view: view,
             bind_group,
+            width,
+            height,
+            levels: levels.len() as u32,
+            layers,
+            color_space,
+            bytes: library_stats::texture_bytes(width, height, layers, levels.len() as u32, 4),
         }
     }
+
+    // Copies the first layer of the mip level that is size by size texels into a layer of target,
+    // which needs this texture's format. Fails when no level has that size.
+    pub fn copy_level_to_layer(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        size: u32,
+        target: &wgpu::Texture,
+        layer: u32,
+    ) -> Result<(), String> {
+        let level = (0..self.levels)
+            .find(|level| (self.width >> level, self.height >> level) == (size, size))
+            .ok_or_else(|| {
+                format!(
+                    "{}x{} with {} levels has no {}x{} level",
+                    self.width, self.height, self.levels, size, size
+                )
+            })?;
+
+        encoder.copy_texture_to_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.handle,
+                mip_level: level,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyTexture {
+                texture: target,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+// Layout of Texture::bind_group, the texture array and its sampler.
+pub fn bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Texture Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    })
+}
+
+// Any format the image crate was built with, converted to tightly packed rgba8.
+pub fn decode_image(bytes: &[u8]) -> Result<Image, String> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())?
+        .into_rgba8();
+
+    Ok(Image {
+        width: image.width(),
+        height: image.height(),
+        data: image.into_raw(),
+    })
+}
+
+// Level data as stored in the file, uncompressed levels are borrowed as is.
+fn decompress_level(
+    scheme: Option<SupercompressionScheme>,
+    data: &[u8],
+) -> Result<Cow<'_, [u8]>, String> {
+    match scheme {
+        None => Ok(Cow::Borrowed(data)),
+        Some(SupercompressionScheme::Zstandard) => zstd::stream::decode_all(data)
+            .map(Cow::Owned)
+            .map_err(|e| e.to_string()),
+        Some(scheme) => Err(format!("{:?} supercompression is not supported", scheme)),
+    }
 }
 
+// Either a texture from the TextureId table or one registered while running. Dynamic ids are
+// never reused, a handle to an unregistered texture draws the missing texture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TextureHandle {
+    Builtin(TextureId),
+    Dynamic(u32),
+}
+
+impl From<TextureId> for TextureHandle {
+    fn from(id: TextureId) -> Self {
+        TextureHandle::Builtin(id)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DynamicTexture {
+    pub name: String,
+    pub path: PathBuf,
+    pub sampler: SamplerKey,
+    pub lut: bool, // a color grading LUT strip, uploaded as a VolumeTexture
+}
+
+// Where registered textures came from, enough to load them again on a new device with the same
+// handles.
+#[derive(Clone, Debug, Default)]
+pub struct DynamicTextures {
+    sources: HashMap<u32, DynamicTexture>,
+    next_id: u32,
+}
+
+// Sent by the loader thread once a file is decoded.
+type LoadedTexture = (TextureId, Result<DecodedTexture, String>);
+
 pub struct TextureLibrary {
-    textures: HashMap<TextureId, Arc<Texture>>,
+    textures: HashMap<TextureHandle, Arc<Texture>>,
+    luts: HashMap<TextureHandle, Arc<VolumeTexture>>, // color grading, only ever dynamic
+    names: HashMap<String, TextureHandle>,            // builtin textures use their TextureId name
+    dynamic: DynamicTextures,
+    samplers: SamplerCache,
+
+    // files still being decoded on the loader thread, with the sampler to upload them with. The
+    // Mutex is only there to make the library Sync, poll_loaded has exclusive access anyway
+    loading: HashMap<TextureHandle, SamplerKey>,
+    loaded: Option<Mutex<Receiver<LoadedTexture>>>,
+
+    #[cfg(feature = "hot-reload")]
+    watcher: Option<Mutex<hot_reload::TextureWatcher>>, // Mutex only for Sync, like loaded
+
+    // built in, independent of the table so they exist even if every entry fails to load
+    untextured: Arc<Texture>,  // 1x1 white, leaves only the lighting
+    flat_normal: Arc<Texture>, // normal map that leaves the surface normal as is
+    missing: Arc<Texture>,     // checkerboard for ids without a loaded texture
+    identity_lut: Arc<VolumeTexture>,
+
+    missing_reported: Mutex<HashSet<TextureHandle>>, // warned about once each
+
+    revision: u64, // see revision()
 }
 
 impl TextureLibrary {
-    // TODO: implement on the fly shader loading and unloading.
-    #[allow(dead_code)]
-    pub fn load_as_needed() -> Self {
-        todo!();
+    // Only the builtin fallbacks, callers fill in the table.
+    fn empty(device: &Device, queue: &Queue, layout: &BindGroupLayout) -> Self {
+        let mut samplers = SamplerCache::default();
+
+        let sampler = samplers.get(device, SamplerKey::DEFAULT);
+        let white = DecodedTexture::rgba8(1, 1, vec![255; 4], ColorSpace::Srgb);
+        let untextured = Texture::from_decoded(device, queue, layout, &sampler, &white);
+        let missing =
+            Texture::from_procedural(device, queue, layout, &sampler, ProceduralTexture::Checker);
+        let flat_normal = Texture::from_procedural(
+            device,
+            queue,
+            layout,
+            &sampler,
+            ProceduralTexture::FlatNormal,
+        );
+        let identity_lut = VolumeTexture::from_decoded(
+            device,
+            queue,
+            &DecodedVolume::identity_lut(IDENTITY_LUT_SIZE),
+        );
+
+        let names = TEXTURE_DESC_PAIRS
+            .iter()
+            .map(|(id, _)| (format!("{:?}", id), TextureHandle::from(*id)))
+            .collect();
+
+        Self {
+            textures: HashMap::new(),
+            luts: HashMap::new(),
+            names,
+            dynamic: DynamicTextures::default(),
+            samplers,
+            loading: HashMap::new(),
+            loaded: None,
+            #[cfg(feature = "hot-reload")]
+            watcher: hot_reload::TextureWatcher::new()
+                .map_err(|e| log::error!("texture hot reload is disabled: {}", e))
+                .ok()
+                .map(Mutex::new),
+            untextured: Arc::new(untextured),
+            flat_normal: Arc::new(flat_normal),
+            missing: Arc::new(missing),
+            identity_lut: Arc::new(identity_lut),
+            missing_reported: Mutex::new(HashSet::new()),
+            revision: 0,
+        }
     }
 
+    // Procedural textures are ready right away, files are read and decoded on a loader thread and
+    // draw as untextured until poll_loaded uploads them.
+    pub fn load_as_needed(device: &Device, queue: &Queue, layout: &BindGroupLayout) -> Self {
+        let mut library = Self::empty(device, queue, layout);
+
+        let mut files = Vec::new();
+        for (id, desc) in TEXTURE_DESC_PAIRS.iter() {
+            match desc.source {
+                TextureSource::File(path) => {
+                    library
+                        .loading
+                        .insert(TextureHandle::from(*id), desc.sampler);
+                    files.push((*id, path));
+                }
+                TextureSource::Procedural(procedural) => {
+                    library.insert_procedural(device, queue, layout, *id, desc.sampler, procedural)
+                }
+            }
+        }
+
+        // the loader stops at the first failed send, which means the library was dropped
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (id, path) in files {
+                let decoded = DecodedTexture::from_file(Path::new(path));
+                if sender.send((id, decoded)).is_err() {
+                    break;
+                }
+            }
+        });
+        library.loaded = Some(Mutex::new(receiver));
+
+        library
+    }
+
+    // Blocks until every file is loaded. Files that fail to load are left out and show up as the
+    // missing texture.
+    #[allow(dead_code)]
     pub fn load_all(device: &Device, queue: &Queue, layout: &BindGroupLayout) -> Self {
-        let textures = TEXTURE_PATH_PAIRS
-            .iter()
-            .map(|(id, t)| {
-                (
-                    *id,
-                    Arc::new(Texture::from_file(device, queue, layout, Path::new(t))),
-                )
+        let mut library = Self::empty(device, queue, layout);
+
+        for (id, desc) in TEXTURE_DESC_PAIRS.iter() {
+            match desc.source {
+                TextureSource::File(path) => {
+                    let sampler = library.samplers.get(device, desc.sampler);
+                    match Texture::from_file(device, queue, layout, &sampler, Path::new(path)) {
+                        Ok(texture) => {
+                            library.insert(TextureHandle::from(*id), texture);
+                        }
+                        Err(e) => log::error!("{}", e),
+                    }
+                }
+                TextureSource::Procedural(procedural) => {
+                    library.insert_procedural(device, queue, layout, *id, desc.sampler, procedural)
+                }
+            }
+        }
+
+        library.log_summary();
+
+        library
+    }
+
+    fn insert(&mut self, handle: TextureHandle, texture: Texture) {
+        self.textures.insert(handle, Arc::new(texture));
+        self.revision += 1;
+    }
+
+    fn log_summary(&self) {
+        let stats = self.stats();
+        log::info!(
+            "loaded {} textures sharing {} samplers, {} of texture memory",
+            stats.count(),
+            self.samplers.len(),
+            library_stats::format_bytes(stats.total_bytes)
+        );
+        for (handle, bytes) in &stats.entries {
+            log::debug!(
+                "texture {:?}: {}",
+                handle,
+                library_stats::format_bytes(*bytes)
+            );
+        }
+    }
+
+    // The builtin fallback textures are not included, they are a few hundred bytes.
+    pub fn stats(&self) -> LibraryStats<TextureHandle> {
+        LibraryStats::from_entries(
+            self.textures
+                .iter()
+                .map(|(handle, texture)| (*handle, texture.bytes))
+                .chain(self.luts.iter().map(|(handle, lut)| (*handle, lut.bytes))),
+        )
+    }
+
+    // Changes whenever a texture is added, replaced or removed.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    fn insert_procedural(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        id: TextureId,
+        sampler: SamplerKey,
+        procedural: ProceduralTexture,
+    ) {
+        let sampler = self.samplers.get(device, sampler);
+        let texture = Texture::from_procedural(device, queue, layout, &sampler, procedural);
+        self.insert(TextureHandle::from(id), texture);
+    }
+
+    // Uploads whatever the loader thread finished since the last call, never waits for it. Called
+    // once per frame, between frames, which also makes it the place to swap in hot reloaded
+    // textures.
+    pub fn poll_loaded(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout) {
+        self.upload_loaded(device, queue, layout);
+
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed(device, queue, layout);
+    }
+
+    fn upload_loaded(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout) {
+        let receiver = match &mut self.loaded {
+            Some(receiver) => receiver.get_mut().unwrap(),
+            None => return,
+        };
+
+        let mut finished = false;
+        let mut arrived = Vec::new();
+        loop {
+            match receiver.try_recv() {
+                Ok(loaded) => arrived.push(loaded),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+
+        for (id, decoded) in arrived {
+            let handle = TextureHandle::from(id);
+            let sampler = match self.loading.remove(&handle) {
+                Some(sampler) => self.samplers.get(device, sampler),
+                None => continue,
+            };
+
+            match decoded {
+                Ok(decoded) => {
+                    let texture = Texture::from_decoded(device, queue, layout, &sampler, &decoded);
+                    self.insert(handle, texture);
+                }
+                Err(e) => log::error!("{}", e),
+            }
+        }
+
+        if finished {
+            self.loaded = None;
+            self.log_summary();
+        }
+    }
+
+    // Decoding happens right here, changes are rare and only while working on art. A file that
+    // fails to decode, for example one caught halfway through saving, keeps the old texture.
+    #[cfg(feature = "hot-reload")]
+    fn reload_changed(&mut self, device: &Device, queue: &Queue, layout: &BindGroupLayout) {
+        let changed = match &mut self.watcher {
+            Some(watcher) => watcher.get_mut().unwrap().changed(),
+            None => return,
+        };
+
+        for (id, path) in changed {
+            let handle = TextureHandle::from(id);
+            // the loader thread will deliver it soon enough
+            if self.loading.contains_key(&handle) {
+                continue;
+            }
+
+            let decoded = match DecodedTexture::from_file(&path) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    log::error!("keeping the old texture for {:?}: {}", id, e);
+                    continue;
+                }
+            };
+
+            let sampler_key = TEXTURE_DESC_PAIRS[id as usize].1.sampler;
+            let sampler = self.samplers.get(device, sampler_key);
+            let texture = Texture::from_decoded(device, queue, layout, &sampler, &decoded);
+            self.insert(handle, texture);
+            self.missing_reported.get_mut().unwrap().remove(&handle);
+
+            log::info!("reloaded texture {:?} from {}", id, path.display());
+        }
+    }
+
+    // Registering a name again replaces the texture behind its existing handle. Builtin names
+    // can't be replaced.
+    pub fn register(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        name: &str,
+        path: &Path,
+    ) -> Result<TextureHandle, String> {
+        let source = DynamicTexture {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            sampler: SamplerKey::DEFAULT,
+            lut: false,
+        };
+        let handle = self.register_source(device, queue, layout, source)?;
+
+        log::info!("registered texture {} from {}", name, path.display());
+
+        Ok(handle)
+    }
+
+    // Like register for color grading LUTs, see DecodedVolume::from_lut_strip for the layout. The
+    // handle is only useful with lut, drawing with it shows the missing texture.
+    pub fn register_lut(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        name: &str,
+        path: &Path,
+    ) -> Result<TextureHandle, String> {
+        let source = DynamicTexture {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            sampler: SamplerKey::DEFAULT,
+            lut: true,
+        };
+        let handle = self.register_source(device, queue, layout, source)?;
+
+        log::info!(
+            "registered color grading LUT {} from {}",
+            name,
+            path.display()
+        );
+
+        Ok(handle)
+    }
+
+    fn register_source(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        source: DynamicTexture,
+    ) -> Result<TextureHandle, String> {
+        let id = match self.names.get(&source.name) {
+            Some(TextureHandle::Dynamic(id)) => *id,
+            Some(TextureHandle::Builtin(_)) => {
+                return Err(format!("{} is a builtin texture", source.name));
+            }
+            None => {
+                self.dynamic.next_id += 1;
+                self.dynamic.next_id - 1
+            }
+        };
+
+        self.load_dynamic(device, queue, layout, id, source)?;
+
+        Ok(TextureHandle::Dynamic(id))
+    }
+
+    // For textures referenced by assets such as mtl files, registered under their path so assets
+    // sharing a file share the texture. Files that fail to load draw the missing texture instead.
+    pub fn load_referenced(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        path: &Path,
+    ) -> TextureHandle {
+        let name = path.to_string_lossy();
+        if let Some(handle) = self.handle_by_name(&name) {
+            return handle;
+        }
+
+        self.register(device, queue, layout, &name, path)
+            .unwrap_or_else(|e| {
+                log::error!("{}", e);
+                TextureId::UnknownTexture.into()
             })
-            .collect();
+    }
+
+    fn load_dynamic(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        id: u32,
+        source: DynamicTexture,
+    ) -> Result<(), String> {
+        let handle = TextureHandle::Dynamic(id);
+
+        // a name changing kind drops what it was before
+        if source.lut {
+            let lut = DecodedVolume::lut_from_file(&source.path)?;
+            self.textures.remove(&handle);
+            self.luts.insert(
+                handle,
+                Arc::new(VolumeTexture::from_decoded(device, queue, &lut)),
+            );
+            self.revision += 1;
+        } else {
+            let sampler = self.samplers.get(device, source.sampler);
+            let texture = Texture::from_file(device, queue, layout, &sampler, &source.path)?;
+            self.luts.remove(&handle);
+            self.insert(handle, texture);
+        }
+        self.names.insert(source.name.clone(), handle);
+        self.dynamic.sources.insert(id, source);
 
-        Self { textures }
+        Ok(())
     }
 
-    pub fn get(&self, id: Option<TextureId>) -> &Texture {
-        match id {
-            Some(id) => &self
-                .textures
-                .get(&id)
-                .expect("tried to access texture with bad id"),
-            None => &self
-                .textures
-                .get(&TextureId::UnknownTexture)
-                .expect("tried to access default texture and failed"),
+    // The texture and its bind group are freed once the gpu is done with them. Entities still
+    // using the handle draw the missing texture.
+    pub fn unregister(&mut self, handle: TextureHandle) -> Result<(), String> {
+        let id = match handle {
+            TextureHandle::Dynamic(id) => id,
+            TextureHandle::Builtin(id) => {
+                return Err(format!("{:?} is a builtin texture", id));
+            }
+        };
+
+        let source = self
+            .dynamic
+            .sources
+            .remove(&id)
+            .ok_or_else(|| format!("no texture registered as {:?}", handle))?;
+        self.names.remove(&source.name);
+        self.textures.remove(&handle);
+        self.luts.remove(&handle);
+        self.revision += 1;
+
+        log::info!("unregistered texture {}", source.name);
+
+        Ok(())
+    }
+
+    pub fn dynamic_textures(&self) -> &DynamicTextures {
+        &self.dynamic
+    }
+
+    // Loads textures registered on another library, usually one from a lost device, under the
+    // same handles. Failures are logged and leave that handle drawing the missing texture.
+    pub fn restore_dynamic_textures(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        dynamic: DynamicTextures,
+    ) {
+        self.dynamic.next_id = self.dynamic.next_id.max(dynamic.next_id);
+
+        for (id, source) in dynamic.sources {
+            if let Err(e) = self.load_dynamic(device, queue, layout, id, source) {
+                log::error!("{}", e);
+            }
+        }
+    }
+
+    pub fn handle_by_name(&self, name: &str) -> Option<TextureHandle> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, handle: TextureHandle) -> Option<&Texture> {
+        self.textures.get(&handle).map(Arc::as_ref)
+    }
+
+    // Never fails so every draw has a texture to bind. None is for objects without a Texture
+    // component and textures still loading, ids that failed to load get the missing texture.
+    pub fn get_or_default(&self, handle: Option<TextureHandle>) -> &Texture {
+        let handle = match handle {
+            Some(handle) => handle,
+            None => return &self.untextured,
+        };
+
+        match self.get(handle) {
+            Some(texture) => texture,
+            None if self.loading.contains_key(&handle) => &self.untextured,
+            None => {
+                if self.missing_reported.lock().unwrap().insert(handle) {
+                    log::warn!(
+                        "texture {:?} is not loaded, drawing the missing texture",
+                        handle
+                    );
+                }
+                &self.missing
+            }
+        }
+    }
+    // Like get_or_default for the normal map slot, anything not loaded draws flat instead of white
+    // or the checkerboard, either of which would bend every normal the same way.
+    pub fn normal_map_or_flat(&self, handle: Option<TextureHandle>) -> &Texture {
+        handle
+            .and_then(|handle| self.get(handle))
+            .unwrap_or(&self.flat_normal)
+    }
+
+    pub fn lut(&self, handle: TextureHandle) -> Option<&VolumeTexture> {
+        self.luts.get(&handle).map(Arc::as_ref)
+    }
+
+    // The identity LUT for None and anything that isn't a registered LUT, grading then leaves the
+    // colors as they are.
+    pub fn lut_or_identity(&self, handle: Option<TextureHandle>) -> &VolumeTexture {
+        handle
+            .and_then(|handle| self.lut(handle))
+            .unwrap_or(&self.identity_lut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x4 srgb with a full mip chain, every level zstd compressed
+    const ZSTD_TEXTURE: &str = "tests/texture/zstd-mips.ktx2";
+
+    #[test]
+    fn zstd_ktx2_test() {
+        let decoded = DecodedTexture::from_file(Path::new(ZSTD_TEXTURE)).unwrap();
+
+        assert_eq!((decoded.width, decoded.height, decoded.layers), (4, 4, 1));
+        assert_eq!(decoded.color_space, ColorSpace::Srgb);
+        let sizes: Vec<_> = decoded.levels.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [4 * 16, 4 * 4, 4]);
+
+        // the base level is a red and green gradient, smaller levels are solid red and blue
+        assert_eq!(decoded.levels[0][..8], [0, 0, 0, 255, 64, 0, 0, 255]);
+        assert_eq!(decoded.levels[0][4 * 15..], [192, 192, 0, 255]);
+        assert_eq!(decoded.levels[1][..4], [255, 0, 0, 255]);
+        assert_eq!(decoded.levels[2], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn verify_mip_chain_test() {
+        let chain = |width: u32, height: u32, sizes: &[u32]| DecodedTexture {
+            width,
+            height,
+            layers: 1,
+            levels: sizes
+                .iter()
+                .map(|size| vec![0; *size as usize * 4])
+                .collect(),
+            color_space: ColorSpace::Srgb,
+        };
+
+        // full chains, short chains and non square ones stopping at 1 on the short side
+        assert!(chain(4, 4, &[16, 4, 1]).verify_mip_chain().is_ok());
+        assert!(chain(4, 4, &[16]).verify_mip_chain().is_ok());
+        assert!(chain(8, 2, &[16, 4, 2, 1]).verify_mip_chain().is_ok());
+        assert!(chain(5, 3, &[15, 2, 1]).verify_mip_chain().is_ok());
+
+        let error = |texture: DecodedTexture| texture.verify_mip_chain().unwrap_err();
+        assert!(error(chain(4, 4, &[16, 4, 1, 1])).contains("1 to 3"));
+        assert!(error(chain(4, 4, &[])).contains("0 levels"));
+        assert!(error(chain(4, 4, &[16, 2, 1])).contains("level 1"));
+        assert!(error(chain(0, 4, &[])).contains("empty"));
+
+        // every level holds all layers
+        let mut layered = chain(2, 2, &[8, 2]);
+        layered.layers = 2;
+        assert!(layered.verify_mip_chain().is_ok());
+        layered.layers = 3;
+        assert!(error(layered).contains("level 0"));
+    }
+
+    // Strip whose texels hold their own coordinates, (red, green, blue) in the cube.
+    fn coordinate_strip(size: u32, horizontal: bool) -> DecodedTexture {
+        let (width, height) = match horizontal {
+            true => (size * size, size),
+            false => (size, size * size),
+        };
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let (red, green, blue) = match horizontal {
+                    true => (x % size, y, x / size),
+                    false => (x, y % size, y / size),
+                };
+                data.extend_from_slice(&[red as u8, green as u8, blue as u8, 255]);
+            }
+        }
+
+        DecodedTexture::rgba8(width, height, data, ColorSpace::Srgb)
+    }
+
+    #[test]
+    fn lut_strip_to_volume_test() {
+        for horizontal in [true, false] {
+            let volume = DecodedVolume::from_lut_strip(&coordinate_strip(3, horizontal)).unwrap();
+            assert_eq!((volume.width, volume.height, volume.depth), (3, 3, 3));
+
+            // red along x, then green along y, then blue across slices
+            for (i, texel) in volume.data.chunks(4).enumerate() {
+                let i = i as u8;
+                assert_eq!(texel, [i % 3, i / 3 % 3, i / 9, 255], "texel {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn lut_strip_must_form_a_cube_test() {
+        let strip = |width, height| {
+            DecodedTexture::rgba8(
+                width,
+                height,
+                vec![0; (4 * width * height) as usize],
+                ColorSpace::Srgb,
+            )
+        };
+
+        assert!(DecodedVolume::from_lut_strip(&strip(16 * 16, 16)).is_ok());
+        assert!(DecodedVolume::from_lut_strip(&strip(16, 16 * 16)).is_ok());
+        for (width, height) in [(16, 16), (16 * 16, 15), (15 * 16, 16), (1, 1), (4, 8)] {
+            let error = DecodedVolume::from_lut_strip(&strip(width, height)).unwrap_err();
+            assert!(error.contains("does not form a LUT cube"), "{}", error);
+        }
+
+        let mut layered = strip(4, 2);
+        layered.layers = 2;
+        layered.levels[0].resize(4 * 4 * 2 * 2, 0);
+        assert!(DecodedVolume::from_lut_strip(&layered).is_err());
+    }
+
+    #[test]
+    fn identity_lut_test() {
+        let lut = DecodedVolume::identity_lut(IDENTITY_LUT_SIZE);
+        assert_eq!(lut.data.len(), 4 * 16 * 16 * 16);
+
+        // every entry holds its own coordinate scaled to 0..255
+        for (i, texel) in lut.data.chunks(4).enumerate() {
+            let (red, green, blue) = (i % 16, i / 16 % 16, i / 256);
+            assert_eq!(
+                texel,
+                [17 * red as u8, 17 * green as u8, 17 * blue as u8, 255]
+            );
         }
+
+        // sizes not dividing 255 round to the nearest value
+        let small = DecodedVolume::identity_lut(3);
+        assert_eq!(
+            small.data[..12],
+            [0, 0, 0, 255, 128, 0, 0, 255, 255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn register_lut_test() {
+        let (device, queue) = match crate::util::test_device() {
+            Some(device) => device,
+            None => return,
+        };
+        let layout = bind_group_layout(&device);
+        let mut library = TextureLibrary::empty(&device, &queue, &layout);
+
+        let write_png = |name: &str, strip: &DecodedTexture| {
+            let path =
+                std::env::temp_dir().join(format!("card_game_{}_{}.png", name, std::process::id()));
+            image::save_buffer(
+                &path,
+                &strip.levels[0],
+                strip.width,
+                strip.height,
+                image::ColorType::Rgba8,
+            )
+            .unwrap();
+            path
+        };
+        let lut_path = write_png("lut", &coordinate_strip(4, true));
+        let flat_path = write_png(
+            "not_a_lut",
+            &DecodedTexture::rgba8(4, 4, vec![0; 64], ColorSpace::Srgb),
+        );
+
+        let handle = library
+            .register_lut(&device, &queue, &layout, "warm", &lut_path)
+            .unwrap();
+        let rejected = library.register_lut(&device, &queue, &layout, "flat", &flat_path);
+        std::fs::remove_file(&lut_path).unwrap();
+        std::fs::remove_file(&flat_path).unwrap();
+
+        assert!(rejected.unwrap_err().contains("does not form a LUT cube"));
+        assert_eq!(library.handle_by_name("flat"), None);
+
+        // a LUT is no regular texture, and stays out of the draw path
+        assert_eq!(library.handle_by_name("warm"), Some(handle));
+        assert_eq!(library.lut(handle).unwrap().bytes, 4 * 64);
+        assert!(library.get(handle).is_none());
+        assert!(std::ptr::eq(
+            library.lut_or_identity(Some(handle)),
+            library.lut(handle).unwrap()
+        ));
+        assert!(std::ptr::eq(
+            library.lut_or_identity(None),
+            library.lut_or_identity(Some(TextureId::CheckerTexture.into()))
+        ));
+        assert_eq!(library.stats().total_bytes, 4 * 64);
+
+        library.unregister(handle).unwrap();
+        assert!(library.lut(handle).is_none());
+    }
+
+    #[test]
+    fn malformed_zstd_level_test() {
+        let garbage = [0x12, 0x34, 0x56, 0x78, 0x9a];
+        assert!(decompress_level(Some(SupercompressionScheme::Zstandard), &garbage).is_err());
+        assert!(decompress_level(Some(SupercompressionScheme::ZLIB), &garbage).is_err());
+        assert_eq!(decompress_level(None, &garbage).unwrap(), &garbage[..]);
+
+        // the same file with the frame of its smallest level broken
+        let mut contents = std::fs::read(ZSTD_TEXTURE).unwrap();
+        let level_offset = 80 + 2 * 24;
+        let offset =
+            u64::from_le_bytes(contents[level_offset..level_offset + 8].try_into().unwrap());
+        contents[offset as usize..offset as usize + 4].copy_from_slice(&garbage[..4]);
+
+        let path =
+            std::env::temp_dir().join(format!("card_game_broken_{}.ktx2", std::process::id()));
+        std::fs::write(&path, &contents).unwrap();
+        let result = DecodedTexture::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = result.err().unwrap();
+        assert!(error.contains("level 2"), "{}", error);
+    }
+
+    #[test]
+    fn decode_png_test() {
+        // red, green on top, blue, half transparent white below
+        let pixels: [u8; 16] = [
+            255, 0, 0, 255, 0, 255, 0, 255, //
+            0, 0, 255, 255, 255, 255, 255, 128,
+        ];
+        let mut png = Vec::new();
+        image::ImageEncoder::write_image(
+            image::codecs::png::PngEncoder::new(&mut png),
+            &pixels,
+            2,
+            2,
+            image::ColorType::Rgba8,
+        )
+        .unwrap();
+
+        let decoded = decode_image(&png).unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+        assert_eq!(decoded.data, pixels);
+    }
+
+    #[test]
+    fn decode_rgb_png_adds_alpha_test() {
+        let mut png = Vec::new();
+        image::ImageEncoder::write_image(
+            image::codecs::png::PngEncoder::new(&mut png),
+            &[10, 20, 30],
+            1,
+            1,
+            image::ColorType::Rgb8,
+        )
+        .unwrap();
+
+        assert_eq!(decode_image(&png).unwrap().data, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn decode_garbage_test() {
+        assert!(decode_image(b"not an image").is_err());
+    }
+
+    #[test]
+    fn register_and_unregister_test() {
+        let (device, queue) = match crate::util::test_device() {
+            Some(device) => device,
+            None => return,
+        };
+        let layout = bind_group_layout(&device);
+        let mut library = TextureLibrary::empty(&device, &queue, &layout);
+        let path = Path::new(ZSTD_TEXTURE);
+
+        let handle = library
+            .register(&device, &queue, &layout, "mips", path)
+            .unwrap();
+        assert_eq!(library.handle_by_name("mips"), Some(handle));
+        assert_eq!(library.get(handle).unwrap().bytes, 4 * (16 + 4 + 1));
+
+        // registering the name again keeps the handle, a new name gets a new one
+        let again = library
+            .register(&device, &queue, &layout, "mips", path)
+            .unwrap();
+        let other = library
+            .register(&device, &queue, &layout, "other", path)
+            .unwrap();
+        assert_eq!(again, handle);
+        assert_ne!(other, handle);
+
+        library.unregister(handle).unwrap();
+        assert_eq!(library.handle_by_name("mips"), None);
+        assert!(library.get(handle).is_none());
+        assert!(library.get(other).is_some());
+        assert!(library.unregister(handle).is_err());
+    }
+
+    #[test]
+    fn register_failures_test() {
+        let (device, queue) = match crate::util::test_device() {
+            Some(device) => device,
+            None => return,
+        };
+        let layout = bind_group_layout(&device);
+        let mut library = TextureLibrary::empty(&device, &queue, &layout);
+
+        let missing = library.register(
+            &device,
+            &queue,
+            &layout,
+            "missing",
+            Path::new("texture/does-not-exist.png"),
+        );
+        assert!(missing.is_err());
+        assert_eq!(library.handle_by_name("missing"), None);
+
+        // builtin names and handles stay put
+        let builtin = format!("{:?}", TextureId::UnknownTexture);
+        let path = Path::new(ZSTD_TEXTURE);
+        assert!(library
+            .register(&device, &queue, &layout, &builtin, path)
+            .is_err());
+        assert!(library
+            .unregister(TextureId::UnknownTexture.into())
+            .is_err());
     }
 }
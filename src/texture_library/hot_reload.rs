@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::Duration,
+};
+
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{TextureId, TextureSource, TEXTURE_DESC_PAIRS};
+
+// Editors often save by writing a temporary file and renaming it over the old one, events are
+// debounced so a save shows up as a single change.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Watches the files in TEXTURE_DESC_PAIRS. Directories are watched instead of the files
+// themselves since a rename replaces the watched inode.
+pub struct TextureWatcher {
+    _watcher: RecommendedWatcher, // stops watching when dropped
+    events: Receiver<DebouncedEvent>,
+    paths: HashMap<PathBuf, TextureId>, // canonicalized, event paths are compared against these
+}
+
+impl TextureWatcher {
+    pub fn new() -> Result<Self, String> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = watcher(sender, DEBOUNCE).map_err(|e| e.to_string())?;
+
+        let mut paths = HashMap::new();
+        for (id, desc) in TEXTURE_DESC_PAIRS.iter() {
+            if let TextureSource::File(path) = desc.source {
+                match Path::new(path).canonicalize() {
+                    Ok(path) => {
+                        paths.insert(path, *id);
+                    }
+                    Err(e) => log::warn!("not watching texture file {}: {}", path, e),
+                }
+            }
+        }
+
+        let mut directories: Vec<&Path> = paths.keys().filter_map(|path| path.parent()).collect();
+        directories.sort();
+        directories.dedup();
+        for directory in directories {
+            watcher
+                .watch(directory, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("failed to watch {}: {}", directory.display(), e))?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            paths,
+        })
+    }
+
+    // Textures whose files changed since the last call along with their path, each at most once.
+    pub fn changed(&self) -> Vec<(TextureId, PathBuf)> {
+        let mut changed = Vec::new();
+
+        loop {
+            let path = match self.events.try_recv() {
+                Ok(DebouncedEvent::Create(path))
+                | Ok(DebouncedEvent::Write(path))
+                | Ok(DebouncedEvent::Rename(_, path)) => path,
+                Ok(DebouncedEvent::Error(e, path)) => {
+                    log::error!("texture watcher error on {:?}: {}", path, e);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+            };
+
+            let path = path.canonicalize().unwrap_or(path);
+            if let Some(id) = self.paths.get(&path) {
+                if !changed.iter().any(|(changed_id, _)| changed_id == id) {
+                    changed.push((*id, path));
+                }
+            }
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,190 @@
+// Generated RGBA8 images for placeholders and debugging. Rows are top to bottom, 4 bytes per pixel.
+
+use super::ColorSpace;
+
+pub type Rgba = [u8; 4];
+
+// Well known generated textures, see TextureLibrary for their handles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProceduralTexture {
+    Checker,
+    UvGrid,
+    White,
+    FlatNormal,
+    BumpNormal,
+}
+
+impl ProceduralTexture {
+    pub fn generate(&self) -> Image {
+        const MAGENTA: Rgba = [255, 0, 255, 255];
+        const BLACK: Rgba = [0, 0, 0, 255];
+
+        match self {
+            Self::Checker => checkerboard(64, 64, 8, MAGENTA, BLACK),
+            Self::UvGrid => uv_grid(512, 8),
+            Self::White => solid(4, 4, [255, 255, 255, 255]),
+            Self::FlatNormal => flat_normal(4, 4),
+            Self::BumpNormal => bump_normal(256, 8),
+        }
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        match self {
+            Self::FlatNormal | Self::BumpNormal => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        }
+    }
+}
+
+// Also used for decoded image files.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    fn from_fn(width: u32, height: u32, pixel: impl Fn(u32, u32) -> Rgba) -> Self {
+        let data = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect();
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, color: Rgba) {
+        if x < self.width && y < self.height {
+            let i = 4 * (y * self.width + x) as usize;
+            self.data[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+// The cell containing the top left pixel uses color a.
+pub fn checkerboard(width: u32, height: u32, cell_size: u32, a: Rgba, b: Rgba) -> Image {
+    let cell_size = cell_size.max(1);
+
+    Image::from_fn(width, height, |x, y| {
+        if (x / cell_size + y / cell_size).is_multiple_of(2) {
+            a
+        } else {
+            b
+        }
+    })
+}
+
+pub fn solid(width: u32, height: u32, color: Rgba) -> Image {
+    Image::from_fn(width, height, |_, _| color)
+}
+
+// Normal map texel for a normal pointing straight out of the surface.
+pub fn flat_normal(width: u32, height: u32) -> Image {
+    solid(width, height, [128, 128, 255, 255])
+}
+
+// Normal map of a grid of round domes, one per cell, flat in between. x follows u and y follows v,
+// which runs down the image, matching the tangents geometry_library generates.
+pub fn bump_normal(size: u32, cells: u32) -> Image {
+    let cell_size = (size / cells.max(1)).max(1);
+    let radius = cell_size as f32 * 0.4;
+    let encode = |n: f32| ((n * 0.5 + 0.5) * 255.0).round() as u8;
+
+    Image::from_fn(size, size, |x, y| {
+        let center = cell_size as f32 / 2.0;
+        let dx = ((x % cell_size) as f32 + 0.5 - center) / radius;
+        let dy = ((y % cell_size) as f32 + 0.5 - center) / radius;
+
+        // the normal of a unit sphere is the offset from its center
+        let r2 = dx * dx + dy * dy;
+        if r2 >= 1.0 {
+            return [128, 128, 255, 255];
+        }
+        [encode(dx), encode(dy), encode((1.0 - r2).sqrt()), 255]
+    })
+}
+
+// Square grid of cells numbered row by row from the top left, tinted red along u and green along
+// v so flipped or swapped texture coordinates are easy to spot.
+pub fn uv_grid(size: u32, cells: u32) -> Image {
+    const LINE: Rgba = [255, 255, 255, 255];
+    const TEXT: Rgba = [0, 0, 0, 255];
+    const DIGIT_SCALE: u32 = 2;
+
+    let cells = cells.max(1);
+    let cell_size = (size / cells).max(1);
+
+    let mut image = Image::from_fn(size, size, |x, y| {
+        if x % cell_size == 0 || y % cell_size == 0 {
+            return LINE;
+        }
+
+        let shade = if (x / cell_size + y / cell_size).is_multiple_of(2) {
+            160
+        } else {
+            200
+        };
+        let u = (x * 255 / size.max(1)) as u16;
+        let v = (y * 255 / size.max(1)) as u16;
+
+        [
+            ((shade + u) / 2) as u8,
+            ((shade + v) / 2) as u8,
+            shade as u8 / 2,
+            255,
+        ]
+    });
+
+    for row in 0..cells {
+        for column in 0..cells {
+            let label = (row * cells + column).to_string();
+            let (x, y) = (column * cell_size + 2, row * cell_size + 2);
+
+            for (i, digit) in label.bytes().enumerate() {
+                let x = x + i as u32 * (DIGIT_WIDTH + 1) * DIGIT_SCALE;
+                draw_digit(&mut image, digit - b'0', x, y, DIGIT_SCALE, TEXT);
+            }
+        }
+    }
+
+    image
+}
+
+const DIGIT_WIDTH: u32 = 3;
+const DIGIT_HEIGHT: u32 = 5;
+
+// 3x5 bitmap digits, one row per entry with the most significant of the 3 bits on the left.
+const DIGITS: [[u8; DIGIT_HEIGHT as usize]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn draw_digit(image: &mut Image, digit: u8, x: u32, y: u32, scale: u32, color: Rgba) {
+    let rows = &DIGITS[digit as usize % 10];
+
+    for (row, bits) in rows.iter().enumerate() {
+        for column in 0..DIGIT_WIDTH {
+            if bits & (1 << (DIGIT_WIDTH - 1 - column)) == 0 {
+                continue;
+            }
+
+            for sy in 0..scale {
+                for sx in 0..scale {
+                    image.set_pixel(x + column * scale + sx, y + row as u32 * scale + sy, color);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, num::NonZeroU8, sync::Arc};
+
+use wgpu::Device;
+
+// The parts of a sampler descriptor textures choose between. The same address mode is used on
+// every axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_mode: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub anisotropy_clamp: Option<NonZeroU8>, // needs all filters Linear
+}
+
+impl SamplerKey {
+    // Tiling world textures.
+    pub const DEFAULT: Self = Self {
+        address_mode: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        anisotropy_clamp: None,
+    };
+
+    // Pixel exact, keeps debug textures crisp up close.
+    pub const NEAREST: Self = Self {
+        address_mode: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        anisotropy_clamp: None,
+    };
+
+    fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: self.anisotropy_clamp,
+            ..Default::default()
+        }
+    }
+}
+
+// One sampler per distinct key, shared by every texture using it.
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerKey, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn get(&mut self, device: &Device, key: SamplerKey) -> Arc<wgpu::Sampler> {
+        self.samplers
+            .entry(key)
+            .or_insert_with(|| Arc::new(device.create_sampler(&key.descriptor())))
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_device;
+
+    #[test]
+    fn sampler_cache_test() {
+        let (device, _) = match test_device() {
+            Some(device) => device,
+            None => return,
+        };
+        let mut cache = SamplerCache::default();
+
+        let default = cache.get(&device, SamplerKey::DEFAULT);
+        let nearest = cache.get(&device, SamplerKey::NEAREST);
+        assert_eq!(cache.len(), 2);
+        assert!(!Arc::ptr_eq(&default, &nearest));
+
+        // hits hand out the sampler created by the first miss
+        let again = cache.get(&device, SamplerKey::DEFAULT);
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&default, &again));
+
+        let clamped = SamplerKey {
+            address_mode: wgpu::AddressMode::ClampToEdge,
+            ..SamplerKey::DEFAULT
+        };
+        assert!(!Arc::ptr_eq(&default, &cache.get(&device, clamped)));
+        assert_eq!(cache.len(), 3);
+    }
+}
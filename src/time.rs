@@ -2,29 +2,29 @@ use std::time::{Duration, Instant};
 
 use bevy_ecs::{schedule::ShouldRun, system::ResMut};
 
-// TODO: This system should be split so that unsimulated time is updated and then update systems are ran before the current frame is drawn. That change will reduce felt latency
-// TODO: implement blending in the render system
-pub fn frame_criteria(mut time: ResMut<TimeResource>) -> ShouldRun {
-    // acts as a frame limiter
-    let elapsed = time.last_frame.elapsed();
-    if elapsed >= time.frame_dt {
-        // register passed time for update_criteria and update last frame so that next call to frame_criteria calculated the correct elapsed time
-        time.last_frame = Instant::now();
-        time.unsimulated_time += elapsed;
-
-        ShouldRun::Yes
-    } else {
-        ShouldRun::No
+pub fn update_criteria(mut time: ResMut<TimeResource>) -> ShouldRun {
+    if time.paused {
+        return ShouldRun::No;
     }
-}
 
-pub fn update_criteria(mut time: ResMut<TimeResource>) -> ShouldRun {
     let dt = time.update_dt;
+
+    // after a long hitch catching up would take longer than the hitch itself, so drop the excess
+    let max = time.max_unsimulated_time.max(dt);
+    if time.unsimulated_time > max {
+        let dropped = time.unsimulated_time - max;
+        log::warn!("dropping {:?} of unsimulated time", dropped);
+
+        time.unsimulated_time = max;
+        time.dropped_time += dropped;
+    }
+
     // This will cause all update systems to loop as long as there is still unsimulated time.
     if time.unsimulated_time >= dt {
         // move dt time from unsimulated to ingame
         time.unsimulated_time -= dt;
         time.ingame_time += dt;
+        time.tick += 1;
 
         ShouldRun::YesAndCheckAgain
     } else {
@@ -32,23 +32,25 @@ pub fn update_criteria(mut time: ResMut<TimeResource>) -> ShouldRun {
     }
 }
 
-/*
-fn do_frame<F: FnMut(f64) -> ()>(&self, mut draw: F) {
-    let blend = self.acc.as_secs_f64() / self.frame_dt.as_secs_f64();
-    draw(blend);
-}
-*/
-
 #[derive(Clone, Debug)]
 pub struct TimeResource {
     // target delta time
     pub update_dt: Duration,
     pub frame_dt: Duration, // actual dt will be variable
 
-    pub ingame_time: Duration, // amount of ingame time elapsed. Maybe should be replaced with tick counter and getter
+    pub ingame_time: Duration, // amount of ingame time elapsed
+    pub tick: u64, // fixed updates run so far, already counts the current one while update systems run
 
     pub last_frame: Instant,
+    pub next_frame: Instant, // frame limiter deadline, frame_dt of 0 leaves it in the past
     pub unsimulated_time: Duration, // amount of realtime passed that hasn't been simulated yet. This will increase when the amount of realtime passed is not an exact multiple of update_dt
+    pub max_unsimulated_time: Duration, // backlog cap, bounds the updates run per frame to about max / update_dt
+    pub dropped_time: Duration, // total realtime discarded by the backlog cap, never simulated
+
+    // frames keep being drawn while paused, only updates stop. That includes console commands,
+    // they queue up until the game resumes
+    pub paused: bool,
+    pub time_scale: f32, // realtime is scaled by this before it is simulated, 0 acts like paused
 }
 
 impl TimeResource {
@@ -58,8 +60,213 @@ impl TimeResource {
             frame_dt,
 
             ingame_time: Duration::default(),
+            tick: 0,
             last_frame: Instant::now(),
+            next_frame: Instant::now(),
             unsimulated_time: Duration::default(),
+            max_unsimulated_time: Duration::from_millis(250),
+            dropped_time: Duration::default(),
+
+            paused: false,
+            time_scale: 1.0,
+        }
+    }
+
+    pub fn ticks_since(&self, tick: u64) -> u64 {
+        self.tick.saturating_sub(tick)
+    }
+
+    // True on every nth tick, never for n of 0.
+    pub fn every_n_ticks(&self, n: u64) -> bool {
+        n != 0 && self.tick.is_multiple_of(n)
+    }
+
+    pub fn frame_due(&self) -> bool {
+        Instant::now() >= self.next_frame
+    }
+
+    // Acts as a frame limiter, false until the next frame is due. Otherwise hands the passed
+    // realtime to update_criteria, updates should run before the frame is drawn.
+    pub fn begin_frame(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_frame {
+            return false;
+        }
+
+        // paused time is never handed out, unpausing carries on from where the game stopped
+        if !self.paused {
+            self.unsimulated_time += (now - self.last_frame).mul_f32(self.time_scale.max(0.0));
+        }
+        self.last_frame = now;
+
+        // scheduled from the previous deadline so waking up late doesn't lower the frame rate,
+        // unless a whole frame was missed, then catching up would just burst frames
+        self.next_frame = if now - self.next_frame < self.frame_dt {
+            self.next_frame + self.frame_dt
+        } else {
+            now + self.frame_dt
+        };
+
+        true
+    }
+
+    // How far realtime is between the last two updates, 0 at the previous one and 1 at the latest.
+    // Rendering blends between them with this so motion is smooth when frames outnumber updates.
+    pub fn blend(&self) -> f32 {
+        (self.unsimulated_time.as_secs_f32() / self.update_dt.as_secs_f32()).clamp(0.0, 1.0)
+    }
+}
+
+// Frame pacing while the window is unfocused. Fixed updates keep running either way, only the
+// frame rate drops so an alt tabbed game doesn't burn the gpu.
+#[derive(Clone, Debug)]
+pub struct BackgroundThrottle {
+    pub frame_dt: Duration,      // frame limiter cap while unfocused
+    pub suspend_rendering: bool, // keep simulating but skip drawing entirely while unfocused
+
+    foreground_frame_dt: Option<Duration>, // Some while in the background
+}
+
+impl Default for BackgroundThrottle {
+    fn default() -> Self {
+        Self {
+            frame_dt: Duration::from_secs_f64(1.0 / 10.0),
+            suspend_rendering: false,
+            foreground_frame_dt: None,
+        }
+    }
+}
+
+impl BackgroundThrottle {
+    pub fn is_background(&self) -> bool {
+        self.foreground_frame_dt.is_some()
+    }
+
+    pub fn rendering_suspended(&self) -> bool {
+        self.is_background() && self.suspend_rendering
+    }
+
+    pub fn set_focused(&mut self, focused: bool, time: &mut TimeResource) {
+        match (focused, self.foreground_frame_dt) {
+            (false, None) => {
+                self.foreground_frame_dt = Some(time.frame_dt);
+                time.frame_dt = self.frame_dt;
+            }
+            (true, Some(foreground_frame_dt)) => {
+                self.foreground_frame_dt = None;
+                time.frame_dt = foreground_frame_dt;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_ecs::{
+        system::{IntoSystem, System},
+        world::World,
+    };
+
+    fn world_with(time: TimeResource) -> World {
+        let mut world = World::new();
+        world.insert_resource(time);
+        world
+    }
+
+    // Runs update_criteria the way the schedule does, returning how many updates it let through.
+    fn run_updates(world: &mut World) -> u32 {
+        let mut criteria = IntoSystem::into_system(update_criteria);
+        criteria.initialize(world);
+
+        let mut updates = 0;
+        while criteria.run((), world) == ShouldRun::YesAndCheckAgain {
+            updates += 1;
+            assert!(updates < 10_000, "update_criteria never stops");
+        }
+        updates
+    }
+
+    #[test]
+    fn large_backlog_is_capped_test() {
+        let mut time = TimeResource::new(Duration::from_millis(10), Duration::ZERO);
+        time.unsimulated_time = Duration::from_secs(60);
+        let mut world = world_with(time);
+
+        // max_unsimulated_time / update_dt
+        assert_eq!(run_updates(&mut world), 25);
+
+        let time = world.resource::<TimeResource>();
+        assert_eq!(time.ingame_time, Duration::from_millis(250));
+        assert_eq!(time.dropped_time, Duration::from_millis(59_750));
+        assert_eq!(time.unsimulated_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn small_backlog_is_kept_test() {
+        let mut time = TimeResource::new(Duration::from_millis(10), Duration::ZERO);
+        time.unsimulated_time = Duration::from_millis(35);
+        let mut world = world_with(time);
+
+        assert_eq!(run_updates(&mut world), 3);
+
+        let time = world.resource::<TimeResource>();
+        assert_eq!(time.dropped_time, Duration::ZERO);
+        // the remainder waits for the next frame
+        assert_eq!(time.unsimulated_time, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn cap_below_update_dt_still_updates_test() {
+        let mut time = TimeResource::new(Duration::from_millis(100), Duration::ZERO);
+        time.max_unsimulated_time = Duration::from_millis(10);
+        time.unsimulated_time = Duration::from_secs(1);
+        let mut world = world_with(time);
+
+        assert_eq!(run_updates(&mut world), 1);
+    }
+
+    #[test]
+    fn ticks_follow_elapsed_time_test() {
+        let mut world = world_with(TimeResource::new(Duration::from_millis(10), Duration::ZERO));
+
+        // frames of uneven length, 10ms updates
+        for frame in [16, 16, 3, 40, 25] {
+            world.resource_mut::<TimeResource>().unsimulated_time += Duration::from_millis(frame);
+            run_updates(&mut world);
         }
+
+        let time = world.resource::<TimeResource>();
+        assert_eq!(time.tick, 10);
+        assert_eq!(time.ingame_time, Duration::from_millis(100));
+        assert_eq!(time.ticks_since(4), 6);
+        assert_eq!(time.ticks_since(20), 0);
+    }
+
+    #[test]
+    fn paused_does_not_tick_test() {
+        let mut time = TimeResource::new(Duration::from_millis(10), Duration::ZERO);
+        time.unsimulated_time = Duration::from_millis(50);
+        time.paused = true;
+        let mut world = world_with(time);
+
+        assert_eq!(run_updates(&mut world), 0);
+        assert_eq!(world.resource::<TimeResource>().tick, 0);
+    }
+
+    #[test]
+    fn every_n_ticks_test() {
+        let mut time = TimeResource::new(Duration::from_millis(10), Duration::ZERO);
+        let mut due = Vec::new();
+        for tick in 1..=12 {
+            time.tick = tick;
+            if time.every_n_ticks(4) {
+                due.push(tick);
+            }
+            assert!(!time.every_n_ticks(0));
+        }
+
+        assert_eq!(due, [4, 8, 12]);
     }
 }
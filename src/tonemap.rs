@@ -0,0 +1,130 @@
+use bevy_ecs::world::World;
+
+use crate::texture_library::TextureHandle;
+
+// Maps lit colors above 1 back into display range. Applied by the tonemap pass reading the HDR
+// target, the operator is a uniform so switching never rebuilds a pipeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TonemapOperator {
+    None, // passthrough, clamps like the output from before tonemapping existed
+    Reinhard,
+    Aces, // Narkowicz's fit of the ACES filmic curve
+    KhronosNeutral,
+}
+
+impl TonemapOperator {
+    pub const ALL: [TonemapOperator; 4] =
+        [Self::None, Self::Reinhard, Self::Aces, Self::KhronosNeutral];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces",
+            Self::KhronosNeutral => "khronos_neutral",
+        }
+    }
+
+    // Errors list the valid names so a typo in a command or settings file is easy to fix.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|op| op.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|op| op.name()).collect();
+                format!(
+                    "unknown tonemap operator {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+
+    // Value the fragment shader switches on.
+    pub fn shader_id(&self) -> u32 {
+        *self as u32
+    }
+
+    // Picked so mid grey lands at roughly the same brightness whichever operator is active.
+    pub fn default_exposure(&self) -> f32 {
+        match self {
+            Self::None => 1.0,
+            Self::Reinhard => 1.5,
+            Self::Aces => 0.8,
+            Self::KhronosNeutral => 1.0,
+        }
+    }
+}
+
+// Resource, render falls back to TonemapSettings::default() when it's missing.
+#[derive(Clone, Copy, Debug)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    pub exposure: Option<f32>, // None uses the operator's default
+
+    // debug side by side view, the right half of the screen uses this operator instead
+    pub compare: Option<TonemapOperator>,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::None,
+            exposure: None,
+            compare: None,
+        }
+    }
+}
+
+impl TonemapSettings {
+    pub fn exposure_for(&self, operator: TonemapOperator) -> f32 {
+        self.exposure.unwrap_or_else(|| operator.default_exposure())
+    }
+}
+
+// Part of RenderSettings. The tonemap pass always samples a LUT, the identity one when lut is
+// None, so grading costs the same whether it's in use or not.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorGrading {
+    pub lut: Option<TextureHandle>, // registered with RenderState::register_lut
+    pub strength: f32,              // 0 leaves the colors ungraded, 1 applies the LUT fully
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            lut: None,
+            strength: 1.0,
+        }
+    }
+}
+
+pub fn tonemap_command(world: &mut World, args: &[&str]) -> Result<(), String> {
+    if world.get_resource::<TonemapSettings>().is_none() {
+        world.insert_resource(TonemapSettings::default());
+    }
+    let mut settings = world.resource_mut::<TonemapSettings>();
+
+    match args {
+        ["compare", "off"] => settings.compare = None,
+        ["compare", name] => settings.compare = Some(TonemapOperator::from_name(name)?),
+        ["exposure", "default"] => settings.exposure = None,
+        ["exposure", value] => {
+            settings.exposure = Some(
+                value
+                    .parse::<f32>()
+                    .map_err(|e| format!("bad exposure {}: {}", value, e))?,
+            )
+        }
+        [name] => settings.operator = TonemapOperator::from_name(name)?,
+        _ => {
+            return Err(
+                "expected an operator, compare <operator|off> or exposure <value|default>"
+                    .to_string(),
+            )
+        }
+    }
+
+    Ok(())
+}
@@ -36,3 +36,37 @@ where
         }
     }
 }
+
+// Same features and limits as RenderState::init. None without a usable adapter, tests skip
+// themselves then since a gpu is not a given on every machine running them.
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let init = async {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            })
+            .await?;
+
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::default().using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await
+            .ok()
+    };
+
+    let device = init.block_on();
+    if device.is_none() {
+        eprintln!("no adapter available, skipping");
+    }
+    device
+}
@@ -0,0 +1,69 @@
+// Bloom passes run by PostProcess, each draws a fullscreen triangle into one mip of the bloom
+// texture. fs_prefilter keeps what is brighter than the threshold while halving the HDR target,
+// fs_downsample halves it further and fs_upsample blends each mip back into the next larger one.
+
+struct Bloom {
+    threshold: f32,
+}
+
+@group(0) @binding(0) var<uniform> bloom: Bloom;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var source_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the target, the corners outside it are clipped.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Four bilinear taps one source texel out, averaging a 4x4 block around the pixel.
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    var sum = textureSample(source, source_sampler, uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    sum = sum + textureSample(source, source_sampler, uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    sum = sum + textureSample(source, source_sampler, uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    sum = sum + textureSample(source, source_sampler, uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+    return sum * 0.25;
+}
+
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv);
+
+    // scaled by how far the brightest channel is over the threshold so the hue is kept
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent over the smaller mip, added onto the target by the blend state.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    var sum = vec3<f32>(0.0);
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let weight = f32((2 - abs(x)) * (2 - abs(y)));
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            sum = sum + textureSampleLevel(source, source_sampler, in.uv + offset, 0.0).rgb * weight;
+        }
+    }
+    return vec4<f32>(sum / 16.0, 1.0);
+}
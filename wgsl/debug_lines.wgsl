@@ -0,0 +1,31 @@
+// Unlit lines drawn by DebugDraw into the HDR target, tonemapped along with the scene.
+
+struct Camera {
+    projection_view: mat4x4<f32>,
+    position: vec3<f32>,
+}
+
+@group(0) @binding(0) var<uniform> cam: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = cam.projection_view * vec4<f32>(vertex.position, 1.0);
+    out.color = vertex.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,472 @@
+// WGSL copy of vertex_shader.vert and fragment_shader.frag, keep the two in sync. Loaded straight
+// from the source tree, set CARD_GAME_WGSL to draw with it instead of the SPIR-V pair. It is always
+// used when point lights come from a storage buffer since the SPIR-V pair only has the uniform path.
+//
+// Preprocessed per ShaderVariant, TEXTURED samples the bound texture, DEBUG_NORMALS, DEBUG_DEPTH and
+// DEBUG_UVS output the world space normal, camera distance or texture coordinates instead of the
+// lit color and STORAGE_LIGHTS reads point lights from a storage buffer instead of the fixed size
+// uniform array, and only goes through the point and spot lights binned into the fragment's
+// screen tile.
+
+let GLOBAL_LIGHT_COUNT: u32 = 8u;
+let POINT_LIGHT_COUNT: u32 = 8u;
+let SPOT_LIGHT_COUNT: u32 = 8u;
+
+let FOG_LINEAR: u32 = 1u;
+let FOG_EXPONENTIAL: u32 = 2u;
+
+struct Camera {
+    projection_view: mat4x4<f32>,
+    position: vec3<f32>,
+    fog_mode: u32, // FogFalloff::shader_id, 0 while fog is off
+    forward: vec3<f32>,
+    fog_density: f32,
+    fog_color: vec3<f32>,
+    fog_start: f32,
+    fog_end: f32,
+}
+
+struct GlobalLight {
+    color: vec3<f32>,
+    power: f32,
+    direction: vec3<f32>,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    power: f32,
+}
+
+struct SpotLight {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    power: f32,
+    direction: vec3<f32>,
+    cut_off: f32,
+    // tile in the spot shadow atlas, no shadow while strength is 0
+    shadow_view_projection: mat4x4<f32>,
+    shadow_rect: vec4<f32>,
+    shadow_strength: f32,
+    // layer in spot_cookies, 0 is white
+    cookie_layer: u32,
+}
+
+struct GlobalLights {
+    lights: array<GlobalLight, GLOBAL_LIGHT_COUNT>,
+}
+
+#ifdef STORAGE_LIGHTS
+// sized by RenderState, LightCounts says how many slots are in use
+struct PointLights {
+    lights: array<PointLight>,
+}
+#else
+struct PointLights {
+    lights: array<PointLight, POINT_LIGHT_COUNT>,
+}
+#endif
+
+struct SpotLights {
+    lights: array<SpotLight, SPOT_LIGHT_COUNT>,
+}
+
+struct AmbientLight {
+    sky_color: vec3<f32>,
+    intensity: f32,
+    ground_color: vec3<f32>,
+}
+
+#ifdef STORAGE_LIGHTS
+let MAX_LIGHTS_PER_TILE: u32 = 32u;
+let SPOT_LIGHT_BIT: u32 = 2147483648u;
+
+// per screen tile lists of the point and spot lights reaching into it, see LightTiles
+struct LightTiles {
+    screen_size: vec2<f32>,
+    columns: u32,
+    rows: u32,
+    // for every tile, row by row from the top left, the number of lights followed by
+    // MAX_LIGHTS_PER_TILE entries. An entry is an index into point_lights, or into spot_lights
+    // with SPOT_LIGHT_BIT set.
+    tiles: array<u32>,
+}
+#endif
+
+// number of used slots in each light array
+struct LightCounts {
+    global_count: u32,
+    point_count: u32,
+    spot_count: u32,
+}
+
+// shadow map of the first global light
+struct Shadow {
+    view_projection: mat4x4<f32>,
+    enabled: u32,
+}
+
+@group(0) @binding(0) var<uniform> cam: Camera;
+
+@group(1) @binding(0) var tex: texture_2d_array<f32>;
+@group(1) @binding(1) var sam: sampler;
+
+@group(2) @binding(0) var<uniform> global_lights: GlobalLights;
+#ifdef STORAGE_LIGHTS
+@group(2) @binding(1) var<storage, read> point_lights: PointLights;
+#else
+@group(2) @binding(1) var<uniform> point_lights: PointLights;
+#endif
+@group(2) @binding(2) var<uniform> spot_lights: SpotLights;
+@group(2) @binding(3) var<uniform> ambient_light: AmbientLight;
+@group(2) @binding(4) var<uniform> light_counts: LightCounts;
+@group(2) @binding(5) var<uniform> shadow: Shadow;
+@group(2) @binding(6) var shadow_map: texture_depth_2d;
+@group(2) @binding(7) var shadow_sampler: sampler_comparison;
+@group(2) @binding(8) var spot_shadow_atlas: texture_depth_2d;
+#ifdef STORAGE_LIGHTS
+@group(2) @binding(9) var<storage, read> light_tiles: LightTiles;
+#endif
+@group(2) @binding(10) var spot_cookies: texture_2d_array<f32>;
+@group(2) @binding(11) var cookie_sampler: sampler;
+
+// FlatNormalTexture for objects without a NormalMap
+@group(3) @binding(0) var normal_tex: texture_2d_array<f32>;
+@group(3) @binding(1) var normal_sam: sampler;
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) tex_coord: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) tangent: vec4<f32>, // w is the bitangent's handedness, zero without uvs
+}
+
+// per instance, see data_types::Instance
+struct InstanceInput {
+    @location(5) model_0: vec4<f32>,
+    @location(6) model_1: vec4<f32>,
+    @location(7) model_2: vec4<f32>,
+    @location(8) model_3: vec4<f32>,
+    @location(9) params_0: vec4<f32>,
+    @location(10) params_1: vec4<f32>,
+    @location(11) scale: vec4<f32>, // w is the texture array layer
+    @location(12) tint: vec4<f32>,
+    @location(13) material_0: vec4<f32>, // emissive, w is roughness
+    @location(14) material_1: vec4<f32>, // x is metallic
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+    @location(1) normal_world: vec3<f32>,
+    @location(2) position_world: vec3<f32>,
+    @location(3) color: vec4<f32>,
+    // from the MaterialParams component, zero when an entity has none.
+    // params_0.x dissolve amount, params_0.y dissolve edge width, params_1.xyz dissolve edge color
+    @location(4) @interpolate(flat) params_0: vec4<f32>,
+    @location(5) @interpolate(flat) params_1: vec4<f32>,
+    // array layer of the texture, the frame of an animated texture
+    @location(6) @interpolate(flat) layer: f32,
+    // from the Material component, emissive.xyz and roughness then metallic in material_1.x
+    @location(7) @interpolate(flat) material_0: vec4<f32>,
+    @location(8) @interpolate(flat) material_1: vec4<f32>,
+    @location(9) tangent_world: vec4<f32>, // w is the bitangent's handedness, zero without uvs
+}
+
+@vertex
+fn vs_main(vertex: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let model_3x3 = mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz);
+    let scale = instance.scale.xyz;
+
+    var out: VertexOutput;
+    out.clip_position = cam.projection_view * model * vertex.position;
+    out.tex_coord = vertex.tex_coord;
+    // the inverse transpose of rotation * scale is rotation * inverse scale, which is model * scale^-2
+    out.normal_world = normalize(model_3x3 * (vertex.normal.xyz / (scale * scale)));
+    out.position_world = (model * vertex.position).xyz;
+    out.color = vertex.color * instance.tint;
+    out.params_0 = instance.params_0;
+    out.params_1 = instance.params_1;
+    out.layer = instance.scale.w;
+    out.material_0 = instance.material_0;
+    out.material_1 = instance.material_1;
+    // tangents lie in the surface so they transform like positions, not like normals
+    out.tangent_world = vec4<f32>(model_3x3 * vertex.tangent.xyz, vertex.tangent.w);
+    return out;
+}
+
+// Stable per texel noise in [0, 1) for the dissolve threshold.
+fn dissolve_noise(uv: vec2<f32>) -> f32 {
+    return fract(sin(dot(floor(uv * 256.0), vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+fn point_light_capacity() -> u32 {
+#ifdef STORAGE_LIGHTS
+    return arrayLength(&point_lights.lights);
+#else
+    return POINT_LIGHT_COUNT;
+#endif
+}
+
+// Fraction of the first global light reaching position, 3x3 pcf over the shadow map. The Level
+// variant has no derivatives so it can run after the dissolve discard.
+fn shadow_factor(position: vec3<f32>) -> f32 {
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+
+    let clip = shadow.view_projection * vec4<f32>(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    // nothing was drawn there so it counts as lit
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Fraction of a spot light reaching position, 3x3 pcf inside the light's atlas tile. Taps are
+// clamped to the tile since its neighbours belong to other lights. Fades to fully lit with the
+// light's shadow strength.
+fn spot_shadow_factor(light: SpotLight, position: vec3<f32>) -> f32 {
+    if (light.shadow_strength <= 0.0) {
+        return 1.0;
+    }
+
+    let clip = light.shadow_view_projection * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(spot_shadow_atlas));
+    let tile_min = light.shadow_rect.xy + texel * 0.5;
+    let tile_max = light.shadow_rect.xy + light.shadow_rect.zw - texel * 0.5;
+    let center = light.shadow_rect.xy + uv * light.shadow_rect.zw;
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let coord = clamp(center + vec2<f32>(f32(x), f32(y)) * texel, tile_min, tile_max);
+            lit = lit + textureSampleCompareLevel(spot_shadow_atlas, shadow_sampler, coord, ndc.z);
+        }
+    }
+    return mix(1.0, lit / 9.0, light.shadow_strength);
+}
+
+// Tint of a spot light at position, its cookie projected the same way as its shadow.
+fn spot_cookie(light: SpotLight, position: vec3<f32>) -> vec3<f32> {
+    if (light.cookie_layer == 0u) {
+        return vec3<f32>(1.0);
+    }
+
+    let clip = light.shadow_view_projection * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+    return textureSampleLevel(spot_cookies, cookie_sampler, uv, i32(light.cookie_layer), 0.0).rgb;
+}
+
+// Bends normal by a tangent space normal map sample. Without a tangent, from meshes without uvs,
+// the normal is kept as is.
+fn perturbed_normal(normal: vec3<f32>, tangent: vec4<f32>, map_sample: vec3<f32>) -> vec3<f32> {
+    let n = normalize(normal);
+    // interpolation shortens the tangent and can leave it off the normal's plane
+    let t = tangent.xyz - n * dot(n, tangent.xyz);
+    if (abs(tangent.w) < 0.5 || dot(t, t) < 1e-8) {
+        return n;
+    }
+
+    let t = normalize(t);
+    let b = cross(n, t) * sign(tangent.w);
+    return normalize(mat3x3<f32>(t, b, n) * (map_sample * 2.0 - 1.0));
+}
+
+// Blinn-Phong exponent for a roughness, the usual alpha = roughness^2 mapping. Roughness 0.5 gives
+// about 32, the exponent used before materials. Clamped so a roughness of 0 keeps a visible highlight.
+fn specular_exponent(roughness: f32) -> f32 {
+    let alpha = max(roughness * roughness, 0.05);
+    return 2.0 / (alpha * alpha) - 2.0;
+}
+
+// Diffuse and specular strength of one light, shared by every light type. The specular term is
+// normalized so tighter highlights get brighter instead of just smaller, scaled to 1 at exponent 32.
+fn blinn_phong(normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>, exponent: f32) -> vec2<f32> {
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), exponent) * (exponent + 8.0) / 40.0;
+
+    return vec2<f32>(diffuse_strength, specular_strength);
+}
+
+// Diffuse and specular light reaching a fragment, summed over every light.
+struct Lighting {
+    diffuse: vec3<f32>,
+    specular: vec3<f32>,
+}
+
+fn add_point_light(sum: Lighting, light: PointLight, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, exponent: f32) -> Lighting {
+    let light_dir = normalize(light.position - position);
+    let strength = blinn_phong(normal, view_dir, light_dir, exponent);
+    return Lighting(sum.diffuse + light.color * strength.x, sum.specular + light.color * strength.y);
+}
+
+fn add_spot_light(sum: Lighting, light: SpotLight, position: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, exponent: f32) -> Lighting {
+    let light_dir = normalize(light.position - position);
+
+    // TODO: create gradual falloff for lighting
+    let theta = dot(light_dir, normalize(-light.direction));
+    if (theta <= light.cut_off) {
+        return sum;
+    }
+    let strength = blinn_phong(normal, view_dir, light_dir, exponent) * spot_shadow_factor(light, position);
+    let color = light.color * spot_cookie(light, position);
+    return Lighting(sum.diffuse + color * strength.x, sum.specular + color * strength.y);
+}
+
+#ifdef STORAGE_LIGHTS
+// Start of the list of the tile covering a fragment at pixel position.
+fn light_tile_start(pixel: vec2<f32>) -> u32 {
+    let grid = vec2<u32>(light_tiles.columns, light_tiles.rows);
+    let tile = min(vec2<u32>(pixel / light_tiles.screen_size * vec2<f32>(grid)), grid - vec2<u32>(1u));
+    return (tile.y * grid.x + tile.x) * (MAX_LIGHTS_PER_TILE + 1u);
+}
+#endif
+
+// How much of a fragment at this view space depth the fog covers.
+fn fog_factor(depth: f32) -> f32 {
+    if (cam.fog_mode == FOG_LINEAR) {
+        return clamp((depth - cam.fog_start) / max(cam.fog_end - cam.fog_start, 0.0001), 0.0, 1.0);
+    }
+    return 1.0 - exp(-cam.fog_density * max(depth, 0.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal_sample = textureSample(normal_tex, normal_sam, in.tex_coord, 0).xyz;
+    let normal = perturbed_normal(in.normal_world, in.tangent_world, normal_sample);
+
+    // hemisphere ambient, blends from ground to sky color as the normal turns upwards
+    let sky_factor = normal.y * 0.5 + 0.5;
+    let ambient_color = mix(ambient_light.ground_color, ambient_light.sky_color, sky_factor) * ambient_light.intensity;
+
+#ifdef TEXTURED
+    let texture_color = textureSample(tex, sam, in.tex_coord, i32(in.layer)).xyz;
+#else
+    let texture_color = vec3<f32>(1.0);
+#endif
+
+    // after sampling so the implicit derivatives stay in uniform control flow
+    let dissolve_amount = in.params_0.x;
+    var dissolve_edge = 0.0;
+    if (dissolve_amount > 0.0) {
+        let noise = dissolve_noise(in.tex_coord);
+        if (noise < dissolve_amount) {
+            discard;
+        }
+        let edge_width = in.params_0.y;
+        if (edge_width > 0.0) {
+            dissolve_edge = 1.0 - smoothstep(dissolve_amount, dissolve_amount + edge_width, noise);
+        }
+    }
+
+#ifdef DEBUG_NORMALS
+    return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+#else
+#ifdef DEBUG_DEPTH
+    // black at the camera fading to white, linear in distance unlike the depth buffer
+    let depth = 1.0 - exp(-distance(cam.position, in.position_world) / 20.0);
+    return vec4<f32>(vec3<f32>(depth), 1.0);
+#else
+#ifdef DEBUG_UVS
+    // fract shows tiling and wrapping as repeated gradients
+    return vec4<f32>(fract(in.tex_coord), 0.0, 1.0);
+#else
+    let view_dir = normalize(cam.position - in.position_world);
+    let exponent = specular_exponent(in.material_0.w);
+    let metallic = in.material_1.x;
+
+    var lighting = Lighting(vec3<f32>(0.0), vec3<f32>(0.0));
+
+    for (var i = 0u; i < min(light_counts.global_count, GLOBAL_LIGHT_COUNT); i = i + 1u) {
+        let light = global_lights.lights[i];
+        let visibility = select(1.0, shadow_factor(in.position_world), i == 0u);
+        let strength = blinn_phong(normal, view_dir, normalize(-light.direction), exponent) * visibility;
+        lighting.diffuse = lighting.diffuse + light.color * strength.x;
+        lighting.specular = lighting.specular + light.color * strength.y;
+    }
+
+#ifdef STORAGE_LIGHTS
+    // only the point and spot lights binned into this fragment's tile
+    let start = light_tile_start(in.clip_position.xy);
+    let count = min(light_tiles.tiles[start], MAX_LIGHTS_PER_TILE);
+    for (var n = 1u; n <= count; n = n + 1u) {
+        // lights end at their radius here, tiles partly inside it would cut them off at their edges
+        let entry = light_tiles.tiles[start + n];
+        let i = entry & ~SPOT_LIGHT_BIT;
+        if ((entry & SPOT_LIGHT_BIT) != 0u) {
+            if (i < min(light_counts.spot_count, SPOT_LIGHT_COUNT)) {
+                let light = spot_lights.lights[i];
+                if (distance(light.position, in.position_world) <= light.radius) {
+                    lighting = add_spot_light(lighting, light, in.position_world, normal, view_dir, exponent);
+                }
+            }
+        } else if (i < min(light_counts.point_count, point_light_capacity())) {
+            let light = point_lights.lights[i];
+            if (distance(light.position, in.position_world) <= light.radius) {
+                lighting = add_point_light(lighting, light, in.position_world, normal, view_dir, exponent);
+            }
+        }
+    }
+#else
+    for (var i = 0u; i < min(light_counts.point_count, point_light_capacity()); i = i + 1u) {
+        lighting = add_point_light(lighting, point_lights.lights[i], in.position_world, normal, view_dir, exponent);
+    }
+
+    for (var i = 0u; i < min(light_counts.spot_count, SPOT_LIGHT_COUNT); i = i + 1u) {
+        lighting = add_spot_light(lighting, spot_lights.lights[i], in.position_world, normal, view_dir, exponent);
+    }
+#endif
+
+    // metals have no diffuse and highlights in their own color, everything else reflects white
+    let base_color = texture_color * in.color.rgb;
+    let specular_color = mix(vec3<f32>(1.0), base_color, metallic);
+    var color = (ambient_color + lighting.diffuse * (1.0 - metallic)) * base_color + lighting.specular * specular_color;
+
+    // emissive is unlit
+    color = color + in.material_0.xyz;
+
+    // the dissolve edge glows regardless of lighting
+    color = color + in.params_1.xyz * dissolve_edge;
+
+    // skipped entirely while off so frames without fog are unchanged
+    if (cam.fog_mode != 0u) {
+        let depth = dot(in.position_world - cam.position, cam.forward);
+        color = mix(color, cam.fog_color, fog_factor(depth));
+    }
+
+    // linear and unbounded, the tonemap pass brings it into the output's range
+    return vec4<f32>(color, 1.0);
+#endif
+#endif
+#endif
+}
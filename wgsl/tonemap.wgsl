@@ -0,0 +1,111 @@
+// Fullscreen pass mapping the HDR scene into the output's range. Only exists as WGSL, it is used with
+// the SPIR-V pair too.
+
+struct Tonemap {
+    op: u32, // TonemapOperator::shader_id
+    compare_op: u32,
+    exposure: f32,
+    compare_exposure: f32,
+    split_x: f32, // pixels right of this use the compare operator, 0 disables the split
+    manual_gamma: u32,
+    bloom_intensity: f32, // 0 while bloom is off
+    grading_strength: f32, // 0 leaves the colors ungraded
+}
+
+@group(0) @binding(0) var<uniform> settings: Tonemap;
+@group(0) @binding(1) var hdr: texture_2d<f32>;
+@group(0) @binding(2) var hdr_sampler: sampler;
+// half size, the first mip of the bloom chain
+@group(0) @binding(3) var bloom: texture_2d<f32>;
+@group(0) @binding(4) var bloom_sampler: sampler;
+// color grading, indexed by display encoded color. The identity LUT when none is set
+@group(0) @binding(5) var grading_lut: texture_3d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// One triangle covering the screen, the corners outside it are clipped.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+let TONEMAP_NONE: u32 = 0u;
+let TONEMAP_REINHARD: u32 = 1u;
+let TONEMAP_ACES: u32 = 2u;
+let TONEMAP_KHRONOS_NEUTRAL: u32 = 3u;
+
+fn tonemap_aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn tonemap_khronos_neutral(input: vec3<f32>) -> vec3<f32> {
+    let start_compression = 0.8 - 0.04;
+    let desaturation = 0.15;
+
+    var color = input;
+    let x = min(color.r, min(color.g, color.b));
+    let offset = select(0.04, x - 6.25 * x * x, x < 0.08);
+    color = color - offset;
+
+    let peak = max(color.r, max(color.g, color.b));
+    if (peak < start_compression) {
+        return color;
+    }
+
+    let d = 1.0 - start_compression;
+    let new_peak = 1.0 - d * d / (peak + d - start_compression);
+    color = color * (new_peak / peak);
+
+    let g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+    return mix(color, vec3<f32>(new_peak), g);
+}
+
+// operator ids match TonemapOperator::shader_id, none is clamped by the 8 bit output
+fn tonemap(color: vec3<f32>, op: u32) -> vec3<f32> {
+    if (op == TONEMAP_REINHARD) {
+        return color / (color + 1.0);
+    } else if (op == TONEMAP_ACES) {
+        return tonemap_aces(color);
+    } else if (op == TONEMAP_KHRONOS_NEUTRAL) {
+        return tonemap_khronos_neutral(color);
+    }
+    return color;
+}
+
+// Samples between the first and last texel centers so 0 and 1 map to the LUT's edges exactly.
+fn color_grade(color: vec3<f32>) -> vec3<f32> {
+    let size = f32(textureDimensions(grading_lut).x);
+    let coord = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)) * ((size - 1.0) / size) + 0.5 / size;
+    return textureSample(grading_lut, bloom_sampler, coord).rgb;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = textureSample(hdr, hdr_sampler, in.uv).rgb;
+    color = color + textureSample(bloom, bloom_sampler, in.uv).rgb * settings.bloom_intensity;
+
+    // the compare operator is shown right of split_x so operators can be judged side by side
+    let compare_side = settings.split_x > 0.0 && in.clip_position.x >= settings.split_x;
+    let op = select(settings.op, settings.compare_op, compare_side);
+    let exposure = select(settings.exposure, settings.compare_exposure, compare_side);
+    color = tonemap(color * exposure, op);
+
+    // LUTs are authored on display colors, the same gamma as the manual encoding below
+    var display = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
+    display = mix(display, color_grade(display), settings.grading_strength);
+
+    // the surface format does not encode to srgb so it has to be done here
+    if (settings.manual_gamma != 0u) {
+        return vec4<f32>(display, 1.0);
+    }
+
+    return vec4<f32>(pow(display, vec3<f32>(2.2)), 1.0);
+}